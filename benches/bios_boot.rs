@@ -0,0 +1,39 @@
+// Boots the BIOS for a few emulated seconds, once running every idle loop and once skipping them.
+// The BIOS is not part of the repository, the benchmark is skipped without one.
+
+use psx_rust::Emulator;
+use std::{env, fs, time::Instant};
+
+const DEFAULT_BIOS_PATH: &str = "./static/bios/PSXBIOS.bin";
+const CPU_CLOCK: u64 = 33_868_800;
+const SECONDS: u64 = 5;
+
+fn main() {
+    let path = env::var("PSX_BIOS").unwrap_or_else(|_| DEFAULT_BIOS_PATH.to_string());
+    let Ok(bios) = fs::read(&path) else {
+        println!("Skipping the BIOS boot, no BIOS at {} (set PSX_BIOS)", path);
+        return;
+    };
+
+    for skip_idle in [false, true] {
+        let mut emulator = Emulator::new(bios.clone()).unwrap();
+        emulator.set_tty_enabled(false);
+        emulator.set_idle_loop_skipping(skip_idle);
+
+        let start = Instant::now();
+        emulator.run_cycles(SECONDS * CPU_CLOCK).unwrap();
+        let elapsed = start.elapsed();
+        println!(
+            "{} s of BIOS boot{}: {:.0} ms, {:.1}x real time, {} frames",
+            SECONDS,
+            if skip_idle {
+                " (idle loops skipped)"
+            } else {
+                ""
+            },
+            elapsed.as_secs_f64() * 1000.0,
+            SECONDS as f64 / elapsed.as_secs_f64(),
+            emulator.frame_count()
+        );
+    }
+}
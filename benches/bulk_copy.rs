@@ -0,0 +1,51 @@
+// Copies 64KB of RAM with the bulk accessors and with single byte accesses
+
+use psx_rust::{mmu::BIOS_SIZE, Emulator};
+use std::time::{Duration, Instant};
+
+const SOURCE: u32 = 0x80010000;
+const SIZE: usize = 64 * 1024;
+const ROUNDS: u32 = 100;
+
+fn time(mut copy: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        copy();
+    }
+    start.elapsed() / ROUNDS
+}
+
+fn main() {
+    let mut emulator = Emulator::new(vec![0; BIOS_SIZE as usize]).unwrap();
+    let mmu = emulator.mmu_mut();
+    let data: Vec<u8> = (0..SIZE).map(|i| (i * 7) as u8).collect();
+    mmu.write_bytes(SOURCE, &data).unwrap();
+
+    let mut buffer = vec![0; SIZE];
+    let bulk = time(|| mmu.read_bytes(SOURCE, &mut buffer).unwrap());
+    assert_eq!(buffer, data);
+
+    let single = time(|| {
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = mmu.read(SOURCE + i as u32, 1).unwrap() as u8;
+        }
+    });
+    assert_eq!(buffer, data);
+
+    let bulk_write = time(|| mmu.write_bytes(SOURCE, &data).unwrap());
+    let single_write = time(|| {
+        for (i, byte) in data.iter().enumerate() {
+            mmu.write(SOURCE + i as u32, 1, *byte as u32).unwrap();
+        }
+    });
+
+    for (name, bulk, single) in [("read", bulk, single), ("write", bulk_write, single_write)] {
+        println!(
+            "64KB {}: {:.1} us bulk, {:.1} us per byte, {:.0}x faster",
+            name,
+            bulk.as_secs_f64() * 1e6,
+            single.as_secs_f64() * 1e6,
+            single.as_secs_f64() / bulk.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,47 @@
+// Draws large flat and gouraud shaded triangles with and without the SIMD span fill
+
+use psx_rust::{mmu::BIOS_SIZE, Emulator};
+use std::time::{Duration, Instant};
+
+const GP0: u32 = 0x1F801810;
+const TRIANGLES: u32 = 500;
+
+fn vertex(x: u32, y: u32) -> u32 {
+    (y << 16) | x
+}
+
+fn draw(emulator: &mut Emulator, words: &[u32]) -> Duration {
+    let mmu = emulator.mmu_mut();
+    let start = Instant::now();
+    for &word in words {
+        mmu.write(GP0, 4, word).unwrap();
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let setup = [0xE3000000, 0xE4000000 | (511 << 10) | 1023];
+    let mut flat = Vec::new();
+    let mut gouraud = Vec::new();
+    for i in 0..TRIANGLES {
+        let (a, b, c) = (vertex(0, 0), vertex(1000, i % 16), vertex(i % 32, 500));
+        flat.extend([0x20000000 | i, a, b, c]);
+        gouraud.extend([0x30FF0000 | i, a, 0x0000FF00, b, 0x000000FF, c]);
+    }
+
+    for (name, words) in [("flat", &flat), ("gouraud", &gouraud)] {
+        for simd in [false, true] {
+            let mut emulator = Emulator::new(vec![0; BIOS_SIZE as usize]).unwrap();
+            emulator.set_simd_rasterizer(simd);
+            draw(&mut emulator, &setup);
+            let elapsed = draw(&mut emulator, words);
+            println!(
+                "{} triangles{}: {:.2} ms, {:.0} us per triangle",
+                name,
+                if simd { " (SIMD)" } else { "" },
+                elapsed.as_secs_f64() * 1000.0,
+                elapsed.as_secs_f64() * 1e6 / TRIANGLES as f64
+            );
+        }
+    }
+}
@@ -0,0 +1,542 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+// Zip and gzip archives, the files in them are extracted to memory. Only what dumps of BIOSes and
+// games use is supported: stored and deflated entries without encryption or zip64.
+
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054B50;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x02014B50;
+const LOCAL_HEADER: u32 = 0x04034B50;
+// The end record is followed by a comment of up to 64KB
+const MAX_END_RECORD_OFFSET: u64 = 22 + 0xFFFF;
+
+// What is loaded from a zip without a name after the #, see Zip::find
+pub const BIOS_EXTENSIONS: [&str; 2] = ["bin", "rom"];
+pub const EXE_EXTENSIONS: [&str; 2] = ["exe", "psx"];
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+// WinZip AES, the method of the data is in an extra field
+const AES_ENCRYPTED: u16 = 99;
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    // Not a zip or gzip file, or a broken one
+    Invalid {
+        path: PathBuf,
+        message: String,
+    },
+    Encrypted {
+        path: PathBuf,
+        name: String,
+    },
+    Unsupported {
+        path: PathBuf,
+        name: String,
+        message: String,
+    },
+    // More than one file could be the one to load, path.zip#name picks one
+    Ambiguous {
+        path: PathBuf,
+        candidates: Vec<String>,
+    },
+    NoCandidate {
+        path: PathBuf,
+        extensions: Vec<String>,
+    },
+    NotFound {
+        path: PathBuf,
+        name: String,
+    },
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArchiveError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            ArchiveError::Invalid { path, message } => {
+                write!(f, "{}: Invalid archive, {}", path.display(), message)
+            }
+            ArchiveError::Encrypted { path, name } => write!(
+                f,
+                "{}: '{}' is password protected, extract it first",
+                path.display(),
+                name
+            ),
+            ArchiveError::Unsupported {
+                path,
+                name,
+                message,
+            } => write!(f, "{}: '{}' {}", path.display(), name, message),
+            ArchiveError::Ambiguous { path, candidates } => write!(
+                f,
+                "{}: More than one file could be loaded ({}), pick one with {}#<name>",
+                path.display(),
+                candidates.join(", "),
+                path.display()
+            ),
+            ArchiveError::NoCandidate { path, extensions } => write!(
+                f,
+                "{}: No .{} file in the archive",
+                path.display(),
+                extensions.join(", .")
+            ),
+            ArchiveError::NotFound { path, name } => {
+                write!(f, "{}: No file '{}' in the archive", path.display(), name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+struct Entry {
+    name: String,
+    method: u16,
+    flags: u16,
+    crc: u32,
+    compressed_size: u64,
+    size: u64,
+    local_header: u64,
+}
+
+pub struct Zip {
+    path: PathBuf,
+    file: File,
+    entries: Vec<Entry>,
+}
+
+impl Zip {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ArchiveError> {
+        let path = path.as_ref().to_path_buf();
+        let io_error = |error| ArchiveError::Io {
+            path: path.clone(),
+            error,
+        };
+        let invalid = |message: &str| ArchiveError::Invalid {
+            path: path.clone(),
+            message: message.to_string(),
+        };
+
+        let mut file = File::open(&path).map_err(io_error)?;
+        let size = file.metadata().map_err(io_error)?.len();
+
+        // The end record is the last thing in the file, search backwards past the comment
+        let tail_size = size.min(MAX_END_RECORD_OFFSET);
+        let mut tail = vec![0; tail_size as usize];
+        file.seek(SeekFrom::Start(size - tail_size))
+            .and_then(|_| file.read_exact(&mut tail))
+            .map_err(io_error)?;
+        let end = tail
+            .windows(4)
+            .rposition(|window| le32(window, 0) == END_OF_CENTRAL_DIRECTORY)
+            .filter(|&offset| offset + 22 <= tail.len())
+            .ok_or_else(|| invalid("missing the end of central directory record"))?;
+        let end = &tail[end..];
+
+        let count = le16(end, 10);
+        let directory_size = le32(end, 12);
+        let directory_offset = le32(end, 16);
+        if count == 0xFFFF || directory_offset == 0xFFFFFFFF {
+            return Err(invalid("zip64 archives are not supported"));
+        }
+
+        let mut directory = vec![0; directory_size as usize];
+        file.seek(SeekFrom::Start(directory_offset as u64))
+            .and_then(|_| file.read_exact(&mut directory))
+            .map_err(io_error)?;
+
+        let mut entries = Vec::with_capacity(count as usize);
+        let mut offset = 0;
+        for _ in 0..count {
+            let header = directory
+                .get(offset..offset + 46)
+                .filter(|header| le32(header, 0) == CENTRAL_DIRECTORY_HEADER)
+                .ok_or_else(|| invalid("broken central directory"))?;
+            let name_length = le16(header, 28) as usize;
+            let extra_length = le16(header, 30) as usize;
+            let comment_length = le16(header, 32) as usize;
+            let name = directory
+                .get(offset + 46..offset + 46 + name_length)
+                .ok_or_else(|| invalid("broken central directory"))?;
+
+            entries.push(Entry {
+                name: String::from_utf8_lossy(name).into_owned(),
+                method: le16(header, 10),
+                flags: le16(header, 8),
+                crc: le32(header, 16),
+                compressed_size: le32(header, 20) as u64,
+                size: le32(header, 24) as u64,
+                local_header: le32(header, 42) as u64,
+            });
+            offset += 46 + name_length + extra_length + comment_length;
+        }
+
+        Ok(Self {
+            path,
+            file,
+            entries,
+        })
+    }
+
+    // The files in the archive, without the directories
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .filter(|name| !name.ends_with('/'))
+    }
+
+    // The file to load when the archive is given without a name. The extensions are tried in
+    // order and only one file may have the first one that matches. An archive with a single file
+    // loads that one.
+    pub fn find(&self, extensions: &[&str]) -> Result<String, ArchiveError> {
+        for extension in extensions {
+            let candidates: Vec<String> = self
+                .names()
+                .filter(|name| has_extension(name, extension))
+                .map(str::to_string)
+                .collect();
+            match candidates.len() {
+                0 => continue,
+                1 => return Ok(candidates.into_iter().next().unwrap()),
+                _ => {
+                    return Err(ArchiveError::Ambiguous {
+                        path: self.path.clone(),
+                        candidates,
+                    })
+                }
+            }
+        }
+
+        let mut names = self.names();
+        match (names.next(), names.next()) {
+            (Some(name), None) => Ok(name.to_string()),
+            _ => Err(ArchiveError::NoCandidate {
+                path: self.path.clone(),
+                extensions: extensions
+                    .iter()
+                    .map(|extension| extension.to_string())
+                    .collect(),
+            }),
+        }
+    }
+
+    // Extracts a file, names are compared without case when there is no exact match
+    pub fn read(&mut self, name: &str) -> Result<Vec<u8>, ArchiveError> {
+        let index = self
+            .entries
+            .iter()
+            .position(|entry| entry.name == name)
+            .or_else(|| {
+                self.entries
+                    .iter()
+                    .position(|entry| entry.name.eq_ignore_ascii_case(name))
+            })
+            .ok_or_else(|| ArchiveError::NotFound {
+                path: self.path.clone(),
+                name: name.to_string(),
+            })?;
+        let entry = &self.entries[index];
+        let path = &self.path;
+        let io_error = |error| ArchiveError::Io {
+            path: path.clone(),
+            error,
+        };
+        let unsupported = |message: &str| ArchiveError::Unsupported {
+            path: path.clone(),
+            name: entry.name.clone(),
+            message: message.to_string(),
+        };
+
+        if entry.flags & 1 != 0 || entry.method == AES_ENCRYPTED {
+            return Err(ArchiveError::Encrypted {
+                path: path.clone(),
+                name: entry.name.clone(),
+            });
+        }
+
+        let mut header = [0; 30];
+        self.file
+            .seek(SeekFrom::Start(entry.local_header))
+            .and_then(|_| self.file.read_exact(&mut header))
+            .map_err(io_error)?;
+        if le32(&header, 0) != LOCAL_HEADER {
+            return Err(ArchiveError::Invalid {
+                path: path.clone(),
+                message: format!("broken local header of '{}'", entry.name),
+            });
+        }
+        let data_offset =
+            entry.local_header + 30 + le16(&header, 26) as u64 + le16(&header, 28) as u64;
+
+        let mut compressed = vec![0; entry.compressed_size as usize];
+        self.file
+            .seek(SeekFrom::Start(data_offset))
+            .and_then(|_| self.file.read_exact(&mut compressed))
+            .map_err(io_error)?;
+
+        let data = match entry.method {
+            STORED => compressed,
+            DEFLATED => inflate(&compressed, entry.size).map_err(|error| match error {
+                Some(error) => ArchiveError::Invalid {
+                    path: path.clone(),
+                    message: format!("'{}': {}", entry.name, error),
+                },
+                None => unsupported("is deflated, which needs the deflate feature"),
+            })?,
+            method => {
+                return Err(unsupported(&format!(
+                    "uses compression method {}, only stored and deflated files are supported",
+                    method
+                )))
+            }
+        };
+
+        if data.len() as u64 != entry.size || crc32(&data) != entry.crc {
+            return Err(ArchiveError::Invalid {
+                path: path.clone(),
+                message: format!("'{}' is corrupt", entry.name),
+            });
+        }
+
+        Ok(data)
+    }
+}
+
+// Raw deflate data of a zip entry, None without the deflate feature
+#[cfg(feature = "deflate")]
+fn inflate(compressed: &[u8], size: u64) -> Result<Vec<u8>, Option<io::Error>> {
+    let mut data = Vec::with_capacity(size as usize);
+    flate2::read::DeflateDecoder::new(compressed)
+        .read_to_end(&mut data)
+        .map_err(Some)?;
+    Ok(data)
+}
+
+#[cfg(not(feature = "deflate"))]
+fn inflate(_compressed: &[u8], _size: u64) -> Result<Vec<u8>, Option<io::Error>> {
+    Err(None)
+}
+
+// The whole content of a .gz file
+#[cfg(feature = "deflate")]
+pub fn gunzip(path: impl AsRef<Path>) -> Result<Vec<u8>, ArchiveError> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|error| ArchiveError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+
+    let mut data = Vec::new();
+    flate2::read::MultiGzDecoder::new(io::BufReader::new(file))
+        .read_to_end(&mut data)
+        .map_err(|error| ArchiveError::Invalid {
+            path: path.to_path_buf(),
+            message: error.to_string(),
+        })?;
+
+    Ok(data)
+}
+
+#[cfg(not(feature = "deflate"))]
+pub fn gunzip(path: impl AsRef<Path>) -> Result<Vec<u8>, ArchiveError> {
+    let path = path.as_ref();
+    Err(ArchiveError::Unsupported {
+        path: path.to_path_buf(),
+        name: path.display().to_string(),
+        message: "is gzip compressed, which needs the deflate feature".to_string(),
+    })
+}
+
+// Where an archive path points: a plain file, a gzip file, or a zip and optionally the file
+// in it after a #
+pub enum Source {
+    File(PathBuf),
+    Gzip(PathBuf),
+    Zip(PathBuf, Option<String>),
+}
+
+impl Source {
+    pub fn of(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let text = path.to_string_lossy();
+
+        if let Some((archive, name)) = text.rsplit_once('#') {
+            let archive = PathBuf::from(archive);
+            // Plain files can have a # in their name too
+            if has_extension(archive.to_string_lossy().as_ref(), "zip") && !path.exists() {
+                return Source::Zip(archive, Some(name.to_string()));
+            }
+        }
+
+        if has_extension(&text, "zip") {
+            Source::Zip(path.to_path_buf(), None)
+        } else if has_extension(&text, "gz") {
+            Source::Gzip(path.to_path_buf())
+        } else {
+            Source::File(path.to_path_buf())
+        }
+    }
+
+    pub fn is_archive(&self) -> bool {
+        !matches!(self, Source::File(_))
+    }
+}
+
+// Loads a file that may be in an archive. Returns the name of the file, inside the archive or
+// without the .gz, and the data. Zips without a name after # are searched for the extensions,
+// see Zip::find.
+pub fn read_file(
+    path: impl AsRef<Path>,
+    extensions: &[&str],
+) -> Result<(String, Vec<u8>), ArchiveError> {
+    let path = path.as_ref();
+    match Source::of(path) {
+        Source::File(path) => {
+            let data = std::fs::read(&path).map_err(|error| ArchiveError::Io {
+                path: path.clone(),
+                error,
+            })?;
+            Ok((path.to_string_lossy().into_owned(), data))
+        }
+        Source::Gzip(path) => {
+            let name = path.with_extension("").to_string_lossy().into_owned();
+            Ok((name, gunzip(&path)?))
+        }
+        Source::Zip(path, name) => {
+            let mut zip = Zip::open(&path)?;
+            let name = match name {
+                Some(name) => name,
+                None => zip.find(extensions)?,
+            };
+            let data = zip.read(&name)?;
+            Ok((name, data))
+        }
+    }
+}
+
+pub fn has_extension(name: &str, extension: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .is_some_and(|name| name.eq_ignore_ascii_case(extension))
+}
+
+fn le16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn le32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+// The CRC-32 zip and gzip use
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xEDB88320
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+
+    !data.iter().fold(!0, |crc, &byte| {
+        TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/archives")
+            .join(name)
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn bios_loads_from_zip_and_gzip() {
+        use crate::{mmu::BIOS_SIZE, Emulator};
+
+        let (name, zipped) = read_file(fixture("bios.zip"), &BIOS_EXTENSIONS).unwrap();
+        assert_eq!(name, "SCPH1001.BIN");
+        assert_eq!(zipped.len(), BIOS_SIZE as usize);
+        assert_eq!(&zipped[..4], &[0, 7, 14, 21]);
+
+        let (name, gzipped) = read_file(fixture("bios.bin.gz"), &BIOS_EXTENSIONS).unwrap();
+        assert!(name.ends_with("bios.bin"));
+        assert!(gzipped == zipped);
+        assert!(Emulator::new(gzipped).is_ok());
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn exe_is_picked_by_its_extension() {
+        use crate::exe::Exe;
+
+        let (name, data) = read_file(fixture("exe.zip"), &EXE_EXTENSIONS).unwrap();
+        assert_eq!(name, "game.exe");
+        assert_eq!(Exe::parse(&data).unwrap().pc, 0x80010000);
+
+        let path = format!("{}#notes.txt", fixture("exe.zip").display());
+        assert_eq!(read_file(path, &EXE_EXTENSIONS).unwrap().1, b"hi");
+    }
+
+    #[test]
+    fn ambiguous_missing_and_protected_files_are_reported() {
+        let zip = Zip::open(fixture("two-discs.zip")).unwrap();
+        match zip.find(&["cue", "bin"]) {
+            Err(ArchiveError::Ambiguous { candidates, .. }) => {
+                assert_eq!(candidates, ["Disc 1.cue", "Disc 2.cue"])
+            }
+            _ => panic!("two cue sheets are ambiguous"),
+        }
+        assert!(matches!(
+            zip.find(&["exe"]),
+            Err(ArchiveError::NoCandidate { .. })
+        ));
+
+        let path = format!("{}#Disc 3.cue", fixture("two-discs.zip").display());
+        assert!(matches!(
+            read_file(path, &[]),
+            Err(ArchiveError::NotFound { .. })
+        ));
+
+        let error = read_file(fixture("encrypted.zip"), &BIOS_EXTENSIONS).unwrap_err();
+        assert!(matches!(error, ArchiveError::Encrypted { .. }));
+        assert!(error.to_string().contains("password protected"));
+
+        // Not a zip at all
+        assert!(matches!(
+            Zip::open(fixture("bios.bin.gz")),
+            Err(ArchiveError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn crc32_matches_the_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+}
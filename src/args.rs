@@ -0,0 +1,252 @@
+use std::{env, str::FromStr};
+
+use psx_rust::{WatchdogConfig, DEFAULT_TURBO_RATE};
+
+const DEFAULT_BIOS_PATH: &str = "./static/bios/PSXBIOS.bin";
+
+pub const USAGE: &str =
+    "Usage: psx-rust [--bios <path>] [--exe <path>] [--psf <path>] [--wav <path>] [--raw <path>@<address>[:<entry>]]... [--disc <path>]... [--exp1-rom <path>] [--memcard <path>] [--fast-cd] [--fast-spu] [--analog] [--turbo-rate <frames>] [--link-listen <address>] [--link-connect <address>] [--max-cycles <n>] [--no-tty] [--trace-bios] [--trace <path>] [--permissive] [--headless] [--speed <multiplier>] [--fast-forward] [--skip-idle] [--testing] [--no-watchdog] [--watchdog-window <bytes>] [--watchdog-instructions <n>] [--watchdog-repeats <n>]
+       psx-rust trace dump <trace> [--disasm]
+       psx-rust trace compare <expected> <actual>
+       psx-rust memcard list|export|import|delete <card> [<save>] [<file.mcs>]";
+
+// A binary copied to RAM as it is, addresses are in hex
+pub struct RawLoad {
+    pub path: String,
+    pub address: u32,
+    pub entry: Option<u32>,
+}
+
+pub struct Args {
+    pub bios: String,
+    pub exe: Option<String>,
+    // PSF1 song or minipsf to play without video, the audio goes to the WAV file or, with -, to a
+    // pipe into a player
+    pub psf: Option<String>,
+    pub wav: Option<String>,
+    // Loaded in order once the BIOS reaches the shell, like --exe. Programs come with an entry
+    // point, their data without.
+    pub raws: Vec<RawLoad>,
+    // A .cue sheet or a single track .bin, also in a .zip (path.zip#name picks the file) or .gz.
+    // The first one is inserted, F2 changes to the next.
+    pub discs: Vec<String>,
+    // Short CDROM command and seek delays instead of the ones of the console
+    pub fast_cd: bool,
+    // Linear interpolation of the SPU voices instead of the gaussian filter of the console
+    pub fast_spu: bool,
+    pub expansion_rom: Option<String>,
+    // Image of the card in the first slot, created when missing
+    pub memory_card: Option<String>,
+    // Plug in a DualShock instead of the digital pad
+    pub analog: bool,
+    // Frames turbo buttons stay pressed and then released, Shift and a button key toggle turbo
+    pub turbo_rate: u32,
+    // Link cable to another instance, one side listens and the other connects
+    pub link_listen: Option<String>,
+    pub link_connect: Option<String>,
+    pub max_cycles: Option<u64>,
+    pub tty: bool,
+    pub trace_bios: bool,
+    // Binary trace of every instruction, see psx-rust trace dump
+    pub trace: Option<String>,
+    pub permissive: bool,
+    // Run without a window
+    pub headless: bool,
+    // Frame rate relative to the console
+    pub speed: f64,
+    // Start without the frame limiter, it can be toggled with Tab
+    pub fast_forward: bool,
+    // Skip the time spent in idle loops, see Emulator::set_idle_loop_skipping
+    pub skip_idle: bool,
+    // The emulator registers of test ROMs, they exit with the code the ROM wrote
+    pub testing: bool,
+    // Hang detection of headless runs
+    pub watchdog: bool,
+    pub watchdog_config: WatchdogConfig,
+}
+
+impl Args {
+    pub fn parse() -> Result<Self, String> {
+        Self::parse_from(env::args().skip(1))
+    }
+
+    pub fn parse_from(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Args {
+            bios: DEFAULT_BIOS_PATH.to_string(),
+            exe: None,
+            psf: None,
+            wav: None,
+            raws: Vec::new(),
+            discs: Vec::new(),
+            fast_cd: false,
+            fast_spu: false,
+            expansion_rom: None,
+            memory_card: None,
+            analog: false,
+            turbo_rate: DEFAULT_TURBO_RATE,
+            link_listen: None,
+            link_connect: None,
+            max_cycles: None,
+            tty: true,
+            trace_bios: false,
+            trace: None,
+            permissive: false,
+            headless: false,
+            speed: 1.0,
+            fast_forward: false,
+            skip_idle: false,
+            testing: false,
+            watchdog: true,
+            watchdog_config: WatchdogConfig::default(),
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--bios" => parsed.bios = value(&arg, args.next())?,
+                "--exe" => parsed.exe = Some(value(&arg, args.next())?),
+                "--psf" => parsed.psf = Some(value(&arg, args.next())?),
+                "--wav" => parsed.wav = Some(value(&arg, args.next())?),
+                "--raw" => parsed.raws.push(raw(&value(&arg, args.next())?)?),
+                "--disc" => parsed.discs.push(value(&arg, args.next())?),
+                "--fast-cd" => parsed.fast_cd = true,
+                "--fast-spu" => parsed.fast_spu = true,
+                "--exp1-rom" => parsed.expansion_rom = Some(value(&arg, args.next())?),
+                "--memcard" => parsed.memory_card = Some(value(&arg, args.next())?),
+                "--analog" => parsed.analog = true,
+                "--turbo-rate" => {
+                    parsed.turbo_rate = number(&arg, args.next())?;
+                    if parsed.turbo_rate == 0 {
+                        return Err("The turbo rate must be at least one frame".into());
+                    }
+                }
+                "--link-listen" => parsed.link_listen = Some(value(&arg, args.next())?),
+                "--link-connect" => parsed.link_connect = Some(value(&arg, args.next())?),
+                "--max-cycles" => parsed.max_cycles = Some(number(&arg, args.next())?),
+                "--no-tty" => parsed.tty = false,
+                "--trace-bios" => parsed.trace_bios = true,
+                "--trace" => parsed.trace = Some(value(&arg, args.next())?),
+                "--permissive" => parsed.permissive = true,
+                "--headless" => parsed.headless = true,
+                "--speed" => {
+                    let speed = value(&arg, args.next())?;
+                    parsed.speed = speed
+                        .parse()
+                        .ok()
+                        .filter(|speed: &f64| speed.is_finite() && *speed > 0.0)
+                        .ok_or_else(|| format!("Invalid speed '{}'", speed))?;
+                }
+                "--fast-forward" => parsed.fast_forward = true,
+                "--skip-idle" => parsed.skip_idle = true,
+                "--testing" => parsed.testing = true,
+                "--no-watchdog" => parsed.watchdog = false,
+                "--watchdog-window" => {
+                    parsed.watchdog_config.window = number(&arg, args.next())?;
+                }
+                "--watchdog-instructions" => {
+                    parsed.watchdog_config.instructions = number(&arg, args.next())?;
+                }
+                "--watchdog-repeats" => {
+                    parsed.watchdog_config.repeats = number(&arg, args.next())?;
+                }
+                _ => return Err(format!("Unknown argument '{}'", arg)),
+            }
+        }
+
+        if parsed.wav.is_some() && parsed.psf.is_none() {
+            return Err("--wav needs a --psf to play".to_string());
+        }
+
+        Ok(parsed)
+    }
+}
+
+fn value(flag: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| format!("Missing value for {}", flag))
+}
+
+// <path>@<address>[:<entry>], the path may have an @ of its own
+fn raw(value: &str) -> Result<RawLoad, String> {
+    let invalid = || {
+        format!(
+            "Invalid --raw '{}', expected <path>@<address>[:<entry>]",
+            value
+        )
+    };
+    let (path, location) = value.rsplit_once('@').ok_or_else(invalid)?;
+    let (address, entry) = match location.split_once(':') {
+        Some((address, entry)) => (address, Some(entry)),
+        None => (location, None),
+    };
+
+    let hex = |text: &str| {
+        let text = text.strip_prefix("0x").unwrap_or(text);
+        u32::from_str_radix(text, 16).map_err(|_| invalid())
+    };
+    if path.is_empty() {
+        return Err(invalid());
+    }
+
+    Ok(RawLoad {
+        path: path.to_string(),
+        address: hex(address)?,
+        entry: entry.map(hex).transpose()?,
+    })
+}
+
+fn number<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = self::value(flag, value)?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value '{}' for {}", value, flag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse_from(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn numbers_are_parsed_or_rejected() {
+        let args = parse(&["--max-cycles", "33868800", "--turbo-rate", "4"]).unwrap();
+        assert_eq!(args.max_cycles, Some(33868800));
+        assert_eq!(args.turbo_rate, 4);
+        assert_eq!(parse(&[]).unwrap().max_cycles, None);
+
+        assert_eq!(
+            parse(&["--max-cycles"]).err().unwrap(),
+            "Missing value for --max-cycles"
+        );
+        for cycles in ["lots", "-1", "1e9", ""] {
+            assert_eq!(
+                parse(&["--max-cycles", cycles]).err().unwrap(),
+                format!("Invalid value '{}' for --max-cycles", cycles)
+            );
+        }
+        assert!(parse(&["--turbo-rate", "0"]).is_err());
+        assert!(parse(&["--watchdog-window", "many"]).is_err());
+    }
+
+    #[test]
+    fn raw_loads_take_an_address_and_entry() {
+        let args = parse(&[
+            "--raw",
+            "a@b.bin@0x80010000:80010010",
+            "--raw",
+            "c.bin@1f000000",
+        ]);
+        let raws = args.unwrap().raws;
+        assert_eq!(raws[0].path, "a@b.bin");
+        assert_eq!(
+            (raws[0].address, raws[0].entry),
+            (0x80010000, Some(0x80010010))
+        );
+        assert_eq!((raws[1].address, raws[1].entry), (0x1F000000, None));
+
+        for raw in ["c.bin", "@80010000", "c.bin@zz", "c.bin@80010000:"] {
+            assert!(parse(&["--raw", raw]).is_err(), "{}", raw);
+        }
+    }
+}
@@ -0,0 +1,1093 @@
+use std::fmt;
+
+// The kernel exposes its functions through three jump tables at A0h, B0h and C0h, the function number is passed in r9
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum BiosTable {
+    A0,
+    B0,
+    C0,
+}
+
+pub struct BiosCall {
+    pub table: BiosTable,
+    pub function: u32,
+    pub arguments: [u32; 4], // r4..r7
+}
+
+impl BiosCall {
+    pub fn name(&self) -> Option<&'static str> {
+        let names = match self.table {
+            BiosTable::A0 => A0_FUNCTIONS,
+            BiosTable::B0 => B0_FUNCTIONS,
+            BiosTable::C0 => C0_FUNCTIONS,
+        };
+
+        names.get(self.function as usize).copied()
+    }
+}
+
+impl fmt::Display for BiosCall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let table = match self.table {
+            BiosTable::A0 => "A0",
+            BiosTable::B0 => "B0",
+            BiosTable::C0 => "C0",
+        };
+
+        write!(
+            f,
+            "{}h:{:02X}h {}(0x{:08x}, 0x{:08x}, 0x{:08x}, 0x{:08x})",
+            table,
+            self.function,
+            self.name().unwrap_or("unknown"),
+            self.arguments[0],
+            self.arguments[1],
+            self.arguments[2],
+            self.arguments[3]
+        )
+    }
+}
+
+// What the tracer reports to its sink
+pub enum TraceEntry<'a> {
+    Call(&'a BiosCall),
+    // The call got back to its return address, with v0
+    Return { call: &'a BiosCall, value: u32 },
+    Kernel(&'a KernelEvent),
+}
+
+impl fmt::Display for TraceEntry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceEntry::Call(call) => write!(f, "{}", call),
+            TraceEntry::Return { call, value } => write!(
+                f,
+                "{} returned 0x{:08x}",
+                call.name().unwrap_or("unknown"),
+                value
+            ),
+            TraceEntry::Kernel(event) => write!(f, "{}", event),
+        }
+    }
+}
+
+// The B0h dispatcher takes a few instructions to jump to the function
+const DISPATCH_STEPS: u32 = 16;
+// Calls that never return, like ReturnFromException, leave their entry behind until it is pushed out
+const MAX_PENDING_RETURNS: usize = 32;
+
+pub struct BiosCallTracer {
+    sink: Box<dyn FnMut(&TraceEntry)>,
+    // Return addresses of the calls in progress, innermost last
+    pending: Vec<(u32, BiosCall)>,
+    kernel: KernelTables,
+    // An event or thread function called through B0h, and the instructions left for the
+    // dispatcher to reach it
+    dispatched: Option<(u32, u32)>,
+}
+
+impl BiosCallTracer {
+    pub fn new(sink: Box<dyn FnMut(&TraceEntry)>) -> Self {
+        Self {
+            sink,
+            pending: Vec::new(),
+            kernel: KernelTables::default(),
+            dispatched: None,
+        }
+    }
+
+    // Logs every call to stderr
+    pub fn stderr() -> Self {
+        Self::new(Box::new(|entry| eprintln!("{}", entry)))
+    }
+
+    // The events and threads the guest opened so far
+    pub fn kernel(&self) -> &KernelTables {
+        &self.kernel
+    }
+
+    // Should be called before the instruction at pc executes
+    pub fn trace(&mut self, pc: u32, registers: &[u32; 32], ram: &[u8]) {
+        self.dispatched = self
+            .dispatched
+            .and_then(|(function, steps)| (steps > 0).then_some((function, steps - 1)));
+
+        if let Some(index) = self.pending.iter().rposition(|(address, _)| *address == pc) {
+            let (_, call) = self.pending.remove(index);
+            self.pending.truncate(index);
+
+            let value = registers[2];
+            (self.sink)(&TraceEntry::Return { call: &call, value });
+            if let Some(event) = self.kernel.returned(&call, value) {
+                (self.sink)(&TraceEntry::Kernel(&event));
+            }
+        }
+
+        let table = match pc & 0x1FFFFFFF {
+            0xA0 => Some(BiosTable::A0),
+            0xB0 => Some(BiosTable::B0),
+            0xC0 => Some(BiosTable::C0),
+            _ => None,
+        };
+        let arguments = [registers[4], registers[5], registers[6], registers[7]];
+        if let Some(table) = table {
+            let call = BiosCall {
+                table,
+                function: registers[9],
+                arguments,
+            };
+            (self.sink)(&TraceEntry::Call(&call));
+
+            if is_kernel_hook(&call) {
+                self.dispatched = Some((call.function, DISPATCH_STEPS));
+                self.kernel_call(call, registers[31]);
+            } else {
+                self.call(call, registers[31]);
+            }
+        } else if let Some(function) = kernel_hook(pc, ram) {
+            // The kernel also calls the event functions directly, without the B0h table
+            let dispatched = self.dispatched.take();
+            if dispatched.is_none_or(|(dispatched, _)| dispatched != function) {
+                let call = BiosCall {
+                    table: BiosTable::B0,
+                    function,
+                    arguments,
+                };
+                self.kernel_call(call, registers[31]);
+            }
+        }
+    }
+
+    fn kernel_call(&mut self, call: BiosCall, return_address: u32) {
+        if let Some(event) = self.kernel.called(&call, return_address) {
+            (self.sink)(&TraceEntry::Kernel(&event));
+        }
+        self.call(call, return_address);
+    }
+
+    fn call(&mut self, call: BiosCall, return_address: u32) {
+        if self.pending.len() == MAX_PENDING_RETURNS {
+            self.pending.remove(0);
+        }
+        self.pending.push((return_address, call));
+    }
+}
+
+// The B0h functions from DeliverEvent (07h) to ChangeThread (10h)
+const KERNEL_HOOKS: std::ops::RangeInclusive<u32> = 0x07..=0x10;
+// The kernel keeps the address of the B0h table here
+const B0_TABLE_POINTER: usize = 0x874;
+
+fn is_kernel_hook(call: &BiosCall) -> bool {
+    call.table == BiosTable::B0 && KERNEL_HOOKS.contains(&call.function)
+}
+
+// The event or thread function that starts at pc, by the B0h table in RAM. Only kernel addresses
+// are looked up, nothing matches before the kernel set up its tables.
+fn kernel_hook(pc: u32, ram: &[u8]) -> Option<u32> {
+    if pc & 0x1FFFFFFF >= 0x10000 || pc & 0x1FFFFFFF < 0x500 {
+        return None;
+    }
+
+    let word = |address: u32| {
+        let offset = (address & 0x1FFFFF) as usize;
+        ram.get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    };
+    let table = word(B0_TABLE_POINTER as u32)?;
+    KERNEL_HOOKS
+        .clone()
+        .find(|function| word(table.wrapping_add(function * 4)) == Some(pc))
+}
+
+// Libapi names of the event classes
+pub fn event_class_name(class: u32) -> Option<&'static str> {
+    let name = match class {
+        0xF0000001 => "HwVBLANK",
+        0xF0000002 => "HwGPU",
+        0xF0000003 => "HwCdRom",
+        0xF0000004 => "HwDMAC",
+        0xF0000005 => "HwRTC0",
+        0xF0000006 => "HwRTC1",
+        0xF0000007 => "HwRTC2",
+        0xF0000008 => "HwCNTL",
+        0xF0000009 => "HwSPU",
+        0xF000000A => "HwPIO",
+        0xF000000B => "HwSIO",
+        0xF0000010 => "HwCPU",
+        0xF0000011 => "HwCARD",
+        0xF0000012 => "HwCARD_0",
+        0xF0000013 => "HwCARD_1",
+        0xF2000000 => "RCntCNT0",
+        0xF2000001 => "RCntCNT1",
+        0xF2000002 => "RCntCNT2",
+        0xF2000003 => "RCntCNT3",
+        0xF4000001 => "SwCARD",
+        0xF4000002 => "SwMATH",
+        _ => return None,
+    };
+    Some(name)
+}
+
+const EVENT_SPECS: [(u32, &str); 12] = [
+    (0x0001, "EvSpTIMOUT"),
+    (0x0002, "EvSpINT"),
+    (0x0004, "EvSpIOE"),
+    (0x0008, "EvSpCLOSE"),
+    (0x0010, "EvSpACK"),
+    (0x0020, "EvSpCOMP"),
+    (0x0040, "EvSpDR"),
+    (0x0080, "EvSpDE"),
+    (0x0100, "EvSpTC"),
+    (0x2000, "EvSpNEW"),
+    (0x4000, "EvSpUNKNOWN"),
+    (0x8000, "EvSpERROR"),
+];
+
+struct Class(u32);
+
+impl fmt::Display for Class {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match event_class_name(self.0) {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "0x{:08x}", self.0),
+        }
+    }
+}
+
+// The spec flags by name, the unknown bits in hex
+struct Spec(u32);
+
+impl fmt::Display for Spec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names: Vec<String> = EVENT_SPECS
+            .iter()
+            .filter(|(bit, _)| self.0 & bit != 0)
+            .map(|(_, name)| name.to_string())
+            .collect();
+        let known = EVENT_SPECS.iter().fold(0, |bits, (bit, _)| bits | bit);
+        if self.0 & !known != 0 || self.0 == 0 {
+            names.push(format!("0x{:04x}", self.0 & !known));
+        }
+        write!(f, "{}", names.join("|"))
+    }
+}
+
+// Events run their callback when delivered in interrupt mode, otherwise they become ready until
+// TestEvent or WaitEvent sees them
+const EVENT_MODE_INTERRUPT: u32 = 0x1000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventEntry {
+    pub handle: u32,
+    pub class: u32,
+    pub spec: u32,
+    pub mode: u32,
+    pub callback: u32,
+    pub enabled: bool,
+    pub ready: bool,
+    pub deliveries: u32,
+    pub tests: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ThreadEntry {
+    pub handle: u32,
+    pub pc: u32,
+    pub sp: u32,
+    pub gp: u32,
+}
+
+// Event and thread calls worth a line of their own, TestEvent polls are only counted
+#[derive(Clone, Debug, PartialEq)]
+pub enum KernelEvent {
+    EventOpened(EventEntry),
+    EventClosed {
+        handle: u32,
+    },
+    EventEnabled {
+        handle: u32,
+        enabled: bool,
+    },
+    // Handles of the enabled events of the class and spec
+    Delivered {
+        class: u32,
+        spec: u32,
+        caller: u32,
+        receivers: Vec<u32>,
+    },
+    ThreadOpened(ThreadEntry),
+    ThreadClosed {
+        handle: u32,
+    },
+    ThreadChanged {
+        from: Option<u32>,
+        to: u32,
+    },
+}
+
+impl fmt::Display for KernelEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KernelEvent::EventOpened(event) => write!(
+                f,
+                "Opened event 0x{:08x}: {} {} mode 0x{:04x} callback 0x{:08x}",
+                event.handle,
+                Class(event.class),
+                Spec(event.spec),
+                event.mode,
+                event.callback
+            ),
+            KernelEvent::EventClosed { handle } => write!(f, "Closed event 0x{:08x}", handle),
+            KernelEvent::EventEnabled { handle, enabled } => write!(
+                f,
+                "{} event 0x{:08x}",
+                if *enabled { "Enabled" } else { "Disabled" },
+                handle
+            ),
+            KernelEvent::Delivered {
+                class,
+                spec,
+                caller,
+                receivers,
+            } => {
+                write!(
+                    f,
+                    "Delivered {} {} from 0x{:08x} to ",
+                    Class(*class),
+                    Spec(*spec),
+                    caller
+                )?;
+                if receivers.is_empty() {
+                    write!(f, "no enabled event")
+                } else {
+                    let handles: Vec<String> = receivers
+                        .iter()
+                        .map(|handle| format!("0x{:08x}", handle))
+                        .collect();
+                    write!(f, "{}", handles.join(", "))
+                }
+            }
+            KernelEvent::ThreadOpened(thread) => write!(
+                f,
+                "Opened thread 0x{:08x}: pc 0x{:08x} sp 0x{:08x} gp 0x{:08x}",
+                thread.handle, thread.pc, thread.sp, thread.gp
+            ),
+            KernelEvent::ThreadClosed { handle } => write!(f, "Closed thread 0x{:08x}", handle),
+            KernelEvent::ThreadChanged { from, to } => match from {
+                Some(from) => write!(f, "Changed from thread 0x{:08x} to 0x{:08x}", from, to),
+                None => write!(f, "Changed to thread 0x{:08x}", to),
+            },
+        }
+    }
+}
+
+// Shadow copies of the kernel event and thread control blocks, built from the calls
+#[derive(Default)]
+pub struct KernelTables {
+    pub events: Vec<EventEntry>,
+    pub threads: Vec<ThreadEntry>,
+    // None until the first ChangeThread, the kernel starts on its first thread
+    pub current_thread: Option<u32>,
+}
+
+impl KernelTables {
+    fn event(&mut self, handle: u32) -> Option<&mut EventEntry> {
+        self.events.iter_mut().find(|event| event.handle == handle)
+    }
+
+    fn called(&mut self, call: &BiosCall, caller: u32) -> Option<KernelEvent> {
+        let [first, second, ..] = call.arguments;
+        match call.function {
+            // DeliverEvent(class, spec)
+            0x07 => {
+                let mut receivers = Vec::new();
+                for event in &mut self.events {
+                    if event.class == first && event.spec == second && event.enabled {
+                        event.deliveries += 1;
+                        event.ready |= event.mode & EVENT_MODE_INTERRUPT == 0;
+                        receivers.push(event.handle);
+                    }
+                }
+                Some(KernelEvent::Delivered {
+                    class: first,
+                    spec: second,
+                    caller,
+                    receivers,
+                })
+            }
+            // CloseEvent(handle)
+            0x09 => {
+                self.events.retain(|event| event.handle != first);
+                Some(KernelEvent::EventClosed { handle: first })
+            }
+            // EnableEvent(handle) and DisableEvent(handle)
+            0x0C | 0x0D => {
+                let enabled = call.function == 0x0C;
+                self.event(first)?.enabled = enabled;
+                Some(KernelEvent::EventEnabled {
+                    handle: first,
+                    enabled,
+                })
+            }
+            // CloseThread(handle)
+            0x0F => {
+                self.threads.retain(|thread| thread.handle != first);
+                Some(KernelEvent::ThreadClosed { handle: first })
+            }
+            // ChangeThread(handle)
+            0x10 => {
+                let from = self.current_thread.replace(first);
+                Some(KernelEvent::ThreadChanged { from, to: first })
+            }
+            _ => None,
+        }
+    }
+
+    // Opening returns the handle, testing whether the event was ready
+    fn returned(&mut self, call: &BiosCall, value: u32) -> Option<KernelEvent> {
+        if !is_kernel_hook(call) {
+            return None;
+        }
+
+        let [first, second, third, fourth] = call.arguments;
+        match call.function {
+            // OpenEvent(class, spec, mode, callback)
+            0x08 if value != 0xFFFFFFFF => {
+                let event = EventEntry {
+                    handle: value,
+                    class: first,
+                    spec: second,
+                    mode: third,
+                    callback: fourth,
+                    enabled: false,
+                    ready: false,
+                    deliveries: 0,
+                    tests: 0,
+                };
+                self.events.retain(|event| event.handle != value);
+                self.events.push(event.clone());
+                Some(KernelEvent::EventOpened(event))
+            }
+            // WaitEvent(handle) and TestEvent(handle)
+            0x0A | 0x0B => {
+                let event = self.event(first)?;
+                event.tests += 1;
+                if value == 1 {
+                    event.ready = false;
+                }
+                None
+            }
+            // OpenThread(pc, sp, gp)
+            0x0E if value != 0xFFFFFFFF => {
+                let thread = ThreadEntry {
+                    handle: value,
+                    pc: first,
+                    sp: second,
+                    gp: third,
+                };
+                self.threads.retain(|thread| thread.handle != value);
+                self.threads.push(thread.clone());
+                Some(KernelEvent::ThreadOpened(thread))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for KernelTables {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Events:")?;
+        for event in &self.events {
+            writeln!(
+                f,
+                "  0x{:08x} {:<8} {:<18} mode 0x{:04x} callback 0x{:08x} {}{} delivered {} tested {}",
+                event.handle,
+                Class(event.class).to_string(),
+                Spec(event.spec).to_string(),
+                event.mode,
+                event.callback,
+                if event.enabled { "enabled" } else { "disabled" },
+                if event.ready { " ready" } else { "" },
+                event.deliveries,
+                event.tests
+            )?;
+        }
+
+        writeln!(f, "Threads:")?;
+        for thread in &self.threads {
+            writeln!(
+                f,
+                "  0x{:08x} pc 0x{:08x} sp 0x{:08x} gp 0x{:08x}{}",
+                thread.handle,
+                thread.pc,
+                thread.sp,
+                thread.gp,
+                if self.current_thread == Some(thread.handle) {
+                    " current"
+                } else {
+                    ""
+                }
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+const A0_FUNCTIONS: &[&str] = &[
+    "FileOpen",                       // 00h
+    "FileSeek",                       // 01h
+    "FileRead",                       // 02h
+    "FileWrite",                      // 03h
+    "FileClose",                      // 04h
+    "FileIoctl",                      // 05h
+    "exit",                           // 06h
+    "FileGetDeviceFlag",              // 07h
+    "FileGetc",                       // 08h
+    "FilePutc",                       // 09h
+    "todigit",                        // 0Ah
+    "atof",                           // 0Bh
+    "strtoul",                        // 0Ch
+    "strtol",                         // 0Dh
+    "abs",                            // 0Eh
+    "labs",                           // 0Fh
+    "atoi",                           // 10h
+    "atol",                           // 11h
+    "atob",                           // 12h
+    "SaveState",                      // 13h
+    "RestoreState",                   // 14h
+    "strcat",                         // 15h
+    "strncat",                        // 16h
+    "strcmp",                         // 17h
+    "strncmp",                        // 18h
+    "strcpy",                         // 19h
+    "strncpy",                        // 1Ah
+    "strlen",                         // 1Bh
+    "index",                          // 1Ch
+    "rindex",                         // 1Dh
+    "strchr",                         // 1Eh
+    "strrchr",                        // 1Fh
+    "strpbrk",                        // 20h
+    "strspn",                         // 21h
+    "strcspn",                        // 22h
+    "strtok",                         // 23h
+    "strstr",                         // 24h
+    "toupper",                        // 25h
+    "tolower",                        // 26h
+    "bcopy",                          // 27h
+    "bzero",                          // 28h
+    "bcmp",                           // 29h
+    "memcpy",                         // 2Ah
+    "memset",                         // 2Bh
+    "memmove",                        // 2Ch
+    "memcmp",                         // 2Dh
+    "memchr",                         // 2Eh
+    "rand",                           // 2Fh
+    "srand",                          // 30h
+    "qsort",                          // 31h
+    "strtod",                         // 32h
+    "malloc",                         // 33h
+    "free",                           // 34h
+    "lsearch",                        // 35h
+    "bsearch",                        // 36h
+    "calloc",                         // 37h
+    "realloc",                        // 38h
+    "InitHeap",                       // 39h
+    "SystemErrorExit",                // 3Ah
+    "std_in_getchar",                 // 3Bh
+    "std_out_putchar",                // 3Ch
+    "std_in_gets",                    // 3Dh
+    "std_out_puts",                   // 3Eh
+    "printf",                         // 3Fh
+    "SystemErrorUnresolvedException", // 40h
+    "LoadExeHeader",                  // 41h
+    "LoadExeFile",                    // 42h
+    "DoExecute",                      // 43h
+    "FlushCache",                     // 44h
+    "init_a0_b0_c0_vectors",          // 45h
+    "GPU_dw",                         // 46h
+    "gpu_send_dma",                   // 47h
+    "SendGP1Command",                 // 48h
+    "GPU_cw",                         // 49h
+    "GPU_cwp",                        // 4Ah
+    "send_gpu_linked_list",           // 4Bh
+    "gpu_abort_dma",                  // 4Ch
+    "GetGPUStatus",                   // 4Dh
+    "gpu_sync",                       // 4Eh
+    "SystemError",                    // 4Fh
+    "SystemError",                    // 50h
+    "LoadAndExecute",                 // 51h
+    "GetSysSp",                       // 52h
+    "SystemError",                    // 53h
+    "CdInit",                         // 54h
+    "_bu_init",                       // 55h
+    "CdRemove",                       // 56h
+    "return_0",                       // 57h
+    "return_0",                       // 58h
+    "return_0",                       // 59h
+    "return_0",                       // 5Ah
+    "dev_tty_init",                   // 5Bh
+    "dev_tty_open",                   // 5Ch
+    "dev_tty_in_out",                 // 5Dh
+    "dev_tty_ioctl",                  // 5Eh
+    "dev_cd_open",                    // 5Fh
+    "dev_cd_read",                    // 60h
+    "dev_cd_close",                   // 61h
+    "dev_cd_firstfile",               // 62h
+    "dev_cd_nextfile",                // 63h
+    "dev_cd_chdir",                   // 64h
+    "dev_card_open",                  // 65h
+    "dev_card_read",                  // 66h
+    "dev_card_write",                 // 67h
+    "dev_card_close",                 // 68h
+    "dev_card_firstfile",             // 69h
+    "dev_card_nextfile",              // 6Ah
+    "dev_card_erase",                 // 6Bh
+    "dev_card_undelete",              // 6Ch
+    "dev_card_format",                // 6Dh
+    "dev_card_rename",                // 6Eh
+    "dev_card_clear_error",           // 6Fh
+    "_bu_init",                       // 70h
+    "CdInit",                         // 71h
+    "CdRemove",                       // 72h
+    "return_0",                       // 73h
+    "return_0",                       // 74h
+    "return_0",                       // 75h
+    "return_0",                       // 76h
+    "return_0",                       // 77h
+    "CdAsyncSeekL",                   // 78h
+    "return_0",                       // 79h
+    "return_0",                       // 7Ah
+    "return_0",                       // 7Bh
+    "CdAsyncGetStatus",               // 7Ch
+    "return_0",                       // 7Dh
+    "CdAsyncReadSector",              // 7Eh
+    "return_0",                       // 7Fh
+    "return_0",                       // 80h
+    "CdAsyncSetMode",                 // 81h
+    "return_0",                       // 82h
+    "return_0",                       // 83h
+    "return_0",                       // 84h
+    "return_0",                       // 85h
+    "return_0",                       // 86h
+    "return_0",                       // 87h
+    "return_0",                       // 88h
+    "return_0",                       // 89h
+    "return_0",                       // 8Ah
+    "return_0",                       // 8Bh
+    "return_0",                       // 8Ch
+    "return_0",                       // 8Dh
+    "return_0",                       // 8Eh
+    "return_0",                       // 8Fh
+    "CdromIoIrqFunc1",                // 90h
+    "CdromDmaIrqFunc1",               // 91h
+    "CdromIoIrqFunc2",                // 92h
+    "CdromDmaIrqFunc2",               // 93h
+    "CdromGetInt5errCode",            // 94h
+    "CdInitSubFunc",                  // 95h
+    "AddCDROMDevice",                 // 96h
+    "AddMemCardDevice",               // 97h
+    "AddDuartTtyDevice",              // 98h
+    "AddDummyTtyDevice",              // 99h
+    "SystemError",                    // 9Ah
+    "SystemError",                    // 9Bh
+    "SetConf",                        // 9Ch
+    "GetConf",                        // 9Dh
+    "SetCdromIrqAutoAbort",           // 9Eh
+    "SetMemSize",                     // 9Fh
+    "WarmBoot",                       // A0h
+    "SystemErrorBootOrDiskFailure",   // A1h
+    "EnqueueCdIntr",                  // A2h
+    "DequeueCdIntr",                  // A3h
+    "CdGetLbn",                       // A4h
+    "CdReadSector",                   // A5h
+    "CdGetStatus",                    // A6h
+    "bu_callback_okay",               // A7h
+    "bu_callback_err_write",          // A8h
+    "bu_callback_err_busy",           // A9h
+    "bu_callback_err_eject",          // AAh
+    "_card_info",                     // ABh
+    "_card_async_load_directory",     // ACh
+    "set_card_auto_format",           // ADh
+    "bu_callback_err_prev_write",     // AEh
+    "card_write_test",                // AFh
+    "return_0",                       // B0h
+    "return_0",                       // B1h
+    "ioabort_raw",                    // B2h
+    "return_0",                       // B3h
+    "GetSystemInfo",                  // B4h
+];
+
+const B0_FUNCTIONS: &[&str] = &[
+    "alloc_kernel_memory",         // 00h
+    "free_kernel_memory",          // 01h
+    "init_timer",                  // 02h
+    "get_timer",                   // 03h
+    "enable_timer_irq",            // 04h
+    "disable_timer_irq",           // 05h
+    "restart_timer",               // 06h
+    "DeliverEvent",                // 07h
+    "OpenEvent",                   // 08h
+    "CloseEvent",                  // 09h
+    "WaitEvent",                   // 0Ah
+    "TestEvent",                   // 0Bh
+    "EnableEvent",                 // 0Ch
+    "DisableEvent",                // 0Dh
+    "OpenThread",                  // 0Eh
+    "CloseThread",                 // 0Fh
+    "ChangeThread",                // 10h
+    "jump_to_00000000h",           // 11h
+    "InitPad",                     // 12h
+    "StartPad",                    // 13h
+    "StopPad",                     // 14h
+    "OutdatedPadInitAndStart",     // 15h
+    "OutdatedPadGetButtons",       // 16h
+    "ReturnFromException",         // 17h
+    "SetDefaultExitFromException", // 18h
+    "SetCustomExitFromException",  // 19h
+    "SystemError",                 // 1Ah
+    "SystemError",                 // 1Bh
+    "SystemError",                 // 1Ch
+    "SystemError",                 // 1Dh
+    "SystemError",                 // 1Eh
+    "SystemError",                 // 1Fh
+    "UnDeliverEvent",              // 20h
+    "SystemError",                 // 21h
+    "SystemError",                 // 22h
+    "SystemError",                 // 23h
+    "jump_to_00000000h",           // 24h
+    "jump_to_00000000h",           // 25h
+    "jump_to_00000000h",           // 26h
+    "jump_to_00000000h",           // 27h
+    "jump_to_00000000h",           // 28h
+    "jump_to_00000000h",           // 29h
+    "SystemError",                 // 2Ah
+    "SystemError",                 // 2Bh
+    "jump_to_00000000h",           // 2Ch
+    "jump_to_00000000h",           // 2Dh
+    "jump_to_00000000h",           // 2Eh
+    "jump_to_00000000h",           // 2Fh
+    "jump_to_00000000h",           // 30h
+    "jump_to_00000000h",           // 31h
+    "FileOpen",                    // 32h
+    "FileSeek",                    // 33h
+    "FileRead",                    // 34h
+    "FileWrite",                   // 35h
+    "FileClose",                   // 36h
+    "FileIoctl",                   // 37h
+    "exit",                        // 38h
+    "FileGetDeviceFlag",           // 39h
+    "FileGetc",                    // 3Ah
+    "FilePutc",                    // 3Bh
+    "std_in_getchar",              // 3Ch
+    "std_out_putchar",             // 3Dh
+    "std_in_gets",                 // 3Eh
+    "std_out_puts",                // 3Fh
+    "chdir",                       // 40h
+    "FormatDevice",                // 41h
+    "firstfile",                   // 42h
+    "nextfile",                    // 43h
+    "FileRename",                  // 44h
+    "FileDelete",                  // 45h
+    "FileUndelete",                // 46h
+    "AddDevice",                   // 47h
+    "RemoveDevice",                // 48h
+    "PrintInstalledDevices",       // 49h
+    "InitCard",                    // 4Ah
+    "StartCard",                   // 4Bh
+    "StopCard",                    // 4Ch
+    "_card_info_subfunc",          // 4Dh
+    "write_card_sector",           // 4Eh
+    "read_card_sector",            // 4Fh
+    "allow_new_card",              // 50h
+    "Krom2RawAdd",                 // 51h
+    "SystemError",                 // 52h
+    "Krom2Offset",                 // 53h
+    "GetLastError",                // 54h
+    "GetLastFileError",            // 55h
+    "GetC0Table",                  // 56h
+    "GetB0Table",                  // 57h
+    "get_bu_callback_port",        // 58h
+    "testdevice",                  // 59h
+    "SystemError",                 // 5Ah
+    "ChangeClearPad",              // 5Bh
+    "get_card_status",             // 5Ch
+    "wait_card_status",            // 5Dh
+];
+
+const C0_FUNCTIONS: &[&str] = &[
+    "EnqueueTimerAndVblankIrqs", // 00h
+    "EnqueueSyscallHandler",     // 01h
+    "SysEnqIntRP",               // 02h
+    "SysDeqIntRP",               // 03h
+    "get_free_EvCB_slot",        // 04h
+    "get_free_TCB_slot",         // 05h
+    "ExceptionHandler",          // 06h
+    "InstallExceptionHandlers",  // 07h
+    "SysInitMemory",             // 08h
+    "SysInitKernelVariables",    // 09h
+    "ChangeClearRCnt",           // 0Ah
+    "SystemError",               // 0Bh
+    "InitDefInt",                // 0Ch
+    "SetIrqAutoAck",             // 0Dh
+    "return_0",                  // 0Eh
+    "return_0",                  // 0Fh
+    "return_0",                  // 10h
+    "return_0",                  // 11h
+    "InstallDevices",            // 12h
+    "FlushStdInOutPut",          // 13h
+    "return_0",                  // 14h
+    "tty_cdevinput",             // 15h
+    "tty_cdevscan",              // 16h
+    "tty_circgetc",              // 17h
+    "tty_circputc",              // 18h
+    "ioabort",                   // 19h
+    "set_card_find_mode",        // 1Ah
+    "KernelRedirect",            // 1Bh
+    "AdjustA0Table",             // 1Ch
+    "get_card_find_mode",        // 1Dh
+];
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+
+    const CALLER: u32 = 0x80010000;
+
+    // A tracer that keeps the lines it logged
+    fn tracer() -> (BiosCallTracer, Rc<RefCell<Vec<String>>>) {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let sink = log.clone();
+        let tracer = BiosCallTracer::new(Box::new(move |entry| {
+            if let TraceEntry::Kernel(event) = entry {
+                sink.borrow_mut().push(event.to_string());
+            }
+        }));
+        (tracer, log)
+    }
+
+    // Calls B0h function through the table from CALLER and returns with value
+    fn call(
+        tracer: &mut BiosCallTracer,
+        ram: &[u8],
+        function: u32,
+        arguments: [u32; 4],
+        value: u32,
+    ) {
+        let mut registers = [0; 32];
+        registers[4..8].copy_from_slice(&arguments);
+        registers[9] = function;
+        registers[31] = CALLER + 8;
+        tracer.trace(0xB0, &registers, ram);
+
+        registers[2] = value;
+        tracer.trace(CALLER + 8, &registers, ram);
+    }
+
+    #[test]
+    fn events_are_followed_from_open_to_delivery() {
+        let (mut tracer, log) = tracer();
+        let ram = vec![0; 0x200000];
+
+        call(
+            &mut tracer,
+            &ram,
+            0x08,
+            [0xF2000003, 0x0002, 0x2000, 0],
+            0xF1000000,
+        );
+        call(
+            &mut tracer,
+            &ram,
+            0x08,
+            [0xF0000003, 0x0020, 0x1000, 0x80020000],
+            0xF1000001,
+        );
+        call(&mut tracer, &ram, 0x0C, [0xF1000000, 0, 0, 0], 1);
+        call(&mut tracer, &ram, 0x07, [0xF2000003, 0x0002, 0, 0], 0);
+        call(&mut tracer, &ram, 0x07, [0xF0000003, 0x0020, 0, 0], 0);
+
+        assert_eq!(
+            *log.borrow(),
+            [
+                "Opened event 0xf1000000: RCntCNT3 EvSpINT mode 0x2000 callback 0x00000000",
+                "Opened event 0xf1000001: HwCdRom EvSpCOMP mode 0x1000 callback 0x80020000",
+                "Enabled event 0xf1000000",
+                "Delivered RCntCNT3 EvSpINT from 0x80010008 to 0xf1000000",
+                "Delivered HwCdRom EvSpCOMP from 0x80010008 to no enabled event",
+            ]
+        );
+
+        let events = &tracer.kernel().events;
+        assert!(events[0].ready);
+        assert_eq!(events[0].deliveries, 1);
+        assert_eq!(events[1].deliveries, 0);
+
+        // TestEvent takes the delivery
+        call(&mut tracer, &ram, 0x0B, [0xF1000000, 0, 0, 0], 1);
+        call(&mut tracer, &ram, 0x0B, [0xF1000000, 0, 0, 0], 0);
+        let event = &tracer.kernel().events[0];
+        assert!(!event.ready);
+        assert_eq!(event.tests, 2);
+    }
+
+    #[test]
+    fn threads_are_followed() {
+        let (mut tracer, log) = tracer();
+        let ram = vec![0; 0x200000];
+
+        call(
+            &mut tracer,
+            &ram,
+            0x0E,
+            [0x80030000, 0x801FF000, 0x80040000, 0],
+            0xFF000001,
+        );
+        call(&mut tracer, &ram, 0x10, [0xFF000001, 0, 0, 0], 1);
+        call(&mut tracer, &ram, 0x10, [0xFF000000, 0, 0, 0], 1);
+
+        assert_eq!(
+            *log.borrow(),
+            [
+                "Opened thread 0xff000001: pc 0x80030000 sp 0x801ff000 gp 0x80040000",
+                "Changed to thread 0xff000001",
+                "Changed from thread 0xff000001 to 0xff000000",
+            ]
+        );
+        assert_eq!(tracer.kernel().current_thread, Some(0xFF000000));
+        assert!(tracer
+            .kernel()
+            .to_string()
+            .contains("0xff000001 pc 0x80030000"));
+    }
+
+    #[test]
+    fn direct_kernel_calls_are_caught_once() {
+        let (mut tracer, log) = tracer();
+
+        // DeliverEvent at 0x1000 in the B0h table at 0x900
+        let mut ram = vec![0; 0x200000];
+        ram[B0_TABLE_POINTER..B0_TABLE_POINTER + 4].copy_from_slice(&0x900u32.to_le_bytes());
+        ram[0x900 + 7 * 4..0x900 + 8 * 4].copy_from_slice(&0x1000u32.to_le_bytes());
+
+        let mut registers = [0; 32];
+        registers[4] = 0xF0000001;
+        registers[5] = 0x0002;
+        registers[31] = 0x2000;
+        tracer.trace(0x1000, &registers, &ram);
+
+        // Through the table the dispatcher reaches the same function
+        registers[9] = 0x07;
+        registers[31] = CALLER + 8;
+        tracer.trace(0xB0, &registers, &ram);
+        tracer.trace(0xB4, &registers, &ram);
+        tracer.trace(0x1000, &registers, &ram);
+
+        assert_eq!(
+            *log.borrow(),
+            [
+                "Delivered HwVBLANK EvSpINT from 0x00002000 to no enabled event",
+                "Delivered HwVBLANK EvSpINT from 0x80010008 to no enabled event",
+            ]
+        );
+    }
+
+    // A tracer that keeps every entry it logged
+    fn full_tracer() -> (BiosCallTracer, Rc<RefCell<Vec<String>>>) {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let sink = log.clone();
+        let tracer = BiosCallTracer::new(Box::new(move |entry| {
+            sink.borrow_mut().push(entry.to_string());
+        }));
+        (tracer, log)
+    }
+
+    #[test]
+    fn calls_are_named_by_table_and_function() {
+        let (mut tracer, log) = full_tracer();
+        let ram = vec![0; 0x200000];
+
+        // Any of the three views of the table addresses, arguments in a0 to a3
+        let mut registers = [0; 32];
+        registers[4..8].copy_from_slice(&[0x80020000, 1, 2, 3]);
+        registers[9] = 0x3F;
+        tracer.trace(0xA0, &registers, &ram);
+        registers[9] = 0x3D;
+        tracer.trace(0x800000B0, &registers, &ram);
+        registers[9] = 0x1B;
+        tracer.trace(0xA00000C0, &registers, &ram);
+        registers[9] = 0xFF;
+        tracer.trace(0xA0, &registers, &ram);
+        // Not a table
+        tracer.trace(0xBFC000A0, &registers, &ram);
+
+        assert_eq!(
+            *log.borrow(),
+            [
+                "A0h:3Fh printf(0x80020000, 0x00000001, 0x00000002, 0x00000003)",
+                "B0h:3Dh std_out_putchar(0x80020000, 0x00000001, 0x00000002, 0x00000003)",
+                "C0h:1Bh KernelRedirect(0x80020000, 0x00000001, 0x00000002, 0x00000003)",
+                "A0h:FFh unknown(0x80020000, 0x00000001, 0x00000002, 0x00000003)",
+            ]
+        );
+
+        let call = |table, function| BiosCall {
+            table,
+            function,
+            arguments: [0; 4],
+        };
+        assert_eq!(call(BiosTable::A0, 0x00).name(), Some("FileOpen"));
+        assert_eq!(call(BiosTable::A0, 0xB4).name(), Some("GetSystemInfo"));
+        assert_eq!(call(BiosTable::B0, 0x5D).name(), Some("wait_card_status"));
+        assert_eq!(call(BiosTable::C0, 0x1D).name(), Some("get_card_find_mode"));
+        assert_eq!(call(BiosTable::C0, 0x1E).name(), None);
+    }
+
+    #[test]
+    fn returns_report_v0_of_the_innermost_call() {
+        let (mut tracer, log) = full_tracer();
+        let ram = vec![0; 0x200000];
+
+        // malloc calls strlen, which returns first
+        let mut registers = [0; 32];
+        registers[9] = 0x33;
+        registers[31] = CALLER + 8;
+        tracer.trace(0xA0, &registers, &ram);
+        registers[9] = 0x1B;
+        registers[31] = 0xBFC01000;
+        tracer.trace(0xA0, &registers, &ram);
+
+        registers[2] = 5;
+        tracer.trace(0xBFC01000, &registers, &ram);
+        registers[2] = 0x80100000;
+        tracer.trace(CALLER + 8, &registers, &ram);
+        // Nothing is pending any more
+        tracer.trace(CALLER + 8, &registers, &ram);
+
+        // A call that never got back, like ReturnFromException, is dropped with its caller
+        registers[9] = 0x17;
+        registers[31] = 0x80001000;
+        tracer.trace(0xB0, &registers, &ram);
+        registers[9] = 0x18;
+        registers[31] = 0x80002000;
+        tracer.trace(0xB0, &registers, &ram);
+        registers[2] = 0;
+        tracer.trace(0x80001000, &registers, &ram);
+        tracer.trace(0x80002000, &registers, &ram);
+
+        assert_eq!(
+            log.borrow()[2..],
+            [
+                "strlen returned 0x00000005",
+                "malloc returned 0x80100000",
+                "B0h:17h ReturnFromException(0x00000000, 0x00000000, 0x00000000, 0x00000000)",
+                "B0h:18h SetDefaultExitFromException(0x00000000, 0x00000000, 0x00000000, 0x00000000)",
+                "ReturnFromException returned 0x00000000",
+            ]
+        );
+    }
+}
@@ -0,0 +1,1490 @@
+use std::collections::VecDeque;
+
+use crate::{
+    disc::{from_bcd, is_subchannel_q_valid, to_bcd, Disc, Msf, TrackKind, SECTOR_SIZE},
+    dma::DmaDevice,
+    resampler::Resampler,
+    xa::XaDecoder,
+};
+
+const PARAMETER_FIFO_SIZE: usize = 16;
+const RESPONSE_FIFO_SIZE: usize = 16;
+
+// Delays in CPU cycles, a sector takes 1/75s at single speed. The command and second response
+// delays are the averages measured on the console.
+const FIRST_RESPONSE_DELAY: u32 = 0xC4E1;
+const SECTOR_DELAY: u32 = 33_868_800 / 75;
+const INIT_DELAY: u32 = 0x1_3CCE;
+const GET_ID_DELAY: u32 = 0x4A00;
+const PAUSE_IDLE_DELAY: u32 = 7_000;
+const STOP_DELAY: u32 = 0xD3_8ACA;
+const READ_TOC_DELAY: u32 = 1_000_000;
+// Seeks take 20ms plus the time to move the head, about a second from one end of the disc to the
+// other
+const SEEK_DELAY: u32 = 33_868_800 / 50;
+const SEEK_DELAY_PER_SECTOR: u32 = 100;
+// With CdTiming::Fast nothing but the sectors takes longer than this
+const FAST_DELAY: u32 = 5_000;
+
+// Status byte bits
+const STAT_ERROR: u8 = 1 << 0;
+const STAT_MOTOR_ON: u8 = 1 << 1;
+const STAT_ID_ERROR: u8 = 1 << 3;
+const STAT_SHELL_OPEN: u8 = 1 << 4;
+const STAT_READ: u8 = 1 << 5;
+const STAT_SEEK: u8 = 1 << 6;
+const STAT_PLAY: u8 = 1 << 7;
+
+// Samples are sent to the SPU at 44100 Hz
+const AUDIO_RATE: u32 = 44100;
+// Half a second of audio, what is queued beyond that is dropped
+const AUDIO_QUEUE_LIMIT: usize = AUDIO_RATE as usize / 2;
+
+// Setmode bits
+const MODE_AUTO_PAUSE: u8 = 1 << 1;
+const MODE_REPORT: u8 = 1 << 2;
+const MODE_XA_FILTER: u8 = 1 << 3;
+const MODE_SECTOR_SIZE: u8 = 1 << 5;
+const MODE_XA_ADPCM: u8 = 1 << 6;
+const MODE_DOUBLE_SPEED: u8 = 1 << 7;
+
+// Error codes, the second byte of an INT5 response
+const ERROR_SEEK_FAILED: u8 = 0x04;
+// The lid was opened during a read or play
+const ERROR_SHELL_OPENED: u8 = 0x08;
+const ERROR_INVALID_SUBFUNCTION: u8 = 0x10;
+const ERROR_WRONG_PARAMETERS: u8 = 0x20;
+const ERROR_INVALID_COMMAND: u8 = 0x40;
+// No disc, the lid is open or nothing was read yet
+const ERROR_NOT_READY: u8 = 0x80;
+
+// Subheader submode bits
+const SUBMODE_AUDIO: u8 = 1 << 2;
+const SUBMODE_REAL_TIME: u8 = 1 << 6;
+
+// Interrupt types in the low bits of the interrupt flag register
+const INT1: u8 = 1;
+const INT2: u8 = 2;
+const INT3: u8 = 3;
+const INT4: u8 = 4;
+const INT5: u8 = 5;
+
+// How long commands and seeks take
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CdTiming {
+    // Like the console, some games rely on loading not being instant
+    Accurate,
+    // Short fixed delays for faster loading, sectors still arrive at the speed of the disc
+    Fast,
+}
+
+// A response waiting to be delivered, the next one is only delivered once the host acknowledged the
+// interrupt of the previous one
+struct Response {
+    interrupt: u8,
+    bytes: Vec<u8>,
+    // Cycles until the response is ready
+    delay: u32,
+    // The sector an INT1 announces, readable through the data FIFO once delivered
+    sector: Option<Box<[u8; SECTOR_SIZE]>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum AfterSeek {
+    // Completes the seek with INT2
+    Complete,
+    Read,
+    Play,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Drive {
+    Idle,
+    // Moving to the Setloc target
+    Seeking { remaining: u32, then: AfterSeek },
+    // Cycles until the next sector is under the head
+    Reading { remaining: u32 },
+    Playing { remaining: u32 },
+}
+
+// CDROM controller. The four registers are banked, the index written to 0x1F801800 selects which
+// registers 0x1F801801..0x1F801803 refer to.
+pub struct CdRom {
+    index: u8,
+    parameters: VecDeque<u8>,
+    // Reads past the length of the response wrap around the 16 byte buffer
+    response: [u8; RESPONSE_FIFO_SIZE],
+    response_length: usize,
+    response_position: usize,
+    data: VecDeque<u8>,
+    // Reading past the end of the data keeps returning the last word, some copy protections check this
+    last_word: u32,
+    // The last sector announced with INT1, loaded into the data FIFO on request
+    sector: Option<Box<[u8; SECTOR_SIZE]>>,
+
+    interrupt_enable: u8,
+    interrupt_flag: u8,
+    // Set when the interrupt line went high since the last check
+    interrupt_pending: bool,
+
+    // Command written to the command register and the cycles until it is executed
+    command: Option<(u8, u32)>,
+    responses: VecDeque<Response>,
+
+    // Status byte without the bits that follow the disc and the drive state. The shell open bit is
+    // latched here, it stays set after the lid is closed until the next GetStat.
+    stat: u8,
+    mode: u8,
+    drive: Drive,
+    // The next sector under the head
+    position: Msf,
+    // Set by Setloc, the next seek or read goes there
+    seek_target: Option<Msf>,
+    disc: Option<Disc>,
+    // The disc can't be read while the lid is open
+    shell_open: bool,
+    timing: CdTiming,
+
+    // Setfilter file and channel, XA sectors of other streams are skipped when the filter is on
+    filter: (u8, u8),
+    xa: XaDecoder,
+    resampler: Resampler,
+    // Decoded audio at 44100 Hz, before the volumes are applied
+    audio: VecDeque<(i16, i16)>,
+    // Whether the queued audio is XA-ADPCM, the status register shows it as ADPBUSY
+    adpcm_queued: bool,
+    // Whether the last sector's audio didn't fit in the queue
+    audio_overflow: bool,
+    // Mute command and the ADPCM mute bit of the volume apply register
+    muted: bool,
+    adpcm_muted: bool,
+    // Left to left, left to right, right to right and right to left, 0x80 is 100%. The written
+    // values only take effect once they are applied.
+    volume: [u8; 4],
+    pending_volume: [u8; 4],
+}
+
+impl CdRom {
+    pub fn new() -> Self {
+        Self {
+            index: 0,
+            parameters: VecDeque::with_capacity(PARAMETER_FIFO_SIZE),
+            response: [0; RESPONSE_FIFO_SIZE],
+            response_length: 0,
+            response_position: 0,
+            data: VecDeque::new(),
+            last_word: 0,
+            sector: None,
+            interrupt_enable: 0,
+            interrupt_flag: 0,
+            interrupt_pending: false,
+            command: None,
+            responses: VecDeque::new(),
+            stat: 0,
+            mode: 0,
+            drive: Drive::Idle,
+            position: Msf::from_lba(0),
+            seek_target: None,
+            disc: None,
+            shell_open: false,
+            timing: CdTiming::Accurate,
+            filter: (0, 0),
+            xa: XaDecoder::new(),
+            resampler: Resampler::new(37800, AUDIO_RATE),
+            audio: VecDeque::new(),
+            adpcm_queued: false,
+            audio_overflow: false,
+            muted: false,
+            adpcm_muted: false,
+            volume: [0x80, 0, 0x80, 0],
+            pending_volume: [0x80, 0, 0x80, 0],
+        }
+    }
+
+    pub fn insert_disc(&mut self, disc: Option<Disc>) {
+        self.stat = if disc.is_some() { STAT_MOTOR_ON } else { 0 };
+        self.disc = disc;
+        self.drive = Drive::Idle;
+        self.shell_open = false;
+    }
+
+    // The motor stops and a read or play in progress fails with INT5
+    pub fn open_lid(&mut self) {
+        if self.shell_open {
+            return;
+        }
+
+        self.shell_open = true;
+        self.stat = (self.stat | STAT_SHELL_OPEN) & !STAT_MOTOR_ON;
+        self.drop_sectors();
+        let busy = !matches!(self.drive, Drive::Idle);
+        self.drive = Drive::Idle;
+        if busy {
+            self.error(ERROR_SHELL_OPENED);
+        }
+    }
+
+    pub fn swap_disc(&mut self, disc: Disc) {
+        self.disc = Some(disc);
+        self.sector = None;
+    }
+
+    // The motor spins up again with the head at the start of the new disc
+    pub fn close_lid(&mut self) {
+        if !self.shell_open {
+            return;
+        }
+
+        self.shell_open = false;
+        self.position = Msf::from_lba(0);
+        self.seek_target = None;
+        if self.disc.is_some() {
+            self.stat |= STAT_MOTOR_ON;
+        }
+    }
+
+    pub fn set_timing(&mut self, timing: CdTiming) {
+        self.timing = timing;
+    }
+
+    // The time something takes with the configured timing
+    fn delay(&self, cycles: u32) -> u32 {
+        match self.timing {
+            CdTiming::Accurate => cycles,
+            CdTiming::Fast => cycles.min(FAST_DELAY),
+        }
+    }
+
+    // Without a disc the shell counts as open
+    fn stat(&self) -> u8 {
+        let mut stat = self.stat;
+        if self.disc.is_none() || self.shell_open {
+            stat |= STAT_SHELL_OPEN;
+        }
+
+        match self.drive {
+            Drive::Idle => stat,
+            Drive::Seeking { .. } => stat | STAT_SEEK,
+            Drive::Reading { .. } => stat | STAT_READ,
+            Drive::Playing { .. } => stat | STAT_PLAY,
+        }
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = self.index;
+        status |= ((self.adpcm_queued && !self.audio.is_empty()) as u8) << 2;
+        status |= (self.parameters.is_empty() as u8) << 3;
+        status |= ((self.parameters.len() < PARAMETER_FIFO_SIZE) as u8) << 4;
+        status |= ((self.response_position < self.response_length) as u8) << 5;
+        status |= (!self.data.is_empty() as u8) << 6;
+        status |= (self.command.is_some() as u8) << 7;
+
+        status
+    }
+
+    // Offsets are relative to 0x1F801800, all registers are 8 bit wide
+    pub fn read(&mut self, offset: u32) -> u8 {
+        match (offset, self.index) {
+            (0, _) => self.status(),
+            (1, _) => {
+                let value = self.response[self.response_position % RESPONSE_FIFO_SIZE];
+                self.response_position += 1;
+                value
+            }
+            (2, _) => self.data.pop_front().unwrap_or(self.last_word as u8),
+            // The unused bits read as 1
+            (3, 0 | 2) => self.interrupt_enable | 0xE0,
+            (3, _) => self.interrupt_flag | 0xE0,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn write(&mut self, offset: u32, value: u8) {
+        match (offset, self.index) {
+            (0, _) => self.index = value & 3,
+            (1, 0) => self.command = Some((value, self.delay(FIRST_RESPONSE_DELAY))),
+            // Sound map data out and coding info, for XA-ADPCM from memory
+            (1, 1 | 2) => {}
+            (2, 0) => {
+                if self.parameters.len() < PARAMETER_FIFO_SIZE {
+                    self.parameters.push_back(value);
+                }
+            }
+            (2, 1) => {
+                self.interrupt_enable = value & 0x1F;
+                self.update_interrupt();
+            }
+            (2, 2) => self.pending_volume[0] = value,
+            (2, 3) => self.pending_volume[1] = value,
+            (3, 0) => self.write_request(value),
+            (3, 1) => {
+                // Bits set to 1 acknowledge the interrupt, bit 6 clears the parameter FIFO
+                self.interrupt_flag &= !(value & 0x1F);
+                if value & 0x40 != 0 {
+                    self.parameters.clear();
+                }
+            }
+            (1, 3) => self.pending_volume[2] = value,
+            (3, 2) => self.pending_volume[3] = value,
+            // Bit 0 mutes XA-ADPCM, bit 5 applies the written volumes
+            (3, 3) => {
+                self.adpcm_muted = value & 1 != 0;
+                if value & 0x20 != 0 {
+                    self.volume = self.pending_volume;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Bit 7 (BFRD) loads the current sector into the data FIFO, clearing it drops the data. Depending
+    // on Setmode the FIFO gets everything after the sync pattern or only the 2048 data bytes.
+    fn write_request(&mut self, value: u8) {
+        if value & 0x80 == 0 {
+            self.data.clear();
+            return;
+        }
+
+        if let Some(sector) = &self.sector {
+            if self.data.is_empty() {
+                let data = match self.mode & MODE_SECTOR_SIZE {
+                    0 => &sector[24..24 + 0x800],
+                    _ => &sector[12..12 + 0x924],
+                };
+                self.data.extend(data);
+            }
+        }
+    }
+
+    fn update_interrupt(&mut self) {
+        if self.interrupt_flag & self.interrupt_enable & 0x1F != 0 {
+            self.interrupt_pending = true;
+        }
+    }
+
+    fn push_response(&mut self, interrupt: u8, bytes: Vec<u8>, delay: u32) {
+        self.responses.push_back(Response {
+            interrupt,
+            bytes,
+            delay,
+            sector: None,
+        });
+    }
+
+    fn acknowledge(&mut self) {
+        self.push_response(INT3, vec![self.stat()], 0);
+    }
+
+    fn error(&mut self, code: u8) {
+        self.push_response(INT5, vec![self.stat() | STAT_ERROR, code], 0);
+    }
+
+    // Sectors read before the drive was stopped or sent elsewhere are not announced anymore
+    fn drop_sectors(&mut self) {
+        self.responses.retain(|response| response.interrupt != INT1);
+    }
+
+    fn sector_delay(&self) -> u32 {
+        match self.mode & MODE_DOUBLE_SPEED {
+            0 => SECTOR_DELAY,
+            _ => SECTOR_DELAY / 2,
+        }
+    }
+
+    // Moves the head to the Setloc target, if there is a new one
+    fn seek(&mut self, then: AfterSeek) {
+        self.drop_sectors();
+        self.stat |= STAT_MOTOR_ON;
+        self.xa.reset();
+        self.resampler.reset();
+
+        self.drive = match (self.seek_target.take(), then) {
+            (Some(target), _) => {
+                let distance = target.sector().abs_diff(self.position.sector());
+                self.position = target;
+                Drive::Seeking {
+                    remaining: self.delay(SEEK_DELAY + distance * SEEK_DELAY_PER_SECTOR),
+                    then,
+                }
+            }
+            (None, AfterSeek::Read) => Drive::Reading {
+                remaining: self.sector_delay(),
+            },
+            (None, AfterSeek::Play) => Drive::Playing {
+                remaining: self.sector_delay(),
+            },
+            // Already there, the seek still takes a moment to complete
+            (None, AfterSeek::Complete) => Drive::Seeking {
+                remaining: self.delay(PAUSE_IDLE_DELAY),
+                then,
+            },
+        };
+    }
+
+    fn execute(&mut self, command: u8) {
+        let parameters: Vec<u8> = self.parameters.drain(..).collect();
+
+        let expected = match command {
+            0x02 => Some(3),
+            0x0D => Some(2),
+            0x0E | 0x14 => Some(1),
+            0x01 | 0x06 | 0x08..=0x0C | 0x10 | 0x11 | 0x13 | 0x15 | 0x16 | 0x1A | 0x1B | 0x1E => {
+                Some(0)
+            }
+            // Play takes an optional track, Test a subfunction and its arguments
+            _ => None,
+        };
+        if expected.is_some_and(|expected| parameters.len() != expected) {
+            return self.error(ERROR_WRONG_PARAMETERS);
+        }
+
+        let needs_disc = matches!(
+            command,
+            0x03 | 0x06 | 0x10 | 0x11 | 0x13..=0x16 | 0x1A | 0x1B | 0x1E
+        );
+        if needs_disc && (self.disc.is_none() || self.shell_open) {
+            return self.error(ERROR_NOT_READY);
+        }
+
+        match command {
+            // GetStat, the response still has the shell open bit of a lid that was closed since the
+            // last one
+            0x01 => {
+                self.acknowledge();
+                if !self.shell_open {
+                    self.stat &= !STAT_SHELL_OPEN;
+                }
+            }
+            // Setloc, in BCD
+            0x02 => {
+                self.seek_target = Some(Msf::from_bcd(parameters[0], parameters[1], parameters[2]));
+                self.acknowledge();
+            }
+            // Play, from the start of the track or else the Setloc or current position
+            0x03 => {
+                if parameters.len() > 1 {
+                    return self.error(ERROR_WRONG_PARAMETERS);
+                }
+
+                if let Some(number) = parameters
+                    .first()
+                    .map(|&track| from_bcd(track))
+                    .filter(|&track| track != 0)
+                {
+                    match self.disc.as_ref().unwrap().track(number) {
+                        Some(track) => self.seek_target = Some(Msf::from_lba(track.start)),
+                        None => return self.error(ERROR_INVALID_SUBFUNCTION),
+                    }
+                }
+
+                self.acknowledge();
+                self.seek(AfterSeek::Play);
+            }
+            // ReadN and ReadS, there is no difference without read retries
+            0x06 | 0x1B => {
+                self.acknowledge();
+                self.seek(AfterSeek::Read);
+            }
+            // Stop, the motor spins down
+            0x08 => {
+                let delay = match self.stat & STAT_MOTOR_ON {
+                    0 => PAUSE_IDLE_DELAY,
+                    _ => STOP_DELAY,
+                };
+                self.acknowledge();
+                self.drop_sectors();
+                self.drive = Drive::Idle;
+                self.stat &= !STAT_MOTOR_ON;
+                self.push_response(INT2, vec![self.stat()], self.delay(delay));
+            }
+            // Pause, takes about a sector when the drive was reading
+            0x09 => {
+                let delay = match self.drive {
+                    Drive::Idle => PAUSE_IDLE_DELAY,
+                    _ => self.sector_delay(),
+                };
+                self.acknowledge();
+                self.drop_sectors();
+                self.drive = Drive::Idle;
+                self.push_response(INT2, vec![self.stat()], self.delay(delay));
+            }
+            // Init, resets the mode and starts the motor
+            0x0A => {
+                self.acknowledge();
+                self.drop_sectors();
+                self.drive = Drive::Idle;
+                self.mode = MODE_SECTOR_SIZE;
+                if self.disc.is_some() {
+                    self.stat |= STAT_MOTOR_ON;
+                }
+                self.push_response(INT2, vec![self.stat()], self.delay(INIT_DELAY));
+            }
+            // Mute and Demute
+            0x0B | 0x0C => {
+                self.muted = command == 0x0B;
+                self.acknowledge();
+            }
+            // Setfilter, the file and channel of the XA-ADPCM stream to play
+            0x0D => {
+                self.filter = (parameters[0], parameters[1]);
+                self.acknowledge();
+            }
+            // Setmode
+            0x0E => {
+                self.mode = parameters[0];
+                self.acknowledge();
+            }
+            // GetlocL, header and subheader of the last data sector
+            0x10 => match &self.sector {
+                Some(sector) => {
+                    let response = sector[12..20].to_vec();
+                    self.push_response(INT3, response, 0);
+                }
+                None => self.error(ERROR_NOT_READY),
+            },
+            // GetlocP, the subchannel Q position
+            0x11 => {
+                let (track, index, relative, absolute) = self.subchannel_q(self.position);
+                let mut response = vec![track, index];
+                response.extend(relative);
+                response.extend(absolute);
+                self.push_response(INT3, response, 0);
+            }
+            // GetTN, first and last track
+            0x13 => {
+                let (first, last) = self.disc.as_ref().unwrap().track_numbers();
+                let response = vec![self.stat(), to_bcd(first), to_bcd(last)];
+                self.push_response(INT3, response, 0);
+            }
+            // GetTD, minute and second a track starts at, track 0 is the lead-out
+            0x14 => {
+                let disc = self.disc.as_ref().unwrap();
+                let start = match from_bcd(parameters[0]) {
+                    0 => Some(disc.lead_out()),
+                    track => disc.track(track).map(|track| Msf::from_lba(track.start)),
+                };
+
+                match start {
+                    Some(start) => {
+                        let [minute, second, _] = start.to_bcd();
+                        self.push_response(INT3, vec![self.stat(), minute, second], 0);
+                    }
+                    None => self.error(ERROR_INVALID_SUBFUNCTION),
+                }
+            }
+            // SeekL and SeekP, data and audio seeks are the same here
+            0x15 | 0x16 => {
+                self.seek(AfterSeek::Complete);
+                self.acknowledge();
+            }
+            // Test, only the BIOS version query
+            0x19 => match parameters.first() {
+                // PU-7 controller from 1994-09-19, version C0
+                Some(0x20) => self.push_response(INT3, vec![0x94, 0x09, 0x19, 0xC0], 0),
+                _ => self.error(ERROR_INVALID_SUBFUNCTION),
+            },
+            // GetID
+            0x1A => {
+                self.acknowledge();
+                self.get_id();
+            }
+            // ReadTOC, the table of contents is known from the start
+            0x1E => {
+                self.acknowledge();
+                self.push_response(INT2, vec![self.stat()], self.delay(READ_TOC_DELAY));
+            }
+            _ => {
+                println!("Unhandled CDROM command 0x{:02x}", command);
+                self.error(ERROR_INVALID_COMMAND);
+            }
+        }
+    }
+
+    // Audio discs have no ID. Data discs report the region from the license string in sector 4,
+    // discs without one (homebrew) pass as licensed american discs, like with a modchip.
+    fn get_id(&mut self) {
+        let disc = self.disc.as_mut().unwrap();
+        if disc.tracks()[0].kind == TrackKind::Audio {
+            let response = vec![self.stat() | STAT_ID_ERROR, 0x90, 0, 0, 0, 0, 0, 0];
+            return self.push_response(INT5, response, self.delay(GET_ID_DELAY));
+        }
+
+        let license = disc.read_sector(Msf::from_lba(4)).ok();
+        let region = match license.as_ref().map(|sector| &sector[24..24 + 0x800]) {
+            Some(data) if contains(data, b"Sony Computer Entertainment Euro") => b"SCEE",
+            Some(data) if contains(data, b"Sony Computer Entertainment Inc.") => b"SCEI",
+            _ => b"SCEA",
+        };
+
+        let mut response = vec![self.stat(), 0x00, 0x20, 0x00];
+        response.extend(region);
+        self.push_response(INT2, response, self.delay(GET_ID_DELAY));
+    }
+
+    // Moves the next response into the response FIFO
+    fn deliver(&mut self, response: Response) {
+        self.response = [0; RESPONSE_FIFO_SIZE];
+        self.response_length = response.bytes.len().min(RESPONSE_FIFO_SIZE);
+        self.response[..self.response_length]
+            .copy_from_slice(&response.bytes[..self.response_length]);
+        self.response_position = 0;
+
+        if response.sector.is_some() {
+            self.sector = response.sector;
+        }
+
+        self.interrupt_flag = (self.interrupt_flag & !7) | response.interrupt;
+        self.update_interrupt();
+    }
+
+    // Announces the sector under the head with INT1, replacing a sector the host did not pick up
+    fn read_sector(&mut self) {
+        let position = self.position;
+        self.position = Msf::from_sector(position.sector() + 1);
+
+        let sector = match self.disc.as_mut().unwrap().read_sector(position) {
+            Ok(sector) => sector,
+            Err(error) => {
+                println!("CDROM read failed: {}", error);
+                self.drive = Drive::Idle;
+                return self.error(ERROR_SEEK_FAILED);
+            }
+        };
+
+        if self.mode & MODE_XA_ADPCM != 0 && self.is_xa_audio(&sector) {
+            return self.play_xa(&sector);
+        }
+
+        self.drop_sectors();
+        self.responses.push_back(Response {
+            interrupt: INT1,
+            bytes: vec![self.stat()],
+            delay: 0,
+            sector: Some(Box::new(sector)),
+        });
+    }
+
+    // Real-time audio sectors (Mode 2 Form 2) are passed to the ADPCM decoder instead of the host
+    fn is_xa_audio(&self, sector: &[u8; SECTOR_SIZE]) -> bool {
+        let submode = sector[18];
+        sector[15] == 2 && submode & SUBMODE_AUDIO != 0 && submode & SUBMODE_REAL_TIME != 0
+    }
+
+    // Sectors of other streams are skipped when the filter is on, neither is announced with INT1
+    fn play_xa(&mut self, sector: &[u8; SECTOR_SIZE]) {
+        let (file, channel) = (sector[16], sector[17]);
+        if self.mode & MODE_XA_FILTER != 0 && (file, channel) != self.filter {
+            return;
+        }
+
+        let mut samples = Vec::new();
+        self.xa.decode_sector(sector, &mut samples);
+
+        let mut output = Vec::new();
+        self.resampler
+            .set_rates(XaDecoder::sample_rate(sector[19]), AUDIO_RATE);
+        self.resampler.process(&samples, &mut output);
+        if self.adpcm_muted {
+            output.fill((0, 0));
+        }
+        self.queue_audio(output, true);
+    }
+
+    // The SPU takes 44100 samples a second. What it can't keep up with, like CD-DA at double speed,
+    // is dropped from the front so the audio doesn't fall behind.
+    fn queue_audio(&mut self, samples: Vec<(i16, i16)>, adpcm: bool) {
+        self.audio.extend(samples);
+        self.adpcm_queued = adpcm;
+
+        let excess = self.audio.len().saturating_sub(AUDIO_QUEUE_LIMIT);
+        if excess > 0 && !self.audio_overflow {
+            println!("Dropping CD audio, the SPU doesn't take it as fast as it is read");
+        }
+        self.audio.drain(..excess);
+        self.audio_overflow = excess > 0;
+    }
+
+    // Track, index, relative and absolute time of the position in BCD. The lead-out is track 0xAA.
+    // Images with subchannel data have it stored, otherwise it is worked out from the tracks.
+    fn subchannel_q(&mut self, mut position: Msf) -> (u8, u8, [u8; 3], [u8; 3]) {
+        let disc = self.disc.as_mut().unwrap();
+
+        // The drive keeps the last Q with a valid CRC, reading on that is the sector before. This
+        // is what LibCrypt checks for.
+        let mut q = disc.read_subchannel_q(position);
+        while let Some(bad) = q.filter(|q| !is_subchannel_q_valid(q)) {
+            println!(
+                "Ignored subchannel Q with a bad CRC at {}: {:02x?}",
+                position, bad
+            );
+            if position.sector() == 0 {
+                q = None;
+                break;
+            }
+            position = Msf::from_sector(position.sector() - 1);
+            q = disc.read_subchannel_q(position);
+        }
+
+        // Only mode 1 Q data holds the position
+        if let Some(q) = q.filter(|q| q[0] & 0xF == 1) {
+            return (q[1], q[2], [q[3], q[4], q[5]], [q[7], q[8], q[9]]);
+        }
+
+        match disc.locate(position) {
+            Some((track, index, relative)) => (
+                to_bcd(track),
+                to_bcd(index),
+                relative.to_bcd(),
+                position.to_bcd(),
+            ),
+            None => {
+                let relative =
+                    Msf::from_sector(position.sector().saturating_sub(disc.lead_out().sector()));
+                (0xAA, 0x01, relative.to_bcd(), position.to_bcd())
+            }
+        }
+    }
+
+    // Sends the sector under the head to the SPU as CD-DA, data sectors play as silence. In report
+    // mode the position is announced with INT1 every 10 sectors, alternating between absolute and
+    // relative time, with the peak level of the sector.
+    fn play_sector(&mut self) {
+        let position = self.position;
+        self.position = Msf::from_sector(position.sector() + 1);
+
+        let disc = self.disc.as_mut().unwrap();
+        let track = disc
+            .track_at(position)
+            .map(|track| (track.number, track.kind));
+        let (Some((number, kind)), Ok(sector)) = (track, disc.read_sector(position)) else {
+            // The end of the disc
+            self.drive = Drive::Idle;
+            return self.push_response(INT4, vec![self.stat()], 0);
+        };
+
+        let mut peak = 0;
+        if kind == TrackKind::Audio {
+            let samples: Vec<_> = (sector.chunks_exact(4))
+                .map(|sample| {
+                    let left = i16::from_le_bytes([sample[0], sample[1]]);
+                    let right = i16::from_le_bytes([sample[2], sample[3]]);
+                    peak = peak.max(left.unsigned_abs()).max(right.unsigned_abs());
+                    (left, right)
+                })
+                .collect();
+            self.queue_audio(samples, false);
+        }
+        let peak = peak.min(0x7FFF);
+
+        if self.mode & MODE_REPORT != 0 && position.frame.is_multiple_of(10) {
+            let (track, index, relative, absolute) = self.subchannel_q(position);
+            let time = if position.frame.is_multiple_of(20) {
+                absolute
+            } else {
+                [relative[0], relative[1] | 0x80, relative[2]]
+            };
+
+            let mut response = vec![self.stat(), track, index];
+            response.extend(time);
+            response.extend(peak.to_le_bytes());
+            self.drop_sectors();
+            self.push_response(INT1, response, 0);
+        }
+
+        let next_track = self
+            .disc
+            .as_ref()
+            .unwrap()
+            .track_at(self.position)
+            .map(|track| track.number);
+        if self.mode & MODE_AUTO_PAUSE != 0 && next_track != Some(number) {
+            self.drive = Drive::Idle;
+            self.push_response(INT4, vec![self.stat()], 0);
+        }
+    }
+
+    // The next 44100 Hz sample for the SPU CD input, with the volumes applied
+    pub fn audio_sample(&mut self) -> (i16, i16) {
+        let Some((left, right)) = self.audio.pop_front() else {
+            return (0, 0);
+        };
+        if self.muted {
+            return (0, 0);
+        }
+
+        let [left_to_left, left_to_right, right_to_right, right_to_left] =
+            self.volume.map(|volume| volume as i32);
+        let (left, right) = (left as i32, right as i32);
+        let mix = |value: i32| (value >> 7).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+
+        (
+            mix(left * left_to_left + right * right_to_left),
+            mix(left * left_to_right + right * right_to_right),
+        )
+    }
+
+    fn step_drive(&mut self, cycles: u32) {
+        self.drive = match self.drive {
+            Drive::Idle => Drive::Idle,
+            Drive::Seeking { remaining, then } if remaining > cycles => Drive::Seeking {
+                remaining: remaining - cycles,
+                then,
+            },
+            Drive::Seeking {
+                then: AfterSeek::Read,
+                ..
+            } => Drive::Reading {
+                remaining: self.sector_delay(),
+            },
+            Drive::Seeking {
+                then: AfterSeek::Play,
+                ..
+            } => Drive::Playing {
+                remaining: self.sector_delay(),
+            },
+            Drive::Seeking {
+                then: AfterSeek::Complete,
+                ..
+            } => {
+                self.drive = Drive::Idle;
+                self.push_response(INT2, vec![self.stat()], 0);
+                Drive::Idle
+            }
+            Drive::Reading { remaining } if remaining > cycles => Drive::Reading {
+                remaining: remaining - cycles,
+            },
+            Drive::Reading { .. } => {
+                self.read_sector();
+                match self.drive {
+                    Drive::Idle => Drive::Idle,
+                    _ => Drive::Reading {
+                        remaining: self.sector_delay(),
+                    },
+                }
+            }
+            Drive::Playing { remaining } if remaining > cycles => Drive::Playing {
+                remaining: remaining - cycles,
+            },
+            Drive::Playing { .. } => {
+                self.play_sector();
+                match self.drive {
+                    Drive::Idle => Drive::Idle,
+                    _ => Drive::Playing {
+                        remaining: self.sector_delay(),
+                    },
+                }
+            }
+        };
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        if let Some((command, remaining)) = self.command {
+            if remaining <= cycles {
+                self.command = None;
+                self.execute(command);
+            } else {
+                self.command = Some((command, remaining - cycles));
+            }
+        }
+
+        self.step_drive(cycles);
+
+        // Only the first response counts down, it is held back while the host did not acknowledge
+        // the previous interrupt yet
+        if let Some(response) = self.responses.front_mut() {
+            response.delay = response.delay.saturating_sub(cycles);
+            if response.delay == 0 && self.interrupt_flag & 7 == 0 {
+                let response = self.responses.pop_front().unwrap();
+                self.deliver(response);
+            }
+        }
+    }
+
+    pub fn cycles_until_event(&self) -> u32 {
+        let command = self.command.map(|(_, remaining)| remaining);
+        let drive = match self.drive {
+            Drive::Idle => None,
+            Drive::Seeking { remaining, .. }
+            | Drive::Reading { remaining }
+            | Drive::Playing { remaining } => Some(remaining),
+        };
+        let response = self
+            .responses
+            .front()
+            .filter(|_| self.interrupt_flag & 7 == 0)
+            .map(|response| response.delay.max(1));
+
+        command
+            .into_iter()
+            .chain(drive)
+            .chain(response)
+            .min()
+            .unwrap_or(u32::MAX)
+    }
+
+    // Whether IRQ2 has to be requested since the last call
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.interrupt_pending)
+    }
+
+    // The state a guest waiting for the drive would be waiting on, for debugging output
+    pub fn summary(&self) -> String {
+        format!(
+            "stat 0x{:02x}, interrupt 0x{:02x} enable 0x{:02x}, command {}, {} responses, {:?} at {}",
+            self.stat,
+            self.interrupt_flag,
+            self.interrupt_enable,
+            match self.command {
+                Some((command, _)) => format!("0x{:02x}", command),
+                None => "none".to_string(),
+            },
+            self.responses.len(),
+            self.drive,
+            self.position
+        )
+    }
+}
+
+impl DmaDevice for CdRom {
+    fn read_word(&mut self) -> u32 {
+        if self.data.is_empty() {
+            return self.last_word;
+        }
+
+        let mut bytes = self.last_word.to_le_bytes();
+        for byte in &mut bytes {
+            if let Some(value) = self.data.pop_front() {
+                *byte = value;
+            }
+        }
+
+        self.last_word = u32::from_le_bytes(bytes);
+        self.last_word
+    }
+
+    // The CDROM can only be read from
+    fn write_word(&mut self, _value: u32) {}
+}
+
+fn contains(data: &[u8], pattern: &[u8]) -> bool {
+    data.windows(pattern.len()).any(|window| window == pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dma::{Dma, Port};
+
+    #[test]
+    fn adpcm_mute_leaves_cd_audio_playing() {
+        let mut cdrom = CdRom::new();
+        cdrom.audio.push_back((0x1000, -0x1000));
+        // Bit 0 of the ADPCM control register
+        cdrom.write(0, 3);
+        cdrom.write(3, 0x01);
+
+        assert_eq!(cdrom.audio_sample(), (0x1000, -0x1000));
+    }
+
+    #[test]
+    fn adpbusy_is_set_while_xa_audio_plays() {
+        let mut cdrom = CdRom::new();
+        let mut sector = [0; SECTOR_SIZE];
+        sector[15] = 2;
+        sector[18] = SUBMODE_AUDIO | SUBMODE_REAL_TIME;
+        assert!(cdrom.is_xa_audio(&sector));
+
+        cdrom.play_xa(&sector);
+        assert!(!cdrom.audio.is_empty());
+        assert_ne!(cdrom.read(0) & (1 << 2), 0);
+
+        while !cdrom.audio.is_empty() {
+            cdrom.audio_sample();
+        }
+        assert_eq!(cdrom.read(0) & (1 << 2), 0);
+    }
+
+    #[test]
+    fn audio_the_spu_does_not_take_is_dropped() {
+        let mut cdrom = CdRom::new();
+        for sector in 0..AUDIO_QUEUE_LIMIT / 588 + 10 {
+            cdrom.queue_audio(vec![(sector as i16, 0); 588], false);
+            assert!(cdrom.audio.len() <= AUDIO_QUEUE_LIMIT);
+        }
+        assert!(cdrom.audio_overflow);
+
+        // The oldest samples went, the newest are still there
+        assert_eq!(cdrom.audio.len(), AUDIO_QUEUE_LIMIT);
+        assert_eq!(
+            cdrom.audio.back(),
+            Some(&(AUDIO_QUEUE_LIMIT as i16 / 588 + 9, 0))
+        );
+        let oldest = (AUDIO_QUEUE_LIMIT / 588 + 10) * 588 - AUDIO_QUEUE_LIMIT;
+        assert_eq!(cdrom.audio_sample(), ((oldest / 588) as i16, 0));
+
+        // Once the SPU caught up nothing is dropped
+        cdrom.audio.clear();
+        cdrom.queue_audio(vec![(1, 1); 588], false);
+        assert!(!cdrom.audio_overflow);
+        assert_eq!(cdrom.audio.len(), 588);
+    }
+
+    fn test_disc(name: &str) -> Disc {
+        iso_disc(name, vec![0; 2048 * 300])
+    }
+
+    fn iso_disc(name: &str, data: Vec<u8>) -> Disc {
+        let path =
+            std::env::temp_dir().join(format!("psx-rust-{}-{}.iso", name, std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        let disc = Disc::open_iso(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        disc
+    }
+
+    // Runs the drive until the next interrupt, then reads and acknowledges the response. Stop takes
+    // the longest, almost half a second.
+    fn next_response(cdrom: &mut CdRom) -> (u8, Vec<u8>) {
+        for _ in 0..20_000 {
+            cdrom.step(1000);
+            cdrom.write(0, 1);
+            let interrupt = cdrom.read(3) & 7;
+            if interrupt != 0 {
+                let bytes = (0..cdrom.response_length).map(|_| cdrom.read(1)).collect();
+                cdrom.write(3, 0x1F);
+                return (interrupt, bytes);
+            }
+        }
+
+        panic!("No response");
+    }
+
+    fn command(cdrom: &mut CdRom, command: u8) -> (u8, Vec<u8>) {
+        command_with(cdrom, command, &[])
+    }
+
+    fn command_with(cdrom: &mut CdRom, command: u8, parameters: &[u8]) -> (u8, Vec<u8>) {
+        cdrom.write(0, 0);
+        for parameter in parameters {
+            cdrom.write(2, *parameter);
+        }
+        cdrom.write(1, command);
+        next_response(cdrom)
+    }
+
+    #[test]
+    fn get_stat_follows_a_disc_swap() {
+        let mut cdrom = CdRom::new();
+        cdrom.insert_disc(Some(test_disc("first")));
+        assert_eq!(command(&mut cdrom, 0x01), (INT3, vec![STAT_MOTOR_ON]));
+
+        // Reading when the lid opens fails
+        assert_eq!(command(&mut cdrom, 0x06), (INT3, vec![STAT_MOTOR_ON]));
+        cdrom.open_lid();
+        let (interrupt, bytes) = next_response(&mut cdrom);
+        assert_eq!(interrupt, INT5);
+        assert_eq!(
+            bytes[0] & (STAT_ERROR | STAT_SHELL_OPEN),
+            STAT_ERROR | STAT_SHELL_OPEN
+        );
+        assert_eq!(bytes[1], ERROR_SHELL_OPENED);
+
+        // While the lid is open GetStat keeps reporting it, the motor is off
+        cdrom.swap_disc(test_disc("second"));
+        assert_eq!(command(&mut cdrom, 0x01), (INT3, vec![STAT_SHELL_OPEN]));
+        assert_eq!(command(&mut cdrom, 0x01), (INT3, vec![STAT_SHELL_OPEN]));
+        assert_eq!(command(&mut cdrom, 0x06).0, INT5);
+
+        // The first GetStat after closing still has the latched bit, the next one doesn't
+        cdrom.close_lid();
+        assert_eq!(
+            command(&mut cdrom, 0x01),
+            (INT3, vec![STAT_SHELL_OPEN | STAT_MOTOR_ON])
+        );
+        assert_eq!(command(&mut cdrom, 0x01), (INT3, vec![STAT_MOTOR_ON]));
+    }
+
+    #[test]
+    fn sector_is_read_through_dma() {
+        let mut cdrom = CdRom::new();
+        let sector: Vec<u8> = (0..2048).map(|i| (i * 3) as u8).collect();
+        cdrom.data.extend(&sector);
+
+        // Two words more than the FIFO has, an empty FIFO repeats the last word
+        let mut dma = Dma::new();
+        let mut ram = vec![0; 0x200000];
+        dma.write(0x70, 0x0FFFFFFF);
+        dma.write(0x30, 0x1000);
+        dma.write(0x34, 514);
+        dma.write(0x38, 0x11000000);
+        assert_eq!(dma.transfer(Port::CdRom, &mut ram, &mut cdrom), Some(514));
+
+        assert_eq!(ram[0x1000..0x1800], sector[..]);
+        assert_eq!(ram[0x1800..0x1804], sector[2044..]);
+        assert_eq!(ram[0x1804..0x1808], sector[2044..]);
+        assert_eq!(ram[0x1808..0x180C], [0; 4]);
+        assert!(cdrom.data.is_empty());
+    }
+
+    #[test]
+    fn index_selects_the_registers() {
+        let mut cdrom = CdRom::new();
+        // Interrupt enable in index 1, read back in index 0 and 2
+        cdrom.write(0, 1);
+        cdrom.write(2, 0x1F);
+        for index in 0..4 {
+            cdrom.write(0, index);
+            assert_eq!(cdrom.read(0) & 3, index);
+            let expected = if index % 2 == 0 { 0xFF } else { 0xE0 };
+            assert_eq!(cdrom.read(3), expected, "index {}", index);
+        }
+
+        // INT3 of GetStat, acknowledged by writing ones in index 1
+        cdrom.write(0, 0);
+        cdrom.write(1, 0x01);
+        for _ in 0..100 {
+            cdrom.step(1000);
+        }
+        assert!(cdrom.take_interrupt());
+        cdrom.write(0, 1);
+        assert_eq!(cdrom.read(3), 0xE0 | INT3);
+        cdrom.write(3, 0x04);
+        assert_eq!(cdrom.read(3), 0xE0 | INT3);
+        cdrom.write(3, 0x03);
+        assert_eq!(cdrom.read(3), 0xE0);
+    }
+
+    #[test]
+    fn fifo_flags_follow_the_contents() {
+        let mut cdrom = CdRom::new();
+        // Parameters empty and not full, no response or data
+        assert_eq!(cdrom.read(0) & 0x78, 0x18);
+
+        cdrom.write(2, 0x12);
+        assert_eq!(cdrom.read(0) & 0x18, 0x10);
+        for _ in 1..PARAMETER_FIFO_SIZE {
+            cdrom.write(2, 0x34);
+        }
+        assert_eq!(cdrom.read(0) & 0x18, 0x00);
+        // Cleared through the interrupt flag register
+        cdrom.write(0, 1);
+        cdrom.write(3, 0x40);
+        assert_eq!(cdrom.read(0) & 0x18, 0x18);
+
+        // The response is there until it was read, the busy bit until it arrived
+        cdrom.write(0, 0);
+        cdrom.write(1, 0x01);
+        assert_ne!(cdrom.read(0) & 0x80, 0);
+        for _ in 0..100 {
+            cdrom.step(1000);
+        }
+        assert_eq!(cdrom.read(0) & 0xA0, 0x20);
+        cdrom.read(1);
+        assert_eq!(cdrom.read(0) & 0x20, 0);
+    }
+
+    // Every byte of a sector is its LBA
+    fn numbered_disc(name: &str) -> Disc {
+        iso_disc(name, (0..300).flat_map(|lba| [lba as u8; 2048]).collect())
+    }
+
+    // Requests the data of the announced sector and reads it from the FIFO
+    fn sector_data(cdrom: &mut CdRom, length: usize) -> Vec<u8> {
+        cdrom.write(0, 0);
+        cdrom.write(3, 0x80);
+        let data = (0..length).map(|_| cdrom.read(2)).collect();
+        assert_eq!(cdrom.read(0) & 0x40, 0);
+        data
+    }
+
+    #[test]
+    fn get_id_reports_a_licensed_disc() {
+        let mut cdrom = CdRom::new();
+        cdrom.insert_disc(Some(test_disc("get-id")));
+
+        assert_eq!(command(&mut cdrom, 0x1A), (INT3, vec![STAT_MOTOR_ON]));
+        assert_eq!(
+            next_response(&mut cdrom),
+            (
+                INT2,
+                [&[STAT_MOTOR_ON, 0x00, 0x20, 0x00][..], b"SCEA"].concat()
+            )
+        );
+
+        // Without a disc
+        let mut cdrom = CdRom::new();
+        assert_eq!(command(&mut cdrom, 0x1A).0, INT5);
+    }
+
+    #[test]
+    fn reads_deliver_one_sector_per_int1() {
+        let mut cdrom = CdRom::new();
+        cdrom.insert_disc(Some(numbered_disc("read")));
+
+        // LBA 5
+        assert_eq!(command_with(&mut cdrom, 0x02, &[0x00, 0x02, 0x05]).0, INT3);
+        assert_eq!(command(&mut cdrom, 0x06).0, INT3);
+        for lba in 5..8 {
+            let (interrupt, stat) = next_response(&mut cdrom);
+            assert_eq!(interrupt, INT1);
+            assert_eq!(stat[0] & STAT_READ, STAT_READ);
+            assert_eq!(sector_data(&mut cdrom, 0x800), vec![lba; 0x800]);
+        }
+
+        // Pause answers twice, then nothing arrives anymore
+        assert_eq!(command(&mut cdrom, 0x09).0, INT3);
+        assert_eq!(next_response(&mut cdrom).0, INT2);
+        for _ in 0..1000 {
+            cdrom.step(1000);
+        }
+        assert!(cdrom.responses.is_empty());
+
+        // Whole sectors from the header on
+        assert_eq!(command_with(&mut cdrom, 0x0E, &[MODE_SECTOR_SIZE]).0, INT3);
+        assert_eq!(command_with(&mut cdrom, 0x02, &[0x00, 0x02, 0x10]).0, INT3);
+        assert_eq!(command(&mut cdrom, 0x06).0, INT3);
+        assert_eq!(next_response(&mut cdrom).0, INT1);
+        let data = sector_data(&mut cdrom, 0x924);
+        assert_eq!(&data[..4], &[0x00, 0x02, 0x10, 0x02]);
+        assert_eq!(&data[12..0x80C], &[10; 0x800][..]);
+    }
+
+    #[test]
+    fn init_resets_the_mode_and_bad_parameters_are_errors() {
+        let mut cdrom = CdRom::new();
+        cdrom.insert_disc(Some(test_disc("init")));
+
+        // Setmode takes exactly one parameter
+        let (interrupt, bytes) = command(&mut cdrom, 0x0E);
+        assert_eq!(interrupt, INT5);
+        assert_eq!(
+            bytes,
+            vec![STAT_MOTOR_ON | STAT_ERROR, ERROR_WRONG_PARAMETERS]
+        );
+        assert_eq!(command_with(&mut cdrom, 0x0E, &[0x80]).0, INT3);
+        assert_eq!(cdrom.mode, 0x80);
+
+        assert_eq!(command(&mut cdrom, 0x0A), (INT3, vec![STAT_MOTOR_ON]));
+        assert_eq!(next_response(&mut cdrom), (INT2, vec![STAT_MOTOR_ON]));
+        assert_eq!(cdrom.mode, MODE_SECTOR_SIZE);
+
+        // Stop turns the motor off
+        assert_eq!(command(&mut cdrom, 0x08).0, INT3);
+        assert_eq!(next_response(&mut cdrom), (INT2, vec![0]));
+    }
+
+    // A single 60 sector audio track, the samples of a sector are its LBA times 100
+    fn audio_disc(name: &str) -> Disc {
+        let directory = std::env::temp_dir();
+        let bin = format!("psx-rust-{}-{}.bin", name, std::process::id());
+        let cue = directory.join(format!("psx-rust-{}-{}.cue", name, std::process::id()));
+        let image: Vec<u8> = (0..60i16)
+            .flat_map(|lba| (lba * 100).to_le_bytes().repeat(588 * 2))
+            .collect();
+        std::fs::write(directory.join(&bin), image).unwrap();
+        let sheet = format!(
+            "FILE \"{}\" BINARY\nTRACK 01 AUDIO\nINDEX 01 00:00:00\n",
+            bin
+        );
+        std::fs::write(&cue, sheet).unwrap();
+
+        let disc = Disc::open(&cue).unwrap();
+        std::fs::remove_file(directory.join(&bin)).unwrap();
+        std::fs::remove_file(&cue).unwrap();
+        disc
+    }
+
+    #[test]
+    fn playing_reports_the_position() {
+        let mut cdrom = CdRom::new();
+        cdrom.insert_disc(Some(audio_disc("play")));
+
+        let mode = MODE_REPORT | MODE_AUTO_PAUSE;
+        assert_eq!(command_with(&mut cdrom, 0x0E, &[mode]).0, INT3);
+        assert_eq!(command_with(&mut cdrom, 0x03, &[0x01]).0, INT3);
+
+        // Every 10 sectors, the absolute and the relative time take turns
+        let stat = STAT_MOTOR_ON | STAT_PLAY;
+        let (interrupt, report) = next_response(&mut cdrom);
+        assert_eq!(interrupt, INT1);
+        assert_eq!(report, vec![stat, 0x01, 0x01, 0x00, 0x02, 0x00, 0, 0]);
+        let (_, report) = next_response(&mut cdrom);
+        assert_eq!(
+            report,
+            [
+                &[stat, 0x01, 0x01, 0x00, 0x80, 0x10][..],
+                &1000u16.to_le_bytes()
+            ]
+            .concat()
+        );
+        let (_, report) = next_response(&mut cdrom);
+        assert_eq!(&report[3..6], &[0x00, 0x02, 0x20]);
+        assert_eq!(cdrom.audio.len(), 21 * 588);
+        // ADPBUSY is only for XA-ADPCM
+        assert_eq!(cdrom.read(0) & (1 << 2), 0);
+
+        // The sector after the report is next
+        let (interrupt, position) = command(&mut cdrom, 0x11);
+        assert_eq!(interrupt, INT3);
+        assert_eq!(
+            position,
+            vec![0x01, 0x01, 0x00, 0x00, 0x21, 0x00, 0x02, 0x21]
+        );
+
+        // Auto pause stops at the end of the track
+        assert_eq!(next_response(&mut cdrom).0, INT1);
+        assert_eq!(next_response(&mut cdrom).0, INT1);
+        assert_eq!(next_response(&mut cdrom).0, INT1);
+        assert_eq!(next_response(&mut cdrom), (INT4, vec![STAT_MOTOR_ON]));
+    }
+
+    // Reads the sector at the LBA in both sector sizes
+    fn read_both_sizes(cdrom: &mut CdRom, lba: u8) -> (Vec<u8>, Vec<u8>) {
+        let mut read = |mode: u8, length: usize| {
+            assert_eq!(command_with(cdrom, 0x0E, &[mode]).0, INT3);
+            assert_eq!(
+                command_with(cdrom, 0x02, &[0x00, 0x02, to_bcd(lba)]).0,
+                INT3
+            );
+            assert_eq!(command(cdrom, 0x06).0, INT3);
+            assert_eq!(next_response(cdrom).0, INT1);
+            let data = sector_data(cdrom, length);
+            assert_eq!(command(cdrom, 0x09).0, INT3);
+            next_response(cdrom);
+            data
+        };
+
+        (read(0, 0x800), read(MODE_SECTOR_SIZE, 0x924))
+    }
+
+    #[test]
+    fn iso_reads_like_a_raw_rip() {
+        let mut iso = numbered_disc("rip-iso");
+
+        // The same sectors as a bin and cue
+        let directory = std::env::temp_dir();
+        let bin = format!("psx-rust-rip-{}.bin", std::process::id());
+        let cue = directory.join(format!("psx-rust-rip-{}.cue", std::process::id()));
+        let image: Vec<u8> = (0..300)
+            .flat_map(|lba| iso.read_sector(Msf::from_lba(lba)).unwrap())
+            .collect();
+        std::fs::write(directory.join(&bin), image).unwrap();
+        let sheet = format!(
+            "FILE \"{}\" BINARY\nTRACK 01 MODE2/2352\nINDEX 01 00:00:00\n",
+            bin
+        );
+        std::fs::write(&cue, sheet).unwrap();
+        let rip = Disc::open(&cue).unwrap();
+        std::fs::remove_file(directory.join(&bin)).unwrap();
+        std::fs::remove_file(&cue).unwrap();
+
+        let mut from_iso = CdRom::new();
+        from_iso.insert_disc(Some(iso));
+        let mut from_rip = CdRom::new();
+        from_rip.insert_disc(Some(rip));
+        for lba in [0, 16, 42] {
+            let (data, raw) = read_both_sizes(&mut from_iso, lba);
+            assert_eq!(data, vec![lba; 0x800]);
+            assert_eq!((data, raw), read_both_sizes(&mut from_rip, lba));
+        }
+    }
+
+    // Steps in small increments until the next interrupt, which is acknowledged
+    fn cycles_until_interrupt(cdrom: &mut CdRom) -> (u8, u32) {
+        cdrom.write(0, 1);
+        for cycles in (100..40_000_000).step_by(100) {
+            cdrom.step(100);
+            let interrupt = cdrom.read(3) & 7;
+            if interrupt != 0 {
+                cdrom.write(3, 0x1F);
+                return (interrupt, cycles);
+            }
+        }
+
+        panic!("No interrupt");
+    }
+
+    #[test]
+    fn sectors_arrive_at_the_speed_of_the_disc() {
+        for (mode, interval) in [(0, SECTOR_DELAY), (MODE_DOUBLE_SPEED, SECTOR_DELAY / 2)] {
+            let mut cdrom = CdRom::new();
+            cdrom.insert_disc(Some(numbered_disc("speed")));
+            assert_eq!(command_with(&mut cdrom, 0x0E, &[mode]).0, INT3);
+            assert_eq!(command_with(&mut cdrom, 0x02, &[0x00, 0x02, 0x05]).0, INT3);
+            cdrom.write(0, 0);
+            cdrom.write(1, 0x06);
+            assert_eq!(cycles_until_interrupt(&mut cdrom).0, INT3);
+
+            // The first sector waits for the seek, 5 sectors away
+            let (interrupt, cycles) = cycles_until_interrupt(&mut cdrom);
+            assert_eq!(interrupt, INT1);
+            assert!(
+                cycles >= SEEK_DELAY + 5 * SEEK_DELAY_PER_SECTOR,
+                "{}",
+                cycles
+            );
+            for _ in 0..3 {
+                let (interrupt, cycles) = cycles_until_interrupt(&mut cdrom);
+                assert_eq!(interrupt, INT1);
+                assert!(cycles.abs_diff(interval) <= 100, "{} cycles", cycles);
+            }
+        }
+    }
+
+    #[test]
+    fn seeks_take_longer_the_further_they_go() {
+        let seek = |timing, lba: u8| {
+            let mut cdrom = CdRom::new();
+            cdrom.set_timing(timing);
+            cdrom.insert_disc(Some(numbered_disc("seek")));
+            assert_eq!(command_with(&mut cdrom, 0x02, &[0x00, 0x02, lba]).0, INT3);
+            cdrom.write(0, 0);
+            cdrom.write(1, 0x15);
+            assert_eq!(cycles_until_interrupt(&mut cdrom).0, INT3);
+            let (interrupt, cycles) = cycles_until_interrupt(&mut cdrom);
+            assert_eq!(interrupt, INT2);
+            cycles
+        };
+
+        // 0x70 is BCD, LBA 70
+        let near = seek(CdTiming::Accurate, 0x01);
+        let far = seek(CdTiming::Accurate, 0x70);
+        assert!(near >= SEEK_DELAY, "{}", near);
+        assert!(
+            far - near >= 69 * SEEK_DELAY_PER_SECTOR - 100,
+            "{} {}",
+            near,
+            far
+        );
+        assert!(seek(CdTiming::Fast, 0x70) <= FAST_DELAY + 100);
+    }
+
+    #[test]
+    fn getlocp_skips_sectors_with_a_bad_subchannel_crc() {
+        let mut cdrom = CdRom::new();
+        let mut disc = test_disc("libcrypt");
+        let sbi = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/sbi/libcrypt.sbi");
+        assert_eq!(disc.load_subchannel(sbi).unwrap(), 2);
+        cdrom.insert_disc(Some(disc));
+
+        let mut getlocp = |lba: u8| {
+            assert_eq!(
+                command_with(&mut cdrom, 0x02, &[0x00, 0x02, to_bcd(lba)]).0,
+                INT3
+            );
+            assert_eq!(command(&mut cdrom, 0x15).0, INT3);
+            assert_eq!(next_response(&mut cdrom).0, INT2);
+            command(&mut cdrom, 0x11)
+        };
+
+        // The LibCrypt sector reports the position of the one before
+        assert_eq!(
+            getlocp(10),
+            (INT3, vec![0x01, 0x01, 0x00, 0x00, 0x09, 0x00, 0x02, 0x09])
+        );
+        assert_eq!(
+            getlocp(11),
+            (INT3, vec![0x01, 0x01, 0x00, 0x00, 0x11, 0x00, 0x02, 0x11])
+        );
+    }
+}
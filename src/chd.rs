@@ -0,0 +1,353 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use ::chd::{
+    metadata::{KnownMetadata, Metadata},
+    Chd,
+};
+
+use crate::disc::{is_subchannel_q_valid, DiscError, TrackKind, SECTOR_SIZE};
+
+// MAME compressed CD images. The sectors are stored as frames with 96 bytes of subchannel data
+// after them, several frames to a hunk.
+
+pub const FRAME_SIZE: usize = SECTOR_SIZE + 96;
+// Every track starts on a multiple of 4 frames
+const TRACK_PADDING: u32 = 4;
+
+// A track as described by the CHT2 (or the older CHTR) metadata
+pub struct ChdTrack {
+    pub number: u8,
+    pub kind: TrackKind,
+    // Frames stored in the image, including the pregap when it is stored
+    pub frames: u32,
+    pub pregap: u32,
+    pub pregap_stored: bool,
+    pub has_subchannel: bool,
+    // Frame in the image the track starts at
+    first_frame: u32,
+}
+
+pub struct ChdImage {
+    path: PathBuf,
+    chd: Chd<BufReader<File>>,
+    tracks: Vec<ChdTrack>,
+    frames_per_hunk: u32,
+    // The last decompressed hunk, reads are mostly sequential
+    hunk: Vec<u8>,
+    hunk_number: Option<u32>,
+    compressed: Vec<u8>,
+}
+
+impl ChdImage {
+    pub fn open(path: &Path) -> Result<Self, DiscError> {
+        let error = |message: String| DiscError::Chd {
+            path: path.to_path_buf(),
+            message,
+        };
+
+        let file = File::open(path).map_err(|error| DiscError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        let mut chd = Chd::open(BufReader::new(file), None).map_err(|e| error(e.to_string()))?;
+
+        let hunk_size = chd.header().hunk_size();
+        if !(hunk_size as usize).is_multiple_of(FRAME_SIZE) {
+            return Err(error("Not a CD image".to_string()));
+        }
+
+        let metadata: Vec<Metadata> = chd
+            .metadata_refs()
+            .try_into()
+            .map_err(|e: ::chd::Error| error(e.to_string()))?;
+
+        let mut tracks = Vec::new();
+        let mut first_frame = 0;
+        for entry in metadata {
+            let tag = entry.metatag;
+            if tag != KnownMetadata::CdRomTrack2 as u32 && tag != KnownMetadata::CdRomTrack as u32 {
+                continue;
+            }
+
+            let text = String::from_utf8_lossy(&entry.value);
+            let mut track = parse_track(text.trim_end_matches('\0'), first_frame).map_err(error)?;
+            let padded_frames = track.frames.div_ceil(TRACK_PADDING) * TRACK_PADDING;
+
+            // The pregap of the first track is the lead-in, skip it when it is stored
+            if tracks.is_empty() && track.pregap_stored {
+                track.first_frame += track.pregap;
+                track.frames = track.frames.saturating_sub(track.pregap);
+                track.pregap_stored = false;
+            }
+
+            first_frame += padded_frames;
+            tracks.push(track);
+        }
+
+        if tracks.is_empty() {
+            return Err(DiscError::NoTracks);
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            hunk: chd.get_hunksized_buffer(),
+            chd,
+            tracks,
+            frames_per_hunk: hunk_size / FRAME_SIZE as u32,
+            hunk_number: None,
+            compressed: Vec::new(),
+        })
+    }
+
+    pub fn tracks(&self) -> &[ChdTrack] {
+        &self.tracks
+    }
+
+    // A frame of the track, counted from the first stored one. Audio is stored big endian.
+    pub fn read_frame(&mut self, track: usize, frame: u32) -> Result<[u8; FRAME_SIZE], DiscError> {
+        let track = &self.tracks[track];
+        let frame = track.first_frame + frame;
+        let swap = track.kind == TrackKind::Audio;
+
+        let hunk_number = frame / self.frames_per_hunk;
+        if self.hunk_number != Some(hunk_number) {
+            self.hunk_number = None;
+            self.chd
+                .hunk(hunk_number)
+                .and_then(|mut hunk| hunk.read_hunk_in(&mut self.compressed, &mut self.hunk))
+                .map_err(|error| DiscError::Chd {
+                    path: self.path.clone(),
+                    message: error.to_string(),
+                })?;
+            self.hunk_number = Some(hunk_number);
+        }
+
+        let offset = (frame % self.frames_per_hunk) as usize * FRAME_SIZE;
+        let mut data: [u8; FRAME_SIZE] = self.hunk[offset..offset + FRAME_SIZE].try_into().unwrap();
+        if swap {
+            for sample in data[..SECTOR_SIZE].chunks_exact_mut(2) {
+                sample.swap(0, 1);
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+// "TRACK:1 TYPE:MODE2_RAW SUBTYPE:NONE FRAMES:1234 PREGAP:0 PGTYPE:MODE2_RAW PGSUB:NONE POSTGAP:0",
+// a V in front of the pregap type means the pregap is stored
+fn parse_track(text: &str, first_frame: u32) -> Result<ChdTrack, String> {
+    let field = |name: &str| {
+        text.split_whitespace()
+            .find_map(|pair| pair.strip_prefix(name)?.strip_prefix(':'))
+    };
+    let number = |name: &str| -> Result<u32, String> {
+        field(name)
+            .unwrap_or("0")
+            .parse()
+            .map_err(|_| format!("Invalid {} in '{}'", name, text))
+    };
+
+    let kind = match field("TYPE") {
+        Some("MODE1_RAW") => TrackKind::Mode1,
+        Some("MODE2_RAW") => TrackKind::Mode2,
+        Some("AUDIO") => TrackKind::Audio,
+        kind => return Err(format!("Unsupported track type {:?}", kind)),
+    };
+
+    Ok(ChdTrack {
+        number: number("TRACK")? as u8,
+        kind,
+        frames: number("FRAMES")?,
+        pregap: number("PREGAP")?,
+        pregap_stored: field("PGTYPE").is_some_and(|kind| kind.starts_with('V')),
+        has_subchannel: field("SUBTYPE").is_some_and(|kind| kind != "NONE"),
+        first_frame,
+    })
+}
+
+// The Q channel of the subchannel data, packed as bit 6 of the 96 bytes or already separated
+// into the second 12 bytes. None when neither has a valid CRC.
+pub fn subchannel_q(subchannel: &[u8]) -> Option<[u8; 12]> {
+    let mut packed = [0; 12];
+    for (i, byte) in subchannel.iter().enumerate() {
+        packed[i / 8] |= ((byte >> 6) & 1) << (7 - i % 8);
+    }
+    let separated: [u8; 12] = subchannel[12..24].try_into().unwrap();
+
+    [packed, separated].into_iter().find(is_subchannel_q_valid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disc::{Disc, Msf, SYNC};
+
+    const FRAMES_PER_HUNK: usize = 8;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("psx-rust-{}-{}", std::process::id(), name))
+    }
+
+    // Every byte of a sector is its LBA, data sectors also get a sync pattern and header
+    fn sector(lba: u32, data: bool) -> Vec<u8> {
+        let mut sector = vec![lba as u8; SECTOR_SIZE];
+        if data {
+            sector[..SYNC.len()].copy_from_slice(&SYNC);
+            sector[12..15].copy_from_slice(&Msf::from_lba(lba).to_bcd());
+            sector[15] = 2;
+        }
+        sector
+    }
+
+    // Q channel with a valid CRC, packed into bit 6 of every subchannel byte as chdman stores it
+    fn subchannel(lba: u32) -> ([u8; 12], Vec<u8>) {
+        let mut q = [
+            0x01, 0x01, 0x01, 0, 0, lba as u8, 0, 0x00, 0x02, lba as u8, 0, 0,
+        ];
+        let crc = q[..10].iter().fold(0u16, |crc, &byte| {
+            (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+                (crc << 1) ^ if crc & 0x8000 != 0 { 0x1021 } else { 0 }
+            })
+        });
+        q[10..].copy_from_slice(&(!crc).to_be_bytes());
+
+        let packed = (0..96)
+            .map(|i| ((q[i / 8] >> (7 - i % 8)) & 1) << 6)
+            .collect();
+        (q, packed)
+    }
+
+    // An uncompressed V5 CHD holding the frames with the given CHT2 track metadata
+    fn write_chd(path: &Path, frames: &[Vec<u8>], tracks: &[String]) {
+        let hunk_bytes = FRAME_SIZE * FRAMES_PER_HUNK;
+        let hunks = frames.len().div_ceil(FRAMES_PER_HUNK);
+        let map_offset = 124;
+        // Hunks are addressed in units of the hunk size, the first one after the map
+        let data_offset = hunk_bytes;
+        let meta_offset = data_offset + hunks * hunk_bytes;
+
+        let mut image = vec![0; meta_offset];
+        image[..8].copy_from_slice(b"MComprHD");
+        image[8..12].copy_from_slice(&124u32.to_be_bytes());
+        image[12..16].copy_from_slice(&5u32.to_be_bytes());
+        image[32..40].copy_from_slice(&((hunks * hunk_bytes) as u64).to_be_bytes());
+        image[40..48].copy_from_slice(&(map_offset as u64).to_be_bytes());
+        image[48..56].copy_from_slice(&(meta_offset as u64).to_be_bytes());
+        image[56..60].copy_from_slice(&(hunk_bytes as u32).to_be_bytes());
+        image[60..64].copy_from_slice(&(FRAME_SIZE as u32).to_be_bytes());
+        for hunk in 0..hunks {
+            let entry = map_offset + hunk * 4;
+            image[entry..entry + 4].copy_from_slice(&(1 + hunk as u32).to_be_bytes());
+        }
+        for (i, frame) in frames.iter().enumerate() {
+            let offset = data_offset + i * FRAME_SIZE;
+            image[offset..offset + FRAME_SIZE].copy_from_slice(frame);
+        }
+
+        for (i, track) in tracks.iter().enumerate() {
+            let mut value = track.clone().into_bytes();
+            value.push(0);
+            let next = if i + 1 < tracks.len() {
+                image.len() + 16 + value.len()
+            } else {
+                0
+            };
+            image.extend_from_slice(b"CHT2");
+            image.extend_from_slice(&(1 << 24 | value.len() as u32).to_be_bytes());
+            image.extend_from_slice(&(next as u64).to_be_bytes());
+            image.extend_from_slice(&value);
+        }
+
+        std::fs::write(path, image).unwrap();
+    }
+
+    #[test]
+    fn chd_reads_like_the_cue_it_was_made_from() {
+        // An 8 sector data track and an audio track with a 2 sector pregap
+        let sectors: Vec<Vec<u8>> = (0..16).map(|lba| sector(lba, lba < 8)).collect();
+
+        let bin = temp_path("chd.bin");
+        let cue = temp_path("chd.cue");
+        std::fs::write(&bin, sectors.concat()).unwrap();
+        let sheet = format!(
+            "FILE \"{}\" BINARY\n  TRACK 01 MODE2/2352\n    INDEX 01 00:00:00\n  TRACK 02 AUDIO\n    INDEX 00 00:00:08\n    INDEX 01 00:00:10\n",
+            bin.file_name().unwrap().to_str().unwrap()
+        );
+        std::fs::write(&cue, sheet).unwrap();
+
+        // chdman stores audio big endian and the pregap of the audio track with it
+        let frames: Vec<Vec<u8>> = sectors
+            .iter()
+            .enumerate()
+            .map(|(lba, sector)| {
+                let mut frame = sector.clone();
+                if lba >= 8 {
+                    for sample in frame.chunks_exact_mut(2) {
+                        sample.swap(0, 1);
+                    }
+                }
+                frame.extend(subchannel(lba as u32).1);
+                frame
+            })
+            .collect();
+        let chd = temp_path("chd.chd");
+        write_chd(
+            &chd,
+            &frames,
+            &[
+                "TRACK:1 TYPE:MODE2_RAW SUBTYPE:RW_RAW FRAMES:8 PREGAP:0 PGTYPE:MODE2_RAW PGSUB:RW_RAW POSTGAP:0".to_string(),
+                "TRACK:2 TYPE:AUDIO SUBTYPE:RW_RAW FRAMES:8 PREGAP:2 PGTYPE:VAUDIO PGSUB:RW_RAW POSTGAP:0".to_string(),
+            ],
+        );
+
+        let mut from_cue = Disc::open(&cue).unwrap();
+        let mut from_chd = Disc::open(&chd).unwrap();
+        for path in [&bin, &cue, &chd] {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(from_chd.track_numbers(), (1, 2));
+        assert_eq!(from_chd.lead_out(), from_cue.lead_out());
+        let track = from_chd.track(2).unwrap();
+        assert_eq!((track.pregap_start, track.start, track.end), (8, 10, 16));
+
+        for lba in 0..16 {
+            let msf = Msf::from_lba(lba);
+            let sector = from_chd.read_sector(msf).unwrap();
+            assert_eq!(
+                sector[..],
+                from_cue.read_sector(msf).unwrap()[..],
+                "LBA {}",
+                lba
+            );
+            assert_eq!(sector[..], sectors[lba as usize][..]);
+            assert_eq!(from_chd.locate(msf), from_cue.locate(msf));
+            assert_eq!(from_chd.read_subchannel_q(msf), Some(subchannel(lba).0));
+            assert_eq!(from_cue.read_subchannel_q(msf), None);
+        }
+    }
+
+    #[test]
+    fn track_metadata_is_parsed() {
+        let track = parse_track(
+            "TRACK:2 TYPE:AUDIO SUBTYPE:NONE FRAMES:1500 PREGAP:150 PGTYPE:VAUDIO PGSUB:NONE POSTGAP:0",
+            4,
+        )
+        .unwrap();
+        assert_eq!((track.number, track.kind), (2, TrackKind::Audio));
+        assert_eq!(
+            (track.frames, track.pregap, track.first_frame),
+            (1500, 150, 4)
+        );
+        assert!(track.pregap_stored);
+        assert!(!track.has_subchannel);
+
+        assert!(parse_track("TRACK:1 TYPE:MODE1/2048 FRAMES:1", 0).is_err());
+        assert!(parse_track("TRACK:1 TYPE:MODE1_RAW FRAMES:many", 0).is_err());
+    }
+}
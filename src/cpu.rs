@@ -1,8 +1,10 @@
-use crate::mmu::MMU;
+use std::fmt;
+
+use crate::{error::EmuError, gte::Gte, mmu::MMU};
 
 #[derive(Clone, Copy)]
 struct InstructionCacheLine {
-    valid: usize, // The index of the first valid word
+    valid: u8, // Bitmask of the valid words in the line
     tag: u32,
     data: [u32; 4], // Four words each cache line
 }
@@ -10,11 +12,51 @@ struct InstructionCacheLine {
 impl InstructionCacheLine {
     pub fn new() -> Self {
         Self {
-            valid: 0xFFFF,
+            valid: 0,
             tag: 0,
             data: [0; 4],
         }
     }
+
+    pub fn is_valid(&self, tag: u32, index: usize) -> bool {
+        self.tag == tag && self.valid & (1 << index) != 0
+    }
+}
+
+// Counters describing how well the instruction cache is doing
+#[derive(Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub refill_words: u64, // Words fetched from memory to refill cache lines
+    pub uncached_fetches: u64, // Fetches bypassing the cache (KSEG1 or cache disabled)
+    pub isolated_stores: u64, // Stores redirected to the cache while it is isolated
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            return 0.0;
+        }
+
+        (self.hits as f64 / total as f64) * 100.0
+    }
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "I-cache: {} hits, {} misses ({:.2}% hit rate), {} refill words, {} uncached fetches, {} isolated stores",
+            self.hits,
+            self.misses,
+            self.hit_rate(),
+            self.refill_words,
+            self.uncached_fetches,
+            self.isolated_stores
+        )
+    }
 }
 
 pub struct CPU {
@@ -27,12 +69,22 @@ pub struct CPU {
     lo: u32,         // Registers used for mult and div results
     mmu: MMU,
     cop0: Coprocessor,
+    gte: Gte,
+    cycles: u64,           // Cycles run so far
+    gte_busy_until: u64,   // The cycle the running GTE command finishes on
+    gte_stall: u32,        // Cycles the current instruction waited for the GTE
     next_load: (u32, u32), // Temporarily store loaded values between instruction execution
+    branch: bool, // Set when the current instruction branches, making the next one a delay slot
+    delay_slot: bool, // Set when the current instruction is in a branch delay slot
     instruction_cache: [InstructionCacheLine; 256],
+    cache_stats: CacheStats,
 }
 
 const START_PC: u32 = 0xBFC00000;
 
+// Value of the COP0 PRId register
+const PROCESSOR_ID: u32 = 0x00000002;
+
 impl CPU {
     pub fn new(mmu: MMU) -> Self {
         Self {
@@ -44,12 +96,78 @@ impl CPU {
             lo: 0,
             mmu,
             cop0: Coprocessor::new(),
+            gte: Gte::new(),
+            cycles: 0,
+            gte_busy_until: 0,
+            gte_stall: 0,
             next_load: (0, 0),
+            branch: false,
+            delay_slot: false,
             instruction_cache: [InstructionCacheLine::new(); 256],
+            cache_stats: CacheStats::default(),
         }
     }
 
-    fn load_instruction(&self) -> Instruction {
+    pub fn pc(&self) -> u32 {
+        self.pc
+    }
+
+    // Jumps to the given address, discarding any pending branch
+    pub fn set_pc(&mut self, pc: u32) {
+        self.pc = pc;
+        self.next_pc = pc.wrapping_add(4);
+        self.branch = false;
+    }
+
+    // Whether the instruction at pc runs in the delay slot of a branch
+    pub fn is_delay_slot(&self) -> bool {
+        self.branch
+    }
+
+    pub fn registers(&self) -> &[u32; 32] {
+        &self.registers
+    }
+
+    pub fn register(&self, index: usize) -> u32 {
+        self.registers[index]
+    }
+
+    pub fn set_register(&mut self, index: usize, value: u32) {
+        if index != 0 {
+            self.registers[index] = value;
+        }
+    }
+
+    // COP0 SR, with the interrupt enable and mask bits
+    pub fn status(&self) -> u32 {
+        self.cop0.status
+    }
+
+    pub fn hi(&self) -> u32 {
+        self.hi
+    }
+
+    pub fn lo(&self) -> u32 {
+        self.lo
+    }
+
+    pub fn mmu(&self) -> &MMU {
+        &self.mmu
+    }
+
+    pub fn mmu_mut(&mut self) -> &mut MMU {
+        &mut self.mmu
+    }
+
+    pub fn cache_stats(&self) -> &CacheStats {
+        &self.cache_stats
+    }
+
+    pub fn reset_cache_stats(&mut self) {
+        self.cache_stats = CacheStats::default();
+    }
+
+    fn load_instruction(&mut self) -> Result<Instruction, EmuError> {
         // TODO: If the instruction cache is used one step != one cycle
         if self.mmu.is_instruction_cache_enabled() && self.pc < 0xa0000000 {
             // Cache tag is bit 12..30
@@ -61,44 +179,119 @@ impl CPU {
             // Line is bit 2..3
             let index = ((self.pc >> 2) & 3) as usize;
 
-            let mut line = self.instruction_cache[line];
+            let line = &mut self.instruction_cache[line];
 
             // Refetch instruction if cache is invalid
-            if (tag != line.tag) || (line.valid > index) || (line.valid > 4) {
+            if !line.is_valid(tag, index) {
+                // The words of a line belonging to another tag are no longer valid
+                if line.tag != tag {
+                    line.valid = 0;
+                }
+
+                // The line is refilled from the fetched word until the end of the line
                 let mut address = self.pc;
                 for i in index..4 {
-                    let instruction = self.mmu.read(address, 4);
+                    let instruction = self.mmu.read(address, 4)?;
                     line.data[i] = instruction;
 
                     address += 4;
                 }
 
                 line.tag = tag;
-                line.valid = index;
+                line.valid |= (0xF << index) & 0xF;
+
+                self.cache_stats.misses += 1;
+                self.cache_stats.refill_words += (4 - index) as u64;
+            } else {
+                self.cache_stats.hits += 1;
             }
 
-            return Instruction(line.data[index]);
+            return Ok(Instruction(line.data[index]));
         }
 
-        let word = self.mmu.read(self.pc, 4);
+        self.cache_stats.uncached_fetches += 1;
+
+        let word = self.mmu.read(self.pc, 4)?;
 
-        Instruction(word)
+        Ok(Instruction(word))
     }
 
-    pub fn step(&mut self) {
-        let instruction = self.load_instruction();
+    // Returns the number of cycles the instruction took
+    pub fn step(&mut self) -> Result<u32, EmuError> {
+        // Cause bit 10 reflects the live state of the interrupt line
+        self.cop0
+            .set_interrupt_pending(self.mmu.pending_interrupts());
+
+        if self.cop0.should_interrupt() {
+            // The interrupted instruction hasn't executed yet and runs again once the handler returns
+            self.current_pc = self.pc;
+            self.delay_slot = self.branch;
+
+            self.finish_load();
+            self.trigger_exception(Exception::Interrupt);
+
+            return Ok(self.finish_step());
+        }
+
+        let instruction = self.load_instruction()?;
+
+        if self.mmu.take_bus_error() {
+            self.current_pc = self.pc;
+            self.delay_slot = self.branch;
+
+            self.finish_load();
+            self.trigger_exception(Exception::InstructionBusError);
+
+            return Ok(self.finish_step());
+        }
 
         self.current_pc = self.pc;
         self.pc = self.next_pc;
         self.next_pc = self.next_pc.wrapping_add(4);
 
-        self.execute(instruction);
+        self.delay_slot = self.branch;
+        self.branch = false;
+
+        if self.cop0.is_code_breakpoint(self.current_pc) {
+            self.trigger_breakpoint();
+        } else {
+            self.execute(instruction)?;
+        }
 
-        // Each instruction takes one cycle
-        self.mmu.step(1);
+        // The target of the last taken branch or jump
+        if self.branch {
+            self.cop0.jump_dest = self.next_pc;
+        }
+
+        if self.mmu.take_bus_error() {
+            // Discard whatever the faulting load was going to deliver
+            self.next_load = (0, 0);
+            self.trigger_exception(Exception::DataBusError);
+        }
+
+        // R0 is hardwired to zero, any writes to it (including delayed loads) are discarded
+        self.registers[0] = 0;
+
+        Ok(self.finish_step())
+    }
+
+    // Lets time pass without running instructions, for skipping idle loops
+    pub fn skip_cycles(&mut self, cycles: u32) {
+        self.mmu.step(cycles);
+        self.cycles += cycles as u64;
     }
 
-    fn execute(&mut self, instruction: Instruction) {
+    // Each instruction takes one cycle, plus the stalls of the memory accesses it made and the
+    // wait for the GTE
+    fn finish_step(&mut self) -> u32 {
+        let cycles = 1 + self.mmu.take_access_cycles() + std::mem::take(&mut self.gte_stall);
+        self.mmu.step(cycles);
+        self.cycles += cycles as u64;
+
+        cycles
+    }
+
+    fn execute(&mut self, instruction: Instruction) -> Result<(), EmuError> {
         let opcode = instruction.opcode();
 
         let secondary_opcode = instruction.secondary_opcode();
@@ -127,7 +320,7 @@ impl CPU {
 
                     self.finish_load();
 
-                    self.registers[d] = value as u32;
+                    self.registers[d] = value;
                 }
                 0b000011 => {
                     // SRA
@@ -184,6 +377,7 @@ impl CPU {
                     self.finish_load();
 
                     self.next_pc = next;
+                    self.branch = true;
                 }
                 0b001001 => {
                     // JALR
@@ -193,6 +387,7 @@ impl CPU {
                     let return_address = self.next_pc;
 
                     self.next_pc = self.registers[s];
+                    self.branch = true;
 
                     self.finish_load();
 
@@ -205,7 +400,10 @@ impl CPU {
                     self.trigger_exception(Exception::SysCall);
                 }
                 0b001101 => {
-                    panic!("BREAK")
+                    // BREAK
+                    self.finish_load();
+
+                    self.trigger_exception(Exception::Break);
                 }
                 0b010000 => {
                     // MFHI
@@ -240,10 +438,34 @@ impl CPU {
                     self.finish_load();
                 }
                 0b011000 => {
-                    panic!("MULT")
+                    // MULT
+                    let s = instruction.s() as usize;
+                    let t = instruction.t() as usize;
+
+                    let a = (self.registers[s] as i32) as i64;
+                    let b = (self.registers[t] as i32) as i64;
+
+                    self.finish_load();
+
+                    let value = (a * b) as u64;
+
+                    self.hi = (value >> 32) as u32;
+                    self.lo = value as u32;
                 }
                 0b011001 => {
-                    panic!("MULTU")
+                    // MULTU
+                    let s = instruction.s() as usize;
+                    let t = instruction.t() as usize;
+
+                    let a = self.registers[s] as u64;
+                    let b = self.registers[t] as u64;
+
+                    self.finish_load();
+
+                    let value = a * b;
+
+                    self.hi = (value >> 32) as u32;
+                    self.lo = value as u32;
                 }
                 0b011010 => {
                     // DIV
@@ -255,16 +477,18 @@ impl CPU {
 
                     self.finish_load();
 
-                    // TODO: Handle these cases
                     if denominator == 0 {
-                        panic!("Division by zero");
-                    } else if denominator == -1 && numerator as u32 == (i32::MIN as u32) {
-                        panic!("Division by -1");
+                        // Division by zero doesn't trap, the results are well defined
+                        self.hi = numerator as u32;
+                        self.lo = if numerator >= 0 { 0xFFFFFFFF } else { 1 };
+                    } else if denominator == -1 && numerator == i32::MIN {
+                        // The result doesn't fit in 32 bits
+                        self.hi = 0;
+                        self.lo = i32::MIN as u32;
+                    } else {
+                        self.hi = (numerator % denominator) as u32;
+                        self.lo = (numerator / denominator) as u32;
                     }
-
-                    // Default case
-                    self.hi = (numerator % denominator) as u32;
-                    self.lo = (numerator / denominator) as u32;
                 }
                 0b011011 => {
                     // DIVU
@@ -276,14 +500,14 @@ impl CPU {
 
                     self.finish_load();
 
-                    // TODO: Handle this case
                     if denominator == 0 {
-                        panic!("Division by zero");
+                        // Division by zero doesn't trap, the results are well defined
+                        self.hi = numerator;
+                        self.lo = 0xFFFFFFFF;
+                    } else {
+                        self.hi = numerator % denominator;
+                        self.lo = numerator / denominator;
                     }
-
-                    // Default case
-                    self.hi = numerator % denominator;
-                    self.lo = numerator / denominator;
                 }
                 0b100000 => {
                     // ADD
@@ -370,7 +594,16 @@ impl CPU {
                     self.registers[d] = value;
                 }
                 0b100110 => {
-                    panic!("XOR")
+                    // XOR
+                    let s = instruction.s() as usize;
+                    let t = instruction.t() as usize;
+                    let d = instruction.d() as usize;
+
+                    let value = self.registers[s] ^ self.registers[t];
+
+                    self.finish_load();
+
+                    self.registers[d] = value;
                 }
                 0b100111 => {
                     // NOR
@@ -417,50 +650,42 @@ impl CPU {
                     self.registers[d] = value;
                 }
                 _ => {
-                    panic!("Invalid instruction")
+                    self.finish_load();
+
+                    self.trigger_illegal_instruction(instruction);
                 }
             },
             0b000001 => {
-                match instruction.t() {
-                    0b00000 => {
-                        // BLTZ
-                        let s = instruction.s() as usize;
+                // BLTZ, BGEZ, BLTZAL, BGEZAL
+                let s = instruction.s() as usize;
+                let t = instruction.t();
 
-                        let value = (self.registers[s] as i32) < 0;
+                // Bit 16 selects BGEZ over BLTZ, the link variants are only decoded when bits 17..20 are 0x8
+                let is_bgez = t & 1 != 0;
+                let is_link = t & 0x1E == 0x10;
 
-                        if value {
-                            let immediate = instruction.immediate_sign_extended();
-                            self.next_pc = self.pc.wrapping_add(immediate << 2);
-                        }
+                let value = self.registers[s] as i32;
+                let condition = if is_bgez { value >= 0 } else { value < 0 };
 
-                        self.finish_load();
-                    }
-                    0b00001 => {
-                        // BGEZ
-                        let s = instruction.s() as usize;
+                let return_address = self.next_pc;
 
-                        let value = (self.registers[s] as i32) >= 0;
+                if condition {
+                    let immediate = instruction.immediate_sign_extended();
+                    self.branch(immediate);
+                }
 
-                        if value {
-                            let immediate = instruction.immediate_sign_extended();
-                            self.next_pc = self.pc.wrapping_add(immediate << 2);
-                        }
+                self.finish_load();
 
-                        self.finish_load();
-                    }
-                    0b10000 => {
-                        panic!("BLTZAL");
-                    }
-                    0b10001 => {
-                        panic!("BGEZAL");
-                    }
-                    t => panic!("Unsupported branching instruction: {}", t),
+                // The return address is stored even if the branch isn't taken
+                if is_link {
+                    self.registers[31] = return_address;
                 }
             }
             0b000010 => {
                 // J
                 let jump = instruction.immediate_jump();
                 self.next_pc = (self.pc & 0xF0000000) | jump;
+                self.branch = true;
 
                 self.finish_load();
             }
@@ -470,6 +695,7 @@ impl CPU {
 
                 let jump = instruction.immediate_jump();
                 self.next_pc = (self.pc & 0xF0000000) | jump;
+                self.branch = true;
 
                 self.finish_load();
 
@@ -484,7 +710,7 @@ impl CPU {
 
                 if value {
                     let immediate = instruction.immediate_sign_extended();
-                    self.next_pc = self.pc.wrapping_add(immediate << 2);
+                    self.branch(immediate);
                 }
 
                 self.finish_load();
@@ -498,7 +724,7 @@ impl CPU {
 
                 if value {
                     let immediate = instruction.immediate_sign_extended();
-                    self.next_pc = self.pc.wrapping_add(immediate << 2);
+                    self.branch(immediate);
                 }
 
                 self.finish_load();
@@ -511,7 +737,7 @@ impl CPU {
 
                 if value {
                     let immediate = instruction.immediate_sign_extended();
-                    self.next_pc = self.pc.wrapping_add(immediate << 2);
+                    self.branch(immediate);
                 }
 
                 self.finish_load();
@@ -524,7 +750,7 @@ impl CPU {
 
                 if value {
                     let immediate = instruction.immediate_sign_extended();
-                    self.next_pc = self.pc.wrapping_add(immediate << 2);
+                    self.branch(immediate);
                 }
 
                 self.finish_load();
@@ -609,7 +835,16 @@ impl CPU {
                 self.registers[t] = value;
             }
             0b001110 => {
-                panic!("XORI")
+                // XORI
+                let immediate = instruction.immediate();
+                let s = instruction.s() as usize;
+                let t = instruction.t() as usize;
+
+                let value = self.registers[s] ^ immediate;
+
+                self.finish_load();
+
+                self.registers[t] = value;
             }
             0b001111 => {
                 // LUI
@@ -632,8 +867,26 @@ impl CPU {
                         let cop0_r = instruction.d() as usize;
 
                         match cop0_r {
-                            3 | 5 | 6 | 7 | 9 | 11 => {
-                                // No-op, ignoring breakpoints for now
+                            3 => {
+                                self.setup_load(r as u32, self.cop0.bpc);
+                            }
+                            5 => {
+                                self.setup_load(r as u32, self.cop0.bda);
+                            }
+                            6 => {
+                                self.setup_load(r as u32, self.cop0.jump_dest);
+                            }
+                            7 => {
+                                self.setup_load(r as u32, self.cop0.dcic);
+                            }
+                            9 => {
+                                self.setup_load(r as u32, self.cop0.bdam);
+                            }
+                            11 => {
+                                self.setup_load(r as u32, self.cop0.bpcm);
+                            }
+                            8 => {
+                                self.setup_load(r as u32, self.cop0.bad_vaddr);
                             }
                             12 => {
                                 self.setup_load(r as u32, self.cop0.status);
@@ -644,7 +897,10 @@ impl CPU {
                             14 => {
                                 self.setup_load(r as u32, self.cop0.epc);
                             }
-                            _ => panic!("Unsupported COP0 register {}", cop0_r),
+                            15 => {
+                                self.setup_load(r as u32, PROCESSOR_ID);
+                            }
+                            _ => return Err(self.unhandled_instruction(instruction)),
                         }
                     }
                     0b00100 => {
@@ -657,8 +913,20 @@ impl CPU {
                         self.finish_load();
 
                         match cop0_r {
-                            3 | 5 | 6 | 7 | 9 | 11 => {
-                                // No-op, ignoring breakpoints for now
+                            3 => {
+                                self.cop0.bpc = value;
+                            }
+                            5 => {
+                                self.cop0.bda = value;
+                            }
+                            7 => {
+                                self.cop0.dcic = value;
+                            }
+                            9 => {
+                                self.cop0.bdam = value;
+                            }
+                            11 => {
+                                self.cop0.bpcm = value;
                             }
                             12 => {
                                 self.cop0.status = value;
@@ -666,7 +934,10 @@ impl CPU {
                             13 => {
                                 self.cop0.cause = (self.cop0.cause & !0x300) | (value & 0x300);
                             }
-                            _ => panic!("Unsupported COP0 register {}", cop0_r),
+                            6 | 8 | 14 | 15 => {
+                                // Read-only registers, writes are ignored
+                            }
+                            _ => return Err(self.unhandled_instruction(instruction)),
                         }
                     }
                     0b10000 => {
@@ -675,19 +946,68 @@ impl CPU {
                         let mode = self.cop0.status & 0x3F;
                         self.cop0.status = (self.cop0.status & !0xF) | (mode >> 2);
                     }
-                    o => {
-                        panic!("Unhandled coprocessor opcode {}", o);
-                    }
+                    _ => return Err(self.unhandled_instruction(instruction)),
                 }
             }
             0b010001 => {
-                panic!("COP1")
+                // COP1
+                self.finish_load();
+
+                self.trigger_coprocessor_error(1);
             }
             0b010010 => {
-                panic!("COP2")
+                // COP2
+                if !self.cop0.is_gte_enabled() {
+                    self.finish_load();
+                    self.trigger_coprocessor_error(2);
+                    return Ok(());
+                }
+
+                if instruction.0 & (1 << 25) != 0 {
+                    // A command starts once the previous one is done
+                    self.finish_load();
+                    self.wait_for_gte();
+                    let cycles = self.gte.execute(instruction.0);
+                    self.gte_busy_until = self.cycles + self.gte_stall as u64 + cycles as u64;
+                    return Ok(());
+                }
+
+                let r = instruction.t();
+                let gte_r = instruction.d() as usize;
+
+                match instruction.coprocessor_opcode() {
+                    0b00000 => {
+                        // MFC2
+                        self.wait_for_gte();
+                        self.setup_load(r, self.gte.read_data(gte_r));
+                    }
+                    0b00010 => {
+                        // CFC2
+                        self.wait_for_gte();
+                        self.setup_load(r, self.gte.read_control(gte_r));
+                    }
+                    0b00100 => {
+                        // MTC2
+                        let value = self.registers[r as usize];
+                        self.finish_load();
+                        self.wait_for_gte();
+                        self.gte.write_data(gte_r, value);
+                    }
+                    0b00110 => {
+                        // CTC2
+                        let value = self.registers[r as usize];
+                        self.finish_load();
+                        self.wait_for_gte();
+                        self.gte.write_control(gte_r, value);
+                    }
+                    _ => return Err(self.unhandled_instruction(instruction)),
+                }
             }
             0b010011 => {
-                panic!("COP3")
+                // COP3
+                self.finish_load();
+
+                self.trigger_coprocessor_error(3);
             }
             0b100000 => {
                 // LB
@@ -697,8 +1017,12 @@ impl CPU {
 
                 let address = self.registers[s].wrapping_add(immediate);
 
+                if self.data_breakpoint(address, false) {
+                    return Ok(());
+                }
+
                 // Should be sign-extended
-                let value = self.mmu.read(address, 1) as i8;
+                let value = self.mmu.read(address, 1)? as i8;
                 self.setup_load(t as u32, value as u32);
             }
             0b100001 => {
@@ -709,12 +1033,45 @@ impl CPU {
 
                 let address = self.registers[s].wrapping_add(immediate);
 
+                if self.data_breakpoint(address, false) {
+                    return Ok(());
+                }
+
+                if address & 1 != 0 {
+                    self.finish_load();
+                    self.trigger_address_error(address, Exception::LoadAddressError);
+                    return Ok(());
+                }
+
                 // Should be sign-extended
-                let value = self.mmu.read(address, 2) as i16;
+                let value = self.mmu.read(address, 2)? as i16;
                 self.setup_load(t as u32, value as u32);
             }
             0b100010 => {
-                panic!("LWL")
+                // LWL
+                let immediate = instruction.immediate_sign_extended();
+                let s = instruction.s() as usize;
+                let t = instruction.t() as usize;
+
+                let address = self.registers[s].wrapping_add(immediate);
+
+                if self.data_breakpoint(address, false) {
+                    return Ok(());
+                }
+
+                // Merges with the pending value of the register so LWL/LWR pairs work in the load delay slot
+                let current = self.pending_register(t);
+
+                let word = self.mmu.read(address & !3, 4)?;
+
+                let value = match address & 3 {
+                    0 => (current & 0x00FFFFFF) | (word << 24),
+                    1 => (current & 0x0000FFFF) | (word << 16),
+                    2 => (current & 0x000000FF) | (word << 8),
+                    _ => word,
+                };
+
+                self.setup_load(t as u32, value);
             }
             0b100011 => {
                 // LW
@@ -724,8 +1081,18 @@ impl CPU {
 
                 let address = self.registers[s].wrapping_add(immediate);
 
-                let value = self.mmu.read(address, 4);
-                self.setup_load(t as u32, value as u32);
+                if self.data_breakpoint(address, false) {
+                    return Ok(());
+                }
+
+                if address & 3 != 0 {
+                    self.finish_load();
+                    self.trigger_address_error(address, Exception::LoadAddressError);
+                    return Ok(());
+                }
+
+                let value = self.mmu.read(address, 4)?;
+                self.setup_load(t as u32, value);
             }
             0b100100 => {
                 // LBU
@@ -735,8 +1102,12 @@ impl CPU {
 
                 let address = self.registers[s].wrapping_add(immediate);
 
-                let value = self.mmu.read(address, 1);
-                self.setup_load(t as u32, value as u32);
+                if self.data_breakpoint(address, false) {
+                    return Ok(());
+                }
+
+                let value = self.mmu.read(address, 1)?;
+                self.setup_load(t as u32, value);
             }
             0b100101 => {
                 // LHU
@@ -746,11 +1117,44 @@ impl CPU {
 
                 let address = self.registers[s].wrapping_add(immediate);
 
-                let value = self.mmu.read(address, 2);
-                self.setup_load(t as u32, value as u32);
+                if self.data_breakpoint(address, false) {
+                    return Ok(());
+                }
+
+                if address & 1 != 0 {
+                    self.finish_load();
+                    self.trigger_address_error(address, Exception::LoadAddressError);
+                    return Ok(());
+                }
+
+                let value = self.mmu.read(address, 2)?;
+                self.setup_load(t as u32, value);
             }
             0b100110 => {
-                panic!("LWR")
+                // LWR
+                let immediate = instruction.immediate_sign_extended();
+                let s = instruction.s() as usize;
+                let t = instruction.t() as usize;
+
+                let address = self.registers[s].wrapping_add(immediate);
+
+                if self.data_breakpoint(address, false) {
+                    return Ok(());
+                }
+
+                // Merges with the pending value of the register so LWL/LWR pairs work in the load delay slot
+                let current = self.pending_register(t);
+
+                let word = self.mmu.read(address & !3, 4)?;
+
+                let value = match address & 3 {
+                    0 => word,
+                    1 => (current & 0xFF000000) | (word >> 8),
+                    2 => (current & 0xFFFF0000) | (word >> 16),
+                    _ => (current & 0xFFFFFF00) | (word >> 24),
+                };
+
+                self.setup_load(t as u32, value);
             }
             0b101000 => {
                 // SB
@@ -758,6 +1162,10 @@ impl CPU {
                 let s = instruction.s() as usize;
 
                 let address = self.registers[s].wrapping_add(immediate);
+
+                if self.data_breakpoint(address, true) {
+                    return Ok(());
+                }
                 let t = instruction.t() as usize;
                 let value = self.registers[t];
 
@@ -765,10 +1173,10 @@ impl CPU {
 
                 if self.cop0.is_cache_isolated() {
                     self.store_instruction_cache(address, value);
-                    return;
+                    return Ok(());
                 }
 
-                self.mmu.write(address, 1, value);
+                self.mmu.write(address, 1, value)?;
             }
             0b101001 => {
                 // SH
@@ -776,20 +1184,64 @@ impl CPU {
                 let s = instruction.s() as usize;
 
                 let address = self.registers[s].wrapping_add(immediate);
+
+                if self.data_breakpoint(address, true) {
+                    return Ok(());
+                }
                 let t = instruction.t() as usize;
                 let value = self.registers[t];
 
                 self.finish_load();
 
+                if address & 1 != 0 {
+                    self.trigger_address_error(address, Exception::StoreAddressError);
+                    return Ok(());
+                }
+
                 if self.cop0.is_cache_isolated() {
                     self.store_instruction_cache(address, value);
-                    return;
+                    return Ok(());
                 }
 
-                self.mmu.write(address, 2, value);
+                self.mmu.write(address, 2, value)?;
             }
             0b101010 => {
-                panic!("SWL")
+                // SWL
+                let immediate = instruction.immediate_sign_extended();
+                let s = instruction.s() as usize;
+
+                let address = self.registers[s].wrapping_add(immediate);
+
+                if self.data_breakpoint(address, true) {
+                    return Ok(());
+                }
+                let t = instruction.t() as usize;
+                let value = self.registers[t];
+
+                self.finish_load();
+
+                // With the cache isolated the bytes are merged into the cached word
+                let aligned_address = address & !3;
+                let isolated = self.cop0.is_cache_isolated();
+                let current = if isolated {
+                    self.load_instruction_cache(aligned_address)
+                } else {
+                    self.mmu.read(aligned_address, 4)?
+                };
+
+                let value = match address & 3 {
+                    0 => (current & 0xFFFFFF00) | (value >> 24),
+                    1 => (current & 0xFFFF0000) | (value >> 16),
+                    2 => (current & 0xFF000000) | (value >> 8),
+                    _ => value,
+                };
+
+                if isolated {
+                    self.store_instruction_cache(aligned_address, value);
+                    return Ok(());
+                }
+
+                self.mmu.write(aligned_address, 4, value)?;
             }
             0b101011 => {
                 // SW
@@ -797,87 +1249,304 @@ impl CPU {
                 let s = instruction.s();
 
                 let address = self.registers[s as usize].wrapping_add(immediate);
+
+                if self.data_breakpoint(address, true) {
+                    return Ok(());
+                }
                 let t = instruction.t();
                 let value = self.registers[t as usize];
 
                 self.finish_load();
 
+                if address & 3 != 0 {
+                    self.trigger_address_error(address, Exception::StoreAddressError);
+                    return Ok(());
+                }
+
                 if self.cop0.is_cache_isolated() {
                     self.store_instruction_cache(address, value);
-                    return;
+                    return Ok(());
                 }
 
-                self.mmu.write(address, 4, value);
+                self.mmu.write(address, 4, value)?;
             }
             0b101110 => {
-                panic!("SWR")
+                // SWR
+                let immediate = instruction.immediate_sign_extended();
+                let s = instruction.s() as usize;
+
+                let address = self.registers[s].wrapping_add(immediate);
+
+                if self.data_breakpoint(address, true) {
+                    return Ok(());
+                }
+                let t = instruction.t() as usize;
+                let value = self.registers[t];
+
+                self.finish_load();
+
+                // With the cache isolated the bytes are merged into the cached word
+                let aligned_address = address & !3;
+                let isolated = self.cop0.is_cache_isolated();
+                let current = if isolated {
+                    self.load_instruction_cache(aligned_address)
+                } else {
+                    self.mmu.read(aligned_address, 4)?
+                };
+
+                let value = match address & 3 {
+                    0 => value,
+                    1 => (current & 0x000000FF) | (value << 8),
+                    2 => (current & 0x0000FFFF) | (value << 16),
+                    _ => (current & 0x00FFFFFF) | (value << 24),
+                };
+
+                if isolated {
+                    self.store_instruction_cache(aligned_address, value);
+                    return Ok(());
+                }
+
+                self.mmu.write(aligned_address, 4, value)?;
             }
             0b110000 => {
-                panic!("LWC0")
+                // LWC0
+                self.finish_load();
+
+                self.trigger_coprocessor_error(0);
             }
             0b110001 => {
-                panic!("LWC1")
+                // LWC1
+                self.finish_load();
+
+                self.trigger_coprocessor_error(1);
             }
             0b110010 => {
-                panic!("LWC2")
+                // LWC2
+                if !self.cop0.is_gte_enabled() {
+                    self.finish_load();
+                    self.trigger_coprocessor_error(2);
+                    return Ok(());
+                }
+
+                let immediate = instruction.immediate_sign_extended();
+                let s = instruction.s() as usize;
+                let t = instruction.t() as usize;
+
+                let address = self.registers[s].wrapping_add(immediate);
+
+                if self.data_breakpoint(address, false) {
+                    return Ok(());
+                }
+
+                self.finish_load();
+
+                if address & 3 != 0 {
+                    self.trigger_address_error(address, Exception::LoadAddressError);
+                    return Ok(());
+                }
+
+                let value = self.mmu.read(address, 4)?;
+                self.wait_for_gte();
+                self.gte.write_data(t, value);
             }
             0b110011 => {
-                panic!("LWC3")
+                // LWC3
+                self.finish_load();
+
+                self.trigger_coprocessor_error(3);
             }
             0b111000 => {
-                panic!("SWC0")
+                // SWC0
+                self.finish_load();
+
+                self.trigger_coprocessor_error(0);
             }
             0b111001 => {
-                panic!("SWC1")
+                // SWC1
+                self.finish_load();
+
+                self.trigger_coprocessor_error(1);
             }
             0b111010 => {
-                panic!("SWC2")
+                // SWC2
+                if !self.cop0.is_gte_enabled() {
+                    self.finish_load();
+                    self.trigger_coprocessor_error(2);
+                    return Ok(());
+                }
+
+                let immediate = instruction.immediate_sign_extended();
+                let s = instruction.s() as usize;
+
+                let address = self.registers[s].wrapping_add(immediate);
+
+                if self.data_breakpoint(address, true) {
+                    return Ok(());
+                }
+
+                self.finish_load();
+
+                if address & 3 != 0 {
+                    self.trigger_address_error(address, Exception::StoreAddressError);
+                    return Ok(());
+                }
+
+                self.wait_for_gte();
+                let value = self.gte.read_data(instruction.t() as usize);
+
+                if self.cop0.is_cache_isolated() {
+                    self.store_instruction_cache(address, value);
+                    return Ok(());
+                }
+
+                self.mmu.write(address, 4, value)?;
             }
             0b111011 => {
-                panic!("SWC3")
+                // SWC3
+                self.finish_load();
+
+                self.trigger_coprocessor_error(3);
             }
             _ => {
-                panic!("Invalid instruction")
+                self.finish_load();
+
+                self.trigger_illegal_instruction(instruction);
             }
         }
+
+        Ok(())
+    }
+
+    // GTE accesses wait until the running command is done, other instructions overlap with it
+    fn wait_for_gte(&mut self) {
+        let now = self.cycles + self.gte_stall as u64;
+        self.gte_stall += self.gte_busy_until.saturating_sub(now) as u32;
+    }
+
+    fn unhandled_instruction(&self, instruction: Instruction) -> EmuError {
+        EmuError::UnhandledInstruction {
+            pc: self.current_pc,
+            word: instruction.0,
+        }
+    }
+
+    fn branch(&mut self, offset: u32) {
+        self.next_pc = self.pc.wrapping_add(offset << 2);
+        self.branch = true;
     }
 
     fn trigger_exception(&mut self, exception: Exception) {
-        self.pc = self.cop0.trigger_exception(self.current_pc, exception);
+        self.pc = self
+            .cop0
+            .trigger_exception(self.current_pc, self.delay_slot, exception);
+        self.next_pc = self.pc.wrapping_add(4);
+
+        // The exception handler is not in the delay slot, even if the faulting instruction was a branch
+        self.branch = false;
+    }
+
+    // Hardware breakpoints use the debug vector instead of the general exception vector
+    fn trigger_breakpoint(&mut self) {
+        self.trigger_exception(Exception::Break);
+
+        self.pc = if self.cop0.status & 0x00400000 != 0 {
+            0xBFC00140
+        } else {
+            0x80000040
+        };
         self.next_pc = self.pc.wrapping_add(4);
     }
 
+    fn data_breakpoint(&mut self, address: u32, write: bool) -> bool {
+        if !self.cop0.is_data_breakpoint(address, write) {
+            return false;
+        }
+
+        self.finish_load();
+        self.trigger_breakpoint();
+
+        true
+    }
+
+    fn trigger_illegal_instruction(&mut self, instruction: Instruction) {
+        println!(
+            "Illegal instruction 0x{:08x} at 0x{:08x}",
+            instruction.0, self.current_pc
+        );
+        self.trigger_exception(Exception::IllegalInstruction);
+    }
+
+    fn trigger_coprocessor_error(&mut self, coprocessor: u32) {
+        self.trigger_exception(Exception::CoprocessorError);
+
+        // The coprocessor number is stored in bits 28..29
+        self.cop0.cause = (self.cop0.cause & !0x30000000) | ((coprocessor & 3) << 28);
+    }
+
+    fn trigger_address_error(&mut self, address: u32, exception: Exception) {
+        self.cop0.bad_vaddr = address;
+        self.trigger_exception(exception);
+    }
+
+    // Loads into R0 are dropped here already, the exception paths return before the end of step
+    // resets it
     fn setup_load(&mut self, register: u32, value: u32) {
-        if self.next_load.0 != register {
+        if self.next_load.0 != register && self.next_load.0 != 0 {
             self.registers[self.next_load.0 as usize] = self.next_load.1;
         }
 
-        self.next_load = (register, value);
+        self.next_load = match register {
+            0 => (0, 0),
+            _ => (register, value),
+        };
+    }
+
+    // The value a register will have once the in-flight load completes
+    fn pending_register(&self, register: usize) -> u32 {
+        if self.next_load.0 as usize == register {
+            self.next_load.1
+        } else {
+            self.registers[register]
+        }
     }
 
     fn finish_load(&mut self) {
-        self.registers[self.next_load.0 as usize] = self.next_load.1;
+        if self.next_load.0 != 0 {
+            self.registers[self.next_load.0 as usize] = self.next_load.1;
+        }
 
         self.next_load = (0, 0);
     }
 
+    // The word an isolated store would replace, the tag in tag test mode
+    fn load_instruction_cache(&self, address: u32) -> u32 {
+        let cache_line = &self.instruction_cache[((address >> 4) & 0xFF) as usize];
+        if self.mmu.is_instruction_cache_tag_test_mode() {
+            cache_line.tag
+        } else {
+            cache_line.data[((address >> 2) & 3) as usize]
+        }
+    }
+
     fn store_instruction_cache(&mut self, address: u32, value: u32) {
         let line = ((address >> 4) & 0xFF) as usize;
         let index = ((address >> 2) & 3) as usize;
 
-        let mut cache_line = self.instruction_cache[line];
+        let cache_line = &mut self.instruction_cache[line];
+
+        self.cache_stats.isolated_stores += 1;
 
         if self.mmu.is_instruction_cache_tag_test_mode() {
+            // Writing the tag invalidates the whole line, this is how the BIOS flushes the cache
             cache_line.tag = value;
+            cache_line.valid = 0;
         } else {
             cache_line.data[index] = value;
         }
-
-        cache_line.valid = 4;
     }
 }
 
-struct Instruction(u32);
+pub(crate) struct Instruction(pub(crate) u32);
 
 impl Instruction {
     // Opcode is last 6 bits
@@ -932,9 +1601,16 @@ impl Instruction {
 }
 
 struct Coprocessor {
-    status: u32, // System status register
-    cause: u32,  // Describes the most recently recognized exception
-    epc: u32,    // Return address from trap
+    status: u32,    // System status register
+    cause: u32,     // Describes the most recently recognized exception
+    epc: u32,       // Return address from trap
+    bad_vaddr: u32, // Address that caused the most recent address error
+    bpc: u32,       // Breakpoint on execute address
+    bpcm: u32,      // Breakpoint on execute mask
+    bda: u32,       // Breakpoint on data access address
+    bdam: u32,      // Breakpoint on data access mask
+    dcic: u32,      // Breakpoint control
+    jump_dest: u32, // Target of the last taken branch or jump
 }
 
 impl Coprocessor {
@@ -943,24 +1619,85 @@ impl Coprocessor {
             status: 0,
             cause: 0,
             epc: 0,
+            bad_vaddr: 0,
+            bpc: 0,
+            bpcm: 0,
+            bda: 0,
+            bdam: 0,
+            dcic: 0,
+            jump_dest: 0,
+        }
+    }
+
+    // DCIC bit 23 and 31 are the super-master enables, bit 30 enables the code and data breakpoints
+    fn are_breakpoints_enabled(&self) -> bool {
+        self.dcic & 0xC0800000 == 0xC0800000
+    }
+
+    pub fn is_code_breakpoint(&mut self, pc: u32) -> bool {
+        let hit = self.are_breakpoints_enabled()
+            && self.dcic & (1 << 24) != 0
+            && (pc ^ self.bpc) & self.bpcm == 0;
+
+        if hit {
+            // Flag any break and code break
+            self.dcic |= 0x3;
+        }
+
+        hit
+    }
+
+    pub fn is_data_breakpoint(&mut self, address: u32, write: bool) -> bool {
+        let access_bit = if write { 1 << 27 } else { 1 << 26 };
+
+        let hit = self.are_breakpoints_enabled()
+            && self.dcic & (1 << 25) != 0
+            && self.dcic & access_bit != 0
+            && (address ^ self.bda) & self.bdam == 0;
+
+        if hit {
+            // Flag any break, data break and either data read or data write break
+            self.dcic |= 0x5 | if write { 0x10 } else { 0x8 };
         }
+
+        hit
+    }
+
+    pub fn set_interrupt_pending(&mut self, pending: bool) {
+        self.cause = (self.cause & !0x400) | ((pending as u32) << 10);
+    }
+
+    // Interrupts are taken when IEc is set and any pending interrupt (hardware or software) is unmasked
+    pub fn should_interrupt(&self) -> bool {
+        self.status & 1 != 0 && (self.status & self.cause & 0x700) != 0
+    }
+
+    // The CU2 bit of the status register
+    pub fn is_gte_enabled(&self) -> bool {
+        self.status & (1 << 30) != 0
     }
 
     pub fn is_cache_isolated(&self) -> bool {
         self.status & 0x10000 != 0
     }
 
-    pub fn trigger_exception(&mut self, pc: u32, exception: Exception) -> u32 {
+    pub fn trigger_exception(&mut self, pc: u32, delay_slot: bool, exception: Exception) -> u32 {
         let mode = self.status & 0x3F;
-        self.status = self.status & !0x3F;
-        self.status = self.status | (mode << 2) & 0x3F;
+        self.status &= !0x3F;
+        self.status |= (mode << 2) & 0x3F;
 
-        self.cause = self.cause & !0x7C;
+        self.cause &= !0x7C;
 
-        // TODO: Deal with delay slots which changes these values
-        self.epc = pc;
-        self.cause = self.cause & !(1 << 31);
-        self.cause = self.cause | (exception as u32) << 2;
+        self.cause &= !(1 << 31);
+        self.cause |= (exception as u32) << 2;
+
+        // When the exception happens in a delay slot, return to the branch so it gets re-executed
+        if delay_slot {
+            self.epc = pc.wrapping_sub(4);
+            self.cause |= 1 << 31;
+        } else {
+            self.epc = pc;
+        }
 
         let is_bev = self.status & 0x00400000;
 
@@ -976,9 +1713,687 @@ enum Exception {
     Interrupt = 0x0,
     LoadAddressError = 0x4,
     StoreAddressError = 0x5,
+    InstructionBusError = 0x6,
+    DataBusError = 0x7,
     SysCall = 0x8,
     Break = 0x9,
     IllegalInstruction = 0xa,
     CoprocessorError = 0xb,
     Overflow = 0xc,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmu::{CycleAccuracy, Irq, MmuMode};
+
+    const PROGRAM: u32 = 0x80010000;
+    const EXCEPTION_VECTOR: u32 = 0x80000080;
+
+    fn i_type(opcode: u32, s: u32, t: u32, immediate: u16) -> u32 {
+        (opcode << 26) | (s << 21) | (t << 16) | immediate as u32
+    }
+
+    fn r_type(function: u32, s: u32, t: u32, d: u32) -> u32 {
+        (s << 21) | (t << 16) | (d << 11) | function
+    }
+
+    fn lw(t: u32, s: u32, offset: u16) -> u32 {
+        i_type(0x23, s, t, offset)
+    }
+
+    fn addu(d: u32, s: u32, t: u32) -> u32 {
+        r_type(0x21, s, t, d)
+    }
+
+    fn addiu(t: u32, s: u32, immediate: u16) -> u32 {
+        i_type(0x09, s, t, immediate)
+    }
+
+    const NOP: u32 = 0;
+
+    // A CPU running the program from RAM, the BIOS is empty
+    fn cpu_with_program(program: &[u32]) -> CPU {
+        let mut mmu = MMU::new(vec![0; 512 * 1024]);
+        for (i, word) in program.iter().enumerate() {
+            mmu.write(PROGRAM + i as u32 * 4, 4, *word).unwrap();
+        }
+
+        let mut cpu = CPU::new(mmu);
+        cpu.set_pc(PROGRAM);
+        cpu
+    }
+
+    fn run(cpu: &mut CPU, instructions: usize) {
+        for _ in 0..instructions {
+            cpu.step().unwrap();
+        }
+    }
+
+    // The same with the instruction cache enabled, KSEG0 fetches go through it
+    fn cpu_with_cached_program(program: &[u32]) -> CPU {
+        let mut cpu = cpu_with_program(program);
+        cpu.mmu_mut().write(0xFFFE0130, 4, 0x800).unwrap();
+        cpu
+    }
+
+    // Executes the instruction at the address
+    fn fetch(cpu: &mut CPU, pc: u32) {
+        cpu.set_pc(pc);
+        cpu.step().unwrap();
+    }
+
+    #[test]
+    fn load_into_r0_is_discarded() {
+        let mut cpu = cpu_with_program(&[lw(0, 0, 0x100), NOP, addu(2, 0, 0)]);
+        cpu.mmu_mut().write(0x100, 4, 0xDEADBEEF).unwrap();
+        cpu.set_register(2, 0x1234);
+
+        run(&mut cpu, 3);
+
+        assert_eq!(cpu.register(0), 0);
+        assert_eq!(cpu.register(2), 0);
+    }
+
+    #[test]
+    fn interrupt_after_load_into_r0_keeps_r0_zero() {
+        let mut cpu = cpu_with_program(&[lw(0, 0, 0x100), NOP]);
+        cpu.mmu_mut().write(0x100, 4, 0xDEADBEEF).unwrap();
+        cpu.mmu_mut()
+            .write(EXCEPTION_VECTOR, 4, addu(2, 0, 0))
+            .unwrap();
+        cpu.set_register(2, 0x1234);
+        // IEc and the interrupt controller line in IM
+        cpu.cop0.status = 0x401;
+        cpu.mmu_mut().write(0x1F801074, 4, 1).unwrap();
+
+        run(&mut cpu, 1);
+        cpu.mmu_mut().request_interrupt(Irq::VBlank);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.pc(), EXCEPTION_VECTOR);
+        assert_eq!(cpu.register(0), 0);
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.register(2), 0);
+    }
+
+    #[test]
+    fn ram_outside_of_the_ram_size_window_is_a_bus_error() {
+        let lui = |t, immediate| i_type(0x0F, 0, t, immediate);
+        let mut cpu = cpu_with_program(&[
+            lui(8, 0x0020),
+            lw(9, 8, 0xFFFC),
+            lui(8, 0x0040),
+            lw(10, 8, 0),
+        ]);
+        // 2MB of RAM with the rest of the 8MB window locked
+        cpu.mmu_mut().write(0x1F801060, 4, 0x888).unwrap();
+        cpu.mmu_mut().write(0x001FFFFC, 4, 0x12345678).unwrap();
+
+        run(&mut cpu, 3);
+        assert_eq!(cpu.register(9), 0x12345678);
+        assert_eq!(cpu.cop0.cause & 0x7C, 0);
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.pc(), EXCEPTION_VECTOR);
+        assert_eq!((cpu.cop0.cause >> 2) & 0x1F, Exception::DataBusError as u32);
+        assert_eq!(cpu.mmu().peek(0x00400000, 4), None);
+        assert_eq!(cpu.mmu().peek(0x001FFFFC, 4), Some(0x12345678));
+    }
+
+    #[test]
+    fn refilled_line_is_served_from_the_cache() {
+        let mut cpu = cpu_with_cached_program(&[addiu(8, 8, 1); 4]);
+
+        run(&mut cpu, 4);
+        assert_eq!(cpu.cache_stats().misses, 1);
+        assert_eq!(cpu.cache_stats().hits, 3);
+
+        // RAM isn't read again for the second pass
+        cpu.mmu_mut().write(PROGRAM, 4, addiu(8, 8, 0x100)).unwrap();
+        cpu.set_pc(PROGRAM);
+        run(&mut cpu, 4);
+        assert_eq!(cpu.cache_stats().misses, 1);
+        assert_eq!(cpu.cache_stats().hits, 7);
+        assert_eq!(cpu.register(8), 8);
+    }
+
+    #[test]
+    fn isolated_stores_write_the_cache() {
+        let mut cpu = cpu_with_cached_program(&[addiu(8, 0, 1)]);
+        fetch(&mut cpu, PROGRAM);
+
+        // With the cache isolated stores go to the cached word instead of RAM
+        cpu.store_instruction_cache(PROGRAM, addiu(8, 0, 2));
+        assert_eq!(cpu.mmu_mut().read(PROGRAM, 4).unwrap(), addiu(8, 0, 1));
+        fetch(&mut cpu, PROGRAM);
+        assert_eq!(cpu.register(8), 2);
+
+        // In tag test mode the store replaces the tag, the line has to be fetched again
+        cpu.mmu_mut().write(0xFFFE0130, 4, 0x804).unwrap();
+        cpu.store_instruction_cache(PROGRAM, 0);
+        cpu.mmu_mut().write(0xFFFE0130, 4, 0x800).unwrap();
+        fetch(&mut cpu, PROGRAM);
+        assert_eq!(cpu.register(8), 1);
+        assert_eq!(cpu.cache_stats().isolated_stores, 2);
+        assert_eq!(cpu.cache_stats().misses, 2);
+    }
+
+    #[test]
+    fn isolated_partial_stores_merge_into_the_cached_word() {
+        let swl = |t, s, offset| i_type(0x2A, s, t, offset);
+        let swr = |t, s, offset| i_type(0x2E, s, t, offset);
+        let mut cpu = cpu_with_cached_program(&[swl(9, 8, 0x101), swr(9, 8, 0x102)]);
+        cpu.store_instruction_cache(PROGRAM + 0x100, 0x11223344);
+        cpu.set_register(8, PROGRAM);
+        cpu.set_register(9, 0xAABBCCDD);
+
+        // Run from the uncached mirror, the stores only see the cache
+        cpu.cop0.status |= 0x10000;
+        fetch(&mut cpu, PROGRAM + 0x20000000);
+        assert_eq!(cpu.load_instruction_cache(PROGRAM + 0x100), 0x1122AABB);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.load_instruction_cache(PROGRAM + 0x100), 0xCCDDAABB);
+        cpu.cop0.status &= !0x10000;
+
+        assert_eq!(cpu.load_instruction_cache(PROGRAM + 0x104), 0);
+        assert_eq!(cpu.mmu_mut().read(PROGRAM + 0x100, 4).unwrap(), 0);
+    }
+
+    #[test]
+    fn cache_stats_count_a_loop() {
+        let mut cpu = cpu_with_cached_program(&[
+            addiu(8, 0, 10),
+            addiu(8, 8, 0xFFFF),
+            i_type(0x05, 8, 0, 0xFFFE),
+            NOP,
+            NOP,
+        ]);
+
+        // The first fetch fills the line, the 10 passes through the loop hit it
+        run(&mut cpu, 31);
+        let stats = *cpu.cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.refill_words), (30, 1, 4));
+        assert_eq!(cpu.register(8), 0);
+
+        // The instruction after the loop is in the next line
+        run(&mut cpu, 1);
+        assert_eq!(cpu.cache_stats().misses, 2);
+        assert_eq!(cpu.cache_stats().refill_words, 8);
+        assert!((cpu.cache_stats().hit_rate() - 30.0 / 32.0 * 100.0).abs() < 1e-9);
+
+        // KSEG1 bypasses the cache
+        fetch(&mut cpu, PROGRAM + 0x20000000);
+        assert_eq!(cpu.cache_stats().uncached_fetches, 1);
+
+        cpu.reset_cache_stats();
+        assert_eq!(cpu.cache_stats().hits + cpu.cache_stats().misses, 0);
+    }
+
+    #[test]
+    fn mult_sign_extends_and_multu_does_not() {
+        let mult = |s, t| r_type(0x18, s, t, 0);
+        let multu = |s, t| r_type(0x19, s, t, 0);
+        let mut cpu = cpu_with_program(&[mult(8, 9), mult(8, 10), mult(11, 11), multu(11, 11)]);
+        cpu.set_register(8, -3i32 as u32);
+        cpu.set_register(9, -5i32 as u32);
+        cpu.set_register(10, 7);
+        cpu.set_register(11, 0x80000000);
+
+        run(&mut cpu, 1);
+        assert_eq!((cpu.hi(), cpu.lo()), (0, 15));
+
+        run(&mut cpu, 1);
+        assert_eq!((cpu.hi(), cpu.lo()), (0xFFFFFFFF, -21i32 as u32));
+
+        // (-2^31)^2 and (2^31)^2 are both 2^62
+        run(&mut cpu, 1);
+        assert_eq!((cpu.hi(), cpu.lo()), (0x40000000, 0));
+        run(&mut cpu, 1);
+        assert_eq!((cpu.hi(), cpu.lo()), (0x40000000, 0));
+    }
+
+    #[test]
+    fn variable_shifts_use_the_low_five_bits() {
+        let sllv = |d, t, s| r_type(0x04, s, t, d);
+        let srav = |d, t, s| r_type(0x07, s, t, d);
+        let mut cpu = cpu_with_program(&[sllv(10, 8, 9), srav(11, 8, 9), srav(12, 8, 13)]);
+        cpu.set_register(8, 0x80000001);
+        cpu.set_register(9, 33);
+        cpu.set_register(13, 0xFFFFFFFF);
+
+        run(&mut cpu, 3);
+        assert_eq!(cpu.register(10), 0x00000002);
+        assert_eq!(cpu.register(11), 0xC0000000);
+        assert_eq!(cpu.register(12), 0xFFFFFFFF);
+    }
+
+    #[test]
+    fn logical_immediates_are_zero_extended() {
+        let xori = |t, s, immediate| i_type(0x0E, s, t, immediate);
+        let nor = |d, s, t| r_type(0x27, s, t, d);
+        let mut cpu = cpu_with_program(&[xori(9, 8, 0x8000), nor(10, 8, 0), nor(11, 0, 0)]);
+        cpu.set_register(8, 0xFFFF0000);
+
+        run(&mut cpu, 3);
+        assert_eq!(cpu.register(9), 0xFFFF8000);
+        assert_eq!(cpu.register(10), 0x0000FFFF);
+        assert_eq!(cpu.register(11), 0xFFFFFFFF);
+    }
+
+    #[test]
+    fn lwl_and_lwr_load_a_word_across_the_boundary() {
+        let lwl = |t, s, offset| i_type(0x22, s, t, offset);
+        let lwr = |t, s, offset| i_type(0x26, s, t, offset);
+        // The pair goes back to back, the second one merges with the load in the delay slot
+        let mut cpu = cpu_with_program(&[
+            lwr(8, 0, 0x101),
+            lwl(8, 0, 0x104),
+            NOP,
+            lwl(9, 0, 0x101),
+            NOP,
+        ]);
+        cpu.mmu_mut().write(0x100, 4, 0x44332211).unwrap();
+        cpu.mmu_mut().write(0x104, 4, 0x88776655).unwrap();
+        cpu.set_register(9, 0xAABBCCDD);
+
+        run(&mut cpu, 3);
+        assert_eq!(cpu.register(8), 0x55443322);
+
+        // On its own LWL keeps the low bytes of the register
+        run(&mut cpu, 2);
+        assert_eq!(cpu.register(9), 0x2211CCDD);
+    }
+
+    #[test]
+    fn swl_and_swr_store_a_word_across_the_boundary() {
+        let swl = |t, s, offset| i_type(0x2A, s, t, offset);
+        let swr = |t, s, offset| i_type(0x2E, s, t, offset);
+        let mut cpu = cpu_with_program(&[swr(8, 0, 0x101), swl(8, 0, 0x104)]);
+        cpu.mmu_mut().write(0x100, 4, 0x44332211).unwrap();
+        cpu.mmu_mut().write(0x104, 4, 0x88776655).unwrap();
+        cpu.set_register(8, 0xDDCCBBAA);
+
+        run(&mut cpu, 2);
+        assert_eq!(cpu.mmu_mut().read(0x100, 4).unwrap(), 0xCCBBAA11);
+        assert_eq!(cpu.mmu_mut().read(0x104, 4).unwrap(), 0x887766DD);
+    }
+
+    #[test]
+    fn overflow_keeps_the_destination_and_traps() {
+        let add = |d, s, t| r_type(0x20, s, t, d);
+        let mut cpu = cpu_with_program(&[NOP, add(10, 8, 9)]);
+        cpu.set_register(8, 0x7FFFFFFF);
+        cpu.set_register(9, 1);
+        cpu.set_register(10, 0x1234);
+
+        run(&mut cpu, 2);
+        assert_eq!(cpu.register(10), 0x1234);
+        assert_eq!(cpu.pc(), EXCEPTION_VECTOR);
+        assert_eq!(cpu.cop0.epc, PROGRAM + 4);
+        assert_eq!((cpu.cop0.cause >> 2) & 0x1F, Exception::Overflow as u32);
+    }
+
+    #[test]
+    fn misaligned_store_does_not_reach_memory() {
+        let sw = |t, s, offset| i_type(0x2B, s, t, offset);
+        let mut cpu = cpu_with_program(&[sw(8, 0, 0x102)]);
+        cpu.set_register(8, 0xDEADBEEF);
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.mmu_mut().read(0x100, 4).unwrap(), 0);
+        assert_eq!(cpu.mmu_mut().read(0x104, 4).unwrap(), 0);
+        assert_eq!(cpu.pc(), EXCEPTION_VECTOR);
+        assert_eq!(cpu.cop0.epc, PROGRAM);
+        assert_eq!(cpu.cop0.bad_vaddr, 0x102);
+        assert_eq!(
+            (cpu.cop0.cause >> 2) & 0x1F,
+            Exception::StoreAddressError as u32
+        );
+    }
+
+    #[test]
+    fn undefined_opcode_is_a_reserved_instruction() {
+        let mut cpu = cpu_with_program(&[NOP, 0xFC000000, r_type(0x3F, 0, 0, 0)]);
+
+        run(&mut cpu, 2);
+        assert_eq!(cpu.pc(), EXCEPTION_VECTOR);
+        assert_eq!(cpu.cop0.epc, PROGRAM + 4);
+        assert_eq!(
+            (cpu.cop0.cause >> 2) & 0x1F,
+            Exception::IllegalInstruction as u32
+        );
+
+        // Undefined SPECIAL functions too
+        fetch(&mut cpu, PROGRAM + 8);
+        assert_eq!(cpu.pc(), EXCEPTION_VECTOR);
+        assert_eq!(cpu.cop0.epc, PROGRAM + 8);
+    }
+
+    const SYSCALL: u32 = 0x0000000C;
+    const BREAK: u32 = 0x0000000D;
+
+    #[test]
+    fn syscall_and_break_trap_at_their_own_address() {
+        let mut cpu = cpu_with_program(&[NOP, SYSCALL, BREAK]);
+        cpu.cop0.status = 0x1;
+
+        run(&mut cpu, 2);
+        assert_eq!(cpu.pc(), EXCEPTION_VECTOR);
+        assert_eq!(cpu.cop0.epc, PROGRAM + 4);
+        assert_eq!((cpu.cop0.cause >> 2) & 0x1F, Exception::SysCall as u32);
+        assert_eq!(cpu.cop0.cause >> 31, 0);
+        // Interrupts are off in the handler, the old mode moved up the stack
+        assert_eq!(cpu.cop0.status & 0x3F, 0x4);
+
+        // With BEV set the vector is in the BIOS
+        cpu.cop0.status |= 0x00400000;
+        fetch(&mut cpu, PROGRAM + 8);
+        assert_eq!(cpu.pc(), 0xBFC00180);
+        assert_eq!(cpu.cop0.epc, PROGRAM + 8);
+        assert_eq!((cpu.cop0.cause >> 2) & 0x1F, Exception::Break as u32);
+    }
+
+    #[test]
+    fn exception_in_a_delay_slot_returns_to_the_branch() {
+        let beq = |s, t, offset| i_type(0x04, s, t, offset);
+        let mut cpu = cpu_with_program(&[beq(0, 0, 0x10), SYSCALL, BREAK]);
+
+        run(&mut cpu, 2);
+        assert_eq!(cpu.pc(), EXCEPTION_VECTOR);
+        assert_eq!(cpu.cop0.epc, PROGRAM);
+        assert_eq!((cpu.cop0.cause >> 2) & 0x1F, Exception::SysCall as u32);
+        assert_ne!(cpu.cop0.cause & (1 << 31), 0);
+
+        // The handler's first instruction is not in a delay slot
+        cpu.mmu_mut().write(EXCEPTION_VECTOR, 4, BREAK).unwrap();
+        run(&mut cpu, 1);
+        assert_eq!(cpu.cop0.epc, EXCEPTION_VECTOR);
+        assert_eq!(cpu.cop0.cause & (1 << 31), 0);
+    }
+
+    #[test]
+    fn cop1_is_a_coprocessor_unusable_error() {
+        let mut cpu = cpu_with_program(&[0x44000000, i_type(0x3B, 0, 0, 0)]);
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.pc(), EXCEPTION_VECTOR);
+        assert_eq!(cpu.cop0.epc, PROGRAM);
+        assert_eq!(
+            (cpu.cop0.cause >> 2) & 0x1F,
+            Exception::CoprocessorError as u32
+        );
+        assert_eq!((cpu.cop0.cause >> 28) & 3, 1);
+
+        // SWC3 reports coprocessor 3
+        fetch(&mut cpu, PROGRAM + 4);
+        assert_eq!((cpu.cop0.cause >> 28) & 3, 3);
+    }
+
+    #[test]
+    fn mfc0_reads_every_cop0_register() {
+        let mfc0 = |t: u32, d: u32| 0x40000000 | (t << 16) | (d << 11);
+        let readable = [3, 5, 6, 7, 8, 9, 11, 12, 13, 14, 15];
+        let mut program: Vec<u32> = readable.iter().map(|d| mfc0(*d + 8, *d)).collect();
+        program.push(NOP);
+        let mut cpu = cpu_with_program(&program);
+        cpu.cop0.bpc = 0x03;
+        cpu.cop0.bda = 0x05;
+        cpu.cop0.jump_dest = 0x06;
+        cpu.cop0.dcic = 0x07;
+        cpu.cop0.bad_vaddr = 0x08;
+        cpu.cop0.bdam = 0x09;
+        cpu.cop0.bpcm = 0x0B;
+        cpu.cop0.status = 0x0C;
+        cpu.cop0.cause = 0x0D;
+        cpu.cop0.epc = 0x0E;
+
+        run(&mut cpu, program.len());
+        for d in readable {
+            let expected = match d {
+                15 => PROCESSOR_ID,
+                _ => d,
+            };
+            assert_eq!(cpu.register(d as usize + 8), expected, "cop0r{}", d);
+        }
+    }
+
+    #[test]
+    fn jumpdest_holds_the_last_taken_jump_target() {
+        let mfc0 = |t: u32, d: u32| 0x40000000 | (t << 16) | (d << 11);
+        let j = |target: u32| 0x08000000 | ((target >> 2) & 0x3FFFFFF);
+        let bne = |s, t, offset| i_type(0x05, s, t, offset);
+        let mut cpu = cpu_with_program(&[j(PROGRAM + 0x10), NOP]);
+        // A branch that is not taken leaves it
+        let program = [bne(0, 0, 0x10), NOP, mfc0(8, 6), NOP, NOP];
+        for (i, word) in program.iter().enumerate() {
+            cpu.mmu_mut()
+                .write(PROGRAM + 0x10 + i as u32 * 4, 4, *word)
+                .unwrap();
+        }
+
+        run(&mut cpu, 2 + program.len());
+        assert_eq!(cpu.pc(), PROGRAM + 0x24);
+        assert_eq!(cpu.register(8), PROGRAM + 0x10);
+    }
+
+    #[test]
+    fn code_breakpoint_fires_at_its_address() {
+        let mtc0 = |t: u32, d: u32| 0x40800000 | (t << 16) | (d << 11);
+        let mut cpu = cpu_with_program(&[mtc0(8, 3), mtc0(9, 11), mtc0(10, 7), NOP, NOP, NOP]);
+        cpu.set_register(8, PROGRAM + 16);
+        cpu.set_register(9, 0xFFFFFFFF);
+        // Super-master enables, code breakpoints and the execute breakpoint
+        cpu.set_register(10, 0xC1800000);
+
+        run(&mut cpu, 4);
+        assert_eq!(cpu.pc(), PROGRAM + 16);
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.pc(), 0x80000040);
+        assert_eq!(cpu.cop0.epc, PROGRAM + 16);
+        assert_eq!(cpu.cop0.dcic & 0x3, 0x3);
+    }
+
+    #[test]
+    fn stale_code_runs_until_the_cache_is_flushed() {
+        let sw = |t, s, offset| i_type(0x2B, s, t, offset);
+        let mut cpu = cpu_with_cached_program(&[addiu(8, 0, 1)]);
+        cpu.mmu_mut()
+            .write(PROGRAM + 0x100, 4, sw(0, 9, 0))
+            .unwrap();
+        fetch(&mut cpu, PROGRAM);
+
+        // Code written to RAM isn't seen while the old line is cached
+        cpu.mmu_mut().write(PROGRAM, 4, addiu(8, 0, 2)).unwrap();
+        fetch(&mut cpu, PROGRAM);
+        assert_eq!(cpu.register(8), 1);
+
+        // Flush the line like the BIOS does, from uncached code with the cache isolated
+        cpu.set_register(9, PROGRAM);
+        cpu.cop0.status |= 0x10000;
+        cpu.mmu_mut().write(0xFFFE0130, 4, 0x804).unwrap();
+        fetch(&mut cpu, PROGRAM + 0x20000100);
+        cpu.cop0.status &= !0x10000;
+        cpu.mmu_mut().write(0xFFFE0130, 4, 0x800).unwrap();
+        assert_eq!(cpu.mmu_mut().read(PROGRAM, 4).unwrap(), addiu(8, 0, 2));
+
+        fetch(&mut cpu, PROGRAM);
+        assert_eq!(cpu.register(8), 2);
+    }
+
+    #[test]
+    fn cache_lines_track_each_word() {
+        let mut cpu = cpu_with_cached_program(&[addiu(8, 8, 1); 4]);
+
+        // Jumping into the middle of a line only fills the rest of it
+        fetch(&mut cpu, PROGRAM + 8);
+        fetch(&mut cpu, PROGRAM + 12);
+        assert_eq!((cpu.cache_stats().misses, cpu.cache_stats().hits), (1, 1));
+        assert_eq!(cpu.cache_stats().refill_words, 2);
+
+        // The words before it were never fetched
+        fetch(&mut cpu, PROGRAM);
+        assert_eq!(cpu.cache_stats().misses, 2);
+        assert_eq!(cpu.cache_stats().refill_words, 6);
+        fetch(&mut cpu, PROGRAM + 8);
+        assert_eq!(cpu.cache_stats().hits, 2);
+
+        // Invalidating the line with an isolated store makes the next fetch miss
+        cpu.mmu_mut().write(0xFFFE0130, 4, 0x804).unwrap();
+        cpu.store_instruction_cache(PROGRAM, 0);
+        cpu.mmu_mut().write(0xFFFE0130, 4, 0x800).unwrap();
+        fetch(&mut cpu, PROGRAM + 8);
+        assert_eq!(cpu.cache_stats().misses, 3);
+
+        // Another address in the same line has a different tag
+        cpu.mmu_mut().write(PROGRAM + 0x1008, 4, NOP).unwrap();
+        fetch(&mut cpu, PROGRAM + 0x1008);
+        assert_eq!(cpu.cache_stats().misses, 4);
+        fetch(&mut cpu, PROGRAM + 8);
+        assert_eq!(cpu.cache_stats().misses, 5);
+        assert_eq!(cpu.register(8), 6);
+    }
+
+    #[test]
+    fn io_registers_serve_byte_and_halfword_loads() {
+        let lui = |t, immediate| i_type(0x0F, 0, t, immediate);
+        let lb = |t, s, offset| i_type(0x20, s, t, offset);
+        let lbu = |t, s, offset| i_type(0x24, s, t, offset);
+        let lhu = |t, s, offset| i_type(0x25, s, t, offset);
+        let mut cpu = cpu_with_program(&[
+            lui(8, 0x1F80),
+            lb(9, 8, 0x1070),
+            lhu(10, 8, 0x1074),
+            lbu(11, 8, 0x1075),
+            NOP,
+        ]);
+        cpu.mmu_mut().request_interrupt(Irq::Controller);
+        cpu.mmu_mut().request_interrupt(Irq::Sio);
+        cpu.mmu_mut().write(0x1F801074, 4, 0x485).unwrap();
+
+        run(&mut cpu, 5);
+        assert_eq!(cpu.register(9), 0xFFFFFF80);
+        assert_eq!(cpu.register(10), 0x485);
+        assert_eq!(cpu.register(11), 0x04);
+    }
+
+    #[test]
+    fn permissive_mode_continues_past_unmapped_accesses() {
+        let lui = |t, immediate| i_type(0x0F, 0, t, immediate);
+        let sw = |t, s, offset| i_type(0x2B, s, t, offset);
+        let program = [
+            lui(9, 0x1FB0),
+            sw(9, 9, 0),
+            lw(10, 9, 0),
+            NOP,
+            addiu(8, 0, 1),
+        ];
+
+        let mut cpu = cpu_with_program(&program);
+        cpu.mmu_mut().set_mode(MmuMode::Permissive);
+        run(&mut cpu, 5);
+        assert_eq!(cpu.register(8), 1);
+        assert_eq!(cpu.register(10), 0xFFFFFFFF);
+
+        let mut cpu = cpu_with_program(&program);
+        run(&mut cpu, 1);
+        assert_eq!(
+            cpu.step(),
+            Err(EmuError::UnmappedWrite {
+                address: 0x1FB00000,
+                size: 4,
+                value: 0x1FB00000
+            })
+        );
+        assert_eq!(cpu.register(8), 0);
+    }
+
+    #[test]
+    fn unemulated_instructions_and_accesses_are_errors() {
+        let lui = |t, immediate| i_type(0x0F, 0, t, immediate);
+        // COP0 r1 doesn't exist
+        let mut cpu = cpu_with_program(&[0x40080800, lui(9, 0x1FB0), lw(10, 9, 0x10)]);
+        assert_eq!(
+            cpu.step(),
+            Err(EmuError::UnhandledInstruction {
+                pc: PROGRAM,
+                word: 0x40080800
+            })
+        );
+
+        fetch(&mut cpu, PROGRAM + 4);
+        assert_eq!(
+            cpu.step(),
+            Err(EmuError::UnmappedRead {
+                address: 0x1FB00010,
+                size: 4
+            })
+        );
+    }
+
+    #[test]
+    fn cpu_sees_the_gpu_channel_busy_while_it_transfers() {
+        let lui = |t, immediate| i_type(0x0F, 0, t, immediate);
+        let and = |d, s, t| r_type(0x24, s, t, d);
+        let bne = |s, t, offset| i_type(0x05, s, t, offset);
+        let mut cpu = cpu_with_program(&[
+            lui(8, 0x1F80),
+            lui(11, 0x0100),
+            lw(9, 8, 0x10A8),
+            addiu(10, 10, 1),
+            and(9, 9, 11),
+            bne(9, 0, 0xFFFC),
+            NOP,
+        ]);
+
+        // 256 GP0 NOPs in blocks of 16 words
+        let mmu = cpu.mmu_mut();
+        mmu.write(0x1F801814, 4, 0x04000002).unwrap();
+        mmu.write(0x1F8010F0, 4, 0x0FFFFFFF).unwrap();
+        mmu.write(0x1F8010A0, 4, 0x1000).unwrap();
+        mmu.write(0x1F8010A4, 4, 0x0010_0010).unwrap();
+        mmu.write(0x1F8010A8, 4, 0x01000201).unwrap();
+
+        while cpu.pc() != PROGRAM + 28 {
+            cpu.step().unwrap();
+            assert!(cpu.register(10) < 1000);
+        }
+        assert!(cpu.register(10) > 1);
+    }
+
+    #[test]
+    fn gte_accesses_wait_for_the_running_command() {
+        const RTPT: u32 = 0x4A280030;
+        let mfc2 = |t: u32, d: u32| 0x48000000 | (t << 16) | (d << 11);
+
+        // Instructions in between overlap with the 23 cycles of RTPT
+        for alu in [0, 10, 21, 22, 30] {
+            let mut program = vec![RTPT];
+            program.extend((0..alu).map(|_| addiu(8, 8, 1)));
+            program.push(mfc2(9, 14));
+            let mut cpu = cpu_with_program(&program);
+            cpu.mmu_mut().set_cycle_accuracy(CycleAccuracy::Fast);
+            cpu.cop0.status |= 1 << 30;
+
+            let cycles: u32 = (0..program.len()).map(|_| cpu.step().unwrap()).sum();
+            assert_eq!(cycles, (alu as u32 + 2).max(24), "{} ALU instructions", alu);
+            assert_eq!(cpu.register(8), alu as u32);
+        }
+    }
+
+    #[test]
+    fn gte_is_unusable_until_enabled() {
+        let mut cpu = cpu_with_program(&[0x4A280030, i_type(0x32, 0, 0, 0)]);
+
+        run(&mut cpu, 1);
+        assert_eq!(cpu.pc(), EXCEPTION_VECTOR);
+        assert_eq!((cpu.cop0.cause >> 28) & 3, 2);
+
+        // LWC2 moves a word into a data register
+        cpu.cop0.status |= 1 << 30;
+        cpu.mmu_mut().write(0, 4, 0x12345678).unwrap();
+        fetch(&mut cpu, PROGRAM + 4);
+        assert_eq!(cpu.gte.read_data(0), 0x12345678);
+    }
+}
@@ -0,0 +1,173 @@
+use crate::cpu::Instruction;
+
+// Disassembler for debugging output, e.g. the loop of a hung guest
+
+const REGISTERS: [&str; 32] = [
+    "zero", "at", "v0", "v1", "a0", "a1", "a2", "a3", "t0", "t1", "t2", "t3", "t4", "t5", "t6",
+    "t7", "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "t8", "t9", "k0", "k1", "gp", "sp", "fp",
+    "ra",
+];
+
+// The instruction at pc, branch and jump targets are resolved to addresses
+pub fn disassemble(pc: u32, word: u32) -> String {
+    let instruction = Instruction(word);
+    let s = REGISTERS[instruction.s() as usize];
+    let t = REGISTERS[instruction.t() as usize];
+    let d = REGISTERS[instruction.d() as usize];
+    let immediate = instruction.immediate();
+    let signed = immediate as u16 as i16;
+    let branch = pc
+        .wrapping_add(4)
+        .wrapping_add(instruction.immediate_sign_extended() << 2);
+
+    match instruction.opcode() {
+        0x00 => special(&instruction, s, t, d),
+        0x01 => {
+            // Only bit 0 and bits 1..4 of rt are decoded, the other values alias these four
+            let name = match (instruction.t() & 1, instruction.t() & 0x1E == 0x10) {
+                (0, false) => "bltz",
+                (_, false) => "bgez",
+                (0, true) => "bltzal",
+                (_, true) => "bgezal",
+            };
+            format!("{} {}, 0x{:08x}", name, s, branch)
+        }
+        opcode @ (0x02 | 0x03) => {
+            let target = (pc.wrapping_add(4) & 0xF0000000) | instruction.immediate_jump();
+            let name = if opcode == 0x02 { "j" } else { "jal" };
+            format!("{} 0x{:08x}", name, target)
+        }
+        0x04 => format!("beq {}, {}, 0x{:08x}", s, t, branch),
+        0x05 => format!("bne {}, {}, 0x{:08x}", s, t, branch),
+        0x06 => format!("blez {}, 0x{:08x}", s, branch),
+        0x07 => format!("bgtz {}, 0x{:08x}", s, branch),
+        0x08 => format!("addi {}, {}, {}", t, s, signed),
+        0x09 => format!("addiu {}, {}, {}", t, s, signed),
+        0x0A => format!("slti {}, {}, {}", t, s, signed),
+        0x0B => format!("sltiu {}, {}, {}", t, s, signed),
+        0x0C => format!("andi {}, {}, 0x{:x}", t, s, immediate),
+        0x0D => format!("ori {}, {}, 0x{:x}", t, s, immediate),
+        0x0E => format!("xori {}, {}, 0x{:x}", t, s, immediate),
+        0x0F => format!("lui {}, 0x{:x}", t, immediate),
+        opcode @ 0x10..=0x13 => coprocessor(&instruction, opcode & 3, t),
+        opcode @ (0x20..=0x26 | 0x28..=0x2B | 0x2E) => {
+            let name = match opcode {
+                0x20 => "lb",
+                0x21 => "lh",
+                0x22 => "lwl",
+                0x23 => "lw",
+                0x24 => "lbu",
+                0x25 => "lhu",
+                0x26 => "lwr",
+                0x28 => "sb",
+                0x29 => "sh",
+                0x2A => "swl",
+                0x2B => "sw",
+                _ => "swr",
+            };
+            format!("{} {}, {}({})", name, t, signed, s)
+        }
+        opcode @ (0x30..=0x33 | 0x38..=0x3B) => {
+            let name = if opcode < 0x38 { "lwc" } else { "swc" };
+            format!(
+                "{}{} ${}, {}({})",
+                name,
+                opcode & 3,
+                instruction.t(),
+                signed,
+                s
+            )
+        }
+        _ => format!("illegal 0x{:08x}", word),
+    }
+}
+
+fn special(instruction: &Instruction, s: &str, t: &str, d: &str) -> String {
+    let shift = instruction.immediate_shift();
+
+    match instruction.secondary_opcode() {
+        0x00 if instruction.0 == 0 => "nop".to_string(),
+        0x00 => format!("sll {}, {}, {}", d, t, shift),
+        0x02 => format!("srl {}, {}, {}", d, t, shift),
+        0x03 => format!("sra {}, {}, {}", d, t, shift),
+        0x04 => format!("sllv {}, {}, {}", d, t, s),
+        0x06 => format!("srlv {}, {}, {}", d, t, s),
+        0x07 => format!("srav {}, {}, {}", d, t, s),
+        0x08 => format!("jr {}", s),
+        0x09 => format!("jalr {}, {}", d, s),
+        0x0C => "syscall".to_string(),
+        0x0D => "break".to_string(),
+        0x10 => format!("mfhi {}", d),
+        0x11 => format!("mthi {}", s),
+        0x12 => format!("mflo {}", d),
+        0x13 => format!("mtlo {}", s),
+        function @ 0x18..=0x1B => {
+            let name = ["mult", "multu", "div", "divu"][function as usize - 0x18];
+            format!("{} {}, {}", name, s, t)
+        }
+        function @ (0x20..=0x27 | 0x2A | 0x2B) => {
+            let name = match function {
+                0x20 => "add",
+                0x21 => "addu",
+                0x22 => "sub",
+                0x23 => "subu",
+                0x24 => "and",
+                0x25 => "or",
+                0x26 => "xor",
+                0x27 => "nor",
+                0x2A => "slt",
+                _ => "sltu",
+            };
+            format!("{} {}, {}, {}", name, d, s, t)
+        }
+        _ => format!("illegal 0x{:08x}", instruction.0),
+    }
+}
+
+fn coprocessor(instruction: &Instruction, number: u32, t: &str) -> String {
+    let d = instruction.d();
+
+    // GTE commands and RFE have bit 25 set
+    if instruction.0 & (1 << 25) != 0 {
+        return match (number, instruction.0 & 0x3F) {
+            (0, 0x10) => "rfe".to_string(),
+            _ => format!("cop{} 0x{:07x}", number, instruction.0 & 0x1FFFFFF),
+        };
+    }
+
+    match instruction.coprocessor_opcode() {
+        0x00 => format!("mfc{} {}, ${}", number, t, d),
+        0x02 => format!("cfc{} {}, ${}", number, t, d),
+        0x04 => format!("mtc{} {}, ${}", number, t, d),
+        0x06 => format!("ctc{} {}, ${}", number, t, d),
+        _ => format!("illegal 0x{:08x}", instruction.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn branches_resolve_their_target() {
+        assert_eq!(
+            disassemble(0x80010000, 0x1000FFFF),
+            "beq zero, zero, 0x80010000"
+        );
+        assert_eq!(disassemble(0x80010000, 0x0C004000), "jal 0x80010000");
+        assert_eq!(
+            disassemble(0x80010000, 0x04110002),
+            "bgezal zero, 0x8001000c"
+        );
+    }
+
+    #[test]
+    fn loads_and_stores_show_the_base_register() {
+        assert_eq!(disassemble(0, 0x8D09FFFC), "lw t1, -4(t0)");
+        assert_eq!(disassemble(0, 0xAFBF0010), "sw ra, 16(sp)");
+        assert_eq!(disassemble(0, 0x3C081F80), "lui t0, 0x1f80");
+        assert_eq!(disassemble(0, 0x00000000), "nop");
+        assert_eq!(disassemble(0, 0x42000010), "rfe");
+        assert_eq!(disassemble(0, 0x40086000), "mfc0 t0, $12");
+    }
+}
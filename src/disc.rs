@@ -0,0 +1,1630 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::File,
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use crate::archive::{self, ArchiveError, Source, Zip};
+
+#[cfg(feature = "chd")]
+use crate::chd::ChdImage;
+
+pub const SECTOR_SIZE: usize = 2352;
+// ISO images only store the 2048 bytes of user data
+const ISO_SECTOR_SIZE: usize = 2048;
+
+// The data area starts 2 seconds into the disc, the first track's pregap
+const LEAD_IN_SECTORS: u32 = 150;
+const SECTORS_PER_SECOND: u32 = 75;
+
+// Subchannel files next to a cue sheet, the LibCrypt sectors of PAL games
+const SUBCHANNEL_EXTENSIONS: [&str; 2] = ["sbi", "lsd"];
+const SBI_MAGIC: &[u8; 4] = b"SBI\0";
+
+// What is loaded from archives without a name after the #, in this order
+const IMAGE_EXTENSIONS: [&str; 4] = ["cue", "iso", "bin", "img"];
+
+pub(crate) const SYNC: [u8; 12] = [
+    0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00,
+];
+
+#[derive(Debug)]
+pub enum DiscError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Cue {
+        line: usize,
+        message: String,
+    },
+    NoTracks,
+    // The image ends before the cue sheet says it does, or in the middle of a sector
+    Truncated {
+        path: PathBuf,
+    },
+    InvalidSync {
+        track: u8,
+    },
+    OutOfRange(Msf),
+    Archive(ArchiveError),
+    Subchannel {
+        path: PathBuf,
+        message: String,
+    },
+    #[cfg(feature = "chd")]
+    Chd {
+        path: PathBuf,
+        message: String,
+    },
+}
+
+impl fmt::Display for DiscError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiscError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            DiscError::Cue { line, message } => {
+                write!(f, "Invalid cue sheet line {}: {}", line, message)
+            }
+            DiscError::NoTracks => write!(f, "The cue sheet has no tracks"),
+            DiscError::Truncated { path } => write!(f, "Truncated disc image {}", path.display()),
+            DiscError::InvalidSync { track } => {
+                write!(
+                    f,
+                    "Track {} does not start with a sector sync pattern",
+                    track
+                )
+            }
+            DiscError::OutOfRange(msf) => write!(f, "Sector {} is not on the disc", msf),
+            DiscError::Archive(error) => write!(f, "{}", error),
+            DiscError::Subchannel { path, message } => {
+                write!(f, "Invalid subchannel file {}: {}", path.display(), message)
+            }
+            #[cfg(feature = "chd")]
+            DiscError::Chd { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for DiscError {}
+
+impl From<ArchiveError> for DiscError {
+    fn from(error: ArchiveError) -> Self {
+        DiscError::Archive(error)
+    }
+}
+
+// Position on the disc in minutes, seconds and frames (sectors), 75 frames per second
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Msf {
+    pub minute: u8,
+    pub second: u8,
+    pub frame: u8,
+}
+
+impl Msf {
+    pub fn new(minute: u8, second: u8, frame: u8) -> Self {
+        Self {
+            minute,
+            second,
+            frame,
+        }
+    }
+
+    // The CDROM commands take and return the fields in BCD
+    pub fn from_bcd(minute: u8, second: u8, frame: u8) -> Self {
+        Self::new(from_bcd(minute), from_bcd(second), from_bcd(frame))
+    }
+
+    pub fn to_bcd(self) -> [u8; 3] {
+        [to_bcd(self.minute), to_bcd(self.second), to_bcd(self.frame)]
+    }
+
+    // Sectors since the start of the disc, including the lead-in
+    pub fn sector(self) -> u32 {
+        (self.minute as u32 * 60 + self.second as u32) * SECTORS_PER_SECOND + self.frame as u32
+    }
+
+    pub fn from_sector(sector: u32) -> Self {
+        Self::new(
+            (sector / SECTORS_PER_SECOND / 60) as u8,
+            (sector / SECTORS_PER_SECOND % 60) as u8,
+            (sector % SECTORS_PER_SECOND) as u8,
+        )
+    }
+
+    // Logical block address, 00:02:00 is LBA 0. None inside the lead-in.
+    pub fn lba(self) -> Option<u32> {
+        self.sector().checked_sub(LEAD_IN_SECTORS)
+    }
+
+    pub fn from_lba(lba: u32) -> Self {
+        Self::from_sector(lba + LEAD_IN_SECTORS)
+    }
+}
+
+impl fmt::Display for Msf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02}:{:02}:{:02}", self.minute, self.second, self.frame)
+    }
+}
+
+pub(crate) fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0xF)
+}
+
+pub(crate) fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TrackKind {
+    Mode1,
+    Mode2,
+    Audio,
+}
+
+#[derive(Clone, Debug)]
+pub struct Track {
+    pub number: u8,
+    pub kind: TrackKind,
+    // LBA of index 1, where GetTD says the track starts
+    pub start: u32,
+    // LBA of the first sector of the pregap, the same as start without one
+    pub pregap_start: u32,
+    // LBA after the last sector
+    pub end: u32,
+    // The file, or for CHD images the track in the image
+    file: usize,
+    // LBA of the first sector of the file. Pregaps that are not stored in the image shift the
+    // sectors after them, so this is the LBA the file would start at without them.
+    file_start: u32,
+    // Sectors at the start of the pregap that are not in the file
+    silent_pregap: u32,
+    // Sectors at the end of the track that are not in the file
+    silent_postgap: u32,
+}
+
+// Files on disk or extracted from an archive
+trait Image: Read + Seek {}
+
+impl<T: Read + Seek> Image for T {}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum FileFormat {
+    // Sectors of 2352 bytes, or of 2048 for ISOs and MODE1/2048 tracks
+    Binary(usize),
+    // CD audio with a RIFF header, 16 bit stereo samples at 44100 Hz
+    Wave,
+}
+
+struct BinFile {
+    // Files from archives are named like archive.zip#file.bin
+    path: PathBuf,
+    data: Box<dyn Image>,
+    // Where the sectors start and how many bytes they are, WAVE files have a header
+    offset: u64,
+    length: u64,
+    sectors: u32,
+    sector_size: usize,
+}
+
+// A disc image made of one or more raw 2352 byte sector BIN files, a single ISO or a CHD
+pub struct Disc {
+    tracks: Vec<Track>,
+    files: Vec<BinFile>,
+    // Subchannel Q of the sectors in an SBI or LSD file, replacing what the image has
+    subchannel: BTreeMap<Msf, [u8; 12]>,
+    #[cfg(feature = "chd")]
+    chd: Option<ChdImage>,
+}
+
+impl Disc {
+    // Opens a .cue sheet, a .chd or a bare image, which is a single data track in either the raw .bin
+    // or the .iso format. All but CHDs can also be in a .zip or .gz, see open_archive.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, DiscError> {
+        let path = path.as_ref();
+        let source = Source::of(path);
+        if source.is_archive() {
+            return Self::open_archive(source);
+        }
+
+        let has_extension = |name: &str| {
+            path.extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case(name))
+        };
+
+        #[cfg(feature = "chd")]
+        if has_extension("chd") {
+            return Self::open_chd(path);
+        }
+
+        if has_extension("cue") {
+            Self::open_cue(path)
+        } else if is_iso(path)? {
+            Self::open_iso(path)
+        } else {
+            Self::open_bin(path)
+        }
+    }
+
+    // A single MODE2/2352 track
+    pub fn open_bin(path: impl AsRef<Path>) -> Result<Self, DiscError> {
+        Self::open_single_track(path.as_ref(), SECTOR_SIZE)
+    }
+
+    // A single track of 2048 byte sectors, they are read as Mode 2 Form 1 sectors with the sync
+    // pattern, header, subheader, EDC and ECC filled in
+    pub fn open_iso(path: impl AsRef<Path>) -> Result<Self, DiscError> {
+        Self::open_single_track(path.as_ref(), ISO_SECTOR_SIZE)
+    }
+
+    fn open_single_track(path: &Path, sector_size: usize) -> Result<Self, DiscError> {
+        Self::single_track(BinFile::open(
+            path.to_path_buf(),
+            FileFormat::Binary(sector_size),
+        )?)
+    }
+
+    fn single_track(file: BinFile) -> Result<Self, DiscError> {
+        let track = Track {
+            number: 1,
+            kind: TrackKind::Mode2,
+            start: 0,
+            pregap_start: 0,
+            end: file.sectors,
+            file: 0,
+            file_start: 0,
+            silent_pregap: 0,
+            silent_postgap: 0,
+        };
+
+        Self::new(vec![track], vec![file])
+    }
+
+    pub fn open_cue(path: impl AsRef<Path>) -> Result<Self, DiscError> {
+        let path = path.as_ref();
+        let sheet = std::fs::read_to_string(path).map_err(|error| DiscError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+
+        let mut disc = Self::from_cue(&sheet, |name, format| {
+            BinFile::open(directory.join(name), format)
+        })?;
+
+        // An SBI or LSD file with the same name holds the LibCrypt sectors
+        let sidecar = SUBCHANNEL_EXTENSIONS
+            .iter()
+            .map(|extension| path.with_extension(extension))
+            .find(|path| path.is_file());
+        if let Some(sidecar) = sidecar {
+            let sectors = disc.load_subchannel(&sidecar)?;
+            println!(
+                "Loaded the subchannel of {} sectors from {}",
+                sectors,
+                sidecar.display()
+            );
+        }
+
+        Ok(disc)
+    }
+
+    // Replaces the subchannel Q of the sectors in an SBI or LSD file, returns how many there are.
+    // LSD files have the 12 bytes of Q as dumped. SBI files leave out the CRC, their sectors are
+    // the ones with broken Q data, so they get a bad CRC. They can also only change the relative
+    // or absolute time of the Q the sector would have.
+    pub fn load_subchannel(&mut self, path: impl AsRef<Path>) -> Result<usize, DiscError> {
+        let path = path.as_ref();
+        let data = std::fs::read(path).map_err(|error| DiscError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        let invalid = |message: &str| DiscError::Subchannel {
+            path: path.to_path_buf(),
+            message: message.to_string(),
+        };
+        let msf = |bcd: &[u8]| {
+            bcd.iter()
+                .all(|&byte| byte >> 4 < 10 && byte & 0xF < 10)
+                .then(|| Msf::from_bcd(bcd[0], bcd[1], bcd[2]))
+                .ok_or_else(|| invalid("Invalid sector time"))
+        };
+
+        let mut sectors = Vec::new();
+        if let Some(mut entries) = data.strip_prefix(SBI_MAGIC) {
+            while let [m, s, f, kind, rest @ ..] = entries {
+                let position = msf(&[*m, *s, *f])?;
+                let length = match kind {
+                    1 => 10,
+                    2 | 3 => 3,
+                    _ => return Err(invalid("Unknown entry type")),
+                };
+                let fields = rest
+                    .get(..length)
+                    .ok_or_else(|| invalid("Truncated entry"))?;
+                entries = &rest[length..];
+
+                let mut q = self.position_q(position);
+                match kind {
+                    1 => q[..10].copy_from_slice(fields),
+                    2 => q[3..6].copy_from_slice(fields),
+                    _ => q[7..10].copy_from_slice(fields),
+                }
+                let crc = !subchannel_crc(&q[..10]);
+                q[10..].copy_from_slice(&crc.to_be_bytes());
+                sectors.push((position, q));
+            }
+            if !entries.is_empty() {
+                return Err(invalid("Truncated entry"));
+            }
+        } else {
+            if data.is_empty() || !data.len().is_multiple_of(15) {
+                return Err(invalid("Neither an SBI nor an LSD file"));
+            }
+            for entry in data.chunks_exact(15) {
+                sectors.push((msf(&entry[..3])?, entry[3..].try_into().unwrap()));
+            }
+        }
+
+        self.subchannel.extend(sectors.iter().copied());
+        Ok(sectors.len())
+    }
+
+    // The Q data a sector has on a clean disc, with a valid CRC
+    fn position_q(&self, msf: Msf) -> [u8; 12] {
+        let mut q = [0; 12];
+        let (track, index, relative) = self.locate(msf).unwrap_or((0xAA, 1, Msf::from_lba(0)));
+        let data = self
+            .track_at(msf)
+            .is_some_and(|track| track.kind != TrackKind::Audio);
+        q[0] = if data { 0x41 } else { 0x01 };
+        q[1] = if track == 0xAA { track } else { to_bcd(track) };
+        q[2] = to_bcd(index);
+        q[3..6].copy_from_slice(&relative.to_bcd());
+        q[7..10].copy_from_slice(&msf.to_bcd());
+        let crc = subchannel_crc(&q[..10]);
+        q[10..].copy_from_slice(&crc.to_be_bytes());
+        q
+    }
+
+    // The BINs of the sheet are opened by the caller, relative to wherever the sheet came from
+    fn from_cue(
+        sheet: &str,
+        mut open_file: impl FnMut(&str, FileFormat) -> Result<BinFile, DiscError>,
+    ) -> Result<Self, DiscError> {
+        let mut files = Vec::new();
+        let mut tracks = Vec::new();
+        for entry in parse_cue(sheet)? {
+            if entry.tracks.is_empty() {
+                continue;
+            }
+
+            let file = open_file(&entry.file, entry.format)?;
+            let mut file_start = tracks.last().map_or(0, |track: &Track| track.end);
+            let first = tracks.len();
+
+            for cue_track in entry.tracks {
+                // The previous track of the same file ends where this one's pregap starts in the
+                // file, after its postgap
+                let data_end = file_start + cue_track.index0.unwrap_or(cue_track.index1);
+                if tracks.len() > first {
+                    let previous = tracks.last_mut().unwrap();
+                    previous.end = data_end + previous.silent_postgap;
+                    file_start += previous.silent_postgap;
+                }
+
+                // Gaps that are not in the file move everything after them
+                file_start += cue_track.silent_pregap;
+
+                let start = file_start + cue_track.index1;
+                let pregap_start = file_start + cue_track.index0.unwrap_or(cue_track.index1)
+                    - cue_track.silent_pregap;
+
+                tracks.push(Track {
+                    number: cue_track.number,
+                    kind: cue_track.kind,
+                    start,
+                    pregap_start,
+                    end: 0,
+                    file: files.len(),
+                    file_start,
+                    silent_pregap: cue_track.silent_pregap,
+                    silent_postgap: cue_track.silent_postgap,
+                });
+            }
+
+            let last = tracks.last_mut().ok_or(DiscError::NoTracks)?;
+            last.end = last.file_start + file.sectors + last.silent_postgap;
+            if last.end < last.start + last.silent_postgap {
+                return Err(DiscError::Truncated { path: file.path });
+            }
+
+            files.push(file);
+        }
+
+        Self::new(tracks, files)
+    }
+
+    // Images in an archive are extracted to memory. A .gz holds a single image or cue sheet, whose
+    // BINs are next to the .gz. A cue sheet in a zip finds its BINs in the same zip.
+    fn open_archive(source: Source) -> Result<Self, DiscError> {
+        match source {
+            Source::File(path) => Self::open(path),
+            Source::Gzip(path) => {
+                let data = archive::gunzip(&path)?;
+                let name = path.with_extension("");
+                if archive::has_extension(&name.to_string_lossy(), "cue") {
+                    let directory = path.parent().unwrap_or(Path::new(""));
+                    return Self::from_cue(&cue_text(&path, data)?, |name, format| {
+                        BinFile::open(directory.join(name), format)
+                    });
+                }
+
+                Self::single_track(BinFile::memory(path, data, None)?)
+            }
+            Source::Zip(path, name) => {
+                let mut zip = Zip::open(&path)?;
+                let name = match name {
+                    Some(name) => name,
+                    None => zip.find(&IMAGE_EXTENSIONS)?,
+                };
+                let member_path =
+                    |name: &str| PathBuf::from(format!("{}#{}", path.display(), name));
+                let data = zip.read(&name)?;
+
+                if !archive::has_extension(&name, "cue") {
+                    return Self::single_track(BinFile::memory(member_path(&name), data, None)?);
+                }
+
+                let sheet = cue_text(&member_path(&name), data)?;
+                // Zips always use / between directories
+                let directory = name.rsplit_once('/').map_or("", |(directory, _)| directory);
+                Self::from_cue(&sheet, |file, format| {
+                    let file = match directory {
+                        "" => file.to_string(),
+                        _ => format!("{}/{}", directory, file),
+                    };
+                    let data = zip.read(&file)?;
+                    BinFile::memory(member_path(&file), data, Some(format))
+                })
+            }
+        }
+    }
+
+    // The tracks come from the CD metadata. Pregaps are stored in the image when the metadata says
+    // so, the one of the first track is the lead-in and not readable.
+    #[cfg(feature = "chd")]
+    pub fn open_chd(path: impl AsRef<Path>) -> Result<Self, DiscError> {
+        let image = ChdImage::open(path.as_ref())?;
+
+        let mut tracks = Vec::new();
+        let mut lba = 0;
+        for (index, chd_track) in image.tracks().iter().enumerate() {
+            let stored_pregap = if chd_track.pregap_stored {
+                chd_track.pregap
+            } else {
+                0
+            };
+            let silent_pregap = if index == 0 {
+                0
+            } else {
+                chd_track.pregap - stored_pregap
+            };
+
+            let file_start = lba + silent_pregap;
+            tracks.push(Track {
+                number: chd_track.number,
+                kind: chd_track.kind,
+                start: file_start + stored_pregap,
+                pregap_start: lba,
+                end: file_start + chd_track.frames,
+                file: index,
+                file_start,
+                silent_pregap,
+                silent_postgap: 0,
+            });
+            lba = file_start + chd_track.frames;
+        }
+
+        Self {
+            tracks,
+            files: Vec::new(),
+            subchannel: BTreeMap::new(),
+            chd: Some(image),
+        }
+        .check_tracks()
+    }
+
+    fn new(tracks: Vec<Track>, files: Vec<BinFile>) -> Result<Self, DiscError> {
+        Self {
+            tracks,
+            files,
+            subchannel: BTreeMap::new(),
+            #[cfg(feature = "chd")]
+            chd: None,
+        }
+        .check_tracks()
+    }
+
+    // Data tracks have to start with a sync pattern
+    fn check_tracks(mut self) -> Result<Self, DiscError> {
+        if self.tracks.is_empty() {
+            return Err(DiscError::NoTracks);
+        }
+
+        for index in 0..self.tracks.len() {
+            let track = self.tracks[index].clone();
+            if track.kind == TrackKind::Audio || track.start == track.end {
+                continue;
+            }
+
+            let sector = self.read_sector(Msf::from_lba(track.start))?;
+            if sector[..SYNC.len()] != SYNC {
+                return Err(DiscError::InvalidSync {
+                    track: track.number,
+                });
+            }
+        }
+
+        Ok(self)
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    pub fn track(&self, number: u8) -> Option<&Track> {
+        self.tracks.iter().find(|track| track.number == number)
+    }
+
+    // (first, last) track number, for GetTN
+    pub fn track_numbers(&self) -> (u8, u8) {
+        (
+            self.tracks[0].number,
+            self.tracks[self.tracks.len() - 1].number,
+        )
+    }
+
+    // Start of the lead-out, GetTD reports it as track 0
+    pub fn lead_out(&self) -> Msf {
+        Msf::from_lba(self.tracks[self.tracks.len() - 1].end)
+    }
+
+    // The track containing the sector, pregaps are part of the track they precede
+    pub fn track_at(&self, msf: Msf) -> Option<&Track> {
+        let lba = msf.lba()?;
+        self.tracks
+            .iter()
+            .find(|track| (track.pregap_start..track.end).contains(&lba))
+    }
+
+    // Track number, index and time within the track, as in subchannel Q. In the pregap (index 0)
+    // the time counts down to the start of the track.
+    pub fn locate(&self, msf: Msf) -> Option<(u8, u8, Msf)> {
+        let track = self.track_at(msf)?;
+        let lba = msf.lba()?;
+
+        Some(match lba.checked_sub(track.start) {
+            Some(offset) => (track.number, 1, Msf::from_sector(offset)),
+            None => (track.number, 0, Msf::from_sector(track.start - lba)),
+        })
+    }
+
+    // The raw sector including sync and header. Pregaps and postgaps that are not in the image read
+    // as silence for audio tracks and as empty sectors of the track's mode otherwise.
+    pub fn read_sector(&mut self, msf: Msf) -> Result<[u8; SECTOR_SIZE], DiscError> {
+        let track = self
+            .track_at(msf)
+            .ok_or(DiscError::OutOfRange(msf))?
+            .clone();
+
+        let mut sector = [0; SECTOR_SIZE];
+        let lba = msf.lba().unwrap();
+        if lba < track.pregap_start + track.silent_pregap || lba >= track.end - track.silent_postgap
+        {
+            if track.kind != TrackKind::Audio {
+                sector[..SYNC.len()].copy_from_slice(&SYNC);
+                sector[12..15].copy_from_slice(&msf.to_bcd());
+                sector[15] = if track.kind == TrackKind::Mode1 { 1 } else { 2 };
+            }
+            return Ok(sector);
+        }
+
+        #[cfg(feature = "chd")]
+        if let Some(chd) = &mut self.chd {
+            let frame = chd.read_frame(track.file, lba - track.file_start)?;
+            sector.copy_from_slice(&frame[..SECTOR_SIZE]);
+            return Ok(sector);
+        }
+
+        let file = &mut self.files[track.file];
+        let position = (lba - track.file_start) as u64 * file.sector_size as u64;
+        let data = match (file.sector_size, track.kind) {
+            (ISO_SECTOR_SIZE, TrackKind::Mode1) => &mut sector[16..16 + ISO_SECTOR_SIZE],
+            (ISO_SECTOR_SIZE, _) => &mut sector[24..24 + ISO_SECTOR_SIZE],
+            _ => &mut sector[..],
+        };
+        // The last sector of a WAVE file can be short, the rest is silence
+        let available = file.length.saturating_sub(position).min(data.len() as u64);
+        let data = &mut data[..available as usize];
+        file.data
+            .seek(SeekFrom::Start(file.offset + position))
+            .and_then(|_| file.data.read_exact(data))
+            .map_err(|error| match error.kind() {
+                io::ErrorKind::UnexpectedEof => DiscError::Truncated {
+                    path: file.path.clone(),
+                },
+                _ => DiscError::Io {
+                    path: file.path.clone(),
+                    error,
+                },
+            })?;
+
+        match (file.sector_size, track.kind) {
+            (ISO_SECTOR_SIZE, TrackKind::Mode1) => encode_mode1(&mut sector, msf),
+            (ISO_SECTOR_SIZE, _) => encode_mode2_form1(&mut sector, msf),
+            _ => {}
+        }
+
+        Ok(sector)
+    }
+
+    // The Q channel of the sector's subchannel data, from a subchannel file or a CHD image. Sectors
+    // of a subchannel file can have a bad CRC.
+    pub fn read_subchannel_q(&mut self, msf: Msf) -> Option<[u8; 12]> {
+        if let Some(q) = self.subchannel.get(&msf) {
+            println!("Read the replaced subchannel Q of sector {}", msf);
+            return Some(*q);
+        }
+
+        #[cfg(feature = "chd")]
+        if let Some(track) = self.track_at(msf).cloned() {
+            let lba = msf.lba().unwrap();
+            let stored = lba >= track.pregap_start + track.silent_pregap
+                && lba < track.end - track.silent_postgap;
+            let chd = self
+                .chd
+                .as_mut()
+                .filter(|chd| chd.tracks()[track.file].has_subchannel);
+            if let (true, Some(chd)) = (stored, chd) {
+                let frame = chd.read_frame(track.file, lba - track.file_start).ok()?;
+                return crate::chd::subchannel_q(&frame[SECTOR_SIZE..]);
+            }
+        }
+
+        None
+    }
+}
+
+impl BinFile {
+    fn open(path: PathBuf, format: FileFormat) -> Result<Self, DiscError> {
+        let io_error = |error| DiscError::Io {
+            path: path.clone(),
+            error,
+        };
+
+        let file = File::open(&path).map_err(io_error)?;
+        let size = file.metadata().map_err(io_error)?.len();
+        Self::new(path, Box::new(file), size, format)
+    }
+
+    // An extracted file, the sector size of bare images is detected like for files on disk
+    fn memory(path: PathBuf, data: Vec<u8>, format: Option<FileFormat>) -> Result<Self, DiscError> {
+        let size = data.len() as u64;
+        let mut data = Cursor::new(data);
+        let format = match format {
+            Some(format) => format,
+            None if is_iso_image(&mut data, size) => FileFormat::Binary(ISO_SECTOR_SIZE),
+            None => FileFormat::Binary(SECTOR_SIZE),
+        };
+
+        Self::new(path, Box::new(data), size, format)
+    }
+
+    fn new(
+        path: PathBuf,
+        mut data: Box<dyn Image>,
+        size: u64,
+        format: FileFormat,
+    ) -> Result<Self, DiscError> {
+        let (offset, length, sector_size) = match format {
+            FileFormat::Binary(sector_size) => {
+                if !size.is_multiple_of(sector_size as u64) {
+                    return Err(DiscError::Truncated { path });
+                }
+                (0, size, sector_size)
+            }
+            FileFormat::Wave => {
+                let (offset, length) = wave_data(&mut data).map_err(|error| DiscError::Io {
+                    path: path.clone(),
+                    error,
+                })?;
+                (offset, length.min(size.saturating_sub(offset)), SECTOR_SIZE)
+            }
+        };
+
+        Ok(Self {
+            path,
+            data,
+            offset,
+            length,
+            sectors: length.div_ceil(sector_size as u64) as u32,
+            sector_size,
+        })
+    }
+}
+
+// Offset and length of the samples of a WAVE file, which have to be CD audio
+fn wave_data(file: &mut Box<dyn Image>) -> io::Result<(u64, u64)> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    let mut header = [0; 12];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header)?;
+    if header[..4] != *b"RIFF" || header[8..] != *b"WAVE" {
+        return Err(invalid("Not a WAVE file"));
+    }
+
+    // Chunks of an ID and a little endian size, padded to an even size
+    let mut offset = 12;
+    let mut has_format = false;
+    loop {
+        let mut chunk = [0; 8];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut chunk)
+            .map_err(|_| invalid("The WAVE file has no data chunk"))?;
+        let size = u32::from_le_bytes(chunk[4..].try_into().unwrap()) as u64;
+        offset += 8;
+
+        match &chunk[..4] {
+            b"fmt " => {
+                let mut format = [0; 16];
+                file.read_exact(&mut format)?;
+                // PCM, 2 channels, 44100 Hz, 4 bytes per sample frame, 16 bits
+                let cd_audio = u16::from_le_bytes([format[0], format[1]]) == 1
+                    && u16::from_le_bytes([format[2], format[3]]) == 2
+                    && u32::from_le_bytes(format[4..8].try_into().unwrap()) == 44100
+                    && u16::from_le_bytes([format[12], format[13]]) == 4
+                    && u16::from_le_bytes([format[14], format[15]]) == 16;
+                if !cd_audio {
+                    return Err(invalid(
+                        "Only 16 bit stereo WAVE files at 44100 Hz are supported",
+                    ));
+                }
+                has_format = true;
+            }
+            b"data" if has_format => return Ok((offset, size)),
+            b"data" => return Err(invalid("The WAVE file has no format chunk")),
+            _ => {}
+        }
+        offset += size + (size & 1);
+    }
+}
+
+// CRC-16-CCITT of the first 10 bytes of Q, the disc stores it inverted in the last two
+pub(crate) fn subchannel_crc(data: &[u8]) -> u16 {
+    !data.iter().fold(0u16, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            (crc << 1) ^ if crc & 0x8000 != 0 { 0x1021 } else { 0 }
+        })
+    })
+}
+
+pub(crate) fn is_subchannel_q_valid(q: &[u8; 12]) -> bool {
+    subchannel_crc(&q[..10]) == u16::from_be_bytes([q[10], q[11]])
+}
+
+fn cue_text(path: &Path, data: Vec<u8>) -> Result<String, DiscError> {
+    String::from_utf8(data).map_err(|_| DiscError::Io {
+        path: path.to_path_buf(),
+        error: io::Error::new(io::ErrorKind::InvalidData, "The cue sheet is not text"),
+    })
+}
+
+// Whether a bare image is an ISO. The primary volume descriptor in sector 16 tells them apart, images
+// without one are recognized by their size.
+fn is_iso(path: &Path) -> Result<bool, DiscError> {
+    let io_error = |error| DiscError::Io {
+        path: path.to_path_buf(),
+        error,
+    };
+
+    let mut file = File::open(path).map_err(io_error)?;
+    let size = file.metadata().map_err(io_error)?.len();
+
+    Ok(is_iso_image(&mut file, size))
+}
+
+fn is_iso_image(image: &mut dyn Image, size: u64) -> bool {
+    let mut has_descriptor = |offset: usize| {
+        let mut signature = [0; 6];
+        image
+            .seek(SeekFrom::Start(offset as u64))
+            .and_then(|_| image.read_exact(&mut signature))
+            .is_ok_and(|_| signature == *b"\x01CD001")
+    };
+
+    if has_descriptor(16 * ISO_SECTOR_SIZE) {
+        true
+    } else if has_descriptor(16 * SECTOR_SIZE + 24) {
+        false
+    } else {
+        !size.is_multiple_of(SECTOR_SIZE as u64) && size.is_multiple_of(ISO_SECTOR_SIZE as u64)
+    }
+}
+
+// Fills in everything around the 2048 data bytes of a Mode 2 Form 1 sector
+fn encode_mode2_form1(sector: &mut [u8; SECTOR_SIZE], msf: Msf) {
+    sector[..SYNC.len()].copy_from_slice(&SYNC);
+    sector[12..15].copy_from_slice(&msf.to_bcd());
+    sector[15] = 2;
+    // File, channel, submode (data) and coding info, twice
+    sector[16..24].copy_from_slice(&[0, 0, 0x08, 0, 0, 0, 0x08, 0]);
+
+    let edc = edc(&sector[16..0x818]);
+    sector[0x818..0x81C].copy_from_slice(&edc.to_le_bytes());
+
+    // The header is not covered by the ECC of Mode 2 sectors
+    let header: [u8; 4] = sector[12..16].try_into().unwrap();
+    sector[12..16].fill(0);
+    // P parity over 86 columns of 24 bytes, then Q parity over 52 diagonals of 43 bytes, which
+    // include the P parity
+    ecc_block(sector, 86, 24, 2, 86, 0x81C);
+    ecc_block(sector, 52, 43, 86, 88, 0x8C8);
+    sector[12..16].copy_from_slice(&header);
+}
+
+// Fills in everything around the 2048 data bytes of a Mode 1 sector
+fn encode_mode1(sector: &mut [u8; SECTOR_SIZE], msf: Msf) {
+    sector[..SYNC.len()].copy_from_slice(&SYNC);
+    sector[12..15].copy_from_slice(&msf.to_bcd());
+    sector[15] = 1;
+
+    // The EDC includes the sync pattern and header, 8 zero bytes follow it
+    let edc = edc(&sector[..0x810]);
+    sector[0x810..0x814].copy_from_slice(&edc.to_le_bytes());
+    sector[0x814..0x81C].fill(0);
+
+    // The same parity as Mode 2 Form 1, with the header included
+    ecc_block(sector, 86, 24, 2, 86, 0x81C);
+    ecc_block(sector, 52, 43, 86, 88, 0x8C8);
+}
+
+// CRC-32 with the polynomial 0x8001801B, bit reversed
+const EDC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut edc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            edc = (edc >> 1) ^ if edc & 1 != 0 { 0xD801_8001 } else { 0 };
+            bit += 1;
+        }
+        table[i] = edc;
+        i += 1;
+    }
+    table
+};
+
+fn edc(data: &[u8]) -> u32 {
+    data.iter().fold(0, |edc, &byte| {
+        (edc >> 8) ^ EDC_TABLE[((edc ^ byte as u32) & 0xFF) as usize]
+    })
+}
+
+// Multiplication by 2 in GF(2^8) with the polynomial 0x11D, and the inverse of multiplying by 3
+const ECC_F_TABLE: [u8; 256] = ecc_tables().0;
+const ECC_B_TABLE: [u8; 256] = ecc_tables().1;
+
+const fn ecc_tables() -> ([u8; 256], [u8; 256]) {
+    let mut forward = [0; 256];
+    let mut backward = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let j = (i << 1) ^ if i & 0x80 != 0 { 0x11D } else { 0 };
+        forward[i] = j as u8;
+        backward[i ^ j] = i as u8;
+        i += 1;
+    }
+    (forward, backward)
+}
+
+// Reed-Solomon parity of the bytes starting at the header, written to output and output + major
+fn ecc_block(
+    sector: &mut [u8; SECTOR_SIZE],
+    major_count: usize,
+    minor_count: usize,
+    major_step: usize,
+    minor_step: usize,
+    output: usize,
+) {
+    let size = major_count * minor_count;
+    for major in 0..major_count {
+        let mut index = (major >> 1) * major_step + (major & 1);
+        let mut a = 0;
+        let mut b = 0;
+        for _ in 0..minor_count {
+            let value = sector[12 + index];
+            index += minor_step;
+            if index >= size {
+                index -= size;
+            }
+            a = ECC_F_TABLE[(a ^ value) as usize];
+            b ^= value;
+        }
+
+        let a = ECC_B_TABLE[(ECC_F_TABLE[a as usize] ^ b) as usize];
+        sector[output + major] = a;
+        sector[output + major + major_count] = a ^ b;
+    }
+}
+
+struct CueFile {
+    file: String,
+    format: FileFormat,
+    tracks: Vec<CueTrack>,
+}
+
+struct CueTrack {
+    number: u8,
+    kind: TrackKind,
+    // Sectors relative to the start of the file
+    index0: Option<u32>,
+    index1: u32,
+    // PREGAP and POSTGAP, sectors that are not stored in the file
+    silent_pregap: u32,
+    silent_postgap: u32,
+}
+
+fn parse_cue(sheet: &str) -> Result<Vec<CueFile>, DiscError> {
+    let mut files: Vec<CueFile> = Vec::new();
+    let mut index1_seen = true;
+
+    for (number, line) in sheet.lines().enumerate() {
+        let line_number = number + 1;
+        let error = |message: &str| DiscError::Cue {
+            line: line_number,
+            message: message.to_string(),
+        };
+
+        let line = line.trim();
+        let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+
+        match keyword.to_ascii_uppercase().as_str() {
+            "FILE" => {
+                // The name may be quoted and contain spaces, the type comes last
+                let (name, kind) = rest
+                    .rsplit_once(char::is_whitespace)
+                    .ok_or(error("Missing file type"))?;
+                let format = match kind.to_ascii_uppercase().as_str() {
+                    "BINARY" => FileFormat::Binary(SECTOR_SIZE),
+                    "WAVE" => FileFormat::Wave,
+                    _ => return Err(error("Only BINARY and WAVE files are supported")),
+                };
+
+                files.push(CueFile {
+                    file: name.trim().trim_matches('"').to_string(),
+                    format,
+                    tracks: Vec::new(),
+                });
+            }
+            "TRACK" => {
+                if !index1_seen {
+                    return Err(error("Track without INDEX 01"));
+                }
+                let file = files.last_mut().ok_or(error("TRACK before FILE"))?;
+
+                let (number, mode) = rest
+                    .split_once(char::is_whitespace)
+                    .ok_or(error("Missing track mode"))?;
+                let number = number.parse().map_err(|_| error("Invalid track number"))?;
+                let (kind, sector_size) = match mode.trim().to_ascii_uppercase().as_str() {
+                    "MODE1/2048" => (TrackKind::Mode1, ISO_SECTOR_SIZE),
+                    "MODE1/2352" => (TrackKind::Mode1, SECTOR_SIZE),
+                    "MODE2/2352" => (TrackKind::Mode2, SECTOR_SIZE),
+                    "AUDIO" => (TrackKind::Audio, SECTOR_SIZE),
+                    _ => return Err(error(
+                        "Only MODE1/2048, MODE1/2352, MODE2/2352 and AUDIO tracks are supported",
+                    )),
+                };
+
+                // All tracks of a file have sectors of the same size
+                match file.format {
+                    FileFormat::Wave if kind != TrackKind::Audio => {
+                        return Err(error("WAVE files can only hold AUDIO tracks"))
+                    }
+                    FileFormat::Binary(size) if !file.tracks.is_empty() && size != sector_size => {
+                        return Err(error("The tracks of a file have different sector sizes"))
+                    }
+                    FileFormat::Binary(_) => file.format = FileFormat::Binary(sector_size),
+                    FileFormat::Wave => {}
+                }
+
+                file.tracks.push(CueTrack {
+                    number,
+                    kind,
+                    index0: None,
+                    index1: 0,
+                    silent_pregap: 0,
+                    silent_postgap: 0,
+                });
+                index1_seen = false;
+            }
+            "INDEX" | "PREGAP" | "POSTGAP" => {
+                let track = files
+                    .last_mut()
+                    .and_then(|file| file.tracks.last_mut())
+                    .ok_or(error("INDEX, PREGAP or POSTGAP outside of a track"))?;
+
+                let keyword = keyword.to_ascii_uppercase();
+                let (index, time) = match keyword.as_str() {
+                    "INDEX" => rest
+                        .split_once(char::is_whitespace)
+                        .ok_or(error("Missing index time"))?,
+                    _ => (keyword.as_str(), rest),
+                };
+                let sectors = parse_time(time.trim()).ok_or(error("Invalid time"))?;
+
+                match index {
+                    "PREGAP" => track.silent_pregap = sectors,
+                    "POSTGAP" => track.silent_postgap = sectors,
+                    "00" | "0" => track.index0 = Some(sectors),
+                    "01" | "1" => {
+                        track.index1 = sectors;
+                        index1_seen = true;
+                    }
+                    // Subindices inside the track
+                    _ => {}
+                }
+            }
+            // REM, CATALOG, FLAGS, TITLE, ISRC and friends
+            _ => {}
+        }
+    }
+
+    if !index1_seen {
+        return Err(DiscError::Cue {
+            line: sheet.lines().count(),
+            message: "Track without INDEX 01".to_string(),
+        });
+    }
+    if files.iter().all(|file| file.tracks.is_empty()) {
+        return Err(DiscError::NoTracks);
+    }
+
+    Ok(files)
+}
+
+// mm:ss:ff in decimal
+fn parse_time(time: &str) -> Option<u32> {
+    let mut parts = time.split(':').map(|part| part.parse::<u32>().ok());
+    let (minute, second, frame) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() || second >= 60 || frame >= SECTORS_PER_SECOND {
+        return None;
+    }
+
+    Some((minute * 60 + second) * SECTORS_PER_SECOND + frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("psx-rust-{}-{}", std::process::id(), name))
+    }
+
+    // Data sectors have a sync pattern and header, the remaining bytes of every sector are its LBA
+    fn sector(lba: u32, data: bool) -> Vec<u8> {
+        let mut sector = vec![lba as u8; SECTOR_SIZE];
+        if data {
+            sector[..SYNC.len()].copy_from_slice(&SYNC);
+            sector[12..15].copy_from_slice(&Msf::from_lba(lba).to_bcd());
+            sector[15] = 2;
+        }
+        sector
+    }
+
+    // A 10 sector data track followed by an audio track with a 2 sector pregap, in one file
+    fn two_track_disc(name: &str) -> Disc {
+        let bin = temp_path(&format!("{}.bin", name));
+        let cue = temp_path(&format!("{}.cue", name));
+        let image: Vec<u8> = (0..20).flat_map(|lba| sector(lba, lba < 10)).collect();
+        std::fs::write(&bin, image).unwrap();
+        let sheet = format!(
+            "FILE \"{}\" BINARY\n  TRACK 01 MODE2/2352\n    INDEX 01 00:00:00\n  TRACK 02 AUDIO\n    INDEX 00 00:00:10\n    INDEX 01 00:00:12\n",
+            bin.file_name().unwrap().to_str().unwrap()
+        );
+        std::fs::write(&cue, sheet).unwrap();
+
+        let disc = Disc::open(&cue).unwrap();
+        std::fs::remove_file(&bin).unwrap();
+        std::fs::remove_file(&cue).unwrap();
+        disc
+    }
+
+    #[test]
+    fn msf_and_lba_are_two_seconds_apart() {
+        assert_eq!(Msf::new(0, 2, 0).lba(), Some(0));
+        assert_eq!(Msf::new(0, 1, 74).lba(), None);
+        assert_eq!(Msf::from_lba(75 * 60), Msf::new(1, 2, 0));
+        assert_eq!(Msf::from_bcd(0x12, 0x34, 0x56), Msf::new(12, 34, 56));
+        assert_eq!(Msf::new(12, 34, 56).to_bcd(), [0x12, 0x34, 0x56]);
+    }
+
+    #[test]
+    fn sectors_are_addressed_across_the_track_boundary() {
+        let mut disc = two_track_disc("boundary");
+        assert_eq!(disc.track_numbers(), (1, 2));
+        assert_eq!(disc.lead_out(), Msf::new(0, 2, 20));
+        let track = disc.track(2).unwrap();
+        assert_eq!((track.pregap_start, track.start, track.end), (10, 12, 20));
+
+        // The last sector of the data track and the audio track's pregap
+        let last = Msf::new(0, 2, 9);
+        assert_eq!(disc.read_sector(last).unwrap()[..], sector(9, true)[..]);
+        assert_eq!(disc.locate(last), Some((1, 1, Msf::new(0, 0, 9))));
+        let pregap = Msf::new(0, 2, 10);
+        assert_eq!(disc.read_sector(pregap).unwrap()[..], sector(10, false)[..]);
+        assert_eq!(disc.locate(pregap), Some((2, 0, Msf::new(0, 0, 2))));
+        let start = Msf::new(0, 2, 12);
+        assert_eq!(disc.read_sector(start).unwrap()[..], sector(12, false)[..]);
+        assert_eq!(disc.locate(start), Some((2, 1, Msf::new(0, 0, 0))));
+
+        assert!(matches!(
+            disc.read_sector(Msf::new(0, 2, 20)),
+            Err(DiscError::OutOfRange(_))
+        ));
+        assert!(disc.read_sector(Msf::new(0, 1, 0)).is_err());
+    }
+
+    #[test]
+    fn broken_images_are_rejected() {
+        // Half a sector
+        let bin = temp_path("truncated.bin");
+        std::fs::write(&bin, vec![0; SECTOR_SIZE + SECTOR_SIZE / 2]).unwrap();
+        assert!(matches!(
+            Disc::open_bin(&bin),
+            Err(DiscError::Truncated { .. })
+        ));
+
+        // A data track without the sync pattern
+        std::fs::write(&bin, vec![0; SECTOR_SIZE * 2]).unwrap();
+        assert!(matches!(
+            Disc::open_bin(&bin),
+            Err(DiscError::InvalidSync { track: 1 })
+        ));
+        std::fs::remove_file(&bin).unwrap();
+
+        assert!(matches!(
+            parse_cue("TRACK 01 MODE2/2352\n"),
+            Err(DiscError::Cue { line: 1, .. })
+        ));
+        assert!(matches!(
+            parse_cue("FILE \"a.bin\" BINARY\n  TRACK 01 MODE2/2352\n"),
+            Err(DiscError::Cue { .. })
+        ));
+        for sheet in [
+            "FILE \"a.bin\" MOTOROLA\n",
+            "FILE \"a.wav\" WAVE\n  TRACK 01 MODE2/2352\n",
+            "FILE \"a.bin\" BINARY\n  TRACK 01 MODE1/2048\n    INDEX 01 00:00:00\n  TRACK 02 AUDIO\n",
+        ] {
+            assert!(
+                matches!(parse_cue(sheet), Err(DiscError::Cue { line: 1..=4, .. })),
+                "{}",
+                sheet
+            );
+        }
+    }
+
+    // CRC-32 of the EDC, one bit at a time
+    fn reference_edc(data: &[u8]) -> u32 {
+        let mut edc = 0u32;
+        for byte in data {
+            edc ^= *byte as u32;
+            for _ in 0..8 {
+                edc = (edc >> 1) ^ if edc & 1 != 0 { 0xD8018001 } else { 0 };
+            }
+        }
+        edc
+    }
+
+    // Both syndromes of a Reed-Solomon codeword are zero, the sum of the bytes and the sum of each
+    // byte times alpha to the power of its distance from the end
+    fn is_codeword(bytes: &[u8]) -> bool {
+        let times_alpha =
+            |value: u8| ((value as u16) << 1 ^ if value & 0x80 != 0 { 0x11D } else { 0 }) as u8;
+        let sum = bytes.iter().fold(0, |sum, byte| sum ^ byte);
+        let weighted = bytes.iter().fold(0, |sum, byte| times_alpha(sum) ^ byte);
+        sum == 0 && weighted == 0
+    }
+
+    // The P parity covers columns of the 43 rows of 86 bytes from the header on, the Q parity
+    // diagonals through those and the P parity
+    fn assert_parity(sector: &[u8]) {
+        let area = &sector[12..];
+        for column in 0..86 {
+            let codeword: Vec<u8> = (0..26).map(|row| area[column + row * 86]).collect();
+            assert!(is_codeword(&codeword), "P column {}", column);
+        }
+        for diagonal in 0..52 {
+            let start = (diagonal / 2) * 86 + diagonal % 2;
+            let mut codeword: Vec<u8> = (0..43).map(|i| area[(start + i * 88) % 2236]).collect();
+            codeword.push(area[2236 + diagonal]);
+            codeword.push(area[2236 + 52 + diagonal]);
+            assert!(is_codeword(&codeword), "Q diagonal {}", diagonal);
+        }
+    }
+
+    #[test]
+    fn iso_sectors_are_completed_as_mode2_form1() {
+        let iso = temp_path("form1.iso");
+        // 147 ISO sectors are exactly 128 raw ones
+        let mut image: Vec<u8> = (0..147 * ISO_SECTOR_SIZE)
+            .map(|i| (i * 7 / 3) as u8)
+            .collect();
+        image[16 * ISO_SECTOR_SIZE..][..6].copy_from_slice(b"\x01CD001");
+        std::fs::write(&iso, &image).unwrap();
+        // Recognized by the volume descriptor, even with a size that is also a multiple of 2352
+        assert!(is_iso(&iso).unwrap());
+        let mut disc = Disc::open(&iso).unwrap();
+        std::fs::remove_file(&iso).unwrap();
+
+        let msf = Msf::new(0, 2, 17);
+        let mut sector = disc.read_sector(msf).unwrap();
+        assert_eq!(sector[..12], SYNC);
+        assert_eq!(sector[12..16], [0x00, 0x02, 0x17, 0x02]);
+        assert_eq!(sector[16..24], [0, 0, 0x08, 0, 0, 0, 0x08, 0]);
+        assert_eq!(
+            sector[24..0x818],
+            image[17 * ISO_SECTOR_SIZE..18 * ISO_SECTOR_SIZE]
+        );
+        let edc = u32::from_le_bytes(sector[0x818..0x81C].try_into().unwrap());
+        assert_eq!(edc, reference_edc(&sector[16..0x818]));
+
+        // The header counts as zero
+        sector[12..16].fill(0);
+        assert_parity(&sector);
+    }
+
+    #[cfg(feature = "deflate")]
+    fn archive(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/archives")
+            .join(name)
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn cue_sheets_in_zips_find_their_bins_in_the_archive() {
+        let mut disc = Disc::open(archive("disc.zip")).unwrap();
+
+        assert_eq!(disc.track_numbers(), (1, 2));
+        let audio = disc.track(2).unwrap();
+        assert_eq!((audio.pregap_start, audio.start, audio.end), (4, 6, 10));
+        assert_eq!(disc.read_sector(Msf::from_lba(3)).unwrap()[100], 3);
+        // The fourth sector of the second BIN
+        assert_eq!(disc.read_sector(Msf::from_lba(7)).unwrap()[0], 10);
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn gzipped_isos_are_completed_like_files_on_disk() {
+        let mut disc = Disc::open(archive("disc.iso.gz")).unwrap();
+
+        assert_eq!(disc.lead_out(), Msf::from_lba(20));
+        let sector = disc.read_sector(Msf::from_lba(5)).unwrap();
+        assert_eq!(sector[..SYNC.len()], SYNC);
+        assert_eq!(sector[24 + 100], 5);
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn zips_with_several_discs_need_a_name() {
+        let error = Disc::open(archive("two-discs.zip")).err().unwrap();
+        assert!(matches!(
+            error,
+            DiscError::Archive(ArchiveError::Ambiguous { .. })
+        ));
+        assert!(error.to_string().contains("Disc 1.cue, Disc 2.cue"));
+
+        let path = format!("{}#Disc 2.cue", archive("two-discs.zip").display());
+        assert_eq!(Disc::open(path).unwrap().lead_out(), Msf::from_lba(3));
+    }
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join(name)
+    }
+
+    #[test]
+    fn sbi_next_to_the_cue_replaces_the_subchannel() {
+        let bin = temp_path("libcrypt.bin");
+        let cue = temp_path("libcrypt.cue");
+        let sbi = temp_path("libcrypt.sbi");
+        let image: Vec<u8> = (0..30).flat_map(|lba| sector(lba, true)).collect();
+        std::fs::write(&bin, image).unwrap();
+        let sheet = format!(
+            "FILE \"{}\" BINARY\n  TRACK 01 MODE2/2352\n    INDEX 01 00:00:00\n",
+            bin.file_name().unwrap().to_str().unwrap()
+        );
+        std::fs::write(&cue, sheet).unwrap();
+        std::fs::copy(fixture("sbi/libcrypt.sbi"), &sbi).unwrap();
+
+        let mut disc = Disc::open(&cue).unwrap();
+        for path in [bin, cue, sbi] {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        // The whole Q is stored, with a bad CRC
+        let q = disc.read_subchannel_q(Msf::from_lba(10)).unwrap();
+        assert_eq!(
+            &q[..10],
+            &[0x41, 0x01, 0x01, 0x80, 0x00, 0x10, 0x00, 0x00, 0x02, 0x10]
+        );
+        assert!(!is_subchannel_q_valid(&q));
+
+        // Only the absolute time is replaced
+        let q = disc.read_subchannel_q(Msf::from_lba(20)).unwrap();
+        assert_eq!(
+            &q[..10],
+            &[0x41, 0x01, 0x01, 0x00, 0x00, 0x20, 0x00, 0x00, 0x12, 0x20]
+        );
+        assert!(!is_subchannel_q_valid(&q));
+
+        // Other sectors have no stored subchannel
+        assert_eq!(disc.read_subchannel_q(Msf::from_lba(11)), None);
+    }
+
+    #[test]
+    fn lsd_sectors_keep_their_crc() {
+        let mut disc = two_track_disc("lsd");
+        let lsd = temp_path("subchannel.lsd");
+        let mut q = disc.position_q(Msf::from_lba(11));
+        assert!(is_subchannel_q_valid(&q));
+        // The audio track's pregap
+        assert_eq!(&q[..6], &[0x01, 0x02, 0x00, 0x00, 0x00, 0x01]);
+        q[5] = 0x04;
+        std::fs::write(&lsd, [&Msf::from_lba(11).to_bcd()[..], &q].concat()).unwrap();
+
+        assert_eq!(disc.load_subchannel(&lsd).unwrap(), 1);
+        assert_eq!(disc.read_subchannel_q(Msf::from_lba(11)), Some(q));
+        assert!(!is_subchannel_q_valid(&q));
+
+        std::fs::write(&lsd, [0; 14]).unwrap();
+        assert!(matches!(
+            disc.load_subchannel(&lsd),
+            Err(DiscError::Subchannel { .. })
+        ));
+        std::fs::remove_file(lsd).unwrap();
+    }
+
+    enum Layout {
+        // Raw sectors, the first ones data sectors of the mode
+        Bin { data: u32, mode: u8 },
+        Iso,
+        Wave,
+    }
+
+    struct CueCase {
+        fixture: &'static str,
+        // Name, layout and size in bytes
+        files: &'static [(&'static str, Layout, usize)],
+        // Number, kind, pregap start, start and end
+        tracks: &'static [(u8, TrackKind, u32, u32, u32)],
+        // LBA, bytes 1000 and 2000 of the sector, and its track, index and relative time
+        probes: &'static [(u32, [u8; 2], u8, u8, u32)],
+    }
+
+    // The user data of every sector is a number for the file and sector it came from
+    const fn marker(file: usize, sector: usize) -> u8 {
+        (file * 50 + sector + 1) as u8
+    }
+
+    fn cue_case_file(index: usize, layout: &Layout, size: usize) -> Vec<u8> {
+        match layout {
+            Layout::Bin { data, mode } => (0..size)
+                .map(
+                    |offset| match (offset / SECTOR_SIZE, offset % SECTOR_SIZE) {
+                        (sector, byte) if (sector as u32) < *data && byte < 12 => SYNC[byte],
+                        (sector, 15) if (sector as u32) < *data => *mode,
+                        (sector, _) => marker(index, sector),
+                    },
+                )
+                .collect(),
+            Layout::Iso => (0..size)
+                .map(|offset| marker(index, offset / ISO_SECTOR_SIZE))
+                .collect(),
+            Layout::Wave => {
+                let mut wave = b"RIFF\0\0\0\0WAVE".to_vec();
+                wave.extend(b"fmt \x10\0\0\0\x01\0\x02\0\x44\xAC\0\0\x10\xB1\x02\0\x04\0\x10\0");
+                // Chunks of an odd size are padded
+                wave.extend(b"LIST\x03\0\0\0abc\0");
+                wave.extend(b"data");
+                wave.extend((size as u32).to_le_bytes());
+                wave.extend((0..size).map(|offset| marker(index, offset / SECTOR_SIZE)));
+                wave
+            }
+        }
+    }
+
+    const S: usize = SECTOR_SIZE;
+
+    const CUE_CASES: [CueCase; 4] = [
+        CueCase {
+            fixture: "multi-bin.cue",
+            files: &[
+                (
+                    "Game (Track 1).bin",
+                    Layout::Bin { data: 10, mode: 2 },
+                    10 * S,
+                ),
+                (
+                    "Game (Track 2).bin",
+                    Layout::Bin { data: 0, mode: 0 },
+                    12 * S,
+                ),
+                (
+                    "Game (Track 3).bin",
+                    Layout::Bin { data: 0, mode: 0 },
+                    8 * S,
+                ),
+            ],
+            tracks: &[
+                (1, TrackKind::Mode2, 0, 0, 10),
+                (2, TrackKind::Audio, 10, 14, 22),
+                (3, TrackKind::Audio, 22, 25, 30),
+            ],
+            probes: &[
+                (9, [marker(0, 9); 2], 1, 1, 9),
+                (12, [marker(1, 2); 2], 2, 0, 2),
+                (14, [marker(1, 4); 2], 2, 1, 0),
+                (29, [marker(2, 7); 2], 3, 1, 4),
+            ],
+        },
+        CueCase {
+            fixture: "gaps.cue",
+            files: &[("gaps.bin", Layout::Bin { data: 10, mode: 1 }, 20 * S)],
+            tracks: &[
+                (1, TrackKind::Mode1, 0, 0, 12),
+                (2, TrackKind::Audio, 12, 15, 20),
+                (3, TrackKind::Audio, 20, 22, 26),
+            ],
+            probes: &[
+                (9, [marker(0, 9); 2], 1, 1, 9),
+                // The postgap of a data track is empty data sectors, gaps of audio tracks silence
+                (10, [0; 2], 1, 1, 10),
+                (13, [0; 2], 2, 0, 2),
+                (15, [marker(0, 10); 2], 2, 1, 0),
+                (21, [marker(0, 16); 2], 3, 0, 1),
+                (24, [marker(0, 19); 2], 3, 1, 2),
+                (25, [0; 2], 3, 1, 3),
+            ],
+        },
+        CueCase {
+            fixture: "wave.cue",
+            files: &[
+                ("data.bin", Layout::Bin { data: 6, mode: 2 }, 6 * S),
+                ("track02.wav", Layout::Wave, 5 * S + S / 2),
+            ],
+            tracks: &[
+                (1, TrackKind::Mode2, 0, 0, 6),
+                (2, TrackKind::Audio, 6, 8, 12),
+            ],
+            probes: &[
+                (5, [marker(0, 5); 2], 1, 1, 5),
+                (7, [marker(1, 1); 2], 2, 0, 1),
+                // Half a sector of samples
+                (11, [marker(1, 5), 0], 2, 1, 3),
+            ],
+        },
+        CueCase {
+            fixture: "mode1-2048.cue",
+            files: &[
+                ("data.iso", Layout::Iso, 8 * ISO_SECTOR_SIZE),
+                ("audio.bin", Layout::Bin { data: 0, mode: 0 }, 6 * S),
+            ],
+            tracks: &[
+                (1, TrackKind::Mode1, 0, 0, 8),
+                (2, TrackKind::Audio, 8, 10, 14),
+            ],
+            probes: &[
+                (3, [marker(0, 3); 2], 1, 1, 3),
+                (9, [marker(1, 1); 2], 2, 0, 1),
+                (13, [marker(1, 5); 2], 2, 1, 3),
+            ],
+        },
+    ];
+
+    fn open_cue_case(case: &CueCase) -> Disc {
+        let sheet = std::fs::read_to_string(fixture("cue").join(case.fixture)).unwrap();
+        Disc::from_cue(&sheet, |name, format| {
+            let (index, (_, layout, size)) = (case.files.iter().enumerate())
+                .find(|(_, (file, _, _))| *file == name)
+                .unwrap_or_else(|| panic!("{}: unexpected file {}", case.fixture, name));
+            let expected = match layout {
+                Layout::Bin { .. } => FileFormat::Binary(SECTOR_SIZE),
+                Layout::Iso => FileFormat::Binary(ISO_SECTOR_SIZE),
+                Layout::Wave => FileFormat::Wave,
+            };
+            assert_eq!(format, expected, "{} {}", case.fixture, name);
+
+            let data = cue_case_file(index, layout, *size);
+            BinFile::memory(PathBuf::from(name), data, Some(format))
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn cue_sheets_lay_out_files_gaps_and_indices() {
+        for case in &CUE_CASES {
+            let mut disc = open_cue_case(case);
+
+            let tracks: Vec<_> = (disc.tracks().iter())
+                .map(|track| {
+                    let Track {
+                        number,
+                        kind,
+                        pregap_start,
+                        start,
+                        end,
+                        ..
+                    } = *track;
+                    (number, kind, pregap_start, start, end)
+                })
+                .collect();
+            assert_eq!(tracks, case.tracks, "{}", case.fixture);
+            let last = case.tracks.last().unwrap();
+            assert_eq!(disc.lead_out(), Msf::from_lba(last.4), "{}", case.fixture);
+
+            for &(lba, bytes, number, index, relative) in case.probes {
+                let msf = Msf::from_lba(lba);
+                let sector = disc.read_sector(msf).unwrap();
+                assert_eq!(
+                    [sector[1000], sector[2000]],
+                    bytes,
+                    "{} {}",
+                    case.fixture,
+                    lba
+                );
+                assert_eq!(
+                    disc.locate(msf),
+                    Some((number, index, Msf::from_sector(relative))),
+                    "{} {}",
+                    case.fixture,
+                    lba
+                );
+
+                let kind = disc.track_at(msf).unwrap().kind;
+                if kind != TrackKind::Audio {
+                    assert_eq!(sector[..12], SYNC, "{} {}", case.fixture, lba);
+                    let mode = if kind == TrackKind::Mode1 { 1 } else { 2 };
+                    assert_eq!(sector[15], mode, "{} {}", case.fixture, lba);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mode1_2048_sectors_are_completed_as_mode1() {
+        let case = &CUE_CASES[3];
+        let mut disc = open_cue_case(case);
+
+        let msf = Msf::from_lba(5);
+        let sector = disc.read_sector(msf).unwrap();
+        assert_eq!(sector[..16], [&SYNC[..], &msf.to_bcd(), &[1]].concat()[..]);
+        assert!(sector[16..0x810].iter().all(|&byte| byte == marker(0, 5)));
+        let edc = u32::from_le_bytes(sector[0x810..0x814].try_into().unwrap());
+        assert_eq!(edc, reference_edc(&sector[..0x810]));
+        assert_eq!(sector[0x814..0x81C], [0; 8]);
+        // Mode 1 parity includes the header
+        assert_parity(&sector);
+    }
+}
@@ -0,0 +1,775 @@
+// DMA controller, seven channels that move words between RAM and the peripherals
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Port {
+    MdecIn = 0,
+    MdecOut = 1,
+    Gpu = 2,
+    CdRom = 3,
+    Spu = 4,
+    Pio = 5,
+    Otc = 6,
+}
+
+impl Port {
+    pub fn from_index(index: u32) -> Self {
+        match index {
+            0 => Port::MdecIn,
+            1 => Port::MdecOut,
+            2 => Port::Gpu,
+            3 => Port::CdRom,
+            4 => Port::Spu,
+            5 => Port::Pio,
+            _ => Port::Otc,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    ToRam,
+    FromRam,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SyncMode {
+    // The whole block at once, started by the trigger bit
+    Manual,
+    // BCR blocks of BCR words, paced by the device
+    Block,
+    // Walks a list of packets in RAM, only used by the GPU
+    LinkedList,
+}
+
+// One bit per word of the 2MB RAM, a linked list that comes back to a packet loops forever
+const RAM_WORDS: usize = 0x80000;
+
+// Words a linked list sends before it yields, the rest is sent after the CPU and the other devices
+// had these cycles to run
+const LINKED_LIST_WORDS_PER_CALL: u32 = 0x4000;
+
+// DICR bit 15, the bus error flag. It forces the master flag like the force bit it shares.
+const BUS_ERROR: u32 = 1 << 15;
+
+// A peripheral on the other end of a channel, can_read and can_write are its data request lines
+// which pace block transfers
+pub trait DmaDevice {
+    fn can_read(&self) -> bool {
+        true
+    }
+
+    fn can_write(&self) -> bool {
+        true
+    }
+
+    fn read_word(&mut self) -> u32;
+    fn write_word(&mut self, value: u32);
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Channel {
+    // MADR
+    pub base: u32,
+    // BCR
+    pub block_control: u32,
+    // CHCR
+    pub control: u32,
+}
+
+impl Channel {
+    pub fn direction(&self) -> Direction {
+        if self.control & 1 != 0 {
+            Direction::FromRam
+        } else {
+            Direction::ToRam
+        }
+    }
+
+    // Walk RAM downwards instead of upwards
+    pub fn decrement(&self) -> bool {
+        self.control & 2 != 0
+    }
+
+    pub fn sync_mode(&self) -> SyncMode {
+        match (self.control >> 9) & 3 {
+            0 => SyncMode::Manual,
+            1 => SyncMode::Block,
+            // 3 is reserved, it behaves like linked list mode
+            _ => SyncMode::LinkedList,
+        }
+    }
+
+    // Manual transfers additionally need the trigger bit
+    pub fn is_active(&self) -> bool {
+        let enabled = self.control & (1 << 24) != 0;
+        let triggered = self.control & (1 << 28) != 0;
+
+        match self.sync_mode() {
+            SyncMode::Manual => enabled && triggered,
+            _ => enabled,
+        }
+    }
+
+    // Number of words of a manual or block transfer, None for linked lists
+    pub fn transfer_size(&self) -> Option<u32> {
+        let block_size = self.block_control & 0xFFFF;
+        let block_count = self.block_control >> 16;
+
+        match self.sync_mode() {
+            // A size of 0 means 0x10000 words
+            SyncMode::Manual if block_size == 0 => Some(0x10000),
+            SyncMode::Manual => Some(block_size),
+            SyncMode::Block => Some(block_size * block_count),
+            SyncMode::LinkedList => None,
+        }
+    }
+
+    fn finish(&mut self) {
+        self.control &= !((1 << 24) | (1 << 28));
+    }
+}
+
+pub struct Dma {
+    channels: [Channel; 7],
+    // DPCR, the priority and enable bits of every channel
+    control: u32,
+    // DICR bits 0..23, the force bit, the per channel enables and the master enable
+    interrupt_control: u32,
+    // DICR bits 24..30, acknowledged by writing 1s
+    interrupt_flags: u32,
+    // DICR bit 31, IRQ3 is requested when it goes from 0 to 1
+    master_flag: bool,
+    interrupt_pending: bool,
+    // Cycles until the busy bit of a channel clears, the data itself is moved when it starts
+    completions: [Option<u32>; 7],
+    // The packets the current linked list went through
+    visited: Vec<u64>,
+    // Linked lists that yielded and are continued once their completion is due
+    continued: [bool; 7],
+}
+
+impl Dma {
+    pub fn new() -> Self {
+        Self {
+            channels: [Channel::default(); 7],
+            control: 0x07654321,
+            interrupt_control: 0,
+            interrupt_flags: 0,
+            master_flag: false,
+            interrupt_pending: false,
+            completions: [None; 7],
+            visited: vec![0; RAM_WORDS / 64],
+            continued: [false; 7],
+        }
+    }
+
+    pub fn channel(&self, port: Port) -> &Channel {
+        &self.channels[port as usize]
+    }
+
+    // The enable bit of every channel is bit 3 of its DPCR nibble
+    pub fn is_enabled(&self, port: Port) -> bool {
+        self.control & (8 << ((port as u32) * 4)) != 0
+    }
+
+    // Offsets are relative to 0x1F801080
+    pub fn read(&self, offset: u32) -> u32 {
+        let index = offset >> 4;
+
+        match (index, offset & 0xF) {
+            (0..=6, 0) => self.channels[index as usize].base,
+            (0..=6, 4) => self.channels[index as usize].block_control,
+            (0..=6, 8) => self.channels[index as usize].control,
+            (7, 0) => self.control,
+            (7, 4) => {
+                self.interrupt_control
+                    | (self.interrupt_flags << 24)
+                    | ((self.master_flag as u32) << 31)
+            }
+            _ => 0,
+        }
+    }
+
+    // Returns the channel to run when the write started a transfer
+    pub fn write(&mut self, offset: u32, value: u32) -> Option<Port> {
+        let index = offset >> 4;
+
+        if index < 7 && offset & 0xF == 8 {
+            // A new transfer, or a stopped one
+            self.continued[index as usize] = false;
+        }
+
+        match (index, offset & 0xF) {
+            (0..=6, 0) => self.channels[index as usize].base = value & 0x00FFFFFF,
+            (0..=6, 4) => self.channels[index as usize].block_control = value,
+            (6, 8) => {
+                // The OTC channel always walks backwards towards RAM, only the start bits are writable
+                self.channels[6].control = (value & 0x51000000) | 2;
+            }
+            (0..=5, 8) => self.channels[index as usize].control = value & 0x71770703,
+            (7, 0) => self.control = value,
+            (7, 4) => {
+                // Bits 6..14 are always 0
+                self.interrupt_control = value & 0x00FF803F;
+                self.interrupt_flags &= !(value >> 24) & 0x7F;
+                self.update_master_flag();
+            }
+            _ => {}
+        }
+
+        let port = Port::from_index(index.min(6));
+        if index < 7 && self.is_running(port) {
+            Some(port)
+        } else {
+            None
+        }
+    }
+
+    // OTC, builds an empty ordering table for the GPU. Every entry points to the previous word and
+    // the first one holds the end of list marker.
+    pub fn clear_ordering_table(&self, ram: &mut [u8]) -> u32 {
+        let channel = self.channel(Port::Otc);
+        let mut address = channel.base & 0x1FFFFC;
+        let count = channel.transfer_size().unwrap_or(0);
+
+        for remaining in (0..count).rev() {
+            let value = if remaining == 0 {
+                0x00FFFFFF
+            } else {
+                address.wrapping_sub(4) & 0x1FFFFF
+            };
+
+            set_ram_word(ram, address, value);
+
+            address = address.wrapping_sub(4) & 0x1FFFFC;
+        }
+
+        count
+    }
+
+    // Returns the number of words moved, or None when the device wasn't ready and the transfer
+    // has to be resumed later
+    pub fn transfer(
+        &mut self,
+        port: Port,
+        ram: &mut [u8],
+        device: &mut dyn DmaDevice,
+    ) -> Option<u32> {
+        match self.channel(port).sync_mode() {
+            SyncMode::LinkedList => Some(self.transfer_linked_list(port, ram, device)),
+            _ => self.transfer_block(port, ram, device),
+        }
+    }
+
+    // Started channels that are waiting for their device
+    pub fn is_running(&self, port: Port) -> bool {
+        self.channel(port).is_active()
+            && self.is_enabled(port)
+            && self.completions[port as usize].is_none()
+    }
+
+    // Linked lists that sent part of their packets, the channel is busy until they are continued
+    pub fn is_continued(&self, port: Port) -> bool {
+        self.continued[port as usize]
+    }
+
+    // Bursts (manual mode without chopping) own the bus, the CPU is stalled until they are done
+    pub fn is_burst(&self, port: Port) -> bool {
+        let channel = self.channel(port);
+        channel.sync_mode() == SyncMode::Manual && channel.control & (1 << 8) == 0
+    }
+
+    // A word takes a cycle, with chopping the CPU gets a window of cycles between the DMA windows
+    pub fn duration(&self, port: Port, words: u32) -> u32 {
+        let control = self.channel(port).control;
+        if control & (1 << 8) == 0 {
+            return words;
+        }
+
+        let dma_window = 1 << ((control >> 16) & 7);
+        let cpu_window = 1 << ((control >> 20) & 7);
+        let chunks = words.div_ceil(dma_window).max(1);
+
+        words + (chunks - 1) * cpu_window
+    }
+
+    // The channel stays busy for the given number of cycles
+    pub fn schedule_completion(&mut self, port: Port, cycles: u32) {
+        self.completions[port as usize] = Some(cycles.max(1));
+    }
+
+    pub fn cycles_until_event(&self) -> u32 {
+        self.completions
+            .iter()
+            .flatten()
+            .copied()
+            .min()
+            .unwrap_or(u32::MAX)
+    }
+
+    // Returns whether a linked list that yielded has to be continued
+    pub fn step(&mut self, cycles: u32) -> bool {
+        let mut resume = false;
+
+        for index in 0..7 {
+            match self.completions[index] {
+                Some(remaining) if remaining <= cycles => {
+                    self.completions[index] = None;
+                    if self.continued[index] {
+                        resume = true;
+                    } else {
+                        self.finish(Port::from_index(index as u32));
+                    }
+                }
+                Some(remaining) => self.completions[index] = Some(remaining - cycles),
+                None => {}
+            }
+        }
+
+        resume
+    }
+
+    fn transfer_block(
+        &mut self,
+        port: Port,
+        ram: &mut [u8],
+        device: &mut dyn DmaDevice,
+    ) -> Option<u32> {
+        let channel = &mut self.channels[port as usize];
+        let step: u32 = if channel.decrement() {
+            4u32.wrapping_neg()
+        } else {
+            4
+        };
+        let direction = channel.direction();
+        let mut address = channel.base;
+
+        if channel.sync_mode() == SyncMode::Manual {
+            let words = channel.transfer_size().unwrap_or(0);
+            for _ in 0..words {
+                copy_word(direction, ram, address, device);
+                address = address.wrapping_add(step);
+            }
+
+            return Some(words);
+        }
+
+        // Block transfers wait for the device to request every block, MADR and the block count
+        // in BCR are updated after each one so a paused transfer can pick up where it stopped
+        let block_size = channel.block_control & 0xFFFF;
+        let mut words = 0;
+        while channel.block_control >> 16 > 0 {
+            let ready = match direction {
+                Direction::FromRam => device.can_write(),
+                Direction::ToRam => device.can_read(),
+            };
+            if !ready {
+                return None;
+            }
+
+            for _ in 0..block_size {
+                copy_word(direction, ram, address, device);
+                address = address.wrapping_add(step);
+            }
+
+            channel.base = address & 0x00FFFFFF;
+            channel.block_control -= 0x10000;
+            words += block_size;
+        }
+
+        Some(words)
+    }
+
+    // Each packet starts with a header holding the number of words in the top 8 bits and the
+    // address of the next packet in the low 24 bits. MADR follows the packets as they are sent.
+    fn transfer_linked_list(
+        &mut self,
+        port: Port,
+        ram: &mut [u8],
+        device: &mut dyn DmaDevice,
+    ) -> u32 {
+        let channel = &mut self.channels[port as usize];
+        let mut address = channel.base & 0x1FFFFC;
+        let mut words = 0;
+        if !std::mem::take(&mut self.continued[port as usize]) {
+            self.visited.fill(0);
+        }
+
+        loop {
+            // A corrupted ordering table pointing back to an earlier packet, the transfer is
+            // aborted with the bus error flag so the guest gets a DMA interrupt instead of a hang
+            let node = (address >> 2) as usize;
+            if self.visited[node / 64] & (1 << (node % 64)) != 0 {
+                println!(
+                    "Aborting circular DMA linked list at packet 0x{:08x}",
+                    address
+                );
+                self.interrupt_control |= BUS_ERROR;
+                self.update_master_flag();
+                return words;
+            }
+            self.visited[node / 64] |= 1 << (node % 64);
+
+            let header = ram_word(ram, address);
+
+            for i in 1..=(header >> 24) {
+                device.write_word(ram_word(ram, (address + i * 4) & 0x1FFFFC));
+            }
+            words += 1 + (header >> 24);
+
+            let next = header & 0x00FFFFFF;
+            channel.base = next;
+
+            // The hardware only checks bit 23 of the end marker 0xFFFFFF
+            if next & 0x800000 != 0 {
+                return words;
+            }
+
+            address = next & 0x1FFFFC;
+
+            // Long lists give the CPU a go, MADR already points to the rest
+            if words >= LINKED_LIST_WORDS_PER_CALL {
+                self.continued[port as usize] = true;
+                return words;
+            }
+        }
+    }
+
+    // Marks the transfer as done and flags the channel if its interrupt is enabled
+    pub fn finish(&mut self, port: Port) {
+        self.channels[port as usize].finish();
+
+        if self.interrupt_control & (1 << (16 + port as u32)) != 0 {
+            self.interrupt_flags |= 1 << port as u32;
+            self.update_master_flag();
+        }
+    }
+
+    // Whether IRQ3 has to be requested since the last call
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.interrupt_pending)
+    }
+
+    fn update_master_flag(&mut self) {
+        let force = self.interrupt_control & (1 << 15) != 0;
+        let master_enable = self.interrupt_control & (1 << 23) != 0;
+        let enabled_flags = (self.interrupt_control >> 16) & self.interrupt_flags & 0x7F;
+
+        let master_flag = force || (master_enable && enabled_flags != 0);
+        if master_flag && !self.master_flag {
+            self.interrupt_pending = true;
+        }
+
+        self.master_flag = master_flag;
+    }
+}
+
+fn ram_word(ram: &[u8], address: u32) -> u32 {
+    let offset = address as usize;
+    u32::from_le_bytes(ram[offset..offset + 4].try_into().unwrap())
+}
+
+fn set_ram_word(ram: &mut [u8], address: u32, value: u32) {
+    let offset = address as usize;
+    ram[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn copy_word(direction: Direction, ram: &mut [u8], address: u32, device: &mut dyn DmaDevice) {
+    let address = address & 0x1FFFFC;
+
+    match direction {
+        Direction::FromRam => device.write_word(ram_word(ram, address)),
+        Direction::ToRam => set_ram_word(ram, address, device.read_word()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    const DPCR: u32 = 0x70;
+
+    // CHCR of started block transfers
+    const BLOCK_FROM_RAM: u32 = 0x01000201;
+    const BLOCK_TO_RAM: u32 = 0x01000200;
+
+    fn start(dma: &mut Dma, port: Port, base: u32, block_control: u32, control: u32) {
+        let channel = port as u32 * 0x10;
+        dma.write(DPCR, 0x0FFFFFFF);
+        dma.write(channel, base);
+        dma.write(channel + 4, block_control);
+        dma.write(channel + 8, control);
+    }
+
+    // Stands in for the MDEC, every block of input words comes back out as is. Input is only
+    // requested while the output has room for another block.
+    struct EchoMdec {
+        input: Vec<u32>,
+        output: VecDeque<u32>,
+    }
+
+    const ECHO_BLOCK: usize = 32;
+
+    impl DmaDevice for EchoMdec {
+        fn can_read(&self) -> bool {
+            !self.output.is_empty()
+        }
+
+        fn can_write(&self) -> bool {
+            self.output.len() < ECHO_BLOCK
+        }
+
+        fn read_word(&mut self) -> u32 {
+            self.output.pop_front().unwrap()
+        }
+
+        fn write_word(&mut self, value: u32) {
+            self.input.push(value);
+            if self.input.len() == ECHO_BLOCK {
+                self.output.extend(self.input.drain(..));
+            }
+        }
+    }
+
+    #[test]
+    fn mdec_channels_stream_through_the_device() {
+        let mut dma = Dma::new();
+        let mut ram = vec![0; 0x200000];
+        for i in 0..128 {
+            set_ram_word(&mut ram, 0x1000 + i * 4, 0x1000_0000 | i);
+        }
+        let mut mdec = EchoMdec {
+            input: Vec::new(),
+            output: VecDeque::new(),
+        };
+
+        // Out is started first, like the games do, and waits for the decoded words
+        start(&mut dma, Port::MdecOut, 0x2000, 0x0004_0020, BLOCK_TO_RAM);
+        assert_eq!(dma.transfer(Port::MdecOut, &mut ram, &mut mdec), None);
+        start(&mut dma, Port::MdecIn, 0x1000, 0x0004_0020, BLOCK_FROM_RAM);
+
+        let mut pauses = 0;
+        let mut done = [false; 2];
+        while done != [true; 2] {
+            for (index, port) in [Port::MdecIn, Port::MdecOut].into_iter().enumerate() {
+                if done[index] {
+                    continue;
+                }
+                match dma.transfer(port, &mut ram, &mut mdec) {
+                    Some(_) => {
+                        dma.finish(port);
+                        done[index] = true;
+                    }
+                    None => pauses += 1,
+                }
+            }
+            assert!(pauses < 100);
+        }
+
+        // Each block of MDEC in had to wait for MDEC out to drain the previous one
+        assert!(pauses >= 6);
+        assert_eq!(ram[0x1000..0x1200], ram[0x2000..0x2200]);
+        assert_eq!(dma.channel(Port::MdecIn).block_control >> 16, 0);
+        assert_eq!(dma.channel(Port::MdecOut).base, 0x2200);
+        assert!(!dma.channel(Port::MdecOut).is_active());
+    }
+
+    // Stands in for GP0 and records the words it gets
+    struct GpuSink {
+        words: Vec<u32>,
+    }
+
+    impl DmaDevice for GpuSink {
+        fn read_word(&mut self) -> u32 {
+            0
+        }
+
+        fn write_word(&mut self, value: u32) {
+            self.words.push(value);
+        }
+    }
+
+    const LINKED_LIST: u32 = 0x01000401;
+
+    fn packet(ram: &mut [u8], address: u32, next: u32, words: &[u32]) {
+        set_ram_word(ram, address, ((words.len() as u32) << 24) | next);
+        for (i, word) in words.iter().enumerate() {
+            set_ram_word(ram, address + 4 + i as u32 * 4, *word);
+        }
+    }
+
+    #[test]
+    fn linked_list_sends_the_packets_to_gp0() {
+        let mut dma = Dma::new();
+        let mut ram = vec![0; 0x200000];
+        let mut gpu = GpuSink { words: Vec::new() };
+
+        // Packets can be anywhere in RAM, empty ones only link to the next
+        packet(
+            &mut ram,
+            0x1000,
+            0x3000,
+            &[0x0200_0000, 0x0000_0000, 0x0010_0010],
+        );
+        packet(&mut ram, 0x3000, 0x2000, &[]);
+        packet(&mut ram, 0x2000, 0xFFFFFF, &[0xE100_0000]);
+
+        start(&mut dma, Port::Gpu, 0x1000, 0, LINKED_LIST);
+        assert_eq!(dma.transfer(Port::Gpu, &mut ram, &mut gpu), Some(7));
+
+        assert_eq!(
+            gpu.words,
+            [0x0200_0000, 0x0000_0000, 0x0010_0010, 0xE100_0000]
+        );
+        // MADR is left at the end marker
+        assert_eq!(dma.channel(Port::Gpu).base, 0xFFFFFF);
+        assert_eq!(dma.read(DPCR + 4) & BUS_ERROR, 0);
+    }
+
+    #[test]
+    fn circular_linked_list_is_aborted() {
+        let mut dma = Dma::new();
+        let mut ram = vec![0; 0x200000];
+        let mut gpu = GpuSink { words: Vec::new() };
+
+        packet(&mut ram, 0x1000, 0x2000, &[0x1111_1111]);
+        packet(&mut ram, 0x2000, 0x1000, &[0x2222_2222]);
+
+        start(&mut dma, Port::Gpu, 0x1000, 0, LINKED_LIST);
+        assert_eq!(dma.transfer(Port::Gpu, &mut ram, &mut gpu), Some(4));
+
+        // Both packets are sent once, the channel stops at the packet that closes the loop
+        assert_eq!(gpu.words, [0x1111_1111, 0x2222_2222]);
+        assert_eq!(dma.channel(Port::Gpu).base, 0x1000);
+        assert_ne!(dma.read(DPCR + 4) & BUS_ERROR, 0);
+        assert_ne!(dma.read(DPCR + 4) & (1 << 31), 0);
+        assert!(dma.take_interrupt());
+
+        // The next list starts with a clean slate
+        packet(&mut ram, 0x2000, 0xFFFFFF, &[0x2222_2222]);
+        dma.finish(Port::Gpu);
+        start(&mut dma, Port::Gpu, 0x1000, 0, LINKED_LIST);
+        assert_eq!(dma.transfer(Port::Gpu, &mut ram, &mut gpu), Some(4));
+    }
+
+    #[test]
+    fn long_linked_lists_yield() {
+        let mut dma = Dma::new();
+        let mut ram = vec![0; 0x200000];
+        let mut gpu = GpuSink { words: Vec::new() };
+
+        // Packets of a header and three words, twice as many as fit in one call
+        let packets = LINKED_LIST_WORDS_PER_CALL / 2;
+        for i in 0..packets {
+            let address = 0x1000 + i * 16;
+            let next = if i + 1 == packets {
+                0xFFFFFF
+            } else {
+                address + 16
+            };
+            packet(&mut ram, address, next, &[i, i, i]);
+        }
+
+        start(&mut dma, Port::Gpu, 0x1000, 0, LINKED_LIST);
+        let words = dma.transfer(Port::Gpu, &mut ram, &mut gpu).unwrap();
+        assert_eq!(words, LINKED_LIST_WORDS_PER_CALL);
+        assert!(dma.is_continued(Port::Gpu));
+        assert_eq!(dma.channel(Port::Gpu).base, 0x1000 + words * 4);
+
+        // The channel isn't done when the first part completes, it goes on where it stopped
+        dma.schedule_completion(Port::Gpu, words);
+        assert!(!dma.is_running(Port::Gpu));
+        assert!(dma.step(words));
+        assert!(dma.is_running(Port::Gpu));
+        assert_eq!(
+            dma.transfer(Port::Gpu, &mut ram, &mut gpu),
+            Some(LINKED_LIST_WORDS_PER_CALL)
+        );
+        assert!(!dma.is_continued(Port::Gpu));
+        assert_eq!(dma.channel(Port::Gpu).base, 0xFFFFFF);
+        assert_eq!(gpu.words.len() as u32, packets * 3);
+        assert_eq!(dma.read(DPCR + 4) & BUS_ERROR, 0);
+    }
+
+    // Hands out counting words, like a FIFO that is always full
+    struct Counter {
+        next: u32,
+    }
+
+    impl DmaDevice for Counter {
+        fn read_word(&mut self) -> u32 {
+            self.next += 1;
+            self.next
+        }
+
+        fn write_word(&mut self, _: u32) {}
+    }
+
+    #[test]
+    fn registers_read_back_and_completion_flags_the_channel() {
+        let mut dma = Dma::new();
+        assert_eq!(dma.read(DPCR), 0x07654321);
+        dma.write(0x00, 0xFF123456);
+        dma.write(0x04, 0x0010_0020);
+        dma.write(0x08, 0xFFFFFFFF);
+        assert_eq!(dma.read(0x00), 0x00123456);
+        assert_eq!(dma.read(0x04), 0x0010_0020);
+        assert_eq!(dma.read(0x08), 0x71770703);
+        dma.write(0x08, 0);
+
+        // Every channel enable and the master enable, without the force bit
+        dma.write(DPCR + 4, 0x00FF7FFF);
+        assert_eq!(dma.read(DPCR + 4), 0x00FF003F);
+
+        // A manual transfer of four words from the device
+        let mut ram = vec![0; 0x200000];
+        start(&mut dma, Port::CdRom, 0x100, 4, 0x11000000);
+        assert_eq!(
+            dma.transfer(Port::CdRom, &mut ram, &mut Counter { next: 0 }),
+            Some(4)
+        );
+        assert_eq!(ram_word(&ram, 0x10C), 4);
+
+        // The busy bit stays set until the transfer had its time
+        dma.schedule_completion(Port::CdRom, 4);
+        assert!(!dma.is_running(Port::CdRom));
+        dma.step(3);
+        assert_ne!(dma.read(0x38) & (1 << 24), 0);
+        assert!(!dma.take_interrupt());
+        dma.step(1);
+        assert_eq!(dma.read(0x38) & (1 << 24), 0);
+        assert_eq!(dma.read(DPCR + 4), 0x88FF003F);
+        assert!(dma.take_interrupt());
+    }
+
+    #[test]
+    fn dicr_flags_are_acknowledged_by_writing_ones() {
+        let mut dma = Dma::new();
+        dma.write(DPCR + 4, 0x00850000);
+        for port in [Port::MdecIn, Port::Gpu, Port::Spu] {
+            dma.finish(port);
+        }
+
+        // Only the channels with their enable bit set are flagged
+        assert_eq!(dma.read(DPCR + 4), 0x85850000);
+        assert!(dma.take_interrupt());
+
+        // Acknowledging the GPU leaves the other flags and the enables alone
+        dma.write(DPCR + 4, 0x04850000);
+        assert_eq!(dma.read(DPCR + 4), 0x81850000);
+        dma.write(DPCR + 4, 0x01850000);
+        assert_eq!(dma.read(DPCR + 4), 0x00850000);
+        assert!(!dma.take_interrupt());
+
+        // The force bit sets the master flag on its own, and interrupts on the rising edge
+        dma.write(DPCR + 4, 0x00008000);
+        assert_eq!(dma.read(DPCR + 4), 0x80008000);
+        assert!(dma.take_interrupt());
+        dma.write(DPCR + 4, 0x00008000);
+        assert!(!dma.take_interrupt());
+        dma.write(DPCR + 4, 0);
+        assert_eq!(dma.read(DPCR + 4), 0);
+    }
+}
@@ -0,0 +1,1272 @@
+use std::{fmt, io::Write};
+
+use crate::{
+    bios::{BiosCallTracer, KernelTables},
+    cdrom::CdTiming,
+    cpu::CPU,
+    disasm::disassemble,
+    disc::Disc,
+    error::EmuError,
+    exe::{Exe, ExeError},
+    idle::IdleLoopDetector,
+    memcard::MemoryCard,
+    mmu::{CycleAccuracy, MmuMode, BIOS_SIZE, BIOS_START, MMU, RAM_SIZE},
+    sio::{Axis, Button, PadDevice},
+    sio1::SerialLink,
+    spu::Interpolation,
+    trace::{TraceRecord, TraceWriter, DELAY_SLOT, EXCEPTION, MULTIPLE_WRITES},
+    watchdog::{classify, Watchdog, WatchdogConfig},
+};
+
+// Sideloaded EXEs are injected once the BIOS is about to start the shell, at that point the kernel is set up
+const SHELL_ENTRY: u32 = 0x80030000;
+
+// Where exceptions continue, with SR.BEV clear and set
+const EXCEPTION_VECTORS: [u32; 2] = [0x80000080, 0xBFC00180];
+
+// Where raw binaries without a stack pointer of their own start the stack
+const RAW_STACK: u32 = 0x801FFFF0;
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidBiosSize(usize),
+    // Raw binaries only go to main RAM
+    LoadOverlapsBios { address: u32, size: usize },
+    LoadOutsideRam { address: u32, size: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidBiosSize(size) => write!(
+                f,
+                "Invalid BIOS image size {} bytes, expected {} bytes",
+                size, BIOS_SIZE
+            ),
+            Error::LoadOverlapsBios { address, size } => write!(
+                f,
+                "Cannot load {} bytes at 0x{:08x}, that is the BIOS",
+                size, address
+            ),
+            Error::LoadOutsideRam { address, size } => write!(
+                f,
+                "Cannot load {} bytes at 0x{:08x}, that does not fit in RAM",
+                size, address
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// Entry point for frontends and test harnesses, owns the whole emulated system
+pub struct Emulator {
+    cpu: CPU,
+    cycles: u64,
+    pending_exe: Option<Exe>,
+    pending_raw: Vec<RawProgram>,
+    tty_enabled: bool,
+    tty_buffer: String,
+    tty_callback: Option<Box<dyn FnMut(char)>>,
+    rumble_callback: Option<Box<dyn FnMut(u8, u8)>>,
+    bios_tracer: Option<BiosCallTracer>,
+    instruction_trace: Option<TraceWriter<Box<dyn Write>>>,
+    watchdog: Option<Watchdog>,
+    idle_loops: Option<IdleLoopDetector>,
+}
+
+// A binary copied to RAM as it is, see Emulator::load_raw
+struct RawProgram {
+    data: Vec<u8>,
+    address: u32,
+    entry: Option<u32>,
+}
+
+// The state before a traced instruction, see Emulator::finish_trace
+struct TraceStart {
+    pc: u32,
+    opcode: u32,
+    registers: [u32; 32],
+    delay_slot: bool,
+}
+
+impl Emulator {
+    pub fn new(bios: Vec<u8>) -> Result<Self, Error> {
+        if bios.len() != BIOS_SIZE as usize {
+            return Err(Error::InvalidBiosSize(bios.len()));
+        }
+
+        let mmu = MMU::new(bios);
+
+        Ok(Self {
+            cpu: CPU::new(mmu),
+            cycles: 0,
+            pending_exe: None,
+            pending_raw: Vec::new(),
+            tty_enabled: true,
+            tty_buffer: String::new(),
+            tty_callback: None,
+            rumble_callback: None,
+            bios_tracer: None,
+            instruction_trace: None,
+            watchdog: None,
+            idle_loops: None,
+        })
+    }
+
+    // Parses the EXE and loads it as soon as the BIOS reaches the shell entry point
+    pub fn sideload_exe(&mut self, exe: &[u8]) -> Result<(), ExeError> {
+        self.pending_exe = Some(Exe::parse(exe)?);
+        Ok(())
+    }
+
+    // Like sideload_exe for programs put together in memory, such as PSF songs
+    pub fn sideload_program(&mut self, exe: Exe) -> Result<(), ExeError> {
+        exe.check_ranges()?;
+        self.pending_exe = Some(exe);
+        Ok(())
+    }
+
+    // Whether a sideloaded program still waits for the BIOS to reach the shell
+    pub fn exe_pending(&self) -> bool {
+        self.pending_exe.is_some() || !self.pending_raw.is_empty()
+    }
+
+    // Copies the bytes to RAM right away. With an entry point the CPU continues there with the
+    // stack at the top of RAM, without one the bytes are data for another load.
+    pub fn load_raw(&mut self, data: &[u8], address: u32, entry: Option<u32>) -> Result<(), Error> {
+        check_raw(address, data.len())?;
+        self.write_raw(&RawProgram {
+            data: data.to_vec(),
+            address,
+            entry,
+        });
+        Ok(())
+    }
+
+    // Like load_raw once the BIOS reaches the shell, after a sideloaded EXE. The binaries are
+    // loaded in the order they were added, the last entry point wins.
+    pub fn sideload_raw(
+        &mut self,
+        data: Vec<u8>,
+        address: u32,
+        entry: Option<u32>,
+    ) -> Result<(), Error> {
+        check_raw(address, data.len())?;
+        self.pending_raw.push(RawProgram {
+            data,
+            address,
+            entry,
+        });
+        Ok(())
+    }
+
+    fn write_raw(&mut self, raw: &RawProgram) {
+        let mmu = self.cpu.mmu_mut();
+        // check_raw made sure this is RAM
+        mmu.write_bytes(raw.address, &raw.data).unwrap();
+        mmu.take_access_cycles();
+
+        if let Some(entry) = raw.entry {
+            self.cpu.set_register(29, RAW_STACK);
+            self.cpu.set_register(30, RAW_STACK);
+            self.cpu.set_pc(entry);
+        }
+    }
+
+    // Sideloading made sure the text and bss are in RAM
+    fn load_exe(&mut self, exe: Exe) -> Result<(), EmuError> {
+        let mmu = self.cpu.mmu_mut();
+        mmu.write_bytes(exe.destination, &exe.data)?;
+        mmu.write_bytes(exe.bss_start, &vec![0; exe.bss_size as usize])?;
+        // Loading happens outside of emulated time
+        mmu.take_access_cycles();
+
+        self.cpu.set_register(28, exe.gp);
+        if let Some(sp) = exe.sp {
+            self.cpu.set_register(29, sp);
+            self.cpu.set_register(30, sp);
+        }
+
+        self.cpu.set_pc(exe.pc);
+
+        Ok(())
+    }
+
+    // Captures characters printed through the BIOS putchar functions (A0h:3Ch and B0h:3Dh)
+    pub fn set_tty_enabled(&mut self, enabled: bool) {
+        self.tty_enabled = enabled;
+    }
+
+    // Characters are passed to the callback instead of being collected in the TTY buffer
+    pub fn set_tty_callback(&mut self, callback: Box<dyn FnMut(char)>) {
+        self.tty_callback = Some(callback);
+    }
+
+    pub fn tty_output(&self) -> &str {
+        &self.tty_buffer
+    }
+
+    pub fn take_tty_output(&mut self) -> String {
+        std::mem::take(&mut self.tty_buffer)
+    }
+
+    fn capture_tty(&mut self) {
+        let pc = self.cpu.pc() & 0x1FFFFFFF;
+        let function = self.cpu.register(9);
+
+        if (pc == 0xA0 && function == 0x3C) || (pc == 0xB0 && function == 0x3D) {
+            let character = (self.cpu.register(4) & 0xFF) as u8 as char;
+            self.emit_tty(character);
+        }
+    }
+
+    fn emit_tty(&mut self, character: char) {
+        match &mut self.tty_callback {
+            Some(callback) => callback(character),
+            None => self.tty_buffer.push(character),
+        }
+    }
+
+    pub fn set_bios_tracer(&mut self, tracer: Option<BiosCallTracer>) {
+        self.bios_tracer = tracer;
+    }
+
+    // The events and threads the BIOS tracer saw the guest open
+    pub fn kernel_tables(&self) -> Option<&KernelTables> {
+        self.bios_tracer.as_ref().map(BiosCallTracer::kernel)
+    }
+
+    // Records every instruction from now on, see the trace module
+    pub fn set_instruction_trace(&mut self, trace: Option<TraceWriter<Box<dyn Write>>>) {
+        self.instruction_trace = trace;
+    }
+
+    // Stops tracing, the writer still has to be finished
+    pub fn take_instruction_trace(&mut self) -> Option<TraceWriter<Box<dyn Write>>> {
+        self.instruction_trace.take()
+    }
+
+    fn start_trace(&self) -> TraceStart {
+        let mut opcode = [0; 4];
+        self.cpu.mmu().peek_bytes(self.cpu.pc(), &mut opcode);
+
+        TraceStart {
+            pc: self.cpu.pc(),
+            opcode: u32::from_le_bytes(opcode),
+            registers: *self.cpu.registers(),
+            delay_slot: self.cpu.is_delay_slot(),
+        }
+    }
+
+    fn finish_trace(&mut self, start: TraceStart) -> Result<(), EmuError> {
+        let registers = self.cpu.registers();
+        let mut changed = (1..32).filter(|&i| registers[i] != start.registers[i]);
+        let write = changed.next().map(|i| (i as u8, registers[i]));
+
+        let mut flags = 0;
+        if start.delay_slot {
+            flags |= DELAY_SLOT;
+        }
+        if EXCEPTION_VECTORS.contains(&self.cpu.pc()) {
+            flags |= EXCEPTION;
+        }
+        if changed.next().is_some() {
+            flags |= MULTIPLE_WRITES;
+        }
+
+        let record = TraceRecord {
+            pc: start.pc,
+            opcode: start.opcode,
+            write,
+            flags,
+        };
+        if let Some(trace) = &mut self.instruction_trace {
+            trace
+                .write(&record)
+                .map_err(|error| EmuError::Trace(error.to_string()))?;
+        }
+
+        Ok(())
+    }
+
+    // Skips the time games spend waiting in loops that only read, until the next device event
+    pub fn set_idle_loop_skipping(&mut self, enabled: bool) {
+        self.idle_loops = enabled.then(IdleLoopDetector::new);
+    }
+
+    // Cycles that passed without running the idle loops
+    pub fn skipped_idle_cycles(&self) -> u64 {
+        self.idle_loops
+            .as_ref()
+            .map_or(0, IdleLoopDetector::skipped_cycles)
+    }
+
+    // Stops running with EmuError::Hang once the guest looks hung, meant for automated runs
+    pub fn set_watchdog(&mut self, config: Option<WatchdogConfig>) {
+        self.watchdog = config.map(Watchdog::new);
+    }
+
+    // The loop the guest is stuck in with the state it may be waiting for
+    fn hang(&self) -> EmuError {
+        let watchdog = self.watchdog.as_ref().unwrap();
+        let (start, end) = watchdog.window();
+        let words: Vec<u32> = (start..=end)
+            .step_by(4)
+            .map(|address| self.mmu().peek(address, 4).unwrap_or(0))
+            .collect();
+
+        let mut report = format!(
+            "PC stayed in 0x{:08x}..0x{:08x} for {} instructions\n",
+            start,
+            end + 4,
+            watchdog.instructions()
+        );
+        for (address, word) in (start..).step_by(4).zip(&words) {
+            report += &format!(
+                "  0x{:08x}: {:08x}  {}\n",
+                address,
+                word,
+                disassemble(address, *word)
+            );
+        }
+        report += &format!("SR 0x{:08x} ", self.cpu.status());
+        report += &self.mmu().device_summary();
+        // Shows the event that is waited for but never delivered
+        if let Some(tables) = self.kernel_tables() {
+            report += &format!("\n{}", tables);
+        }
+
+        EmuError::Hang {
+            kind: classify(&words),
+            report,
+        }
+    }
+
+    // Errors leave the emulator in the state of the failing instruction so it can be inspected
+    pub fn step(&mut self) -> Result<(), EmuError> {
+        let hung = match &mut self.watchdog {
+            Some(watchdog) => watchdog.observe(self.cpu.pc(), self.cpu.registers()),
+            None => false,
+        };
+        if hung {
+            return Err(self.hang());
+        }
+
+        if self.tty_enabled {
+            self.capture_tty();
+        }
+
+        if let Some(tracer) = &mut self.bios_tracer {
+            tracer.trace(self.cpu.pc(), self.cpu.registers(), self.cpu.mmu().ram());
+        }
+
+        if self.exe_pending() && self.cpu.pc() == SHELL_ENTRY {
+            if let Some(exe) = self.pending_exe.take() {
+                self.load_exe(exe)?;
+            }
+            for raw in std::mem::take(&mut self.pending_raw) {
+                self.write_raw(&raw);
+            }
+        }
+
+        // Traces have every instruction, so nothing is skipped while tracing
+        if let (Some(detector), None) = (&mut self.idle_loops, &self.instruction_trace) {
+            if let Some(cycles) = detector.observe(&self.cpu, self.cycles) {
+                self.cpu.skip_cycles(cycles);
+                self.cycles += cycles as u64;
+            }
+        }
+
+        let trace = self.instruction_trace.as_ref().map(|_| self.start_trace());
+
+        let cycles = self.cpu.step()?;
+
+        if let Some(start) = trace {
+            self.finish_trace(start)?;
+        }
+
+        // Dev BIOSes print through the expansion 2 DUART instead of the putchar functions, and so
+        // do test ROMs through their TTY register
+        let duart_output = self.cpu.mmu_mut().take_duart_output();
+        if self.tty_enabled {
+            for character in duart_output.chars() {
+                self.emit_tty(character);
+            }
+        }
+
+        if let Some((small, large)) = self.cpu.mmu_mut().take_rumble() {
+            if let Some(callback) = &mut self.rumble_callback {
+                callback(small, large);
+            }
+        }
+
+        self.cycles += cycles as u64;
+
+        Ok(())
+    }
+
+    pub fn run_cycles(&mut self, cycles: u64) -> Result<(), EmuError> {
+        let target = self.cycles + cycles;
+
+        while self.cycles < target && self.exit_code().is_none() {
+            self.step()?;
+        }
+
+        Ok(())
+    }
+
+    // Runs until the GPU finished the current frame
+    pub fn run_frame(&mut self) -> Result<(), EmuError> {
+        let frame = self.cpu.mmu().frame_count();
+
+        while self.cpu.mmu().frame_count() == frame && self.exit_code().is_none() {
+            self.step()?;
+        }
+
+        Ok(())
+    }
+
+    // Test ROMs can see they run in an emulator, print and exit through expansion 2 registers
+    pub fn set_testing(&mut self, enabled: bool) {
+        self.cpu.mmu_mut().set_testing(enabled);
+    }
+
+    // Set once a test ROM wrote its exit code, running stops there
+    pub fn exit_code(&self) -> Option<u8> {
+        self.cpu.mmu().exit_code()
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Frames the GPU finished so far
+    pub fn frame_count(&self) -> u64 {
+        self.cpu.mmu().frame_count()
+    }
+
+    pub fn pc(&self) -> u32 {
+        self.cpu.pc()
+    }
+
+    pub fn register(&self, index: usize) -> u32 {
+        self.cpu.register(index)
+    }
+
+    pub fn set_register(&mut self, index: usize, value: u32) {
+        self.cpu.set_register(index, value);
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        self.cpu.mmu().ram()
+    }
+
+    // The displayed frame as 0xRRGGBBAA pixels, display_size is its width and height
+    pub fn render_frame(&self) -> Vec<u32> {
+        self.cpu.mmu().render_frame()
+    }
+
+    pub fn display_size(&self) -> (usize, usize) {
+        self.cpu.mmu().display_size()
+    }
+
+    // The video mode the GPU is set to, PAL runs at 50 frames per second instead of 60
+    pub fn is_pal(&self) -> bool {
+        self.cpu.mmu().is_pal()
+    }
+
+    // Stereo samples at 44100 Hz since the last call, see Resampler for converting them to the
+    // rate of the audio device. Only the last second is kept when they are not taken.
+    pub fn take_audio_samples(&mut self) -> Vec<(i16, i16)> {
+        self.cpu.mmu_mut().take_audio_samples()
+    }
+
+    // Inserts a card into controller port 0 or 1, None removes it
+    pub fn set_memory_card(&mut self, port: usize, card: Option<MemoryCard>) {
+        self.cpu.mmu_mut().set_memory_card(port, card);
+    }
+
+    pub fn memory_card(&self, port: usize) -> Option<&MemoryCard> {
+        self.cpu.mmu().memory_card(port)
+    }
+
+    // Plugs a controller into port 0 or 1, None unplugs it. Port 0 has a DigitalPad by default.
+    pub fn set_controller(&mut self, port: usize, pad: Option<Box<dyn PadDevice>>) {
+        self.cpu.mmu_mut().set_controller(port, pad);
+    }
+
+    // Puts the disc into the drive, None leaves it empty with the shell open
+    pub fn insert_disc(&mut self, disc: Option<Disc>) {
+        self.cpu.mmu_mut().insert_disc(disc);
+    }
+
+    // Changing discs like on the console: open the lid, swap the disc and close the lid again. While
+    // the lid is open GetStat reports it, reads in progress fail and commands that need the disc
+    // answer with an error.
+    pub fn open_lid(&mut self) {
+        self.cpu.mmu_mut().open_lid();
+    }
+
+    pub fn swap_disc(&mut self, disc: Disc) {
+        self.cpu.mmu_mut().swap_disc(disc);
+    }
+
+    pub fn close_lid(&mut self) {
+        self.cpu.mmu_mut().close_lid();
+    }
+
+    // Connects the serial port to the link, e.g. one end of a ChannelLink::pair shared with a
+    // second emulator. None unplugs the cable.
+    pub fn set_serial_link(&mut self, link: Option<Box<dyn SerialLink>>) {
+        self.cpu.mmu_mut().set_serial_link(link);
+    }
+
+    // Plugs a multitap into port 0 or 1, the controller and card of the port move to slot A
+    pub fn set_multitap(&mut self, port: usize, enabled: bool) {
+        self.cpu.mmu_mut().set_multitap(port, enabled);
+    }
+
+    // Plugs a controller into slot 0 to 3 (A to D) of the multitap in the port, a multitap is
+    // plugged in when slot B to D is used without one
+    pub fn set_multitap_controller(
+        &mut self,
+        port: usize,
+        slot: usize,
+        pad: Option<Box<dyn PadDevice>>,
+    ) {
+        self.cpu.mmu_mut().set_multitap_controller(port, slot, pad);
+    }
+
+    // Input of the pad in the first controller port
+    pub fn set_button_state(&mut self, button: Button, pressed: bool) {
+        self.cpu.mmu_mut().set_button_state(button, pressed);
+    }
+
+    // Ignored by pads without analog sticks
+    pub fn set_axis(&mut self, axis: Axis, value: u8) {
+        self.cpu.mmu_mut().set_axis(axis, value);
+    }
+
+    // Presses the analog button of the first controller
+    pub fn toggle_analog_mode(&mut self) {
+        self.cpu.mmu_mut().toggle_analog_mode();
+    }
+
+    // Called with the small and large motor intensity of the first controller when they change
+    pub fn set_rumble_callback(&mut self, callback: Box<dyn FnMut(u8, u8)>) {
+        self.rumble_callback = Some(callback);
+    }
+
+    pub fn set_mmu_mode(&mut self, mode: MmuMode) {
+        self.cpu.mmu_mut().set_mode(mode);
+    }
+
+    pub fn set_cycle_accuracy(&mut self, accuracy: CycleAccuracy) {
+        self.cpu.mmu_mut().set_cycle_accuracy(accuracy);
+    }
+
+    pub fn set_cd_timing(&mut self, timing: CdTiming) {
+        self.cpu.mmu_mut().set_cd_timing(timing);
+    }
+
+    // Gaussian like the console by default
+    pub fn set_spu_interpolation(&mut self, interpolation: Interpolation) {
+        self.cpu.mmu_mut().set_spu_interpolation(interpolation);
+    }
+
+    // SIMD span filling is used when the host supports it, the scalar rasterizer draws the same
+    // pixels for comparisons and benchmarks
+    pub fn set_simd_rasterizer(&mut self, enabled: bool) {
+        self.cpu.mmu_mut().set_simd_rasterizer(enabled);
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    pub fn mmu(&self) -> &MMU {
+        self.cpu.mmu()
+    }
+
+    pub fn mmu_mut(&mut self) -> &mut MMU {
+        self.cpu.mmu_mut()
+    }
+}
+
+// Raw binaries must be in the first 2MB of the KUSEG, KSEG0 or KSEG1 view of memory
+fn check_raw(address: u32, size: usize) -> Result<(), Error> {
+    let start = (address & 0x1FFFFFFF) as u64;
+    let end = start + size as u64;
+    let bios = BIOS_START as u64..(BIOS_START + BIOS_SIZE) as u64;
+    if start < bios.end && end > bios.start {
+        return Err(Error::LoadOverlapsBios { address, size });
+    }
+
+    if !matches!(address >> 29, 0 | 4 | 5) || end > RAM_SIZE as u64 {
+        return Err(Error::LoadOutsideRam { address, size });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HangKind;
+
+    const PROGRAM: u32 = 0x80010000;
+
+    // Reads the magic into t1, prints "OK" and exits with 3, then spins
+    const TEST_ROM: [u32; 10] = [
+        0x3C081F80, // lui t0, 0x1F80
+        0x8D092080, // lw t1, 0x2080(t0)
+        0x240A004F, // li t2, 'O'
+        0xA10A2080, // sb t2, 0x2080(t0)
+        0x240A004B, // li t2, 'K'
+        0xA10A2080, // sb t2, 0x2080(t0)
+        0x240A0003, // li t2, 3
+        0xA10A2082, // sb t2, 0x2082(t0)
+        0x1000FFFF, // b .
+        0x00000000, // nop
+    ];
+
+    fn emulator_with_test_rom() -> Emulator {
+        let mut emulator = Emulator::new(vec![0; BIOS_SIZE as usize]).unwrap();
+        let program: Vec<u8> = TEST_ROM
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect();
+        emulator.mmu_mut().write_bytes(PROGRAM, &program).unwrap();
+        emulator.cpu_mut().set_pc(PROGRAM);
+        emulator
+    }
+
+    // A BIOS image starting with the program, the rest is NOPs
+    fn bios_with_program(program: &[u32]) -> Vec<u8> {
+        let mut bios = vec![0; BIOS_SIZE as usize];
+        for (i, word) in program.iter().enumerate() {
+            bios[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        bios
+    }
+
+    #[test]
+    fn test_roms_detect_print_and_exit() {
+        let mut emulator = emulator_with_test_rom();
+        emulator.set_testing(true);
+
+        emulator.run_cycles(100_000).unwrap();
+
+        assert_eq!(emulator.register(9), u32::from_le_bytes(*b"PCSX"));
+        assert_eq!(emulator.tty_output(), "OK");
+        assert_eq!(emulator.exit_code(), Some(3));
+        assert!(emulator.cycles() < 1000);
+    }
+
+    #[test]
+    fn test_registers_are_unmapped_on_retail() {
+        let mut emulator = emulator_with_test_rom();
+
+        emulator.run_cycles(1000).unwrap();
+
+        assert_eq!(emulator.register(9), 0xFFFFFFFF);
+        assert_eq!(emulator.tty_output(), "");
+        assert_eq!(emulator.exit_code(), None);
+    }
+
+    const WATCHDOG: WatchdogConfig = WatchdogConfig {
+        window: 64,
+        instructions: 10_000,
+        repeats: 1_000,
+    };
+
+    // Runs the program from RAM with an empty BIOS
+    fn emulator_with_program(program: &[u32]) -> Emulator {
+        let mut emulator = Emulator::new(vec![0; BIOS_SIZE as usize]).unwrap();
+        let program: Vec<u8> = program.iter().flat_map(|word| word.to_le_bytes()).collect();
+        emulator.mmu_mut().write_bytes(PROGRAM, &program).unwrap();
+        emulator.cpu_mut().set_pc(PROGRAM);
+        emulator
+    }
+
+    fn hang(program: &[u32]) -> (HangKind, String) {
+        let mut emulator = emulator_with_program(program);
+        emulator.set_watchdog(Some(WATCHDOG));
+
+        match emulator.run_cycles(1_000_000) {
+            Err(EmuError::Hang { kind, report }) => (kind, report),
+            result => panic!("No hang detected: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn branch_to_itself_is_a_spin() {
+        let (kind, report) = hang(&[
+            0x1000FFFF, // b .
+            0x00000000, // nop
+        ]);
+
+        assert_eq!(kind, HangKind::Spin);
+        assert!(report.contains("0x80010000: 1000ffff  beq zero, zero, 0x80010000"));
+        assert!(report.contains("0x80010004: 00000000  nop"));
+        assert!(report.contains("I_STAT 0x0000 I_MASK 0x0000"));
+        assert!(report.contains("Timers: T0"));
+        assert!(report.contains("CDROM: stat"));
+    }
+
+    #[test]
+    fn waiting_for_an_interrupt_that_never_comes_is_a_poll() {
+        let (kind, report) = hang(&[
+            0x3C081F80, // lui t0, 0x1F80
+            0x34090004, // li t1, 4
+            0xAD091074, // sw t1, 0x1074(t0), the CDROM interrupt in I_MASK
+            0x34090401, // li t1, 0x401
+            0x40896000, // mtc0 t1, $12
+            0x8D0A1070, // wait: lw t2, 0x1070(t0)
+            0x00000000, // nop
+            0x314A0004, // andi t2, t2, 4
+            0x1140FFFC, // beqz t2, wait
+            0x00000000, // nop
+        ]);
+
+        assert_eq!(kind, HangKind::Poll);
+        assert!(report.contains("PC stayed in 0x80010000..0x80010028"));
+        assert!(report.contains("0x80010014: 8d0a1070  lw t2, 4208(t0)"));
+        assert!(report.contains("SR 0x00000401 I_STAT 0x0000 I_MASK 0x0004"));
+    }
+
+    #[test]
+    fn emulator_runs_from_the_reset_vector() {
+        assert!(matches!(
+            Emulator::new(vec![0; 1024]),
+            Err(Error::InvalidBiosSize(1024))
+        ));
+
+        // An empty BIOS is all NOPs
+        let mut emulator = Emulator::new(vec![0; BIOS_SIZE as usize]).unwrap();
+        assert_eq!(emulator.pc(), 0xBFC00000);
+        emulator.step().unwrap();
+        assert_eq!(emulator.pc(), 0xBFC00004);
+        assert!(emulator.cycles() > 0);
+
+        emulator.run_cycles(1000).unwrap();
+        assert!(emulator.cycles() >= 1000);
+
+        emulator.set_register(8, 0x1234);
+        assert_eq!(emulator.register(8), 0x1234);
+        emulator.mmu_mut().write(0x100, 4, 0x12345678).unwrap();
+        assert_eq!(emulator.ram()[0x100..0x104], [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(emulator.ram().len(), 2 * 1024 * 1024);
+    }
+
+    #[test]
+    fn sideloaded_exe_runs_after_the_bios_reaches_the_shell() {
+        // A BIOS that goes straight to the shell
+        let bios = bios_with_program(&[
+            0x3C088003, // lui t0, 0x8003
+            0x01000008, // jr t0
+            0x00000000, // nop
+        ]);
+
+        let program: [u32; 5] = [
+            0x3C08DEAD, // lui t0, 0xDEAD
+            0x3508BEEF, // ori t0, t0, 0xBEEF
+            0xAC080100, // sw t0, 0x100(zero)
+            0x1000FFFF, // b .
+            0x00000000, // nop
+        ];
+        let mut exe = vec![0; 0x800];
+        exe[..8].copy_from_slice(b"PS-X EXE");
+        let mut header = |offset: usize, value: u32| {
+            exe[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        };
+        header(0x10, PROGRAM);
+        header(0x14, 0x8000C000);
+        header(0x18, PROGRAM);
+        header(0x1C, program.len() as u32 * 4);
+        header(0x30, 0x801FFF00);
+        header(0x34, 0xF0);
+        exe.extend(program.iter().flat_map(|word| word.to_le_bytes()));
+
+        let mut emulator = Emulator::new(bios).unwrap();
+        emulator.sideload_exe(&exe).unwrap();
+        assert!(Emulator::new(vec![0; BIOS_SIZE as usize])
+            .unwrap()
+            .sideload_exe(&exe[..0x800])
+            .is_err());
+
+        // Programs from memory are checked the same way, a bss in the scratchpad isn't loaded
+        let mut rejected = Emulator::new(vec![0; BIOS_SIZE as usize]).unwrap();
+        let mut program = Exe::parse(&exe).unwrap();
+        (program.bss_start, program.bss_size) = (0x1F800000, 0x400);
+        assert!(matches!(
+            rejected.sideload_program(program),
+            Err(ExeError::OutsideRam { section: "bss", .. })
+        ));
+        assert!(!rejected.exe_pending());
+
+        emulator.run_cycles(1000).unwrap();
+        assert_eq!(emulator.ram()[0x100..0x104], 0xDEADBEEFu32.to_le_bytes());
+        assert_eq!(emulator.register(28), 0x8000C000);
+        assert_eq!(emulator.register(29), 0x801FFFF0);
+        // Spinning at the end of the program
+        assert!((PROGRAM + 12..=PROGRAM + 16).contains(&emulator.pc()));
+    }
+
+    fn words(program: &[u32]) -> Vec<u8> {
+        program.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn raw_programs_run_from_their_entry() {
+        let mut emulator = Emulator::new(vec![0; BIOS_SIZE as usize]).unwrap();
+        let program = words(&[
+            0x00000000, // nop, skipped by the entry
+            0x3C08C0DE, // lui t0, 0xC0DE
+            0x3508CAFE, // ori t0, t0, 0xCAFE
+            0xAFA80000, // sw t0, 0(sp)
+            0x1000FFFF, // b .
+            0x00000000, // nop
+        ]);
+
+        emulator
+            .load_raw(&program, 0xA0020000, Some(0x80020004))
+            .unwrap();
+        emulator.run_cycles(1000).unwrap();
+
+        let stack = (RAW_STACK & 0x1FFFFF) as usize;
+        assert_eq!(
+            emulator.ram()[stack..stack + 4],
+            0xC0DECAFEu32.to_le_bytes()
+        );
+        assert!((0x80020010..=0x80020014).contains(&emulator.pc()));
+    }
+
+    #[test]
+    fn raw_data_and_code_are_loaded_at_the_shell() {
+        let bios = bios_with_program(&[
+            0x3C088003, // lui t0, 0x8003
+            0x01000008, // jr t0
+            0x00000000, // nop
+        ]);
+        let mut emulator = Emulator::new(bios).unwrap();
+
+        // The code copies the magic from the data
+        let code = words(&[
+            0x3C088004, // lui t0, 0x8004
+            0x8D090000, // lw t1, 0(t0)
+            0x00000000, // nop
+            0xAC090100, // sw t1, 0x100(zero)
+            0x1000FFFF, // b .
+            0x00000000, // nop
+        ]);
+        emulator
+            .sideload_raw(0x1234ABCDu32.to_le_bytes().to_vec(), 0x80040000, None)
+            .unwrap();
+        emulator.sideload_raw(code, PROGRAM, Some(PROGRAM)).unwrap();
+        assert!(emulator.exe_pending());
+
+        emulator.run_cycles(1000).unwrap();
+        assert!(!emulator.exe_pending());
+        assert_eq!(emulator.ram()[0x100..0x104], 0x1234ABCDu32.to_le_bytes());
+        assert_eq!(emulator.register(29), RAW_STACK);
+    }
+
+    #[test]
+    fn raw_loads_stay_in_ram() {
+        let mut emulator = Emulator::new(vec![0; BIOS_SIZE as usize]).unwrap();
+        let mut load = |address: u32, size: usize| emulator.load_raw(&vec![0; size], address, None);
+
+        assert!(load(0x801FFFF0, 16).is_ok());
+        assert!(load(0x00000000, 0x200000).is_ok());
+        assert!(matches!(
+            load(0xBFC00000, 4),
+            Err(Error::LoadOverlapsBios { .. })
+        ));
+        assert!(matches!(
+            load(0x9FBFFFFC, 8),
+            Err(Error::LoadOverlapsBios { .. })
+        ));
+        assert!(matches!(
+            load(0x801FFFF0, 32),
+            Err(Error::LoadOutsideRam { .. })
+        ));
+        assert!(matches!(
+            load(0x1F800000, 4),
+            Err(Error::LoadOutsideRam { .. })
+        ));
+        assert!(matches!(
+            load(0x20000000, 4),
+            Err(Error::LoadOutsideRam { .. })
+        ));
+    }
+
+    #[test]
+    fn putchar_calls_are_captured() {
+        let mut emulator = Emulator::new(vec![0; BIOS_SIZE as usize]).unwrap();
+        let call = |emulator: &mut Emulator, table: u32, function: u32, character: char| {
+            emulator.set_register(9, function);
+            emulator.set_register(4, character as u32);
+            emulator.cpu_mut().set_pc(table);
+            emulator.step().unwrap();
+        };
+
+        call(&mut emulator, 0xA0, 0x3C, 'H');
+        call(&mut emulator, 0x800000B0, 0x3D, 'i');
+        // Other functions of the tables aren't putchar
+        call(&mut emulator, 0xA0, 0x3D, '!');
+        call(&mut emulator, 0xC0, 0x3D, '!');
+        assert_eq!(emulator.take_tty_output(), "Hi");
+        assert_eq!(emulator.tty_output(), "");
+
+        let received = std::rc::Rc::new(std::cell::RefCell::new(String::new()));
+        let sink = received.clone();
+        emulator.set_tty_callback(Box::new(move |character| sink.borrow_mut().push(character)));
+        call(&mut emulator, 0xB0, 0x3D, 'x');
+        assert_eq!(*received.borrow(), "x");
+        assert_eq!(emulator.tty_output(), "");
+
+        emulator.set_tty_enabled(false);
+        call(&mut emulator, 0xB0, 0x3D, 'y');
+        assert_eq!(*received.borrow(), "x");
+    }
+
+    #[test]
+    fn bios_reports_post_codes_and_prints_through_the_duart() {
+        let program: [u32; 16] = [
+            0x3C081F80, // lui t0, 0x1F80
+            0x24090001, // li t1, 1
+            0xA1092041, // post: sb t1, 0x2041(t0)
+            0x25290001, // addiu t1, t1, 1
+            0x292A0010, // slti t2, t1, 0x10
+            0x1540FFFC, // bnez t2, post
+            0x00000000, // nop
+            0x910A2021, // wait: lbu t2, 0x2021(t0)
+            0x00000000, // nop
+            0x314A0004, // andi t2, t2, 4, TxRDY
+            0x1140FFFC, // beqz t2, wait
+            0x00000000, // nop
+            0x2409004B, // li t1, 'K'
+            0xA1092023, // sb t1, 0x2023(t0)
+            0x1000FFFF, // b .
+            0x00000000, // nop
+        ];
+        let mut emulator = Emulator::new(bios_with_program(&program)).unwrap();
+
+        emulator.run_cycles(10_000).unwrap();
+        assert_eq!(emulator.mmu().last_post_code(), 0x0F);
+        assert_eq!(emulator.tty_output(), "K");
+    }
+
+    #[test]
+    fn bios_takes_the_pre_boot_branch_of_a_licensed_expansion_rom() {
+        // The pre-boot check of the BIOS, enter 0x1F000080 if the signature is at 0x1F000084
+        let program: [u32; 14] = [
+            0x3C081F00, // lui t0, 0x1F00
+            0x8D090084, // lw t1, 0x84(t0)
+            0x3C0A6563, // lui t2, 0x6563
+            0x354A694C, // ori t2, t2, 0x694C, "Lice"
+            0x152A0004, // bne t1, t2, skip
+            0x00000000, // nop
+            0x250B0080, // addiu t3, t0, 0x80
+            0x01600008, // jr t3
+            0x00000000, // nop
+            0x3C081F80, // skip: lui t0, 0x1F80
+            0x24090001, // li t1, 1
+            0xA1092041, // sb t1, 0x2041(t0)
+            0x1000FFFF, // b .
+            0x00000000, // nop
+        ];
+        let bios = bios_with_program(&program);
+        let mut rom = vec![0; 0x84];
+        rom.extend(b"Licensed by Sony Computer Entertainment Inc.");
+
+        let run = |rom: Option<Vec<u8>>| {
+            let mut emulator = Emulator::new(bios.clone()).unwrap();
+            if let Some(rom) = rom {
+                emulator.mmu_mut().load_expansion_rom(rom);
+            }
+            for _ in 0..100 {
+                if emulator.pc() == 0x1F000080 {
+                    return None;
+                }
+                emulator.step().unwrap();
+            }
+            Some(emulator.mmu().last_post_code())
+        };
+
+        assert_eq!(run(Some(rom)), None);
+        // Without the signature the BIOS carries on
+        assert_eq!(run(Some(vec![0; 0x100])), Some(1));
+        assert_eq!(run(None), Some(1));
+    }
+
+    #[test]
+    fn bios_code_pays_for_its_memory_accesses() {
+        let bios = bios_with_program(&[
+            0x3C081F80, // lui t0, 0x1F80
+            0x8D091070, // lw t1, 0x1070(t0)
+            0x8C0A0100, // lw t2, 0x100(zero)
+            0x3C0BBFC0, // lui t3, 0xBFC0
+            0x816C0000, // lb t4, 0(t3)
+            0x856D0000, // lh t5, 0(t3)
+        ]);
+
+        // Every fetch is an uncached word read from the BIOS, plus the I/O, RAM, BIOS byte and
+        // BIOS halfword loads
+        let mut emulator = Emulator::new(bios.clone()).unwrap();
+        for _ in 0..6 {
+            emulator.step().unwrap();
+        }
+        assert_eq!(emulator.cycles(), 6 * 24 + 2 + 4 + 7 + 11);
+
+        let mut emulator = Emulator::new(bios).unwrap();
+        emulator.set_cycle_accuracy(CycleAccuracy::Fast);
+        for _ in 0..6 {
+            emulator.step().unwrap();
+        }
+        assert_eq!(emulator.cycles(), 6);
+    }
+
+    // 8 data bits at a baud factor of 1, TX and RX enabled
+    const SERIAL_SETUP: [u32; 7] = [
+        0x3C081F80, // lui t0, 0x1F80
+        0x2409000D, // li t1, 0x0D
+        0xA5091058, // sh t1, 0x1058(t0)
+        0x24090020, // li t1, 0x20
+        0xA509105E, // sh t1, 0x105E(t0)
+        0x24090005, // li t1, 5
+        0xA509105A, // sh t1, 0x105A(t0)
+    ];
+
+    // Waits for a byte and reads it into t3
+    const SERIAL_RECEIVE: [u32; 7] = [
+        0x8D0A1054, // lw t2, 0x1054(t0)
+        0x00000000, // nop
+        0x314A0002, // andi t2, t2, 2
+        0x1140FFFC, // beqz t2, -4
+        0x00000000, // nop
+        0x910B1050, // lbu t3, 0x1050(t0)
+        0x00000000, // nop
+    ];
+
+    #[test]
+    fn linked_emulators_exchange_a_handshake() {
+        // The first one sends 0x5A and waits for the answer, the second one answers with the
+        // received byte plus one
+        let first = [
+            &SERIAL_SETUP[..],
+            &[
+                0x2409005A, // li t1, 0x5A
+                0xA1091050, // sb t1, 0x1050(t0)
+            ],
+            &SERIAL_RECEIVE,
+            &[0x1000FFFF, 0x00000000],
+        ]
+        .concat();
+        let second = [
+            &SERIAL_SETUP[..],
+            &SERIAL_RECEIVE,
+            &[
+                0x25690001, // addiu t1, t3, 1
+                0xA1091050, // sb t1, 0x1050(t0)
+                0x1000FFFF, // b .
+                0x00000000, // nop
+            ],
+        ]
+        .concat();
+
+        let mut emulators = [
+            emulator_with_program(&first),
+            emulator_with_program(&second),
+        ];
+        let (a, b) = crate::ChannelLink::pair();
+        emulators[0].set_serial_link(Some(Box::new(a)));
+        emulators[1].set_serial_link(Some(Box::new(b)));
+
+        // Stepped in turns like a frontend running both
+        for _ in 0..50 {
+            for emulator in &mut emulators {
+                emulator.run_cycles(1000).unwrap();
+            }
+        }
+
+        assert_eq!(emulators[1].register(11), 0x5A);
+        assert_eq!(emulators[0].register(11), 0x5B);
+    }
+
+    // Stores a copy of t1 counting down, loads it back and sums it into t3, then takes a syscall
+    const TRACED_LOOP: [u32; 10] = [
+        0x3C088002, // lui t0, 0x8002
+        0x2409000A, // li t1, 10
+        0xAD090000, // loop: sw t1, 0(t0)
+        0x8D0A0000, // lw t2, 0(t0)
+        0x25080004, // addiu t0, t0, 4
+        0x016A5821, // addu t3, t3, t2
+        0x2529FFFF, // addiu t1, t1, -1
+        0x1520FFFA, // bnez t1, loop
+        0x00000000, // nop
+        0x0000000C, // syscall
+    ];
+
+    // A trace writer the test can still read after handing it to the emulator
+    #[derive(Clone, Default)]
+    struct SharedBuffer(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn trace_program(
+        program: &[u32],
+        instructions: usize,
+        compression: crate::trace::Compression,
+    ) -> Vec<u8> {
+        let mut emulator = emulator_with_program(program);
+        let buffer = SharedBuffer::default();
+        let writer = TraceWriter::new(Box::new(buffer.clone()) as Box<dyn Write>, compression);
+        emulator.set_instruction_trace(Some(writer.unwrap()));
+        for _ in 0..instructions {
+            emulator.step().unwrap();
+        }
+        emulator.take_instruction_trace().unwrap().finish().unwrap();
+
+        let bytes = buffer.0.borrow().clone();
+        bytes
+    }
+
+    #[test]
+    fn traces_record_writes_delay_slots_and_exceptions() {
+        use crate::trace::{Compression, TraceReader};
+
+        let trace = trace_program(&TRACED_LOOP, 80, Compression::None);
+        let records: Vec<_> = TraceReader::new(trace.as_slice())
+            .unwrap()
+            .collect::<std::io::Result<_>>()
+            .unwrap();
+
+        assert_eq!(records.len(), 80);
+        assert_eq!(records[0].pc, PROGRAM);
+        assert_eq!(records[0].write, Some((8, 0x80020000)));
+        // The first delay slot and the store before the branch back
+        assert_eq!(records[8].flags, DELAY_SLOT);
+        assert_eq!(records[9].pc, PROGRAM + 8);
+        assert_eq!(records[3].write, None);
+        let syscall = records.iter().find(|record| record.opcode == 0x0000000C);
+        assert_eq!(syscall.unwrap().flags, EXCEPTION);
+    }
+
+    // The reference trace is compared record by record, GOLDEN_BLESS=1 writes a new one
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn program_matches_its_golden_trace() {
+        use crate::trace::{compare, Compression, TraceReader};
+
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/loop.trace");
+        let actual = trace_program(&TRACED_LOOP, 200, Compression::Deflate);
+        if std::env::var_os("GOLDEN_BLESS").is_some() {
+            std::fs::write(&path, &actual).unwrap();
+            return;
+        }
+
+        let expected = std::fs::read(&path).unwrap();
+        let difference = compare(
+            TraceReader::new(expected.as_slice()).unwrap(),
+            TraceReader::new(actual.as_slice()).unwrap(),
+        )
+        .unwrap();
+        if let Some(difference) = difference {
+            panic!("{}", difference);
+        }
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn million_instruction_traces_compress_to_a_few_megabytes() {
+        let mut program = TRACED_LOOP;
+        // Loop 0x100000 times
+        program[1] = 0x3C090010; // lui t1, 0x10
+        let trace = trace_program(&program, 1_000_000, crate::trace::Compression::Deflate);
+
+        assert!(trace.len() < 3 * 1024 * 1024, "{} bytes", trace.len());
+    }
+
+    #[test]
+    fn idle_loops_skip_to_the_next_event() {
+        // Waits for 10 VBlanks in I_STAT, then sets t2
+        let program = [
+            0x3C081F80, // lui t0, 0x1F80
+            0x240B000A, // li t3, 10
+            0x8D091070, // lw t1, 0x1070(t0)
+            0x00000000, // nop
+            0x31290001, // andi t1, t1, 1
+            0x1120FFFC, // beqz t1, -4
+            0x00000000, // nop
+            0xAD001070, // sw zero, 0x1070(t0)
+            0x256BFFFF, // addiu t3, t3, -1
+            0x1560FFF8, // bnez t3, -8
+            0x00000000, // nop
+            0x240A0001, // li t2, 1
+            0x1000FFFF, // b .
+            0x00000000, // nop
+        ];
+        let run = |skipping| {
+            let mut emulator = emulator_with_program(&program);
+            emulator.set_idle_loop_skipping(skipping);
+            let mut steps = 0;
+            while emulator.register(10) == 0 {
+                emulator.step().unwrap();
+                steps += 1;
+            }
+            (emulator, steps)
+        };
+
+        let (emulator, steps) = run(false);
+        let (skipping, skipped_steps) = run(true);
+        assert_eq!(skipping.cycles(), emulator.cycles());
+        assert_eq!(skipping.cpu.registers(), emulator.cpu.registers());
+        assert_eq!(skipping.pc(), emulator.pc());
+        assert_eq!(skipping.frame_count(), emulator.frame_count());
+        assert!(skipped_steps * 20 < steps, "{} {}", skipped_steps, steps);
+        assert!(skipping.skipped_idle_cycles() > 0);
+    }
+
+    #[test]
+    fn idle_loops_with_side_effects_run_every_iteration() {
+        // Polls the CDROM response FIFO, which pops a byte each read
+        let program = [
+            0x3C081F80, // lui t0, 0x1F80
+            0x81091801, // lb t1, 0x1801(t0)
+            0x1000FFFE, // b -2
+            0x00000000, // nop
+        ];
+        let mut emulator = emulator_with_program(&program);
+        emulator.set_idle_loop_skipping(true);
+        emulator.run_cycles(200_000).unwrap();
+        assert_eq!(emulator.skipped_idle_cycles(), 0);
+
+        // And so do loops that store
+        let program = [
+            0x3C080001, // lui t0, 0x0001
+            0xAD000000, // sw zero, 0(t0)
+            0x1000FFFE, // b -2
+            0x00000000, // nop
+        ];
+        let mut emulator = emulator_with_program(&program);
+        emulator.set_idle_loop_skipping(true);
+        emulator.run_cycles(200_000).unwrap();
+        assert_eq!(emulator.skipped_idle_cycles(), 0);
+    }
+}
@@ -0,0 +1,48 @@
+use std::fmt;
+
+use crate::{hwregs, watchdog::HangKind};
+
+// Errors that stop emulation, a frontend can report these and keep running
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmuError {
+    UnhandledInstruction { pc: u32, word: u32 },
+    UnmappedRead { address: u32, size: u32 },
+    UnmappedWrite { address: u32, size: u32, value: u32 },
+    // Raised by the watchdog, the report has the loop and the state of the devices
+    Hang { kind: HangKind, report: String },
+    // Writing the instruction trace failed
+    Trace(String),
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmuError::UnhandledInstruction { pc, word } => {
+                write!(f, "Unhandled instruction 0x{:08x} at 0x{:08x}", word, pc)
+            }
+            EmuError::UnmappedRead { address, size } => write!(
+                f,
+                "Cannot read {} bytes from address 0x{:08x} ({})",
+                size,
+                address,
+                hwregs::describe(*address, *size)
+            ),
+            EmuError::UnmappedWrite {
+                address,
+                size,
+                value,
+            } => write!(
+                f,
+                "Cannot write 0x{:08x} ({} bytes) to address 0x{:08x} ({})",
+                value,
+                size,
+                address,
+                hwregs::describe(*address, *size)
+            ),
+            EmuError::Hang { kind, report } => write!(f, "Hang detected, {}\n{}", kind, report),
+            EmuError::Trace(error) => write!(f, "Failed to write the instruction trace: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for EmuError {}
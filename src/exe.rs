@@ -0,0 +1,203 @@
+use std::fmt;
+
+use crate::mmu::RAM_SIZE;
+
+// PS-X EXE header layout, the program data starts after the 2KB header
+const MAGIC: &[u8] = b"PS-X EXE";
+const HEADER_SIZE: usize = 0x800;
+
+#[derive(Debug)]
+pub enum ExeError {
+    InvalidMagic,
+    Truncated {
+        expected: usize,
+        actual: usize,
+    },
+    // The text or bss section is not in the first 2MB of the KUSEG, KSEG0 or KSEG1 view of memory
+    OutsideRam {
+        section: &'static str,
+        address: u32,
+        size: u32,
+    },
+}
+
+impl fmt::Display for ExeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExeError::InvalidMagic => write!(f, "Missing PS-X EXE header"),
+            ExeError::Truncated { expected, actual } => write!(
+                f,
+                "Truncated EXE, expected {} bytes but got {}",
+                expected, actual
+            ),
+            ExeError::OutsideRam {
+                section,
+                address,
+                size,
+            } => write!(
+                f,
+                "The {} section of {} bytes at 0x{:08x} does not fit in RAM",
+                section, size, address
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ExeError {}
+
+pub struct Exe {
+    pub pc: u32,
+    pub gp: u32,
+    pub destination: u32,
+    pub bss_start: u32,
+    pub bss_size: u32,
+    pub sp: Option<u32>,
+    pub data: Vec<u8>,
+}
+
+impl Exe {
+    pub fn parse(data: &[u8]) -> Result<Self, ExeError> {
+        if data.len() < HEADER_SIZE {
+            return Err(ExeError::Truncated {
+                expected: HEADER_SIZE,
+                actual: data.len(),
+            });
+        }
+
+        if &data[..MAGIC.len()] != MAGIC {
+            return Err(ExeError::InvalidMagic);
+        }
+
+        let word = |offset: usize| {
+            u32::from_le_bytes([
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            ])
+        };
+
+        let size = word(0x1C) as usize;
+        let expected = HEADER_SIZE + size;
+        if data.len() < expected {
+            return Err(ExeError::Truncated {
+                expected,
+                actual: data.len(),
+            });
+        }
+
+        // The stack pointer is only set up if the EXE specifies a base address
+        let sp_base = word(0x30);
+        let sp = if sp_base != 0 {
+            Some(sp_base.wrapping_add(word(0x34)))
+        } else {
+            None
+        };
+
+        let exe = Self {
+            pc: word(0x10),
+            gp: word(0x14),
+            destination: word(0x18),
+            bss_start: word(0x28),
+            bss_size: word(0x2C),
+            sp,
+            data: data[HEADER_SIZE..expected].to_vec(),
+        };
+        exe.check_ranges()?;
+
+        Ok(exe)
+    }
+
+    // The text and bss are copied to and cleared in RAM, anywhere else they would write to I/O
+    // registers or the BIOS
+    pub fn check_ranges(&self) -> Result<(), ExeError> {
+        let sections = [
+            ("text", self.destination, self.data.len() as u32),
+            ("bss", self.bss_start, self.bss_size),
+        ];
+
+        for (section, address, size) in sections {
+            let end = (address & 0x1FFFFFFF) as u64 + size as u64;
+            if size != 0 && (!matches!(address >> 29, 0 | 4 | 5) || end > RAM_SIZE as u64) {
+                return Err(ExeError::OutsideRam {
+                    section,
+                    address,
+                    size,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exe(destination: u32, words: u32, bss_start: u32, bss_size: u32) -> Vec<u8> {
+        let mut exe = vec![0; HEADER_SIZE + words as usize * 4];
+        exe[..8].copy_from_slice(MAGIC);
+        let header = [
+            (0x10, 0x80010000),
+            (0x18, destination),
+            (0x1C, words * 4),
+            (0x28, bss_start),
+            (0x2C, bss_size),
+        ];
+        for (offset, value) in header {
+            exe[offset..offset + 4].copy_from_slice(&u32::to_le_bytes(value));
+        }
+        exe
+    }
+
+    #[test]
+    fn sections_have_to_be_in_ram() {
+        // Up to the last byte of RAM, in any of the three views
+        for destination in [0x00010000, 0x80010000, 0xA01FFF00] {
+            let parsed = Exe::parse(&exe(destination, 0x40, 0x801FFFF0, 0x10)).unwrap();
+            assert_eq!(
+                (parsed.destination, parsed.data.len()),
+                (destination, 0x100)
+            );
+        }
+        // An empty bss can be anywhere
+        assert!(Exe::parse(&exe(0x80010000, 1, 0xFFFFFFFF, 0)).is_ok());
+
+        let outside = |exe: &[u8]| match Exe::parse(exe) {
+            Err(ExeError::OutsideRam { section, .. }) => Some(section),
+            _ => None,
+        };
+        // Past the end of RAM, into the RAM mirrors, KSEG2 and the scratchpad
+        assert_eq!(outside(&exe(0x801FFF00, 0x41, 0, 0)), Some("text"));
+        assert_eq!(outside(&exe(0x80200000, 1, 0, 0)), Some("text"));
+        assert_eq!(outside(&exe(0xC0010000, 1, 0, 0)), Some("text"));
+        assert_eq!(outside(&exe(0x80010000, 1, 0x1F800000, 4)), Some("bss"));
+        assert_eq!(outside(&exe(0x80010000, 1, 0x801FFFFC, 8)), Some("bss"));
+    }
+
+    #[test]
+    fn broken_headers_are_rejected() {
+        assert!(matches!(
+            Exe::parse(&[0; 0x10]),
+            Err(ExeError::Truncated {
+                expected: HEADER_SIZE,
+                actual: 0x10
+            })
+        ));
+        assert!(matches!(
+            Exe::parse(&[0; HEADER_SIZE]),
+            Err(ExeError::InvalidMagic)
+        ));
+
+        let mut truncated = exe(0x80010000, 4, 0, 0);
+        truncated.truncate(HEADER_SIZE + 8);
+        assert!(matches!(
+            Exe::parse(&truncated),
+            Err(ExeError::Truncated {
+                expected: 0x810,
+                actual: 0x808
+            })
+        ));
+    }
+}
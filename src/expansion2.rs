@@ -0,0 +1,72 @@
+// Expansion Region 2, on retail units only the POST display is used by the BIOS.
+// Dev units (DTL-H2000) additionally have a DUART, the dev BIOSes print their TTY output through it.
+// With testing enabled the registers PCSX-Redux gives test ROMs are mapped at 0x80.
+
+const DUART_STATUS_A: u32 = 0x21;
+const DUART_TX_A: u32 = 0x23;
+const DUART_STATUS_B: u32 = 0x29;
+const DUART_TX_B: u32 = 0x2B;
+const POST: u32 = 0x41;
+// Reads "PCSX" so ROMs know they are running in an emulator
+const TEST_MAGIC: u32 = 0x80;
+const TEST_TTY: u32 = 0x80;
+const TEST_EXIT: u32 = 0x82;
+
+// TxRDY | TxEMT, the transmitter is always idle since characters are sent instantly
+const DUART_TX_READY: u8 = 0x0C;
+
+pub struct Expansion2 {
+    post_code: u8,
+    duart_output: String,
+    testing: bool,
+    exit_code: Option<u8>,
+}
+
+impl Expansion2 {
+    pub fn new() -> Self {
+        Self {
+            post_code: 0,
+            duart_output: String::new(),
+            testing: false,
+            exit_code: None,
+        }
+    }
+
+    pub fn set_testing(&mut self, enabled: bool) {
+        self.testing = enabled;
+    }
+
+    // The code the guest wrote to the exit register
+    pub fn exit_code(&self) -> Option<u8> {
+        self.exit_code
+    }
+
+    pub fn post_code(&self) -> u8 {
+        self.post_code
+    }
+
+    pub fn take_duart_output(&mut self) -> String {
+        std::mem::take(&mut self.duart_output)
+    }
+
+    // All the registers are 8 bit wide, wider accesses are split into bytes by the MMU
+    pub fn read(&self, offset: u32) -> u8 {
+        match offset {
+            DUART_STATUS_A | DUART_STATUS_B => DUART_TX_READY,
+            POST => self.post_code,
+            TEST_MAGIC..=0x83 if self.testing => b"PCSX"[(offset - TEST_MAGIC) as usize],
+            // Nothing connected
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, offset: u32, value: u8) {
+        match offset {
+            DUART_TX_A | DUART_TX_B => self.duart_output.push(value as char),
+            POST => self.post_code = value,
+            TEST_TTY if self.testing => self.duart_output.push(value as char),
+            TEST_EXIT if self.testing => self.exit_code = Some(value),
+            _ => {}
+        }
+    }
+}
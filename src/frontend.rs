@@ -0,0 +1,254 @@
+use std::{
+    process::exit,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
+use psx_rust::{disc::Disc, Axis, Button, EmuError, Emulator, Turbo};
+
+use crate::args::Args;
+
+const TITLE: &str = "psx-rust";
+const INITIAL_WIDTH: usize = 640;
+const INITIAL_HEIGHT: usize = 480;
+
+const NTSC_FRAME_RATE: f64 = 59.94;
+const PAL_FRAME_RATE: f64 = 50.0;
+const CPU_CLOCK: f64 = 33_868_800.0;
+
+// Keyboard layout of the pad in the first port
+const KEY_MAP: [(Key, Button); 16] = [
+    (Key::Up, Button::Up),
+    (Key::Down, Button::Down),
+    (Key::Left, Button::Left),
+    (Key::Right, Button::Right),
+    (Key::Enter, Button::Start),
+    (Key::Backspace, Button::Select),
+    (Key::S, Button::Cross),
+    (Key::D, Button::Circle),
+    (Key::A, Button::Square),
+    (Key::W, Button::Triangle),
+    (Key::Q, Button::L1),
+    (Key::E, Button::R1),
+    (Key::Key1, Button::L2),
+    (Key::Key3, Button::R2),
+    (Key::Z, Button::L3),
+    (Key::C, Button::R3),
+];
+
+// Held together with a button key, toggles turbo for the button
+const TURBO_KEYS: [Key; 2] = [Key::LeftShift, Key::RightShift];
+
+// The keys pushing the left stick to the minimum and the maximum of each axis
+const STICK_KEYS: [(Axis, Key, Key); 2] =
+    [(Axis::LeftX, Key::J, Key::L), (Axis::LeftY, Key::I, Key::K)];
+
+// When the emulation falls further behind than this the limiter stops trying to catch up
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+// Shows the GPU output in a window. The emulation runs on this thread a frame at a time, the window
+// is updated in between. Returns when the window is closed.
+pub fn run(emulator: &mut Emulator, args: &Args) -> Result<(), EmuError> {
+    let options = WindowOptions {
+        resize: true,
+        ..WindowOptions::default()
+    };
+    let mut window =
+        Window::new(TITLE, INITIAL_WIDTH, INITIAL_HEIGHT, options).unwrap_or_else(|error| {
+            eprintln!("Failed to open window: {}", error);
+            exit(1);
+        });
+
+    let mut limiter = FrameLimiter::new();
+    let mut stats = Stats::new(emulator.cycles());
+    let mut fast_forward = args.fast_forward;
+    let mut buffer = Vec::new();
+    let mut lid_open = false;
+    let mut disc_index = 0;
+    let mut turbo = Turbo::new(args.turbo_rate);
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        let turbo_modifier = TURBO_KEYS.iter().any(|key| window.is_key_down(*key));
+        for (key, button) in KEY_MAP {
+            if turbo_modifier && window.is_key_pressed(key, KeyRepeat::No) {
+                let enabled = turbo.toggle(button);
+                println!(
+                    "Turbo {} for {:?}",
+                    if enabled { "on" } else { "off" },
+                    button
+                );
+            }
+            let held = window.is_key_down(key);
+            emulator.set_button_state(button, turbo.apply(button, held, emulator.frame_count()));
+        }
+        for (axis, minimum, maximum) in STICK_KEYS {
+            let value = match (window.is_key_down(minimum), window.is_key_down(maximum)) {
+                (true, false) => 0x00,
+                (false, true) => 0xFF,
+                _ => 0x80,
+            };
+            emulator.set_axis(axis, value);
+        }
+        if window.is_key_pressed(Key::F1, KeyRepeat::No) {
+            emulator.toggle_analog_mode();
+        }
+        // F2 opens the lid and puts in the next --disc, pressing it again closes the lid
+        if window.is_key_pressed(Key::F2, KeyRepeat::No) {
+            if lid_open {
+                emulator.close_lid();
+                println!("Closed the lid");
+            } else {
+                emulator.open_lid();
+                println!("Opened the lid");
+                if !args.discs.is_empty() {
+                    disc_index = (disc_index + 1) % args.discs.len();
+                    let path = &args.discs[disc_index];
+                    match Disc::open(path) {
+                        Ok(disc) => {
+                            emulator.swap_disc(disc);
+                            println!("Changed to disc '{}'", path);
+                        }
+                        Err(error) => eprintln!("Failed to open disc '{}': {}", path, error),
+                    }
+                }
+            }
+            lid_open = !lid_open;
+        }
+
+        // F3 prints the kernel events and threads, which --trace-bios follows
+        if window.is_key_pressed(Key::F3, KeyRepeat::No) {
+            match emulator.kernel_tables() {
+                Some(tables) => print!("{}", tables),
+                None => println!("Start with --trace-bios to follow the kernel events"),
+            }
+        }
+
+        emulator.run_frame()?;
+        if args
+            .max_cycles
+            .is_some_and(|cycles| emulator.cycles() >= cycles)
+            || emulator.exit_code().is_some()
+        {
+            break;
+        }
+
+        let (width, height) = window.get_size();
+        scale(
+            &emulator.render_frame(),
+            emulator.display_size(),
+            &mut buffer,
+            (width, height),
+        );
+        if let Err(error) = window.update_with_buffer(&buffer, width, height) {
+            eprintln!("Failed to update window: {}", error);
+            break;
+        }
+
+        if window.is_key_pressed(Key::Tab, KeyRepeat::No) {
+            fast_forward = !fast_forward;
+        }
+
+        if fast_forward {
+            limiter.reset();
+        } else {
+            let frame_rate = if emulator.is_pal() {
+                PAL_FRAME_RATE
+            } else {
+                NTSC_FRAME_RATE
+            };
+            limiter.wait(Duration::from_secs_f64(1.0 / (frame_rate * args.speed)));
+        }
+
+        if let Some(title) = stats.frame(emulator.cycles()) {
+            window.set_title(&title);
+        }
+    }
+
+    Ok(())
+}
+
+// Sleeps until the deadline of the next frame. The deadline advances by exactly one frame each
+// time, so the time lost oversleeping is made up by the following frames.
+struct FrameLimiter {
+    deadline: Instant,
+}
+
+impl FrameLimiter {
+    fn new() -> Self {
+        Self {
+            deadline: Instant::now(),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.deadline = Instant::now();
+    }
+
+    fn wait(&mut self, frame: Duration) {
+        self.deadline += frame;
+
+        let now = Instant::now();
+        if self.deadline > now {
+            sleep(self.deadline - now);
+        } else if now - self.deadline > MAX_LAG {
+            self.deadline = now;
+        }
+    }
+}
+
+// Frame rate and emulation speed, reported once per second
+struct Stats {
+    start: Instant,
+    frames: u32,
+    cycles: u64,
+}
+
+impl Stats {
+    fn new(cycles: u64) -> Self {
+        Self {
+            start: Instant::now(),
+            frames: 0,
+            cycles,
+        }
+    }
+
+    // Returns the window title when a second has passed
+    fn frame(&mut self, cycles: u64) -> Option<String> {
+        self.frames += 1;
+
+        let elapsed = self.start.elapsed().as_secs_f64();
+        if elapsed < 1.0 {
+            return None;
+        }
+
+        let fps = self.frames as f64 / elapsed;
+        let speed = (cycles - self.cycles) as f64 / elapsed / CPU_CLOCK * 100.0;
+        *self = Self::new(cycles);
+
+        Some(format!("{} - {:.1} fps ({:.0}%)", TITLE, fps, speed))
+    }
+}
+
+// Nearest neighbor scaling of the 0xRRGGBBAA frame into the 0x00RRGGBB window buffer
+fn scale(
+    frame: &[u32],
+    (frame_width, frame_height): (usize, usize),
+    buffer: &mut Vec<u32>,
+    (width, height): (usize, usize),
+) {
+    buffer.clear();
+    buffer.resize(width * height, 0);
+
+    if frame_width == 0 || frame_height == 0 {
+        return;
+    }
+
+    for (y, line) in buffer.chunks_exact_mut(width.max(1)).enumerate() {
+        let source = &frame[(y * frame_height / height) * frame_width..][..frame_width];
+
+        for (x, pixel) in line.iter_mut().enumerate() {
+            *pixel = source[x * frame_width / width] >> 8;
+        }
+    }
+}
@@ -0,0 +1,2171 @@
+use crate::dma::DmaDevice;
+
+pub const VRAM_WIDTH: usize = 1024;
+pub const VRAM_HEIGHT: usize = 512;
+
+// The GPU clock relative to the CPU clock, 53.693175MHz for NTSC and 53.203425MHz for PAL
+const GPU_CLOCK_DENOMINATOR: u64 = 451584;
+const NTSC_GPU_CLOCK_NUMERATOR: u64 = 715909;
+const PAL_GPU_CLOCK_NUMERATOR: u64 = 709379;
+// GPU cycles per scanline
+const NTSC_SCANLINE_CYCLES: u32 = 3413;
+const PAL_SCANLINE_CYCLES: u32 = 3406;
+const NTSC_SCANLINES: u32 = 263;
+const PAL_SCANLINES: u32 = 314;
+// The horizontal blank starts after the 320 visible dots
+const HBLANK_START: u32 = 320 * 8;
+
+// GPU, commands come in through GP0 (drawing) and GP1 (display control), GPUREAD and GPUSTAT are
+// read back from the same addresses
+pub struct Gpu {
+    // 1024x512 15 bit pixels
+    vram: Box<[u16; VRAM_WIDTH * VRAM_HEIGHT]>,
+    // Words of the GP0 command being received
+    command: Vec<u32>,
+    // GP0 0xA0, the following GP0 words are pixels
+    vram_write: Option<Transfer>,
+    // GP0 0xC0, the pixels are read through GPUREAD
+    vram_read: Option<Transfer>,
+    // GP0 0xE1 bits 0..13, also updated by the texpage attribute of textured polygons
+    draw_mode: u32,
+    // GP0 0xE2 bits 0..19
+    texture_window: u32,
+    // GP0 0xE6, bit 0 sets the mask bit of drawn pixels and bit 1 protects pixels that have it set
+    mask_settings: u32,
+    // GP0 0xE3 and 0xE4, the edges are inclusive
+    drawing_area_left: i32,
+    drawing_area_top: i32,
+    drawing_area_right: i32,
+    drawing_area_bottom: i32,
+    // GP0 0xE5, added to all vertices
+    drawing_offset_x: i32,
+    drawing_offset_y: i32,
+    // GP1 0x05, top left corner of the displayed part of VRAM
+    display_start_x: u32,
+    display_start_y: u32,
+    // GP1 0x06, in GPU cycles from the start of the scanline
+    display_range_x1: u32,
+    display_range_x2: u32,
+    // GP1 0x07, in scanlines from the start of the frame
+    display_range_y1: u32,
+    display_range_y2: u32,
+    // GP1 0x08, kept in the GPUSTAT bit layout (bits 14 and 16..22)
+    display_mode: u32,
+    display_disabled: bool,
+    // GP1 0x04
+    dma_direction: u32,
+    // Set by GP0 0x1F, acknowledged with GP1 0x02
+    interrupt: bool,
+    // IRQ1 has to be requested
+    interrupt_pending: bool,
+
+    // Video timing, the remainders carry the fractions of GPU cycles and dots over so no ticks
+    // are lost
+    clock_remainder: u64,
+    dot_remainder: u32,
+    // GPU cycles into the current scanline
+    line_position: u32,
+    scanline: u32,
+    // Outside of the vertical display range
+    in_vblank: bool,
+    // The field of interlaced modes, flips every frame
+    odd_field: bool,
+    // Frames since power on, frontends present a frame every time it changes
+    frame_count: u64,
+    // GPUREAD, holds the last value read so it can be read again
+    read_latch: u32,
+    // A GP1 0x10 result that wasn't read yet, it is returned before the pixels of a VRAM to CPU
+    // copy in progress
+    info_response: Option<u32>,
+    // Fill untextured spans 8 pixels at a time, only set when the host supports it
+    simd: bool,
+}
+
+// A rectangle of VRAM that is copied a pixel at a time, rows first. Coordinates wrap around the
+// edges of VRAM.
+#[derive(Clone, Copy)]
+struct Transfer {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    column: u32,
+    row: u32,
+}
+
+impl Transfer {
+    // From the position and size words of the copy commands
+    fn new(position: u32, size: u32) -> Self {
+        Self {
+            x: position & 0x3FF,
+            y: (position >> 16) & 0x1FF,
+            // A size of 0 is the full width or height
+            width: ((size & 0xFFFF).wrapping_sub(1) & 0x3FF) + 1,
+            height: ((size >> 16).wrapping_sub(1) & 0x1FF) + 1,
+            column: 0,
+            row: 0,
+        }
+    }
+
+    // VRAM index of the next pixel, None once the whole rectangle was copied
+    fn next(&mut self) -> Option<usize> {
+        if self.is_done() {
+            return None;
+        }
+
+        let index = vram_index(self.x + self.column, self.y + self.row);
+        self.column += 1;
+        if self.column == self.width {
+            self.column = 0;
+            self.row += 1;
+        }
+
+        Some(index)
+    }
+
+    fn is_done(&self) -> bool {
+        self.row == self.height
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+struct Color {
+    r: u8,
+    g: u8,
+    b: u8,
+}
+
+impl Color {
+    // From the 24 bit color of the command words
+    fn from_command(word: u32) -> Self {
+        Self {
+            r: word as u8,
+            g: (word >> 8) as u8,
+            b: (word >> 16) as u8,
+        }
+    }
+
+    // Adds the offset of the dither matrix to every channel
+    fn dither(self, offset: i32) -> Self {
+        let channel = |value: u8| (value as i32 + offset).clamp(0, 0xFF) as u8;
+
+        Self {
+            r: channel(self.r),
+            g: channel(self.g),
+            b: channel(self.b),
+        }
+    }
+
+    // Truncates to the 15 bit BGR format of VRAM
+    fn to_bgr15(self) -> u16 {
+        ((self.r >> 3) as u16) | (((self.g >> 3) as u16) << 5) | (((self.b >> 3) as u16) << 10)
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Vertex {
+    x: i32,
+    y: i32,
+    color: Color,
+    u: u8,
+    v: u8,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum TextureDepth {
+    // Indexes into the CLUT
+    Bits4,
+    Bits8,
+    // Direct 15 bit colors
+    Bits15,
+}
+
+// Where and how the texels of a textured primitive are fetched
+#[derive(Clone, Copy)]
+struct Texture {
+    page_x: u32,
+    page_y: u32,
+    depth: TextureDepth,
+    clut_x: u32,
+    clut_y: u32,
+    // Unmodulated, the texels are drawn as they are
+    raw: bool,
+}
+
+// What every pixel of a primitive goes through besides its color
+#[derive(Clone, Copy)]
+struct Primitive {
+    texture: Option<Texture>,
+    semi_transparent: bool,
+    dither: bool,
+}
+
+// A triangle with its vertices in clockwise order, as set up by draw_triangle
+struct Triangle {
+    vertices: [Vertex; 3],
+    bias: [i32; 3],
+    area: i64,
+}
+
+// Offsets added to the 8 bit color channels before they are truncated to 5 bits, indexed by the
+// low two bits of y and x
+const DITHER_MATRIX: [[i32; 4]; 4] = [
+    [-4, 0, -3, 1],
+    [2, -2, 3, -1],
+    [-3, 1, -4, 0],
+    [3, -1, 2, -2],
+];
+
+pub struct VideoEvents {
+    pub dotclocks: u32,
+    pub hblanks: u32,
+    // Some(true) when the blank was entered during the step, Some(false) when it was left
+    pub hblank: Option<bool>,
+    pub vblank: Option<bool>,
+}
+
+// The display ranges after a reset
+const DEFAULT_DISPLAY_RANGE_X: (u32, u32) = (0x200, 0xC00);
+const DEFAULT_DISPLAY_RANGE_Y: (u32, u32) = (0x10, 0x100);
+
+// The version reported by GP1 0x10 sub-command 0x07, 2 for the "new" GPU
+const GPU_VERSION: u32 = 2;
+
+impl Gpu {
+    pub fn new() -> Self {
+        Self {
+            vram: vec![0; VRAM_WIDTH * VRAM_HEIGHT].try_into().unwrap(),
+            command: Vec::with_capacity(16),
+            vram_write: None,
+            vram_read: None,
+            draw_mode: 0,
+            texture_window: 0,
+            mask_settings: 0,
+            drawing_area_left: 0,
+            drawing_area_top: 0,
+            drawing_area_right: 0,
+            drawing_area_bottom: 0,
+            drawing_offset_x: 0,
+            drawing_offset_y: 0,
+            display_start_x: 0,
+            display_start_y: 0,
+            display_range_x1: DEFAULT_DISPLAY_RANGE_X.0,
+            display_range_x2: DEFAULT_DISPLAY_RANGE_X.1,
+            display_range_y1: DEFAULT_DISPLAY_RANGE_Y.0,
+            display_range_y2: DEFAULT_DISPLAY_RANGE_Y.1,
+            display_mode: 0,
+            display_disabled: true,
+            dma_direction: 0,
+            interrupt: false,
+            interrupt_pending: false,
+            clock_remainder: 0,
+            dot_remainder: 0,
+            line_position: 0,
+            scanline: 0,
+            // The first lines are above the display range
+            in_vblank: true,
+            odd_field: false,
+            frame_count: 0,
+            read_latch: 0,
+            info_response: None,
+            simd: simd_available(),
+        }
+    }
+
+    pub fn status(&self) -> u32 {
+        let interlaced = self.display_mode & (1 << 22) != 0;
+
+        // Drawing odd lines, this flips every scanline in 240 line modes and every field in 480 line
+        // modes. It is always 0 during the vertical blank.
+        let odd_line = if self.is_interlaced_480() {
+            self.odd_field
+        } else {
+            self.scanline & 1 != 0
+        };
+
+        let mut status = self.display_mode;
+        status |= self.draw_mode & 0x7FF;
+        status |= self.mask_settings << 11;
+        // Texture disable
+        status |= ((self.draw_mode >> 11) & 1) << 15;
+        // The interlace field always reads as 1 in progressive modes
+        status |= ((self.odd_field || !interlaced) as u32) << 13;
+        status |= (self.display_disabled as u32) << 23;
+        status |= (self.interrupt as u32) << 24;
+
+        status |= (self.data_request() as u32) << 25;
+        status |= (self.ready_for_command() as u32) << 26;
+        status |= (self.ready_to_send_vram() as u32) << 27;
+        status |= (self.ready_for_dma() as u32) << 28;
+        status |= self.dma_direction << 29;
+        status |= ((odd_line && !self.in_vblank) as u32) << 31;
+
+        status
+    }
+
+    // Commands execute as soon as their last word arrives, so the GPU only isn't ready while it
+    // collects the parameters of a command or the data of a VRAM write
+    fn ready_for_command(&self) -> bool {
+        self.command.is_empty() && self.vram_write.is_none()
+    }
+
+    fn ready_to_send_vram(&self) -> bool {
+        self.vram_read.is_some()
+    }
+
+    fn ready_for_dma(&self) -> bool {
+        true
+    }
+
+    // The data request bit mirrors whatever the DMA direction selects
+    fn data_request(&self) -> bool {
+        match self.dma_direction {
+            0 => false,
+            1 => true,
+            2 => self.ready_for_dma(),
+            _ => self.ready_to_send_vram(),
+        }
+    }
+
+    // GPUREAD, returns GP1 0x10 results and streams the pixels of VRAM to CPU copies. When
+    // nothing is pending the last value is read again.
+    pub fn read(&mut self) -> u32 {
+        if let Some(response) = self.info_response.take() {
+            self.read_latch = response;
+        } else if let Some(transfer) = &mut self.vram_read {
+            let mut word = 0;
+            for shift in [0, 16] {
+                if let Some(index) = transfer.next() {
+                    word |= (self.vram[index] as u32) << shift;
+                }
+            }
+            self.read_latch = word;
+
+            if transfer.is_done() {
+                self.vram_read = None;
+            }
+        }
+
+        self.read_latch
+    }
+
+    pub fn gp0(&mut self, value: u32) {
+        // Pixel data of a CPU to VRAM copy, two pixels per word. When the number of pixels is odd
+        // the upper half of the last word is ignored.
+        if let Some(transfer) = &mut self.vram_write {
+            for pixel in [value as u16, (value >> 16) as u16] {
+                if let Some(index) = transfer.next() {
+                    Self::write_masked(&mut self.vram[index], pixel, self.mask_settings);
+                }
+            }
+
+            if transfer.is_done() {
+                self.vram_write = None;
+            }
+            return;
+        }
+
+        self.command.push(value);
+        let opcode = self.command[0] >> 24;
+        let complete = if is_polyline(opcode) {
+            self.is_polyline_complete()
+        } else {
+            self.command.len() >= command_length(opcode)
+        };
+        if !complete {
+            return;
+        }
+
+        let command = std::mem::take(&mut self.command);
+        self.execute(&command);
+        self.command = command;
+        self.command.clear();
+    }
+
+    // Polylines have any number of vertices, the list ends with a terminator word in place of the
+    // next vertex (after at least two vertices)
+    fn is_polyline_complete(&self) -> bool {
+        let shaded = self.command[0] & (0x10 << 24) != 0;
+        let index = self.command.len() - 1;
+        let vertex_start = if shaded {
+            index >= 4 && index.is_multiple_of(2)
+        } else {
+            index >= 3
+        };
+
+        vertex_start && self.command[index] & 0xF000F000 == 0x50005000
+    }
+
+    fn execute(&mut self, command: &[u32]) {
+        match command[0] >> 24 {
+            // NOP and clear cache
+            0x00 | 0x01 => {}
+            0x02 => self.fill_rectangle(command),
+            0x1F => {
+                self.interrupt_pending |= !self.interrupt;
+                self.interrupt = true;
+            }
+            0x20..=0x3F => self.draw_polygon(command),
+            0x40..=0x5F => self.draw_line(command),
+            0x60..=0x7F => self.draw_rectangle(command),
+            0x80..=0x9F => self.copy_vram(command[1], command[2], command[3]),
+            0xA0..=0xBF => self.vram_write = Some(Transfer::new(command[1], command[2])),
+            0xC0..=0xDF => self.vram_read = Some(Transfer::new(command[1], command[2])),
+            0xE1 => self.draw_mode = command[0] & 0x3FFF,
+            0xE2 => self.texture_window = command[0] & 0xFFFFF,
+            0xE3 => {
+                self.drawing_area_left = (command[0] & 0x3FF) as i32;
+                self.drawing_area_top = ((command[0] >> 10) & 0x1FF) as i32;
+            }
+            0xE4 => {
+                self.drawing_area_right = (command[0] & 0x3FF) as i32;
+                self.drawing_area_bottom = ((command[0] >> 10) & 0x1FF) as i32;
+            }
+            0xE5 => {
+                self.drawing_offset_x = sign_extend_11(command[0]);
+                self.drawing_offset_y = sign_extend_11(command[0] >> 11);
+            }
+            0xE6 => self.mask_settings = command[0] & 3,
+            command => println!("Ignoring GP0 command 0x{:02x}", command),
+        }
+    }
+
+    // GP0 0x80, copies a rectangle within VRAM
+    fn copy_vram(&mut self, source: u32, destination: u32, size: u32) {
+        let mut source = Transfer::new(source, size);
+        let mut destination = Transfer::new(destination, size);
+
+        while let (Some(from), Some(to)) = (source.next(), destination.next()) {
+            let pixel = self.vram[from];
+            Self::write_masked(&mut self.vram[to], pixel, self.mask_settings);
+        }
+    }
+
+    // GP0 0x20..0x3F, bit 4 selects Gouraud shading, bit 3 quads and bit 2 textures
+    fn draw_polygon(&mut self, command: &[u32]) {
+        let opcode = command[0] >> 24;
+        let shaded = opcode & 0x10 != 0;
+        let vertex_count = if opcode & 0x08 != 0 { 4 } else { 3 };
+        let textured = opcode & 0x04 != 0;
+
+        // Every vertex is a position, preceded by its color when shaded (the first color is part
+        // of the command word) and followed by the texture coordinates when textured
+        // The CLUT is in the upper half of the first texture coordinate word and the texpage in the
+        // upper half of the second one
+        let mut words = command[1..].iter().copied();
+        let mut color = command[0];
+        let mut attributes = [0; 4];
+        let mut vertices = [Vertex::default(); 4];
+        for (i, vertex) in vertices[..vertex_count].iter_mut().enumerate() {
+            if shaded && i > 0 {
+                color = words.next().unwrap();
+            }
+            let position = words.next().unwrap();
+            let coordinates = if textured { words.next().unwrap() } else { 0 };
+            attributes[i] = coordinates >> 16;
+
+            *vertex = Vertex {
+                x: sign_extend_11(position) + self.drawing_offset_x,
+                y: sign_extend_11(position >> 16) + self.drawing_offset_y,
+                color: Color::from_command(color),
+                u: coordinates as u8,
+                v: (coordinates >> 8) as u8,
+            };
+        }
+
+        let raw = opcode & 0x01 != 0;
+        let texture = if textured {
+            // Only the page, semi-transparency, depth and texture disable bits are replaced
+            self.draw_mode = (self.draw_mode & !0x9FF) | (attributes[1] & 0x9FF);
+            Some(self.texture(attributes[0], raw))
+        } else {
+            None
+        };
+
+        let primitive = Primitive {
+            texture,
+            semi_transparent: opcode & 0x02 != 0,
+            dither: self.is_dithering() && (shaded || (textured && !raw)),
+        };
+
+        // Quads are drawn as two triangles sharing the middle vertices
+        self.draw_triangle(vertices[0], vertices[1], vertices[2], &primitive);
+        if vertex_count == 4 {
+            self.draw_triangle(vertices[1], vertices[2], vertices[3], &primitive);
+        }
+    }
+
+    // Draw mode bit 9
+    fn is_dithering(&self) -> bool {
+        self.draw_mode & (1 << 9) != 0
+    }
+
+    // The texture of the current draw mode with the given CLUT attribute
+    fn texture(&self, clut: u32, raw: bool) -> Texture {
+        Texture {
+            page_x: (self.draw_mode & 0xF) * 64,
+            page_y: ((self.draw_mode >> 4) & 1) * 256,
+            depth: match (self.draw_mode >> 7) & 3 {
+                0 => TextureDepth::Bits4,
+                1 => TextureDepth::Bits8,
+                // 3 is reserved and behaves like 15 bit
+                _ => TextureDepth::Bits15,
+            },
+            clut_x: (clut & 0x3F) * 16,
+            clut_y: (clut >> 6) & 0x1FF,
+            raw,
+        }
+    }
+
+    // Fetches a texel, the texture window replaces the masked coordinate bits with the offset
+    fn sample(&self, texture: &Texture, u: u8, v: u8) -> u16 {
+        let mask_x = (self.texture_window & 0x1F) * 8;
+        let mask_y = ((self.texture_window >> 5) & 0x1F) * 8;
+        let offset_x = ((self.texture_window >> 10) & 0x1F) * 8;
+        let offset_y = ((self.texture_window >> 15) & 0x1F) * 8;
+        let u = (u as u32 & !mask_x) | (offset_x & mask_x);
+        let v = (v as u32 & !mask_y) | (offset_y & mask_y);
+
+        let y = texture.page_y + v;
+        match texture.depth {
+            TextureDepth::Bits4 => {
+                let word = self.vram[vram_index(texture.page_x + u / 4, y)];
+                let index = (word >> ((u & 3) * 4)) & 0xF;
+                self.vram[vram_index(texture.clut_x + index as u32, texture.clut_y)]
+            }
+            TextureDepth::Bits8 => {
+                let word = self.vram[vram_index(texture.page_x + u / 2, y)];
+                let index = (word >> ((u & 1) * 8)) & 0xFF;
+                self.vram[vram_index(texture.clut_x + index as u32, texture.clut_y)]
+            }
+            TextureDepth::Bits15 => self.vram[vram_index(texture.page_x + u, y)],
+        }
+    }
+
+    fn draw_triangle(&mut self, v0: Vertex, mut v1: Vertex, mut v2: Vertex, primitive: &Primitive) {
+        let area = edge(&v0, &v1, v2.x, v2.y);
+        if area == 0 {
+            return;
+        }
+        // Walk the edges clockwise so the inside of the triangle is positive
+        if area < 0 {
+            std::mem::swap(&mut v1, &mut v2);
+        }
+        let area = area.abs() as i64;
+
+        let min_x = v0.x.min(v1.x).min(v2.x);
+        let max_x = v0.x.max(v1.x).max(v2.x);
+        let min_y = v0.y.min(v1.y).min(v2.y);
+        let max_y = v0.y.max(v1.y).max(v2.y);
+
+        // The GPU skips polygons that are too large
+        if max_x - min_x >= VRAM_WIDTH as i32 || max_y - min_y >= VRAM_HEIGHT as i32 {
+            return;
+        }
+
+        let left = min_x.max(self.drawing_area_left);
+        let right = max_x.min(self.drawing_area_right);
+        let top = min_y.max(self.drawing_area_top);
+        let bottom = max_y.min(self.drawing_area_bottom);
+
+        // Pixels on the right and bottom edges are not drawn, so triangles sharing an edge don't
+        // overlap
+        let bias = [
+            fill_bias(&v1, &v2),
+            fill_bias(&v2, &v0),
+            fill_bias(&v0, &v1),
+        ];
+
+        let triangle = Triangle {
+            vertices: [v0, v1, v2],
+            bias,
+            area,
+        };
+
+        for y in top..=bottom {
+            let start = self.fill_span(&triangle, primitive, y, left, right);
+            for x in start..=right {
+                let weights = [
+                    edge(&v1, &v2, x, y),
+                    edge(&v2, &v0, x, y),
+                    edge(&v0, &v1, x, y),
+                ];
+                if weights
+                    .iter()
+                    .zip(bias)
+                    .any(|(&weight, bias)| weight + bias <= 0)
+                {
+                    continue;
+                }
+
+                let interpolate = |c0: u8, c1: u8, c2: u8| {
+                    let sum = weights[0] as i64 * c0 as i64
+                        + weights[1] as i64 * c1 as i64
+                        + weights[2] as i64 * c2 as i64;
+                    (sum / area) as u8
+                };
+                let color = Color {
+                    r: interpolate(v0.color.r, v1.color.r, v2.color.r),
+                    g: interpolate(v0.color.g, v1.color.g, v2.color.g),
+                    b: interpolate(v0.color.b, v1.color.b, v2.color.b),
+                };
+
+                let u = interpolate(v0.u, v1.u, v2.u);
+                let v = interpolate(v0.v, v1.v, v2.v);
+                if let Some((pixel, blend)) = self.shade(x, y, color, u, v, primitive) {
+                    self.put_pixel(x, y, pixel, blend);
+                }
+            }
+        }
+    }
+
+    // Enables the SIMD span fill when the host supports it, the scalar rasterizer gives the same
+    // image
+    pub fn set_simd(&mut self, enabled: bool) {
+        self.simd = enabled && simd_available();
+    }
+
+    // Fills the row of a triangle up to the last full block of 8 pixels when SIMD can draw it and
+    // returns where the scalar loop has to continue. Only untextured, opaque pixels without the
+    // mask check are supported, those don't depend on what is in VRAM.
+    #[cfg(target_arch = "x86_64")]
+    fn fill_span(
+        &mut self,
+        triangle: &Triangle,
+        primitive: &Primitive,
+        y: i32,
+        left: i32,
+        right: i32,
+    ) -> i32 {
+        if !self.simd
+            || primitive.texture.is_some()
+            || primitive.semi_transparent
+            || self.mask_settings & 2 != 0
+        {
+            return left;
+        }
+
+        // Safety: simd is only set when AVX2 was detected
+        unsafe { self.fill_span_avx2(triangle, primitive.dither, y, left, right) }
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn fill_span(
+        &mut self,
+        _triangle: &Triangle,
+        _primitive: &Primitive,
+        _y: i32,
+        left: i32,
+        _right: i32,
+    ) -> i32 {
+        left
+    }
+
+    // The edge functions and colors of 8 pixels at once, the same math as the scalar loop of
+    // draw_triangle
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx2")]
+    fn fill_span_avx2(
+        &mut self,
+        triangle: &Triangle,
+        dither: bool,
+        y: i32,
+        left: i32,
+        right: i32,
+    ) -> i32 {
+        use std::arch::x86_64::*;
+
+        let [v0, v1, v2] = &triangle.vertices;
+        let edges = [(v1, v2), (v2, v0), (v0, v1)];
+        // Moving right by a pixel changes the edge function by the negated height of the edge
+        let lanes = _mm256_setr_epi32(0, 1, 2, 3, 4, 5, 6, 7);
+        let mut steps = [_mm256_setzero_si256(); 3];
+        for (step, (a, b)) in steps.iter_mut().zip(edges) {
+            *step = _mm256_mullo_epi32(lanes, _mm256_set1_epi32(a.y - b.y));
+        }
+
+        let flat = !dither && v1.color == v0.color && v2.color == v0.color;
+        let offsets = if dither {
+            // Blocks start 8 pixels apart, so every block of a row uses the same offsets
+            let row = DITHER_MATRIX[(y & 3) as usize];
+            let offset = |lane: i32| row[((left + lane) & 3) as usize];
+            _mm256_setr_epi32(
+                offset(0),
+                offset(1),
+                offset(2),
+                offset(3),
+                offset(4),
+                offset(5),
+                offset(6),
+                offset(7),
+            )
+        } else {
+            _mm256_setzero_si256()
+        };
+        let area = _mm256_set1_pd(triangle.area as f64);
+        let mask_bit = _mm_set1_epi16(((self.mask_settings & 1) << 15) as i16);
+
+        let mut x = left;
+        while x + 7 <= right {
+            let mut weights = [_mm256_setzero_si256(); 3];
+            let mut inside = _mm256_set1_epi32(-1);
+            for (i, (a, b)) in edges.into_iter().enumerate() {
+                weights[i] = _mm256_add_epi32(_mm256_set1_epi32(edge(a, b, x, y)), steps[i]);
+                let biased = _mm256_add_epi32(weights[i], _mm256_set1_epi32(triangle.bias[i]));
+                inside =
+                    _mm256_and_si256(inside, _mm256_cmpgt_epi32(biased, _mm256_setzero_si256()));
+            }
+
+            if _mm256_testz_si256(inside, inside) == 0 {
+                let pixels = if flat {
+                    _mm_set1_epi16(v0.color.to_bgr15() as i16)
+                } else {
+                    let channel = |values: [u8; 3]| {
+                        let value = interpolate_avx2(&weights, values, area);
+                        let value = _mm256_add_epi32(value, offsets);
+                        let value = _mm256_max_epi32(value, _mm256_setzero_si256());
+                        let value = _mm256_min_epi32(value, _mm256_set1_epi32(0xFF));
+                        _mm256_srli_epi32::<3>(value)
+                    };
+                    let r = channel([v0.color.r, v1.color.r, v2.color.r]);
+                    let g = channel([v0.color.g, v1.color.g, v2.color.g]);
+                    let b = channel([v0.color.b, v1.color.b, v2.color.b]);
+                    narrow_avx2(_mm256_or_si256(
+                        r,
+                        _mm256_or_si256(_mm256_slli_epi32::<5>(g), _mm256_slli_epi32::<10>(b)),
+                    ))
+                };
+                let pixels = _mm_or_si128(pixels, mask_bit);
+
+                // Blocks never cross the right edge of VRAM, the drawing area is inside it
+                let index = vram_index(x as u32, y as u32);
+                let destination = self.vram[index..index + 8].as_mut_ptr() as *mut __m128i;
+                // Safety: the slice above holds the 8 pixels
+                unsafe {
+                    let old = _mm_loadu_si128(destination);
+                    _mm_storeu_si128(
+                        destination,
+                        _mm_blendv_epi8(old, pixels, narrow_avx2(inside)),
+                    );
+                }
+            }
+
+            x += 8;
+        }
+
+        x
+    }
+
+    // The color of a pixel of a primitive and whether it is blended, None when the texel is
+    // transparent. Textured primitives only blend the texels with bit 15 set.
+    fn shade(
+        &self,
+        x: i32,
+        y: i32,
+        color: Color,
+        u: u8,
+        v: u8,
+        primitive: &Primitive,
+    ) -> Option<(u16, bool)> {
+        let dither = if primitive.dither {
+            DITHER_MATRIX[(y & 3) as usize][(x & 3) as usize]
+        } else {
+            0
+        };
+
+        let Some(texture) = &primitive.texture else {
+            return Some((color.dither(dither).to_bgr15(), primitive.semi_transparent));
+        };
+
+        let texel = self.sample(texture, u, v);
+        // Fully black texels are transparent
+        if texel == 0 {
+            return None;
+        }
+
+        let pixel = if texture.raw {
+            texel
+        } else {
+            modulate(texel, color, dither)
+        };
+
+        Some((pixel, primitive.semi_transparent && texel & 0x8000 != 0))
+    }
+
+    // GP0 0x60..0x7F, bits 3..4 select the size (variable, 1x1, 8x8 or 16x16) and bit 2 textures.
+    // The texture is taken from the draw mode, which can flip it.
+    fn draw_rectangle(&mut self, command: &[u32]) {
+        let opcode = command[0] >> 24;
+        let textured = opcode & 0x04 != 0;
+
+        let mut words = command[1..].iter().copied();
+        let position = words.next().unwrap();
+        let coordinates = if textured { words.next().unwrap() } else { 0 };
+        let (width, height) = match (opcode >> 3) & 3 {
+            0 => {
+                let size = words.next().unwrap();
+                ((size & 0x3FF) as i32, ((size >> 16) & 0x1FF) as i32)
+            }
+            1 => (1, 1),
+            2 => (8, 8),
+            _ => (16, 16),
+        };
+
+        let color = Color::from_command(command[0]);
+        // Rectangles are never dithered
+        let primitive = Primitive {
+            texture: textured.then(|| self.texture(coordinates >> 16, opcode & 0x01 != 0)),
+            semi_transparent: opcode & 0x02 != 0,
+            dither: false,
+        };
+        let flip_x = self.draw_mode & (1 << 12) != 0;
+        let flip_y = self.draw_mode & (1 << 13) != 0;
+
+        let left = sign_extend_11(position) + self.drawing_offset_x;
+        let top = sign_extend_11(position >> 16) + self.drawing_offset_y;
+
+        for row in 0..height {
+            let y = top + row;
+            if y < self.drawing_area_top || y > self.drawing_area_bottom {
+                continue;
+            }
+            let v = if flip_y {
+                ((coordinates >> 8) as u8).wrapping_sub(row as u8)
+            } else {
+                ((coordinates >> 8) as u8).wrapping_add(row as u8)
+            };
+
+            for column in 0..width {
+                let x = left + column;
+                if x < self.drawing_area_left || x > self.drawing_area_right {
+                    continue;
+                }
+                let u = if flip_x {
+                    (coordinates as u8).wrapping_sub(column as u8)
+                } else {
+                    (coordinates as u8).wrapping_add(column as u8)
+                };
+
+                if let Some((pixel, blend)) = self.shade(x, y, color, u, v, &primitive) {
+                    self.put_pixel(x, y, pixel, blend);
+                }
+            }
+        }
+    }
+
+    // GP0 0x02, fills a rectangle with a color ignoring the drawing area and offset. The position
+    // and width are in 16 pixel steps.
+    fn fill_rectangle(&mut self, command: &[u32]) {
+        let pixel = Color::from_command(command[0]).to_bgr15();
+        let x = command[1] & 0x3F0;
+        let y = (command[1] >> 16) & 0x1FF;
+        let width = ((command[2] & 0x3FF) + 0xF) & !0xF;
+        let height = (command[2] >> 16) & 0x1FF;
+
+        for row in 0..height {
+            for column in 0..width {
+                self.vram[vram_index(x + column, y + row)] = pixel;
+            }
+        }
+    }
+
+    // GP0 0x40..0x5F, bit 4 selects Gouraud shading and bit 3 polylines. The vertices are a
+    // position, preceded by its color when shaded except for the first one.
+    fn draw_line(&mut self, command: &[u32]) {
+        let shaded = command[0] & (0x10 << 24) != 0;
+        let primitive = Primitive {
+            texture: None,
+            semi_transparent: command[0] & (0x02 << 24) != 0,
+            dither: self.is_dithering() && shaded,
+        };
+
+        let mut words = command[1..].iter().copied();
+        let mut color = command[0];
+        let mut previous: Option<Vertex> = None;
+        while let Some(word) = words.next() {
+            let position = if shaded && previous.is_some() {
+                color = word;
+                match words.next() {
+                    Some(position) => position,
+                    None => break,
+                }
+            } else {
+                word
+            };
+            // The terminator of polylines
+            if previous.is_some() && position & 0xF000F000 == 0x50005000 {
+                break;
+            }
+
+            let vertex = Vertex {
+                x: sign_extend_11(position) + self.drawing_offset_x,
+                y: sign_extend_11(position >> 16) + self.drawing_offset_y,
+                color: Color::from_command(color),
+                u: 0,
+                v: 0,
+            };
+            if let Some(start) = previous {
+                self.draw_segment(start, vertex, &primitive);
+            }
+            previous = Some(vertex);
+        }
+    }
+
+    // Both end points are drawn, the color is interpolated along the major axis
+    fn draw_segment(&mut self, start: Vertex, end: Vertex, primitive: &Primitive) {
+        let dx = end.x - start.x;
+        let dy = end.y - start.y;
+
+        // Like polygons, lines that are too long are skipped
+        if dx.abs() >= VRAM_WIDTH as i32 || dy.abs() >= VRAM_HEIGHT as i32 {
+            return;
+        }
+
+        let steps = dx.abs().max(dy.abs());
+        for step in 0..=steps {
+            let (x, y, color) = if steps == 0 {
+                (start.x, start.y, start.color)
+            } else {
+                let lerp = |from: i32, to: i32| {
+                    from + ((to - from) * step * 2 + steps).div_euclid(steps * 2)
+                };
+                let color = Color {
+                    r: lerp(start.color.r as i32, end.color.r as i32) as u8,
+                    g: lerp(start.color.g as i32, end.color.g as i32) as u8,
+                    b: lerp(start.color.b as i32, end.color.b as i32) as u8,
+                };
+                (lerp(start.x, end.x), lerp(start.y, end.y), color)
+            };
+
+            if x < self.drawing_area_left
+                || x > self.drawing_area_right
+                || y < self.drawing_area_top
+                || y > self.drawing_area_bottom
+            {
+                continue;
+            }
+
+            if let Some((pixel, blend)) = self.shade(x, y, color, 0, 0, primitive) {
+                self.put_pixel(x, y, pixel, blend);
+            }
+        }
+    }
+
+    // Every drawn pixel goes through here, semi-transparent pixels are blended with VRAM using the
+    // equation of the draw mode
+    fn put_pixel(&mut self, x: i32, y: i32, pixel: u16, semi_transparent: bool) {
+        let destination = &mut self.vram[vram_index(x as u32, y as u32)];
+
+        let pixel = if semi_transparent {
+            blend(*destination, pixel, (self.draw_mode >> 5) & 3)
+        } else {
+            pixel
+        };
+
+        Self::write_masked(destination, pixel, self.mask_settings);
+    }
+
+    // Applies the GP0 0xE6 mask settings to a pixel write
+    fn write_masked(destination: &mut u16, pixel: u16, mask_settings: u32) {
+        if mask_settings & 2 != 0 && *destination & 0x8000 != 0 {
+            return;
+        }
+
+        *destination = pixel | ((mask_settings as u16 & 1) << 15);
+    }
+
+    pub fn gp1(&mut self, value: u32) {
+        match value >> 24 {
+            0x00 => self.reset(),
+            0x01 => self.reset_command_buffer(),
+            0x02 => self.interrupt = false,
+            0x03 => self.display_disabled = value & 1 != 0,
+            0x04 => self.dma_direction = value & 3,
+            0x05 => {
+                self.display_start_x = value & 0x3FE;
+                self.display_start_y = (value >> 10) & 0x1FF;
+            }
+            0x06 => {
+                self.display_range_x1 = value & 0xFFF;
+                self.display_range_x2 = (value >> 12) & 0xFFF;
+            }
+            0x07 => {
+                self.display_range_y1 = value & 0x3FF;
+                self.display_range_y2 = (value >> 10) & 0x3FF;
+            }
+            0x08 => {
+                self.display_mode = ((value & 0x3F) << 17)
+                    | (((value >> 6) & 1) << 16)
+                    | (((value >> 7) & 1) << 14);
+            }
+            0x10..=0x1F => self.get_info(value & 0xF),
+            command => println!("Ignoring GP1 command 0x{:02x}", command),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.reset_command_buffer();
+        self.info_response = None;
+        self.draw_mode = 0;
+        self.texture_window = 0;
+        self.mask_settings = 0;
+        self.drawing_area_left = 0;
+        self.drawing_area_top = 0;
+        self.drawing_area_right = 0;
+        self.drawing_area_bottom = 0;
+        self.drawing_offset_x = 0;
+        self.drawing_offset_y = 0;
+        self.display_start_x = 0;
+        self.display_start_y = 0;
+        (self.display_range_x1, self.display_range_x2) = DEFAULT_DISPLAY_RANGE_X;
+        (self.display_range_y1, self.display_range_y2) = DEFAULT_DISPLAY_RANGE_Y;
+        self.display_mode = 0;
+        self.display_disabled = true;
+        self.dma_direction = 0;
+        self.interrupt = false;
+    }
+
+    // GP1 0x01, drops a partially received command and aborts the VRAM copies
+    fn reset_command_buffer(&mut self) {
+        self.command.clear();
+        self.vram_write = None;
+        self.vram_read = None;
+    }
+
+    // GP1 0x10, the result is read through GPUREAD. The indices without a value leave GPUREAD
+    // unchanged.
+    fn get_info(&mut self, index: u32) {
+        let response = match index {
+            0x02 => self.texture_window,
+            0x03 => self.drawing_area_left as u32 | ((self.drawing_area_top as u32) << 10),
+            0x04 => self.drawing_area_right as u32 | ((self.drawing_area_bottom as u32) << 10),
+            0x05 => {
+                (self.drawing_offset_x as u32 & 0x7FF)
+                    | ((self.drawing_offset_y as u32 & 0x7FF) << 11)
+            }
+            0x07 => GPU_VERSION,
+            0x08 => 0,
+            _ => return,
+        };
+
+        self.info_response = Some(response);
+    }
+
+    pub fn is_pal(&self) -> bool {
+        self.display_mode & (1 << 20) != 0
+    }
+
+    // 480 lines, both fields are displayed from consecutive VRAM lines
+    fn is_interlaced_480(&self) -> bool {
+        self.display_mode & (1 << 19) != 0 && self.display_mode & (1 << 22) != 0
+    }
+
+    // Width and height of the frames returned by render_frame
+    pub fn display_size(&self) -> (usize, usize) {
+        let width = if self.display_mode & (1 << 16) != 0 {
+            368
+        } else {
+            [256, 320, 512, 640][((self.display_mode >> 17) & 3) as usize]
+        };
+
+        // The vertical range decides how many lines are shown, at most the whole frame minus the
+        // vertical blank
+        let max_lines = if self.is_pal() { 288 } else { 240 };
+        let lines = self
+            .display_range_y2
+            .saturating_sub(self.display_range_y1)
+            .min(max_lines) as usize;
+
+        if self.is_interlaced_480() {
+            (width, lines * 2)
+        } else {
+            (width, lines)
+        }
+    }
+
+    // The displayed part of VRAM as 0xRRGGBBAA pixels, rows first. In 480 line mode the even and
+    // odd fields are woven together since they are drawn to alternating VRAM lines.
+    pub fn render_frame(&self) -> Vec<u32> {
+        let (width, height) = self.display_size();
+        let mut frame = vec![0x000000FF; width * height];
+        if self.display_disabled {
+            return frame;
+        }
+
+        let true_color = self.display_mode & (1 << 21) != 0;
+        for (row, line) in frame.chunks_exact_mut(width).enumerate() {
+            let y = self.display_start_y + row as u32;
+
+            for (column, pixel) in line.iter_mut().enumerate() {
+                let x = self.display_start_x;
+                *pixel = if true_color {
+                    // Pixels are packed into 3 bytes, spanning the halfwords of the line
+                    let byte = |index: u32| {
+                        let halfword = self.vram[vram_index(x + index / 2, y)];
+                        (halfword >> ((index & 1) * 8)) as u8 as u32
+                    };
+                    let offset = column as u32 * 3;
+                    (byte(offset) << 24) | (byte(offset + 1) << 16) | (byte(offset + 2) << 8) | 0xFF
+                } else {
+                    bgr15_to_rgba(self.vram[vram_index(x + column as u32, y)])
+                };
+            }
+        }
+
+        frame
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    // Whether IRQ1 has to be requested since the last call
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.interrupt_pending)
+    }
+
+    fn clock_numerator(&self) -> u64 {
+        if self.is_pal() {
+            PAL_GPU_CLOCK_NUMERATOR
+        } else {
+            NTSC_GPU_CLOCK_NUMERATOR
+        }
+    }
+
+    fn scanline_cycles(&self) -> u32 {
+        if self.is_pal() {
+            PAL_SCANLINE_CYCLES
+        } else {
+            NTSC_SCANLINE_CYCLES
+        }
+    }
+
+    // GPU cycles per dot of the horizontal resolution, timer 0 can count the dots
+    fn dotclock_divider(&self) -> u32 {
+        if self.display_mode & (1 << 16) != 0 {
+            7
+        } else {
+            [10, 8, 5, 4][((self.display_mode >> 17) & 3) as usize]
+        }
+    }
+
+    // Advances the video timing by the given CPU cycles
+    pub fn step(&mut self, cycles: u32) -> VideoEvents {
+        let gpu_cycles = self.clock_remainder + cycles as u64 * self.clock_numerator();
+        self.clock_remainder = gpu_cycles % GPU_CLOCK_DENOMINATOR;
+        let gpu_cycles = (gpu_cycles / GPU_CLOCK_DENOMINATOR) as u32;
+
+        let dots = self.dot_remainder + gpu_cycles;
+        let divider = self.dotclock_divider();
+        self.dot_remainder = dots % divider;
+
+        let mut events = VideoEvents {
+            dotclocks: dots / divider,
+            hblanks: 0,
+            hblank: None,
+            vblank: None,
+        };
+
+        let scanline_cycles = self.scanline_cycles();
+        let scanlines = if self.is_pal() {
+            PAL_SCANLINES
+        } else {
+            NTSC_SCANLINES
+        };
+
+        let mut remaining = gpu_cycles;
+        while remaining > 0 {
+            let boundary = if self.line_position < HBLANK_START {
+                HBLANK_START
+            } else {
+                scanline_cycles
+            };
+            let advance = remaining.min(boundary.saturating_sub(self.line_position));
+            self.line_position += advance;
+            remaining -= advance;
+
+            if self.line_position == HBLANK_START {
+                events.hblanks += 1;
+                events.hblank = Some(true);
+            }
+
+            if self.line_position >= scanline_cycles {
+                self.line_position = 0;
+                events.hblank = Some(false);
+
+                self.scanline = (self.scanline + 1) % scanlines;
+                if self.scanline == 0 {
+                    self.frame_count += 1;
+                }
+
+                // The vertical blank covers the lines outside of the vertical display range
+                let in_vblank =
+                    self.scanline < self.display_range_y1 || self.scanline >= self.display_range_y2;
+                if in_vblank != self.in_vblank {
+                    self.in_vblank = in_vblank;
+                    events.vblank = Some(in_vblank);
+
+                    if in_vblank {
+                        self.odd_field = !self.odd_field;
+                    }
+                }
+            }
+        }
+
+        events
+    }
+
+    // CPU cycles until the next blank starts or ends
+    pub fn cycles_until_event(&self) -> u32 {
+        let boundary = if self.line_position < HBLANK_START {
+            HBLANK_START
+        } else {
+            self.scanline_cycles()
+        };
+        let gpu_cycles = (boundary.saturating_sub(self.line_position).max(1) as u64)
+            * GPU_CLOCK_DENOMINATOR
+            - self.clock_remainder;
+
+        gpu_cycles.div_ceil(self.clock_numerator()) as u32
+    }
+}
+
+// GP0 writes and GPUREAD reads, the DMA direction set through GP1 0x04 is the data request line
+impl DmaDevice for Gpu {
+    fn can_read(&self) -> bool {
+        self.dma_direction == 3 && self.data_request()
+    }
+
+    fn can_write(&self) -> bool {
+        matches!(self.dma_direction, 1 | 2) && self.data_request()
+    }
+
+    fn read_word(&mut self) -> u32 {
+        self.read()
+    }
+
+    fn write_word(&mut self, value: u32) {
+        self.gp0(value);
+    }
+}
+
+// Number of words of a GP0 command including the command word itself
+fn command_length(command: u32) -> usize {
+    match command {
+        0x20..=0x3F => {
+            let shaded = command & 0x10 != 0;
+            let vertices = if command & 0x08 != 0 { 4 } else { 3 };
+            let textured = command & 0x04 != 0;
+
+            1 + vertices * (1 + textured as usize) + shaded as usize * (vertices - 1)
+        }
+        0x02 => 3,
+        // Polylines are terminated instead
+        0x40..=0x5F => 3 + (command & 0x10 != 0) as usize,
+        0x60..=0x7F => {
+            let textured = command & 0x04 != 0;
+            let variable_size = command & 0x18 == 0;
+
+            2 + textured as usize + variable_size as usize
+        }
+        0x80..=0x9F => 4,
+        0xA0..=0xDF => 3,
+        _ => 1,
+    }
+}
+
+fn is_polyline(command: u32) -> bool {
+    (0x40..=0x5F).contains(&command) && command & 0x08 != 0
+}
+
+fn vram_index(x: u32, y: u32) -> usize {
+    ((y as usize & (VRAM_HEIGHT - 1)) * VRAM_WIDTH) + (x as usize & (VRAM_WIDTH - 1))
+}
+
+// Coordinates are 11 bit signed values
+fn sign_extend_11(value: u32) -> i32 {
+    ((value << 21) as i32) >> 21
+}
+
+// Twice the signed area of the triangle a, b, p, positive when p is right of the edge a to b
+fn edge(a: &Vertex, b: &Vertex, x: i32, y: i32) -> i32 {
+    (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x)
+}
+
+// The SIMD span fill needs AVX2
+fn simd_available() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        is_x86_feature_detected!("avx2")
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        false
+    }
+}
+
+// Interpolates a vertex attribute for 8 pixels like the scalar (sum / area) division. The sums
+// have less than 30 bits and the area less than 22, so the double division truncates to the same
+// integer.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+fn interpolate_avx2(
+    weights: &[std::arch::x86_64::__m256i; 3],
+    values: [u8; 3],
+    area: std::arch::x86_64::__m256d,
+) -> std::arch::x86_64::__m256i {
+    use std::arch::x86_64::*;
+
+    let mut halves = [_mm_setzero_si128(); 2];
+    for (half, result) in halves.iter_mut().enumerate() {
+        let mut sum = _mm256_setzero_pd();
+        for (weight, value) in weights.iter().zip(values) {
+            let weight = if half == 0 {
+                _mm256_castsi256_si128(*weight)
+            } else {
+                _mm256_extracti128_si256::<1>(*weight)
+            };
+            sum = _mm256_add_pd(
+                sum,
+                _mm256_mul_pd(_mm256_cvtepi32_pd(weight), _mm256_set1_pd(value as f64)),
+            );
+        }
+        *result = _mm256_cvttpd_epi32(_mm256_div_pd(sum, area));
+    }
+
+    _mm256_set_m128i(halves[1], halves[0])
+}
+
+// Packs 8 32 bit lanes into 16 bits, with signed saturation so all set masks stay all set
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+fn narrow_avx2(values: std::arch::x86_64::__m256i) -> std::arch::x86_64::__m128i {
+    use std::arch::x86_64::*;
+
+    // The packs work on the 128 bit halves, the permute moves the low 64 bits of both together
+    let packed = _mm256_packs_epi32(values, values);
+    _mm256_castsi256_si128(_mm256_permute4x64_epi64::<0b1000>(packed))
+}
+
+// Top and left edges own the pixels exactly on them, the others don't
+fn fill_bias(a: &Vertex, b: &Vertex) -> i32 {
+    let dx = b.x - a.x;
+    let dy = b.y - a.y;
+
+    if dy < 0 || (dy == 0 && dx > 0) {
+        1
+    } else {
+        0
+    }
+}
+
+// Multiplies the texel with the color, 0x80 leaves the texel unchanged. This happens at 8 bits so
+// the result can be dithered. Bit 15 of the texel is kept.
+fn modulate(texel: u16, color: Color, dither: i32) -> u16 {
+    let channel = |shift: u16, factor: u8| {
+        let value = ((texel >> shift) & 0x1F) as i32 * factor as i32 / 0x10 + dither;
+        ((value.clamp(0, 0xFF) >> 3) as u16) << shift
+    };
+
+    (texel & 0x8000) | channel(0, color.r) | channel(5, color.g) | channel(10, color.b)
+}
+
+// Semi-transparency, the channels of the background B and foreground F are combined with
+// B/2 + F/2, B + F, B - F or B + F/4. Bit 15 of the foreground is kept.
+fn blend(background: u16, foreground: u16, mode: u32) -> u16 {
+    let channel = |shift: u16| {
+        let b = ((background >> shift) & 0x1F) as i32;
+        let f = ((foreground >> shift) & 0x1F) as i32;
+        let value = match mode {
+            0 => (b + f) / 2,
+            1 => b + f,
+            2 => b - f,
+            _ => b + f / 4,
+        };
+
+        (value.clamp(0, 0x1F) as u16) << shift
+    };
+
+    (foreground & 0x8000) | channel(0) | channel(5) | channel(10)
+}
+
+// Expands the 5 bit channels to 8 bits
+fn bgr15_to_rgba(pixel: u16) -> u32 {
+    let channel = |shift: u16| {
+        let value = ((pixel >> shift) & 0x1F) as u32;
+        (value << 3) | (value >> 2)
+    };
+
+    (channel(0) << 24) | (channel(5) << 16) | (channel(10) << 8) | 0xFF
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gp0(gpu: &mut Gpu, words: &[u32]) {
+        for word in words {
+            gpu.gp0(*word);
+        }
+    }
+
+    fn pixel(gpu: &Gpu, x: u32, y: u32) -> u16 {
+        gpu.vram[vram_index(x, y)]
+    }
+
+    // Pixels of a rectangle packed two per word, as the copy commands send them
+    fn pack(pixels: &[u16]) -> Vec<u32> {
+        pixels
+            .chunks(2)
+            .map(|pair| pair[0] as u32 | (*pair.get(1).unwrap_or(&0) as u32) << 16)
+            .collect()
+    }
+
+    #[test]
+    fn vram_rectangle_is_read_back_through_gpuread() {
+        let mut gpu = Gpu::new();
+        let pixels: Vec<u16> = (0..21).map(|i| 0x1000 + i).collect();
+
+        // 7x3 at the bottom right corner, the rows and columns wrap around the edges of VRAM
+        gp0(&mut gpu, &[0xA0000000, (510 << 16) | 1020, (3 << 16) | 7]);
+        gp0(&mut gpu, &pack(&pixels));
+        assert_ne!(gpu.status() & (1 << 26), 0);
+        assert_eq!(pixel(&gpu, 1020, 510), 0x1000);
+        assert_eq!(pixel(&gpu, 2, 510), 0x1006);
+        assert_eq!(pixel(&gpu, 1023, 0), 0x1011);
+
+        gp0(&mut gpu, &[0xC0000000, (510 << 16) | 1020, (3 << 16) | 7]);
+        assert_ne!(gpu.status() & (1 << 27), 0);
+        let words: Vec<u32> = (0..11).map(|_| gpu.read()).collect();
+        assert_eq!(words, pack(&pixels));
+        assert_eq!(gpu.status() & (1 << 27), 0);
+
+        // Nothing left to send, the last word is read again
+        assert_eq!(gpu.read(), words[10]);
+    }
+
+    // The drawing area covers all of VRAM
+    fn gpu_drawing_everywhere() -> Gpu {
+        let mut gpu = Gpu::new();
+        gp0(&mut gpu, &[0xE3000000, 0xE4000000 | (511 << 10) | 1023]);
+        gpu
+    }
+
+    fn vertex(x: i32, y: i32) -> u32 {
+        ((y as u32 & 0x7FF) << 16) | (x as u32 & 0x7FF)
+    }
+
+    #[test]
+    fn flat_triangle_follows_the_fill_rule() {
+        let mut gpu = gpu_drawing_everywhere();
+        gp0(
+            &mut gpu,
+            &[0x200000FF, vertex(0, 0), vertex(8, 0), vertex(0, 8)],
+        );
+
+        // The top and left edges are drawn, the diagonal isn't
+        for y in 0..10 {
+            for x in 0..10 {
+                let expected = if x + y < 8 { 0x001F } else { 0 };
+                assert_eq!(pixel(&gpu, x, y), expected, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn gouraud_triangle_interpolates_the_vertex_colors() {
+        let mut gpu = gpu_drawing_everywhere();
+        gp0(
+            &mut gpu,
+            &[
+                0x300000F8,
+                vertex(0, 0),
+                0x0000F800,
+                vertex(64, 0),
+                0x00F80000,
+                vertex(0, 64),
+            ],
+        );
+
+        assert_eq!(pixel(&gpu, 0, 0), 0x001F);
+        // A quarter of the way to the second vertex
+        assert_eq!(pixel(&gpu, 16, 0) & 0x1F, 0x17);
+        assert_eq!((pixel(&gpu, 16, 0) >> 5) & 0x1F, 0x07);
+        assert_eq!(pixel(&gpu, 63, 0) >> 10, 0);
+        assert_eq!(pixel(&gpu, 0, 63) >> 10, 0x1E);
+    }
+
+    #[test]
+    fn quad_halves_neither_overlap_nor_leave_gaps() {
+        let mut gpu = gpu_drawing_everywhere();
+        // Additive semi-transparency, a pixel drawn twice would be brighter
+        gp0(&mut gpu, &[0xE1000020]);
+        gp0(
+            &mut gpu,
+            &[
+                0x2A080808,
+                vertex(4, 4),
+                vertex(20, 4),
+                vertex(4, 20),
+                vertex(20, 20),
+            ],
+        );
+
+        let drawn: Vec<u16> = (0..24)
+            .flat_map(|y| (0..24).map(move |x| (x, y)))
+            .map(|(x, y)| pixel(&gpu, x, y))
+            .collect();
+        assert!(drawn.iter().all(|pixel| *pixel == 0 || *pixel == 0x0421));
+        assert_eq!(drawn.iter().filter(|pixel| **pixel != 0).count(), 16 * 16);
+        assert_eq!(pixel(&gpu, 4, 4), 0x0421);
+        assert_eq!(pixel(&gpu, 19, 19), 0x0421);
+    }
+
+    // Sum of the pixels weighted by their position in the region
+    fn checksum(gpu: &Gpu, x: u32, y: u32, width: u32, height: u32) -> u64 {
+        let mut sum = 0;
+        for row in y..y + height {
+            for column in x..x + width {
+                let weight = ((row - y) * width + (column - x) + 1) as u64;
+                sum += weight * pixel(gpu, column, row) as u64;
+            }
+        }
+        sum
+    }
+
+    #[test]
+    fn triangles_are_offset_and_clipped_to_the_drawing_area() {
+        let mut gpu = Gpu::new();
+        // Drawing area (12,2)-(15,5), offset (10,-2)
+        gp0(
+            &mut gpu,
+            &[
+                0xE3000000 | (2 << 10) | 12,
+                0xE4000000 | (5 << 10) | 15,
+                0xE5000000 | ((-2i32 as u32 & 0x7FF) << 11) | 10,
+            ],
+        );
+        gp0(
+            &mut gpu,
+            &[0x200000FF, vertex(0, 0), vertex(8, 0), vertex(0, 8)],
+        );
+
+        let mut expected = Gpu::new();
+        for y in 2..6u32 {
+            for x in 12..16u32 {
+                // Vertex (0,0) is drawn at (10,-2)
+                if (x - 10) + (y + 2) < 8 {
+                    expected.vram[vram_index(x, y)] = 0x001F;
+                }
+            }
+        }
+        assert_eq!(
+            checksum(&gpu, 0, 0, 32, 16),
+            checksum(&expected, 0, 0, 32, 16)
+        );
+        assert_ne!(checksum(&gpu, 12, 2, 4, 4), 0);
+    }
+
+    // Sends a rectangle of pixels through GP0 0xA0
+    fn upload(gpu: &mut Gpu, x: u32, y: u32, width: u32, pixels: &[u16]) {
+        let height = pixels.len() as u32 / width;
+        gp0(gpu, &[0xA0000000, (y << 16) | x, (height << 16) | width]);
+        gp0(gpu, &pack(pixels));
+    }
+
+    // A 4x4 quad at the origin mapping texel (u0+x, y) to pixel (x, y), over a white background
+    fn draw_textured_quad(gpu: &mut Gpu, command: u32, u0: u32, clut: u32, texpage: u32) {
+        gp0(gpu, &[0x02FFFFFF, 0, (16 << 16) | 16]);
+        gp0(
+            gpu,
+            &[
+                command,
+                vertex(0, 0),
+                (clut << 16) | u0,
+                vertex(4, 0),
+                (texpage << 16) | (u0 + 4),
+                vertex(0, 4),
+                (4 << 8) | u0,
+                vertex(4, 4),
+                (4 << 8) | (u0 + 4),
+            ],
+        );
+    }
+
+    // Texel (u, v) of the test textures is color 0x0100 + v * 4 + u, except for the transparent
+    // first one
+    fn expected_texel(x: u32, y: u32) -> u16 {
+        match y * 4 + x {
+            0 => 0x7FFF,
+            index => 0x0100 + index as u16,
+        }
+    }
+
+    fn assert_textured_quad(gpu: &Gpu) {
+        for y in 0..4 {
+            for x in 0..4 {
+                assert_eq!(pixel(gpu, x, y), expected_texel(x, y), "({}, {})", x, y);
+            }
+        }
+        assert_eq!(pixel(gpu, 4, 0), 0x7FFF);
+    }
+
+    // The CLUT is at (0,256), the texture page at (64,0)
+    const CLUT: u32 = 256 << 6;
+
+    fn gpu_with_clut() -> Gpu {
+        let mut gpu = gpu_drawing_everywhere();
+        let clut: Vec<u16> = (0..16)
+            .map(|i| if i == 0 { 0 } else { 0x0100 + i })
+            .collect();
+        upload(&mut gpu, 0, 256, 16, &clut);
+        gpu
+    }
+
+    #[test]
+    fn textures_sample_4bit_indices_through_the_clut() {
+        let mut gpu = gpu_with_clut();
+        let texture: Vec<u16> = (0..4)
+            .map(|v| (0..4).map(|u| (v * 4 + u) << (u * 4)).sum())
+            .collect();
+        upload(&mut gpu, 64, 0, 1, &texture);
+
+        draw_textured_quad(&mut gpu, 0x2D000000, 0, CLUT, 1);
+        assert_textured_quad(&gpu);
+        assert_eq!(gpu.draw_mode & 0x1FF, 1);
+    }
+
+    #[test]
+    fn textures_sample_8bit_indices_through_the_clut() {
+        let mut gpu = gpu_with_clut();
+        let texture: Vec<u16> = (0..4)
+            .flat_map(|v| {
+                (0..2).map(move |pair| (v * 4 + pair * 2) | ((v * 4 + pair * 2 + 1) << 8))
+            })
+            .collect();
+        upload(&mut gpu, 64, 0, 2, &texture);
+
+        draw_textured_quad(&mut gpu, 0x2D000000, 0, CLUT, 1 | (1 << 7));
+        assert_textured_quad(&gpu);
+    }
+
+    #[test]
+    fn textures_sample_15bit_texels_directly() {
+        let mut gpu = gpu_drawing_everywhere();
+        let texture: Vec<u16> = (0..16)
+            .map(|i| if i == 0 { 0 } else { 0x0100 + i })
+            .collect();
+        upload(&mut gpu, 64, 0, 4, &texture);
+
+        draw_textured_quad(&mut gpu, 0x2D000000, 0, 0, 1 | (2 << 7));
+        assert_textured_quad(&gpu);
+
+        // The texture window maps u 8..11 back onto 0..3
+        gp0(&mut gpu, &[0xE2000001]);
+        draw_textured_quad(&mut gpu, 0x2D000000, 8, 0, 1 | (2 << 7));
+        assert_textured_quad(&gpu);
+    }
+
+    #[test]
+    fn modulated_textures_are_scaled_by_the_color() {
+        let mut gpu = gpu_drawing_everywhere();
+        // Red 2, green 4 and blue 8
+        upload(&mut gpu, 64, 0, 1, &[(8 << 10) | (4 << 5) | 2]);
+
+        // 0x80 leaves the texel as it is, 0x40 halves it
+        gp0(
+            &mut gpu,
+            &[
+                0x24808080,
+                vertex(0, 0),
+                0,
+                vertex(1, 0),
+                (1 | (2 << 7)) << 16,
+                vertex(0, 1),
+                0,
+            ],
+        );
+        assert_eq!(pixel(&gpu, 0, 0), (8 << 10) | (4 << 5) | 2);
+        gp0(
+            &mut gpu,
+            &[
+                0x24404040,
+                vertex(0, 0),
+                0,
+                vertex(1, 0),
+                (1 | (2 << 7)) << 16,
+                vertex(0, 1),
+                0,
+            ],
+        );
+        assert_eq!(pixel(&gpu, 0, 0), (4 << 10) | (2 << 5) | 1);
+    }
+
+    #[test]
+    fn textured_sprite_samples_through_the_clut() {
+        let mut gpu = gpu_with_clut();
+        // A 16x16 4 bit texture with index (u + v) & 0xF
+        let texture: Vec<u16> = (0..16)
+            .flat_map(|v| {
+                (0..4).map(move |word| {
+                    (0..4)
+                        .map(|nibble| ((word * 4 + nibble + v) & 0xF) << (nibble * 4))
+                        .sum()
+                })
+            })
+            .collect();
+        upload(&mut gpu, 64, 0, 4, &texture);
+        let expected = |u: u32, v: u32| match (u + v) & 0xF {
+            0 => 0,
+            index => 0x0100 + index as u16,
+        };
+
+        gp0(
+            &mut gpu,
+            &[0xE1000001, 0x7D000000, vertex(32, 32), CLUT << 16],
+        );
+        for y in 0..16 {
+            for x in 0..16 {
+                assert_eq!(
+                    pixel(&gpu, 32 + x, 32 + y),
+                    expected(x, y),
+                    "({}, {})",
+                    x,
+                    y
+                );
+            }
+        }
+        assert_eq!(pixel(&gpu, 48, 32), 0);
+        assert_eq!(pixel(&gpu, 32, 48), 0);
+
+        // Flipped horizontally, the coordinates count down from u 15
+        gp0(
+            &mut gpu,
+            &[0xE1001001, 0x7D000000, vertex(64, 32), (CLUT << 16) | 15],
+        );
+        for y in 0..16 {
+            for x in 0..16 {
+                assert_eq!(
+                    pixel(&gpu, 64 + x, 32 + y),
+                    expected(15 - x, y),
+                    "({}, {})",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn shaded_polyline_runs_until_the_terminator() {
+        let mut gpu = gpu_drawing_everywhere();
+        gp0(
+            &mut gpu,
+            &[
+                0x580000F8,
+                vertex(0, 0),
+                0x0000F800,
+                vertex(8, 0),
+                0x00F80000,
+                vertex(8, 8),
+                0x00F8F8F8,
+                vertex(0, 8),
+                0x55555555,
+            ],
+        );
+
+        assert_eq!(pixel(&gpu, 0, 0), 0x001F);
+        assert_eq!(pixel(&gpu, 4, 0), (15 << 5) | 15);
+        assert_eq!(pixel(&gpu, 8, 0), 0x03E0);
+        assert_eq!(pixel(&gpu, 8, 8), 0x7C00);
+        assert_eq!(pixel(&gpu, 0, 8), 0x7FFF);
+        let drawn = (0..16)
+            .flat_map(|y| (0..16).map(move |x| (x, y)))
+            .filter(|(x, y)| pixel(&gpu, *x, *y) != 0)
+            .count();
+        assert_eq!(drawn, 3 * 8 + 1);
+
+        // The next word is a new command
+        gp0(&mut gpu, &[0x680000FF, vertex(20, 20)]);
+        assert_eq!(pixel(&gpu, 20, 20), 0x001F);
+    }
+
+    // Draws a semi-transparent 1x1 rectangle in the given mode over the background pixel
+    fn blended(background: u16, mode: u32, color: u32) -> u16 {
+        let mut gpu = gpu_drawing_everywhere();
+        upload(&mut gpu, 0, 0, 1, &[background]);
+        gp0(
+            &mut gpu,
+            &[0xE1000000 | (mode << 5), 0x6A000000 | color, vertex(0, 0)],
+        );
+        pixel(&gpu, 0, 0)
+    }
+
+    fn bgr15(r: u16, g: u16, b: u16) -> u16 {
+        (b << 10) | (g << 5) | r
+    }
+
+    #[test]
+    fn blend_equations_saturate() {
+        let red = bgr15(31, 0, 0);
+        let white = 0xF8F8F8;
+        let green = 0x00F800;
+
+        // Average
+        assert_eq!(blended(red, 0, white), bgr15(31, 15, 15));
+        // Sum
+        assert_eq!(blended(red, 1, white), bgr15(31, 31, 31));
+        assert_eq!(blended(bgr15(16, 16, 16), 1, 0x808080), bgr15(31, 31, 31));
+        // Difference
+        assert_eq!(blended(red, 2, white), bgr15(0, 0, 0));
+        assert_eq!(blended(red, 2, green), bgr15(31, 0, 0));
+        // Quarter of the foreground added
+        assert_eq!(blended(red, 3, white), bgr15(31, 7, 7));
+
+        // Opaque primitives ignore the equation
+        let mut gpu = gpu_drawing_everywhere();
+        upload(&mut gpu, 0, 0, 1, &[red]);
+        gp0(&mut gpu, &[0xE1000020, 0x6800F800, vertex(0, 0)]);
+        assert_eq!(pixel(&gpu, 0, 0), bgr15(0, 31, 0));
+    }
+
+    #[test]
+    fn masked_pixels_are_skipped() {
+        let mut gpu = gpu_drawing_everywhere();
+        upload(
+            &mut gpu,
+            0,
+            0,
+            2,
+            &[0x8000 | bgr15(1, 2, 3), bgr15(1, 2, 3)],
+        );
+
+        // Check the mask bit and set it on drawn pixels
+        gp0(&mut gpu, &[0xE6000003]);
+        assert_eq!(gpu.status() & (3 << 11), 3 << 11);
+        gp0(
+            &mut gpu,
+            &[0x680000F8, vertex(0, 0), 0x680000F8, vertex(1, 0)],
+        );
+        assert_eq!(pixel(&gpu, 0, 0), 0x8000 | bgr15(1, 2, 3));
+        assert_eq!(pixel(&gpu, 1, 0), 0x8000 | bgr15(31, 0, 0));
+
+        // Now that it is set, the second pixel is protected too
+        gp0(&mut gpu, &[0x6800F800, vertex(1, 0)]);
+        assert_eq!(pixel(&gpu, 1, 0), 0x8000 | bgr15(31, 0, 0));
+
+        // Without the check the mask bit doesn't protect anything
+        gp0(&mut gpu, &[0xE6000000, 0x6800F800, vertex(0, 0)]);
+        assert_eq!(pixel(&gpu, 0, 0), bgr15(0, 31, 0));
+    }
+
+    #[test]
+    fn primitives_straddling_the_drawing_area_stay_inside() {
+        let mut gpu = Gpu::new();
+        // Drawing area (10,10)-(19,19), the edges are inclusive
+        gp0(
+            &mut gpu,
+            &[0xE3000000 | (10 << 10) | 10, 0xE4000000 | (19 << 10) | 19],
+        );
+        gp0(
+            &mut gpu,
+            &[
+                // A quad over the whole area and a rectangle over the bottom right corner
+                0x280000FF,
+                vertex(0, 0),
+                vertex(30, 0),
+                vertex(0, 30),
+                vertex(30, 30),
+                0x6000FF00,
+                vertex(15, 15),
+                (16 << 16) | 16,
+                // A line across the whole area
+                0x40FF0000,
+                vertex(0, 12),
+                vertex(40, 12),
+            ],
+        );
+
+        for y in 0..40 {
+            for x in 0..40 {
+                let inside = (10..20).contains(&x) && (10..20).contains(&y);
+                assert_eq!(pixel(&gpu, x, y) != 0, inside, "({}, {})", x, y);
+            }
+        }
+        assert_eq!(pixel(&gpu, 10, 10), bgr15(31, 0, 0));
+        assert_eq!(pixel(&gpu, 19, 19), bgr15(0, 31, 0));
+        assert_eq!(pixel(&gpu, 19, 12), bgr15(0, 0, 31));
+    }
+
+    // FNV-1a over the bytes of the frame
+    fn hash(frame: &[u32]) -> u64 {
+        frame
+            .iter()
+            .flat_map(|pixel| pixel.to_be_bytes())
+            .fold(0xCBF29CE484222325, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001B3)
+            })
+    }
+
+    #[test]
+    fn displayed_region_is_converted_to_rgba() {
+        let mut gpu = Gpu::new();
+        let pattern = |x: u32, y: u32| ((x * 7 + y * 13) & 0x7FFF) as u16;
+        for y in 0..512 {
+            for x in 0..1024 {
+                gpu.vram[vram_index(x, y)] = pattern(x, y);
+            }
+        }
+
+        // 320x240 NTSC at (64,32), the range shows 240 lines
+        gpu.gp1(0x08000001);
+        gpu.gp1(0x07000000 | (256 << 10) | 16);
+        gpu.gp1(0x05000000 | (32 << 10) | 64);
+        gpu.gp1(0x03000000);
+        assert_eq!(gpu.display_size(), (320, 240));
+
+        let frame = gpu.render_frame();
+        let expected: Vec<u32> = (0..240)
+            .flat_map(|y| (0..320).map(move |x| bgr15_to_rgba(pattern(64 + x, 32 + y))))
+            .collect();
+        assert_eq!(frame.len(), 320 * 240);
+        assert_eq!(hash(&frame), hash(&expected));
+
+        // Full intensity expands to 0xFF, the lowest bits repeat the highest ones
+        assert_eq!(bgr15_to_rgba(0x7FFF), 0xFFFFFFFF);
+        assert_eq!(bgr15_to_rgba(0x0010), 0x84000000 | 0xFF);
+
+        // In 24 bit mode three bytes make a pixel
+        gpu.vram[vram_index(64, 32)] = 0x2211;
+        gpu.vram[vram_index(65, 32)] = 0x4433;
+        gpu.gp1(0x08000011);
+        let frame = gpu.render_frame();
+        assert_eq!(frame[0], 0x112233FF);
+        assert_eq!(frame[1] >> 24, 0x44);
+
+        // A disabled display is black
+        gpu.gp1(0x03000001);
+        assert!(gpu.render_frame().iter().all(|pixel| *pixel == 0x000000FF));
+    }
+
+    // Vertical blanks entered during one second of CPU cycles
+    fn vblanks_per_second(gpu: &mut Gpu) -> u32 {
+        let mut vblanks = 0;
+        for _ in 0..33_868_800 / 100 {
+            if gpu.step(100).vblank == Some(true) {
+                vblanks += 1;
+            }
+        }
+        vblanks
+    }
+
+    #[test]
+    fn vblanks_come_at_the_video_standard_rate() {
+        let mut gpu = Gpu::new();
+        let frames = gpu.frame_count();
+        // 59.8Hz
+        assert!((59..=60).contains(&vblanks_per_second(&mut gpu)));
+        assert!((59..=60).contains(&(gpu.frame_count() - frames)));
+
+        // 49.8Hz
+        let mut gpu = Gpu::new();
+        gpu.gp1(0x08000008);
+        assert!(gpu.is_pal());
+        assert!((49..=50).contains(&vblanks_per_second(&mut gpu)));
+    }
+
+    #[test]
+    fn interrupt_request_command_raises_irq1_once() {
+        let mut gpu = Gpu::new();
+        assert!(!gpu.take_interrupt());
+
+        gpu.gp0(0x1F000000);
+        assert_ne!(gpu.status() & (1 << 24), 0);
+        assert!(gpu.take_interrupt());
+        assert!(!gpu.take_interrupt());
+
+        // Acknowledged through GP1 0x02
+        gpu.gp1(0x02000000);
+        assert_eq!(gpu.status() & (1 << 24), 0);
+    }
+
+    // Golden images in tests/golden: every <name>.gp0 script is rendered on a fresh GPU and the
+    // VRAM crop named by its "crop x y width height" line is compared with <name>.vram, little
+    // endian halfwords row by row. The other lines are hex GP0 words, or GP1 words after "gp1",
+    // and # starts a comment. GOLDEN_BLESS=1 writes the crops instead of comparing them.
+    struct Script {
+        crop: (u32, u32, u32, u32),
+        words: Vec<(bool, u32)>,
+    }
+
+    fn golden_directory() -> std::path::PathBuf {
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+    }
+
+    fn parse_script(text: &str) -> Result<Script, String> {
+        let mut crop = None;
+        let mut words = Vec::new();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap();
+            let mut fields = line.split_whitespace().peekable();
+            match fields.peek() {
+                Some(&"crop") => {
+                    let values: Vec<u32> = fields.skip(1).filter_map(|f| f.parse().ok()).collect();
+                    let [x, y, width, height] = values[..] else {
+                        return Err(format!("Invalid crop '{}'", line));
+                    };
+                    crop = Some((x, y, width, height));
+                }
+                Some(&"gp1") => {
+                    let word = fields.nth(1).and_then(|f| u32::from_str_radix(f, 16).ok());
+                    words.push((true, word.ok_or(format!("Invalid GP1 word '{}'", line))?));
+                }
+                _ => {
+                    for field in fields {
+                        let word = u32::from_str_radix(field, 16)
+                            .map_err(|_| format!("Invalid GP0 word '{}'", field))?;
+                        words.push((false, word));
+                    }
+                }
+            }
+        }
+
+        Ok(Script {
+            crop: crop.ok_or("Missing crop")?,
+            words,
+        })
+    }
+
+    fn render_script(script: &Script, simd: bool) -> Vec<u16> {
+        let mut gpu = Gpu::new();
+        gpu.set_simd(simd);
+        for &(gp1, word) in &script.words {
+            if gp1 {
+                gpu.gp1(word);
+            } else {
+                gpu.gp0(word);
+            }
+        }
+
+        let (x, y, width, height) = script.crop;
+        (y..y + height)
+            .flat_map(|row| (x..x + width).map(move |column| (column, row)))
+            .map(|(column, row)| pixel(&gpu, column, row))
+            .collect()
+    }
+
+    // Expected, actual and the differing pixels in red next to each other, as a PPM in the
+    // temporary directory
+    fn write_diff(name: &str, width: u32, expected: &[u16], actual: &[u16]) -> std::path::PathBuf {
+        let rgb = |pixel: u16| {
+            let [r, g, b, _] = bgr15_to_rgba(pixel).to_be_bytes();
+            [r, g, b]
+        };
+        let height = expected.len() as u32 / width;
+        let mut image = format!("P6\n{} {}\n255\n", width * 3, height).into_bytes();
+        for row in 0..height as usize {
+            let range = row * width as usize..(row + 1) * width as usize;
+            for &pixel in &expected[range.clone()] {
+                image.extend(rgb(pixel));
+            }
+            for &pixel in &actual[range.clone()] {
+                image.extend(rgb(pixel));
+            }
+            for (a, b) in expected[range.clone()].iter().zip(&actual[range]) {
+                image.extend(if a == b {
+                    [0x20, 0x20, 0x20]
+                } else {
+                    [0xFF, 0, 0]
+                });
+            }
+        }
+
+        let path = std::env::temp_dir().join(format!("psx-rust-golden-{}.ppm", name));
+        std::fs::write(&path, image).unwrap();
+        path
+    }
+
+    #[test]
+    fn golden_images_match() {
+        let bless = std::env::var_os("GOLDEN_BLESS").is_some();
+        let mut scripts: Vec<_> = std::fs::read_dir(golden_directory())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "gp0"))
+            .collect();
+        scripts.sort();
+        assert!(!scripts.is_empty());
+
+        let mut failures = Vec::new();
+        for path in scripts {
+            let name = path.file_stem().unwrap().to_str().unwrap().to_string();
+            let script = parse_script(&std::fs::read_to_string(&path).unwrap())
+                .unwrap_or_else(|error| panic!("{}: {}", name, error));
+            // The scalar rasterizer is the reference, SIMD has to match it bit for bit
+            let actual = render_script(&script, false);
+            if render_script(&script, true) != actual {
+                failures.push(format!("{}: SIMD and scalar rendering differ", name));
+            }
+
+            let expected_path = path.with_extension("vram");
+            if bless {
+                let bytes: Vec<u8> = actual
+                    .iter()
+                    .flat_map(|pixel| pixel.to_le_bytes())
+                    .collect();
+                std::fs::write(&expected_path, bytes).unwrap();
+                continue;
+            }
+
+            let expected: Vec<u16> = std::fs::read(&expected_path)
+                .unwrap_or_else(|error| panic!("{}: {}", expected_path.display(), error))
+                .chunks_exact(2)
+                .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+                .collect();
+            if expected != actual {
+                let differing = expected.iter().zip(&actual).filter(|(a, b)| a != b).count();
+                let diff = write_diff(&name, script.crop.2, &expected, &actual);
+                failures.push(format!(
+                    "{}: {} pixels differ, see {}",
+                    name,
+                    differing,
+                    diff.display()
+                ));
+            }
+        }
+
+        assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn simd_and_scalar_triangles_match_everywhere() {
+        // Large and sliver triangles all over VRAM, flat, shaded and dithered, some setting the
+        // mask bit
+        let mut seed = 0x1234_5678u32;
+        let mut random = move |range: u32| {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            (seed >> 8) % range
+        };
+        let mut words = vec![0xE3000000, 0xE4000000 | (511 << 10) | 1023];
+        for i in 0..60 {
+            words.push(0xE1000000 | ((i & 1) << 9));
+            words.push(0xE6000000 | ((i >> 1) & 1));
+            let mut position = || vertex(random(1024) as i32, random(512) as i32);
+            let (a, b, c) = (position(), position(), position());
+            if i % 3 == 0 {
+                words.extend([0x20000000 | random(0x1000000), a, b, c]);
+            } else {
+                words.extend([0x30000000 | random(0x1000000), a, random(0x1000000), b]);
+                words.extend([random(0x1000000), c]);
+            }
+        }
+
+        let render = |simd: bool| {
+            let mut gpu = Gpu::new();
+            gpu.set_simd(simd);
+            gp0(&mut gpu, &words);
+            gpu.vram
+        };
+        assert!(render(false) == render(true));
+    }
+}
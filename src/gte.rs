@@ -0,0 +1,811 @@
+// Geometry Transformation Engine, COP2. Fixed point matrix and vector math for 3D graphics:
+// perspective projection, lighting and depth cueing. Commands are issued with COP2 and the
+// registers are moved with MFC2/MTC2 (data) and CFC2/CTC2 (control).
+
+// FLAG bits 30..23 and 18..13 also set the error bit 31
+const FLAG_ERROR_BITS: u32 = 0x7F87E000;
+const FLAG_IR0_SATURATED: u32 = 1 << 12;
+const FLAG_SY2_SATURATED: u32 = 1 << 13;
+const FLAG_SX2_SATURATED: u32 = 1 << 14;
+const FLAG_MAC0_NEGATIVE: u32 = 1 << 15;
+const FLAG_MAC0_POSITIVE: u32 = 1 << 16;
+const FLAG_DIVIDE_OVERFLOW: u32 = 1 << 17;
+const FLAG_Z_SATURATED: u32 = 1 << 18;
+
+// MAC1..3 have 44 bits of precision
+const MAC_MAX: i64 = (1 << 43) - 1;
+const MAC_MIN: i64 = -(1 << 43);
+
+type Matrix = [[i16; 3]; 3];
+
+// Cycles each command takes before its results can be read, the CPU runs on meanwhile
+fn command_cycles(opcode: u32) -> u32 {
+    match opcode {
+        0x01 => 15, // RTPS
+        0x06 => 8,  // NCLIP
+        0x0C => 6,  // OP
+        0x10 => 8,  // DPCS
+        0x11 => 8,  // INTPL
+        0x12 => 8,  // MVMVA
+        0x13 => 19, // NCDS
+        0x14 => 13, // CDP
+        0x16 => 44, // NCDT
+        0x1B => 17, // NCCS
+        0x1C => 11, // CC
+        0x1E => 14, // NCS
+        0x20 => 30, // NCT
+        0x28 => 5,  // SQR
+        0x29 => 8,  // DCPL
+        0x2A => 17, // DPCT
+        0x2D => 5,  // AVSZ3
+        0x2E => 6,  // AVSZ4
+        0x30 => 23, // RTPT
+        0x3D => 5,  // GPF
+        0x3E => 5,  // GPL
+        0x3F => 39, // NCCT
+        _ => 1,
+    }
+}
+
+// What the lighting commands do with the color of the light
+#[derive(Clone, Copy, PartialEq)]
+enum Lighting {
+    // NCS, the light color as it is
+    Light,
+    // NCCS and CC multiply it by RGBC
+    Color,
+    // NCDS and CDP also depth cue it towards the far color
+    ColorDepth,
+}
+
+#[derive(Default)]
+pub struct Gte {
+    // Control registers
+    rotation: Matrix,
+    translation: [i32; 3],
+    light: Matrix,
+    background_color: [i32; 3],
+    light_color: Matrix,
+    far_color: [i32; 3],
+    screen_offset: [i32; 2],
+    projection_distance: u16,
+    depth_cue: (i16, i32),
+    z_scale: (i16, i16),
+    flag: u32,
+
+    // Data registers
+    vectors: [[i16; 3]; 3],
+    rgbc: [u8; 4],
+    otz: u16,
+    ir: [i16; 4],
+    // The screen XY and Z FIFOs, the last entry is the newest
+    sxy: [[i16; 2]; 3],
+    sz: [u16; 4],
+    rgb: [[u8; 4]; 3],
+    res1: u32,
+    mac: [i32; 4],
+    lzcs: u32,
+}
+
+impl Gte {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read_data(&self, register: usize) -> u32 {
+        match register {
+            0 | 2 | 4 => pack(self.vectors[register / 2][0], self.vectors[register / 2][1]),
+            1 | 3 | 5 => self.vectors[register / 2][2] as i32 as u32,
+            6 => u32::from_le_bytes(self.rgbc),
+            7 => self.otz as u32,
+            8..=11 => self.ir[register - 8] as i32 as u32,
+            12..=14 => pack(self.sxy[register - 12][0], self.sxy[register - 12][1]),
+            // SXYP reads as SXY2
+            15 => pack(self.sxy[2][0], self.sxy[2][1]),
+            16..=19 => self.sz[register - 16] as u32,
+            20..=22 => u32::from_le_bytes(self.rgb[register - 20]),
+            23 => self.res1,
+            24..=27 => self.mac[register - 24] as u32,
+            // IRGB and ORGB read the IRs as 5 bit colors
+            28 | 29 => {
+                let color = |ir: i16| (ir >> 7).clamp(0, 0x1F) as u32;
+                color(self.ir[1]) | color(self.ir[2]) << 5 | color(self.ir[3]) << 10
+            }
+            30 => self.lzcs,
+            // LZCR, the number of leading bits equal to the sign
+            31 => {
+                if self.lzcs as i32 >= 0 {
+                    self.lzcs.leading_zeros()
+                } else {
+                    self.lzcs.leading_ones()
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn write_data(&mut self, register: usize, value: u32) {
+        let (low, high) = (value as i16, (value >> 16) as i16);
+        match register {
+            0 | 2 | 4 => {
+                self.vectors[register / 2][0] = low;
+                self.vectors[register / 2][1] = high;
+            }
+            1 | 3 | 5 => self.vectors[register / 2][2] = low,
+            6 => self.rgbc = value.to_le_bytes(),
+            7 => self.otz = value as u16,
+            8..=11 => self.ir[register - 8] = low,
+            12..=14 => self.sxy[register - 12] = [low, high],
+            // Writing SXYP pushes onto the FIFO
+            15 => {
+                self.sxy.rotate_left(1);
+                self.sxy[2] = [low, high];
+            }
+            16..=19 => self.sz[register - 16] = value as u16,
+            20..=22 => self.rgb[register - 20] = value.to_le_bytes(),
+            23 => self.res1 = value,
+            24..=27 => self.mac[register - 24] = value as i32,
+            // IRGB sets the IRs from 5 bit colors
+            28 => {
+                for i in 0..3 {
+                    self.ir[i + 1] = (((value >> (i * 5)) & 0x1F) * 0x80) as i16;
+                }
+            }
+            30 => self.lzcs = value,
+            // ORGB and LZCR are read only
+            29 | 31 => {}
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn read_control(&self, register: usize) -> u32 {
+        match register {
+            0..=4 => read_matrix(&self.rotation, register),
+            5..=7 => self.translation[register - 5] as u32,
+            8..=12 => read_matrix(&self.light, register - 8),
+            13..=15 => self.background_color[register - 13] as u32,
+            16..=20 => read_matrix(&self.light_color, register - 16),
+            21..=23 => self.far_color[register - 21] as u32,
+            24 | 25 => self.screen_offset[register - 24] as u32,
+            // H is unsigned but reads sign extended
+            26 => self.projection_distance as i16 as i32 as u32,
+            27 => self.depth_cue.0 as i32 as u32,
+            28 => self.depth_cue.1 as u32,
+            29 => self.z_scale.0 as i32 as u32,
+            30 => self.z_scale.1 as i32 as u32,
+            31 => self.flag,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn write_control(&mut self, register: usize, value: u32) {
+        match register {
+            0..=4 => write_matrix(&mut self.rotation, register, value),
+            5..=7 => self.translation[register - 5] = value as i32,
+            8..=12 => write_matrix(&mut self.light, register - 8, value),
+            13..=15 => self.background_color[register - 13] = value as i32,
+            16..=20 => write_matrix(&mut self.light_color, register - 16, value),
+            21..=23 => self.far_color[register - 21] = value as i32,
+            24 | 25 => self.screen_offset[register - 24] = value as i32,
+            26 => self.projection_distance = value as u16,
+            27 => self.depth_cue.0 = value as i16,
+            28 => self.depth_cue.1 = value as i32,
+            29 => self.z_scale.0 = value as i16,
+            30 => self.z_scale.1 = value as i16,
+            31 => {
+                self.flag = value & 0x7FFFF000;
+                self.update_error_flag();
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Runs the command in the low 25 bits of the COP2 instruction and returns the cycles it takes
+    pub fn execute(&mut self, command: u32) -> u32 {
+        let opcode = command & 0x3F;
+        // sf shifts the results right by 12 bits, lm saturates the IRs to positive values
+        let shift = if command & (1 << 19) != 0 { 12 } else { 0 };
+        let lm = command & (1 << 10) != 0;
+
+        self.flag = 0;
+        match opcode {
+            0x01 => self.rtps(0, shift, lm, true),
+            0x06 => self.nclip(),
+            0x0C => self.op(shift, lm),
+            0x10 => self.dpcs(self.rgbc, shift, lm),
+            0x11 => self.intpl(shift, lm),
+            0x12 => self.mvmva(command, shift, lm),
+            0x13 => self.normal_color(0, Lighting::ColorDepth, shift, lm),
+            0x14 => self.color(Lighting::ColorDepth, shift, lm),
+            0x16 => {
+                for vector in 0..3 {
+                    self.normal_color(vector, Lighting::ColorDepth, shift, lm);
+                }
+            }
+            0x1B => self.normal_color(0, Lighting::Color, shift, lm),
+            0x1C => self.color(Lighting::Color, shift, lm),
+            0x1E => self.normal_color(0, Lighting::Light, shift, lm),
+            0x20 => {
+                for vector in 0..3 {
+                    self.normal_color(vector, Lighting::Light, shift, lm);
+                }
+            }
+            0x28 => self.sqr(shift, lm),
+            0x29 => self.dcpl(shift, lm),
+            // Each step depth cues the oldest color of the FIFO
+            0x2A => {
+                for _ in 0..3 {
+                    self.dpcs(self.rgb[0], shift, lm);
+                }
+            }
+            0x2D => self.average_z(self.z_scale.0, &[1, 2, 3]),
+            0x2E => self.average_z(self.z_scale.1, &[0, 1, 2, 3]),
+            0x30 => {
+                for vector in 0..3 {
+                    self.rtps(vector, shift, lm, vector == 2);
+                }
+            }
+            0x3D => self.gpf(shift, lm),
+            0x3E => self.gpl(shift, lm),
+            0x3F => {
+                for vector in 0..3 {
+                    self.normal_color(vector, Lighting::Color, shift, lm);
+                }
+            }
+            _ => println!("Unknown GTE command 0x{:07x}", command & 0x1FFFFFF),
+        }
+        self.update_error_flag();
+
+        command_cycles(opcode)
+    }
+
+    fn update_error_flag(&mut self) {
+        if self.flag & FLAG_ERROR_BITS != 0 {
+            self.flag |= 1 << 31;
+        }
+    }
+
+    // Checks MAC1..3 for 44 bit overflows, the intermediate sums wrap around at 44 bits
+    fn check_mac(&mut self, index: usize, value: i64) -> i64 {
+        if value > MAC_MAX {
+            self.flag |= 1 << (31 - index);
+        } else if value < MAC_MIN {
+            self.flag |= 1 << (28 - index);
+        }
+        (value << 20) >> 20
+    }
+
+    fn set_mac(&mut self, index: usize, value: i64, shift: u32) -> i64 {
+        let value = self.check_mac(index, value) >> shift;
+        self.mac[index] = value as i32;
+        value
+    }
+
+    fn saturate_ir(&mut self, index: usize, value: i64, lm: bool) -> i16 {
+        let min = if lm { 0 } else { -0x8000 };
+        if value < min || value > 0x7FFF {
+            self.flag |= 1 << (25 - index);
+        }
+        value.clamp(min, 0x7FFF) as i16
+    }
+
+    // IR1..3 from MAC1..3
+    fn set_ir_from_mac(&mut self, lm: bool) {
+        for i in 1..=3 {
+            self.ir[i] = self.saturate_ir(i, self.mac[i] as i64, lm);
+        }
+    }
+
+    fn set_mac0(&mut self, value: i64) -> i64 {
+        if value > i32::MAX as i64 {
+            self.flag |= FLAG_MAC0_POSITIVE;
+        } else if value < i32::MIN as i64 {
+            self.flag |= FLAG_MAC0_NEGATIVE;
+        }
+        self.mac[0] = value as i32;
+        value
+    }
+
+    fn set_ir0(&mut self, value: i64) {
+        if !(0..=0x1000).contains(&value) {
+            self.flag |= FLAG_IR0_SATURATED;
+        }
+        self.ir[0] = value.clamp(0, 0x1000) as i16;
+    }
+
+    fn push_sz(&mut self, value: i64) {
+        if !(0..=0xFFFF).contains(&value) {
+            self.flag |= FLAG_Z_SATURATED;
+        }
+        self.sz.rotate_left(1);
+        self.sz[3] = value.clamp(0, 0xFFFF) as u16;
+    }
+
+    fn push_sxy(&mut self, x: i64, y: i64) {
+        let mut saturate = |value: i64, flag: u32| {
+            if !(-0x400..=0x3FF).contains(&value) {
+                self.flag |= flag;
+            }
+            value.clamp(-0x400, 0x3FF) as i16
+        };
+        let xy = [
+            saturate(x, FLAG_SX2_SATURATED),
+            saturate(y, FLAG_SY2_SATURATED),
+        ];
+        self.sxy.rotate_left(1);
+        self.sxy[2] = xy;
+    }
+
+    // The color of MAC1..3 onto the color FIFO, with the code of RGBC
+    fn push_color(&mut self) {
+        let mut color = [0, 0, 0, self.rgbc[3]];
+        for (i, color) in color.iter_mut().take(3).enumerate() {
+            let value = self.mac[i + 1] >> 4;
+            if !(0..=0xFF).contains(&value) {
+                self.flag |= 1 << (21 - i);
+            }
+            *color = value.clamp(0, 0xFF) as u8;
+        }
+        self.rgb.rotate_left(1);
+        self.rgb[2] = color;
+    }
+
+    // MAC1..3 and IR1..3 of translation * 0x1000 + matrix * vector
+    fn transform(
+        &mut self,
+        matrix: &Matrix,
+        vector: [i16; 3],
+        translation: [i32; 3],
+        shift: u32,
+        lm: bool,
+    ) {
+        for row in 0..3 {
+            let mut value = (translation[row] as i64) << 12;
+            for column in 0..3 {
+                value = self.check_mac(
+                    row + 1,
+                    value + matrix[row][column] as i64 * vector[column] as i64,
+                );
+            }
+            self.set_mac(row + 1, value, shift);
+        }
+        self.set_ir_from_mac(lm);
+    }
+
+    // Perspective transformation of a vector, the depth cue is set for the last one
+    fn rtps(&mut self, vector: usize, shift: u32, lm: bool, last: bool) {
+        let mut z = 0;
+        for row in 0..3 {
+            let mut value = (self.translation[row] as i64) << 12;
+            for column in 0..3 {
+                value = self.check_mac(
+                    row + 1,
+                    value + self.rotation[row][column] as i64 * self.vectors[vector][column] as i64,
+                );
+            }
+            self.mac[row + 1] = (value >> shift) as i32;
+            z = value;
+        }
+        self.ir[1] = self.saturate_ir(1, self.mac[1] as i64, lm);
+        self.ir[2] = self.saturate_ir(2, self.mac[2] as i64, lm);
+        // IR3 saturates by MAC3, but without sf its flag is set by MAC3 >> 12
+        let min = if lm { 0 } else { -0x8000 };
+        if !(-0x8000..=0x7FFF).contains(&(z >> 12)) {
+            self.flag |= 1 << 22;
+        }
+        self.ir[3] = (self.mac[3] as i64).clamp(min, 0x7FFF) as i16;
+
+        self.push_sz(z >> 12);
+        let n = self.divide() as i64;
+
+        let x = self.set_mac0(n * self.ir[1] as i64 + self.screen_offset[0] as i64);
+        let y = self.set_mac0(n * self.ir[2] as i64 + self.screen_offset[1] as i64);
+        self.push_sxy(x >> 16, y >> 16);
+
+        if last {
+            let depth = self.set_mac0(n * self.depth_cue.0 as i64 + self.depth_cue.1 as i64);
+            self.set_ir0(depth >> 12);
+        }
+    }
+
+    // H * 0x20000 / SZ3 rounded, by the Newton-Raphson reciprocal of the hardware
+    fn divide(&mut self) -> u32 {
+        let (h, z) = (self.projection_distance as u32, self.sz[3] as u32);
+        if h >= z * 2 {
+            self.flag |= FLAG_DIVIDE_OVERFLOW;
+            return 0x1FFFF;
+        }
+
+        let shift = (z as u16).leading_zeros();
+        let n = (h << shift) as u64;
+        let d = (z << shift) as u64;
+        let u = unr_table((d as usize - 0x7FC0) >> 7) as u64 + 0x101;
+        let d = (0x2000080 - d * u) >> 8;
+        let d = (0x0000080 + d * u) >> 8;
+        (((n * d) + 0x8000) >> 16).min(0x1FFFF) as u32
+    }
+
+    // The winding of the screen triangle, negative for clockwise
+    fn nclip(&mut self) {
+        let [[x0, y0], [x1, y1], [x2, y2]] = self.sxy.map(|xy| xy.map(|value| value as i64));
+        self.set_mac0(x0 * y1 + x1 * y2 + x2 * y0 - x0 * y2 - x1 * y0 - x2 * y1);
+    }
+
+    fn average_z(&mut self, scale: i16, entries: &[usize]) {
+        let sum: i64 = entries.iter().map(|&i| self.sz[i] as i64).sum();
+        let value = self.set_mac0(scale as i64 * sum);
+        let otz = value >> 12;
+        if !(0..=0xFFFF).contains(&otz) {
+            self.flag |= FLAG_Z_SATURATED;
+        }
+        self.otz = otz.clamp(0, 0xFFFF) as u16;
+    }
+
+    // Cross product of IR and the diagonal of the rotation matrix
+    fn op(&mut self, shift: u32, lm: bool) {
+        let d = [
+            self.rotation[0][0],
+            self.rotation[1][1],
+            self.rotation[2][2],
+        ]
+        .map(|d| d as i64);
+        let ir = [self.ir[1], self.ir[2], self.ir[3]].map(|ir| ir as i64);
+        self.set_mac(1, ir[2] * d[1] - ir[1] * d[2], shift);
+        self.set_mac(2, ir[0] * d[2] - ir[2] * d[0], shift);
+        self.set_mac(3, ir[1] * d[0] - ir[0] * d[1], shift);
+        self.set_ir_from_mac(lm);
+    }
+
+    fn sqr(&mut self, shift: u32, lm: bool) {
+        for i in 1..=3 {
+            let ir = self.ir[i] as i64;
+            self.set_mac(i, ir * ir, shift);
+        }
+        self.set_ir_from_mac(lm);
+    }
+
+    // Multiplies a matrix and a vector and adds a translation, all selected by the command
+    fn mvmva(&mut self, command: u32, shift: u32, lm: bool) {
+        let matrix = match (command >> 17) & 3 {
+            0 => self.rotation,
+            1 => self.light,
+            2 => self.light_color,
+            // The reserved matrix reads garbage built from RGBC, IR0 and the rotation matrix
+            _ => {
+                let red = (self.rgbc[0] as i16) << 4;
+                [
+                    [-red, red, self.ir[0]],
+                    [self.rotation[0][2]; 3],
+                    [self.rotation[1][1]; 3],
+                ]
+            }
+        };
+        let vector = match (command >> 15) & 3 {
+            3 => [self.ir[1], self.ir[2], self.ir[3]],
+            index => self.vectors[index as usize],
+        };
+
+        match (command >> 13) & 3 {
+            0 => self.transform(&matrix, vector, self.translation, shift, lm),
+            1 => self.transform(&matrix, vector, self.background_color, shift, lm),
+            // With the far color the first column only sets flags, the result is the other two
+            2 => {
+                for (row, elements) in matrix.iter().enumerate() {
+                    let first = self.check_mac(
+                        row + 1,
+                        ((self.far_color[row] as i64) << 12)
+                            + elements[0] as i64 * vector[0] as i64,
+                    );
+                    self.saturate_ir(row + 1, first >> shift, false);
+
+                    let value = self.check_mac(row + 1, elements[1] as i64 * vector[1] as i64);
+                    let value =
+                        self.check_mac(row + 1, value + elements[2] as i64 * vector[2] as i64);
+                    self.set_mac(row + 1, value, shift);
+                }
+                self.set_ir_from_mac(lm);
+            }
+            _ => self.transform(&matrix, vector, [0; 3], shift, lm),
+        }
+    }
+
+    // Light of the normal vector, then the color stages
+    fn normal_color(&mut self, vector: usize, lighting: Lighting, shift: u32, lm: bool) {
+        let light = self.light;
+        self.transform(&light, self.vectors[vector], [0; 3], shift, lm);
+        self.color(lighting, shift, lm);
+    }
+
+    // Background color plus the light color matrix times IR, then what the lighting needs
+    fn color(&mut self, lighting: Lighting, shift: u32, lm: bool) {
+        let light_color = self.light_color;
+        let ir = [self.ir[1], self.ir[2], self.ir[3]];
+        self.transform(&light_color, ir, self.background_color, shift, lm);
+
+        match lighting {
+            Lighting::Light => {}
+            Lighting::Color => {
+                for i in 1..=3 {
+                    let value = ((self.rgbc[i - 1] as i64) * self.ir[i] as i64) << 4;
+                    self.set_mac(i, value, shift);
+                }
+                self.set_ir_from_mac(lm);
+            }
+            Lighting::ColorDepth => {
+                let values = self.color_times_ir();
+                self.depth_cue(values, shift, lm);
+            }
+        }
+        self.push_color();
+    }
+
+    // RGBC times IR1..3, before the shift
+    fn color_times_ir(&self) -> [i64; 3] {
+        std::array::from_fn(|i| ((self.rgbc[i] as i64) * self.ir[i + 1] as i64) << 4)
+    }
+
+    // Moves the values towards the far color by IR0 into MAC1..3 and IR1..3
+    fn depth_cue(&mut self, values: [i64; 3], shift: u32, lm: bool) {
+        for i in 1..=3 {
+            let distance =
+                self.check_mac(i, ((self.far_color[i - 1] as i64) << 12) - values[i - 1]);
+            let ir = self.saturate_ir(i, distance >> shift, false);
+            let value = ir as i64 * self.ir[0] as i64 + values[i - 1];
+            self.set_mac(i, value, shift);
+        }
+        self.set_ir_from_mac(lm);
+    }
+
+    fn dpcs(&mut self, color: [u8; 4], shift: u32, lm: bool) {
+        let values = std::array::from_fn(|i| (color[i] as i64) << 16);
+        self.depth_cue(values, shift, lm);
+        self.push_color();
+    }
+
+    fn intpl(&mut self, shift: u32, lm: bool) {
+        let values = std::array::from_fn(|i| (self.ir[i + 1] as i64) << 12);
+        self.depth_cue(values, shift, lm);
+        self.push_color();
+    }
+
+    fn dcpl(&mut self, shift: u32, lm: bool) {
+        let values = self.color_times_ir();
+        self.depth_cue(values, shift, lm);
+        self.push_color();
+    }
+
+    // IR times IR0
+    fn gpf(&mut self, shift: u32, lm: bool) {
+        for i in 1..=3 {
+            self.set_mac(i, self.ir[i] as i64 * self.ir[0] as i64, shift);
+        }
+        self.set_ir_from_mac(lm);
+        self.push_color();
+    }
+
+    // IR times IR0 added to MAC1..3
+    fn gpl(&mut self, shift: u32, lm: bool) {
+        for i in 1..=3 {
+            let value = ((self.mac[i] as i64) << shift) + self.ir[i] as i64 * self.ir[0] as i64;
+            self.set_mac(i, value, shift);
+        }
+        self.set_ir_from_mac(lm);
+        self.push_color();
+    }
+}
+
+fn pack(low: i16, high: i16) -> u32 {
+    (low as u16 as u32) | ((high as u16 as u32) << 16)
+}
+
+// Matrices take two elements per register, row by row, the last one has the 9th alone
+fn read_matrix(matrix: &Matrix, register: usize) -> u32 {
+    let element = |i: usize| matrix[i / 3][i % 3];
+    match register {
+        4 => element(8) as i32 as u32,
+        _ => pack(element(register * 2), element(register * 2 + 1)),
+    }
+}
+
+fn write_matrix(matrix: &mut Matrix, register: usize, value: u32) {
+    let mut set = |i: usize, value: i16| matrix[i / 3][i % 3] = value;
+    set(register * 2, value as i16);
+    if register < 4 {
+        set(register * 2 + 1, (value >> 16) as i16);
+    }
+}
+
+// Initial guesses of the reciprocal for the division
+fn unr_table(index: usize) -> u32 {
+    (0x40000 / (index as u32 + 0x100))
+        .div_ceil(2)
+        .saturating_sub(0x101)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RTPS: u32 = 0x0180001;
+    const RTPT: u32 = 0x0280030;
+    const NCLIP: u32 = 0x1400006;
+    const AVSZ3: u32 = 0x158002D;
+    const SQR: u32 = 0x0A00428;
+    const DPCS: u32 = 0x0780010;
+    const MVMVA_RT_V0_TR: u32 = 0x0480012;
+
+    // An identity rotation looking at points 0x1000 in front, with H = 0x1000 a unit of X and Y
+    // is a pixel
+    fn gte_with_camera() -> Gte {
+        let mut gte = Gte::new();
+        gte.write_control(0, 0x1000);
+        gte.write_control(2, 0x1000);
+        gte.write_control(4, 0x1000);
+        gte.write_control(7, 0x1000);
+        gte.write_control(24, 160 << 16);
+        gte.write_control(25, 120 << 16);
+        gte.write_control(26, 0x1000);
+        gte.write_control(27, -0x100i32 as u32);
+        gte.write_control(28, 0x1400000);
+        gte
+    }
+
+    fn set_vector(gte: &mut Gte, index: usize, [x, y, z]: [i16; 3]) {
+        gte.write_data(index * 2, pack(x, y));
+        gte.write_data(index * 2 + 1, z as u32);
+    }
+
+    #[test]
+    fn rtps_projects_onto_the_screen() {
+        let mut gte = gte_with_camera();
+        set_vector(&mut gte, 0, [0x20, -0x10, 0x1000]);
+
+        assert_eq!(gte.execute(RTPS), 15);
+
+        // Twice as far away, half the size
+        assert_eq!(gte.read_data(19), 0x2000);
+        assert_eq!(gte.read_data(14), pack(160 + 0x10, 120 - 0x08));
+        assert_eq!(gte.read_data(9), 0x20);
+        assert_eq!(gte.read_data(10), -0x10i32 as u32);
+        // MAC0 = DQA * 0x8000 + DQB, IR0 = MAC0 >> 12
+        assert_eq!(gte.read_data(24), 0x1400000 - 0x800000);
+        assert_eq!(gte.read_data(8), 0xC00);
+        assert_eq!(gte.read_control(31), 0);
+    }
+
+    #[test]
+    fn rtpt_fills_the_fifos_and_nclip_finds_the_winding() {
+        let mut gte = gte_with_camera();
+        set_vector(&mut gte, 0, [0, 0, 0]);
+        set_vector(&mut gte, 1, [0x40, 0, 0]);
+        set_vector(&mut gte, 2, [0, 0x40, 0]);
+
+        assert_eq!(gte.execute(RTPT), 23);
+        assert_eq!(gte.read_data(12), pack(160, 120));
+        assert_eq!(gte.read_data(13), pack(160 + 0x40, 120));
+        assert_eq!(gte.read_data(14), pack(160, 120 + 0x40));
+        assert_eq!(gte.read_data(15), gte.read_data(14));
+
+        gte.execute(NCLIP);
+        assert_eq!(gte.read_data(24), 0x40 * 0x40);
+
+        // The average of SZ1..3 by ZSF3
+        gte.write_control(29, 0x1000 / 3);
+        gte.execute(AVSZ3);
+        assert_eq!(gte.read_data(7), 0xFFF);
+    }
+
+    #[test]
+    fn division_overflows_and_saturations_set_flags() {
+        let mut gte = gte_with_camera();
+        // Behind the camera
+        gte.write_control(7, 0);
+        set_vector(&mut gte, 0, [0x7FFF, 0, 0x400]);
+        gte.execute(RTPS);
+
+        let flag = gte.read_control(31);
+        assert_ne!(flag & FLAG_DIVIDE_OVERFLOW, 0);
+        assert_ne!(flag & FLAG_SX2_SATURATED, 0);
+        assert_eq!(flag & FLAG_SY2_SATURATED, 0);
+        assert_ne!(flag & 1 << 31, 0);
+        assert_eq!(gte.read_data(14) as i16, 0x3FF);
+
+        // Flags are reset by the next command, only the writable bits can be written
+        gte.write_data(28, 0);
+        gte.execute(SQR);
+        assert_eq!(gte.read_control(31), 0);
+        gte.write_control(31, 0xFFFFFFFF);
+        assert_eq!(gte.read_control(31), 0xFFFFF000);
+    }
+
+    #[test]
+    fn division_is_close_to_the_exact_quotient() {
+        let mut gte = Gte::new();
+        for h in (1..0x10000).step_by(0x1F3) {
+            for z in (h / 2 + 1..0x10000).step_by(0x2B7) {
+                gte.flag = 0;
+                gte.projection_distance = h as u16;
+                gte.sz[3] = z as u16;
+                let exact = (h as f64 * 65536.0 / z as f64).min(0x1FFFF as f64);
+                let n = gte.divide() as f64;
+                assert!(
+                    (n - exact).abs() <= 1.0 + exact / 32768.0,
+                    "{} / {}: {} {}",
+                    h,
+                    z,
+                    n,
+                    exact
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mvmva_multiplies_the_selected_matrix() {
+        let mut gte = gte_with_camera();
+        gte.write_control(0, pack(0x1000, 0x800));
+        set_vector(&mut gte, 0, [0x10, 0x20, 0x30]);
+
+        gte.execute(MVMVA_RT_V0_TR);
+        assert_eq!(gte.read_data(25), 0x10 + 0x10);
+        assert_eq!(gte.read_data(26), 0x20);
+        assert_eq!(gte.read_data(27), 0x1000 + 0x30);
+    }
+
+    #[test]
+    fn dpcs_moves_the_color_towards_the_far_color() {
+        let mut gte = Gte::new();
+        gte.write_data(6, u32::from_le_bytes([0x80, 0x40, 0x20, 0x2C]));
+        gte.write_control(21, 0xFF0);
+        gte.write_control(22, 0);
+        gte.write_control(23, 0x800);
+
+        gte.write_data(8, 0);
+        gte.execute(DPCS);
+        assert_eq!(
+            gte.read_data(22),
+            u32::from_le_bytes([0x80, 0x40, 0x20, 0x2C])
+        );
+
+        gte.write_data(8, 0x1000);
+        gte.execute(DPCS);
+        assert_eq!(
+            gte.read_data(22),
+            u32::from_le_bytes([0xFF, 0x00, 0x80, 0x2C])
+        );
+        assert_eq!(
+            gte.read_data(21),
+            u32::from_le_bytes([0x80, 0x40, 0x20, 0x2C])
+        );
+    }
+
+    #[test]
+    fn register_views_convert_their_values() {
+        let mut gte = Gte::new();
+
+        // IRGB sets the IRs, ORGB reads them back saturated
+        gte.write_data(28, 0x7FFF);
+        assert_eq!(gte.read_data(9), 0xF80);
+        gte.write_data(11, -0x100i32 as u32);
+        assert_eq!(gte.read_data(11), 0xFFFFFF00);
+        assert_eq!(gte.read_data(29), 0x03FF);
+
+        gte.write_data(30, 0x00FF0000);
+        assert_eq!(gte.read_data(31), 8);
+        gte.write_data(30, 0xFFF00000);
+        assert_eq!(gte.read_data(31), 12);
+        gte.write_data(30, 0);
+        assert_eq!(gte.read_data(31), 32);
+
+        // SXYP pushes onto the FIFO
+        gte.write_data(15, 1);
+        gte.write_data(15, 2);
+        assert_eq!([gte.read_data(13), gte.read_data(14)], [1, 2]);
+
+        // H reads sign extended
+        gte.write_control(26, 0x8000);
+        assert_eq!(gte.read_control(26), 0xFFFF8000);
+        gte.write_control(4, 0x12345678);
+        assert_eq!(gte.read_control(4), 0x5678);
+    }
+}
@@ -0,0 +1,361 @@
+use std::fmt;
+
+// Names and bitfield descriptions of the memory mapped hardware registers, used to make
+// addresses shown to humans (warnings, traces, debugger views) readable.
+
+pub struct Field {
+    pub mask: u32,
+    pub value: u32, // The field is shown when (register & mask) == value
+    pub name: &'static str,
+}
+
+pub struct RegisterInfo {
+    pub offset: u32,
+    pub width: u32,
+    pub name: &'static str,
+    pub fields: &'static [Field],
+}
+
+// A group of registers, repeated `count` times every `stride` bytes (e.g. the seven DMA channels)
+struct Block {
+    start: u32,
+    stride: u32,
+    count: u32,
+    prefix: &'static str,
+    registers: &'static [RegisterInfo],
+}
+
+#[derive(Clone, Copy)]
+pub struct Register {
+    pub address: u32,
+    pub info: &'static RegisterInfo,
+    prefix: &'static str,
+    index: Option<u32>,
+}
+
+impl Register {
+    // Expands the known bitfields of the register for the given value, e.g. "start|linked-list|from-ram"
+    pub fn decode(&self, value: u32) -> String {
+        self.info
+            .fields
+            .iter()
+            .filter(|field| value & field.mask == field.value)
+            .map(|field| field.name)
+            .collect::<Vec<_>>()
+            .join("|")
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.index {
+            Some(index) => write!(f, "{}{}_{}", self.prefix, index, self.info.name),
+            None => write!(f, "{}{}", self.prefix, self.info.name),
+        }
+    }
+}
+
+const fn field(mask: u32, value: u32, name: &'static str) -> Field {
+    Field { mask, value, name }
+}
+
+const fn flag(bit: u32, name: &'static str) -> Field {
+    Field {
+        mask: 1 << bit,
+        value: 1 << bit,
+        name,
+    }
+}
+
+const fn register(
+    offset: u32,
+    width: u32,
+    name: &'static str,
+    fields: &'static [Field],
+) -> RegisterInfo {
+    RegisterInfo {
+        offset,
+        width,
+        name,
+        fields,
+    }
+}
+
+const INTERRUPT_FIELDS: &[Field] = &[
+    flag(0, "vblank"),
+    flag(1, "gpu"),
+    flag(2, "cdrom"),
+    flag(3, "dma"),
+    flag(4, "timer0"),
+    flag(5, "timer1"),
+    flag(6, "timer2"),
+    flag(7, "controller"),
+    flag(8, "sio"),
+    flag(9, "spu"),
+    flag(10, "lightpen"),
+];
+
+const CHCR_FIELDS: &[Field] = &[
+    field(1, 0, "to-ram"),
+    field(1, 1, "from-ram"),
+    flag(1, "decrement"),
+    flag(8, "chopping"),
+    field(0x600, 0x000, "manual"),
+    field(0x600, 0x200, "block"),
+    field(0x600, 0x400, "linked-list"),
+    flag(24, "start"),
+    flag(28, "trigger"),
+];
+
+const DICR_FIELDS: &[Field] = &[
+    flag(15, "force-irq"),
+    flag(23, "master-enable"),
+    flag(31, "master-flag"),
+];
+
+const TIMER_MODE_FIELDS: &[Field] = &[
+    flag(0, "sync-enable"),
+    flag(3, "reset-on-target"),
+    flag(4, "irq-on-target"),
+    flag(5, "irq-on-overflow"),
+    flag(6, "repeat"),
+    flag(7, "toggle"),
+    field(1 << 10, 0, "irq-request"), // Active low
+    flag(11, "reached-target"),
+    flag(12, "reached-overflow"),
+];
+
+const JOY_CTRL_FIELDS: &[Field] = &[
+    flag(0, "tx-enable"),
+    flag(1, "joyn-output"),
+    flag(2, "rx-enable"),
+    flag(4, "ack"),
+    flag(6, "reset"),
+    flag(10, "tx-irq"),
+    flag(11, "rx-irq"),
+    flag(12, "ack-irq"),
+    flag(13, "port2"),
+];
+
+const SPUCNT_FIELDS: &[Field] = &[
+    flag(0, "cd-audio"),
+    flag(1, "external-audio"),
+    flag(2, "cd-reverb"),
+    flag(3, "external-reverb"),
+    flag(6, "irq-enable"),
+    flag(7, "reverb"),
+    flag(14, "unmute"),
+    flag(15, "enable"),
+];
+
+const CACHE_CONTROL_FIELDS: &[Field] = &[
+    flag(2, "tag-test"),
+    flag(3, "scratchpad-enable"),
+    flag(7, "scratchpad-enable2"),
+    flag(11, "icache-enable"),
+];
+
+const REGISTERS: &[RegisterInfo] = &[
+    register(0x1F801000, 4, "EXP1_BASE", &[]),
+    register(0x1F801004, 4, "EXP2_BASE", &[]),
+    register(0x1F801008, 4, "EXP1_DELAY", &[]),
+    register(0x1F80100C, 4, "EXP3_DELAY", &[]),
+    register(0x1F801010, 4, "BIOS_DELAY", &[]),
+    register(0x1F801014, 4, "SPU_DELAY", &[]),
+    register(0x1F801018, 4, "CDROM_DELAY", &[]),
+    register(0x1F80101C, 4, "EXP2_DELAY", &[]),
+    register(0x1F801020, 4, "COM_DELAY", &[]),
+    register(0x1F801040, 4, "JOY_DATA", &[]),
+    register(0x1F801044, 4, "JOY_STAT", &[]),
+    register(0x1F801048, 2, "JOY_MODE", &[]),
+    register(0x1F80104A, 2, "JOY_CTRL", JOY_CTRL_FIELDS),
+    register(0x1F80104E, 2, "JOY_BAUD", &[]),
+    register(0x1F801050, 4, "SIO_DATA", &[]),
+    register(0x1F801054, 4, "SIO_STAT", &[]),
+    register(0x1F801058, 2, "SIO_MODE", &[]),
+    register(0x1F80105A, 2, "SIO_CTRL", &[]),
+    register(0x1F80105C, 2, "SIO_MISC", &[]),
+    register(0x1F80105E, 2, "SIO_BAUD", &[]),
+    register(0x1F801060, 4, "RAM_SIZE", &[]),
+    register(0x1F801070, 4, "I_STAT", INTERRUPT_FIELDS),
+    register(0x1F801074, 4, "I_MASK", INTERRUPT_FIELDS),
+    register(0x1F8010F0, 4, "DPCR", &[]),
+    register(0x1F8010F4, 4, "DICR", DICR_FIELDS),
+    register(0x1F801800, 1, "CDROM.IndexStatus", &[]),
+    register(0x1F801801, 1, "CDROM.Reg1", &[]),
+    register(0x1F801802, 1, "CDROM.Reg2", &[]),
+    register(0x1F801803, 1, "CDROM.Reg3", &[]),
+    register(0x1F801810, 4, "GP0", &[]),
+    register(0x1F801814, 4, "GP1", &[]),
+    register(0x1F801820, 4, "MDEC_DATA", &[]),
+    register(0x1F801824, 4, "MDEC_CTRL", &[]),
+    register(0x1F801D80, 2, "SPU_MAIN_VOL_L", &[]),
+    register(0x1F801D82, 2, "SPU_MAIN_VOL_R", &[]),
+    register(0x1F801D84, 2, "SPU_REVERB_VOL_L", &[]),
+    register(0x1F801D86, 2, "SPU_REVERB_VOL_R", &[]),
+    register(0x1F801D88, 4, "SPU_KON", &[]),
+    register(0x1F801D8C, 4, "SPU_KOFF", &[]),
+    register(0x1F801D90, 4, "SPU_PMON", &[]),
+    register(0x1F801D94, 4, "SPU_NON", &[]),
+    register(0x1F801D98, 4, "SPU_EON", &[]),
+    register(0x1F801D9C, 4, "SPU_ENDX", &[]),
+    register(0x1F801DA2, 2, "SPU_REVERB_BASE", &[]),
+    register(0x1F801DA4, 2, "SPU_IRQ_ADDR", &[]),
+    register(0x1F801DA6, 2, "SPU_XFER_ADDR", &[]),
+    register(0x1F801DA8, 2, "SPU_XFER_FIFO", &[]),
+    register(0x1F801DAA, 2, "SPUCNT", SPUCNT_FIELDS),
+    register(0x1F801DAC, 2, "SPU_XFER_CTRL", &[]),
+    register(0x1F801DAE, 2, "SPUSTAT", &[]),
+    register(0x1F801DB0, 2, "SPU_CD_VOL_L", &[]),
+    register(0x1F801DB2, 2, "SPU_CD_VOL_R", &[]),
+    register(0x1F801DB4, 2, "SPU_EXT_VOL_L", &[]),
+    register(0x1F801DB6, 2, "SPU_EXT_VOL_R", &[]),
+    register(0x1F801DB8, 2, "SPU_CURRENT_VOL_L", &[]),
+    register(0x1F801DBA, 2, "SPU_CURRENT_VOL_R", &[]),
+    register(0x1F802021, 1, "DUART_SRA", &[]),
+    register(0x1F802023, 1, "DUART_THRA", &[]),
+    register(0x1F802029, 1, "DUART_SRB", &[]),
+    register(0x1F80202B, 1, "DUART_THRB", &[]),
+    register(0x1F802041, 1, "POST", &[]),
+    register(0x1F802080, 1, "TEST_TTY", &[]),
+    register(0x1F802082, 1, "TEST_EXIT", &[]),
+    register(0xFFFE0130, 4, "CACHE_CONTROL", CACHE_CONTROL_FIELDS),
+];
+
+const DMA_REGISTERS: &[RegisterInfo] = &[
+    register(0x0, 4, "MADR", &[]),
+    register(0x4, 4, "BCR", &[]),
+    register(0x8, 4, "CHCR", CHCR_FIELDS),
+];
+
+const TIMER_REGISTERS: &[RegisterInfo] = &[
+    register(0x0, 4, "COUNT", &[]),
+    register(0x4, 4, "MODE", TIMER_MODE_FIELDS),
+    register(0x8, 4, "TARGET", &[]),
+];
+
+const VOICE_REGISTERS: &[RegisterInfo] = &[
+    register(0x0, 2, "VOL_L", &[]),
+    register(0x2, 2, "VOL_R", &[]),
+    register(0x4, 2, "PITCH", &[]),
+    register(0x6, 2, "START", &[]),
+    register(0x8, 2, "ADSR_LO", &[]),
+    register(0xA, 2, "ADSR_HI", &[]),
+    register(0xC, 2, "ENVX", &[]),
+    register(0xE, 2, "REPEAT", &[]),
+];
+
+const BLOCKS: &[Block] = &[
+    Block {
+        start: 0x1F801080,
+        stride: 0x10,
+        count: 7,
+        prefix: "DMA",
+        registers: DMA_REGISTERS,
+    },
+    Block {
+        start: 0x1F801100,
+        stride: 0x10,
+        count: 3,
+        prefix: "TIMER",
+        registers: TIMER_REGISTERS,
+    },
+    Block {
+        start: 0x1F801C00,
+        stride: 0x10,
+        count: 24,
+        prefix: "VOICE",
+        registers: VOICE_REGISTERS,
+    },
+];
+
+// The registers are far below the top of the address space, so saturating the end of an access
+// at the top (e.g. a word at 0xFFFFFFFC in KSEG2) doesn't change the result
+fn overlaps(start: u32, width: u32, address: u32, size: u32) -> bool {
+    address < start + width && start < address.saturating_add(size)
+}
+
+// Returns every register touched by an access of `size` bytes at `address`.
+// Several registers share a word (e.g. JOY_MODE and JOY_CTRL), so a word access may touch more than one.
+pub fn lookup(address: u32, size: u32) -> Vec<Register> {
+    let mut registers = Vec::new();
+
+    for info in REGISTERS {
+        if overlaps(info.offset, info.width, address, size) {
+            registers.push(Register {
+                address: info.offset,
+                info,
+                prefix: "",
+                index: None,
+            });
+        }
+    }
+
+    for block in BLOCKS {
+        let end = block.start + block.stride * block.count;
+        if address.saturating_add(size) <= block.start || address >= end {
+            continue;
+        }
+
+        for index in 0..block.count {
+            let base = block.start + block.stride * index;
+            for info in block.registers {
+                if overlaps(base + info.offset, info.width, address, size) {
+                    registers.push(Register {
+                        address: base + info.offset,
+                        info,
+                        prefix: block.prefix,
+                        index: Some(index),
+                    });
+                }
+            }
+        }
+    }
+
+    registers
+}
+
+// Human readable name for an access, e.g. "I_MASK" or "JOY_MODE/JOY_CTRL"
+pub fn describe(address: u32, size: u32) -> String {
+    let registers = lookup(address, size);
+    if registers.is_empty() {
+        return "unknown".to_string();
+    }
+
+    registers
+        .iter()
+        .map(|register| register.to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_access_names_registers_sharing_the_word() {
+        assert_eq!(describe(0x1F801048, 4), "JOY_MODE/JOY_CTRL");
+        assert_eq!(describe(0x1F80104A, 2), "JOY_CTRL");
+        assert_eq!(describe(0x1F801070, 1), "I_STAT");
+    }
+
+    #[test]
+    fn block_registers_are_named_by_index() {
+        assert_eq!(describe(0x1F801C1C, 2), "VOICE1_ENVX");
+        assert_eq!(lookup(0x1F801C1C, 2)[0].address, 0x1F801C1C);
+    }
+
+    #[test]
+    fn fields_decode_the_value() {
+        let register = lookup(0x1F80104A, 2)[0];
+        assert_eq!(register.decode(0x2005), "tx-enable|rx-enable|port2");
+        assert_eq!(register.decode(0), "");
+    }
+
+    #[test]
+    fn accesses_at_the_top_of_memory_are_unknown() {
+        assert_eq!(describe(0xFFFFFFFC, 4), "unknown");
+        assert_eq!(describe(0xFFFFFFFF, 1), "unknown");
+    }
+}
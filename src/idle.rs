@@ -0,0 +1,227 @@
+use std::collections::HashSet;
+
+use crate::cpu::CPU;
+
+// Idle loop skipping. Games wait for VBlank or a flag set by an interrupt handler in a short loop
+// that only reads. Once the loop came back to its start with the same registers and cycles
+// enough times, nothing changes until the next device event, so the time up to it is skipped in
+// whole iterations instead of being run.
+
+// A backward branch over at most this many bytes, the delay slot included
+const MAX_LOOP_SIZE: u32 = 64;
+// Identical iterations before the loop is skipped, and before a loop that was skipped before is
+// skipped again
+const CONFIRMATIONS: u32 = 2000;
+const RECONFIRMATIONS: u32 = 2;
+
+struct Candidate {
+    // The branch target and the branch, the delay slot follows it
+    start: u32,
+    end: u32,
+    // The registers, HI and LO the last time the PC was at the start
+    registers: Option<([u32; 32], u32, u32)>,
+    head_cycles: u64,
+    iteration_cycles: u64,
+    iterations: u32,
+    confirmations: u32,
+    // The device events at the start of the last iteration
+    events: u64,
+    // Address and size of the loads in the loop, and what they read at the start of the last
+    // iteration
+    reads: Vec<(u32, u32)>,
+    values: Vec<Option<u32>>,
+}
+
+pub struct IdleLoopDetector {
+    candidate: Option<Candidate>,
+    // Loops that write or read registers with side effects are never skipped
+    rejected: HashSet<(u32, u32)>,
+    confirmed: HashSet<(u32, u32)>,
+    skipped_cycles: u64,
+}
+
+impl IdleLoopDetector {
+    pub fn new() -> Self {
+        Self {
+            candidate: None,
+            rejected: HashSet::new(),
+            confirmed: HashSet::new(),
+            skipped_cycles: 0,
+        }
+    }
+
+    pub fn skipped_cycles(&self) -> u64 {
+        self.skipped_cycles
+    }
+
+    // Called before every instruction, returns the cycles to skip before it runs
+    pub fn observe(&mut self, cpu: &CPU, cycles: u64) -> Option<u32> {
+        let pc = cpu.pc();
+        let word = cpu.mmu().peek(pc, 4)?;
+
+        if let Some(candidate) = &self.candidate {
+            if !(candidate.start..=candidate.end + 4).contains(&pc) {
+                self.candidate = None;
+            }
+        }
+
+        let Some(candidate) = &mut self.candidate else {
+            let start = backward_branch_target(pc, word)?;
+            if self.rejected.contains(&(start, pc)) {
+                return None;
+            }
+
+            let read_only = (start..=pc + 4)
+                .step_by(4)
+                .all(|address| cpu.mmu().peek(address, 4).is_some_and(is_read_only));
+            if read_only {
+                self.candidate = Some(Candidate {
+                    start,
+                    end: pc,
+                    registers: None,
+                    head_cycles: cycles,
+                    iteration_cycles: 0,
+                    iterations: 0,
+                    confirmations: if self.confirmed.contains(&(start, pc)) {
+                        RECONFIRMATIONS
+                    } else {
+                        CONFIRMATIONS
+                    },
+                    events: 0,
+                    reads: Vec::new(),
+                    values: Vec::new(),
+                });
+            } else {
+                self.rejected.insert((start, pc));
+            }
+            return None;
+        };
+
+        // The addresses are only known while the loop runs
+        if (0x20..=0x26).contains(&(word >> 26)) {
+            let base = cpu.register(((word >> 21) & 0x1F) as usize);
+            let address = base.wrapping_add(word as i16 as u32);
+            if !cpu.mmu().is_idle_read(address) {
+                self.rejected.insert((candidate.start, candidate.end));
+                self.candidate = None;
+                return None;
+            }
+
+            let size = match (word >> 26) & 3 {
+                0 => 1,
+                1 => 2,
+                _ => 4,
+            };
+            let read = (address & !(size - 1), size);
+            if !candidate.reads.contains(&read) {
+                candidate.reads.push(read);
+            }
+        }
+
+        if pc != candidate.start || cpu.is_delay_slot() {
+            return None;
+        }
+
+        let state = Some((*cpu.registers(), cpu.hi(), cpu.lo()));
+        let iteration_cycles = cycles - candidate.head_cycles;
+        if state == candidate.registers && iteration_cycles == candidate.iteration_cycles {
+            candidate.iterations = candidate.iterations.saturating_add(1);
+        } else {
+            candidate.registers = state;
+            candidate.iteration_cycles = iteration_cycles;
+            candidate.iterations = 0;
+        }
+        candidate.head_cycles = cycles;
+
+        // An event in the last iteration can have changed what the loop reads. Unless the reads
+        // can be checked, the loop has to run once more to see it.
+        let events = cpu.mmu().event_count();
+        let values: Vec<_> = (candidate.reads.iter())
+            .map(|&(address, size)| cpu.mmu().peek(address, size))
+            .collect();
+        let settled = events == candidate.events
+            || (values.iter().all(Option::is_some) && values == candidate.values);
+        candidate.events = events;
+        candidate.values = values;
+
+        if candidate.iterations < candidate.confirmations
+            || !settled
+            || cpu.mmu().pending_interrupts()
+        {
+            return None;
+        }
+        self.confirmed.insert((candidate.start, candidate.end));
+
+        // Stop short of the event, the iteration seeing it runs as usual
+        let until_event = cpu.mmu().cycles_until_event() as u64;
+        let skipped = until_event.saturating_sub(1) / iteration_cycles * iteration_cycles;
+        if skipped == 0 {
+            return None;
+        }
+
+        candidate.head_cycles += skipped;
+        self.skipped_cycles += skipped;
+        Some(skipped as u32)
+    }
+}
+
+// The target of a branch or jump that goes back a short way
+fn backward_branch_target(pc: u32, word: u32) -> Option<u32> {
+    let next = pc.wrapping_add(4);
+    let target = match word >> 26 {
+        // BLTZ and BGEZ, not the linking ones
+        0x01 if (word >> 16) & 0x1F <= 1 => next.wrapping_add((word as i16 as u32) << 2),
+        0x04..=0x07 => next.wrapping_add((word as i16 as u32) << 2),
+        // J
+        0x02 => (next & 0xF0000000) | ((word & 0x3FFFFFF) << 2),
+        _ => return None,
+    };
+
+    (target <= pc && pc - target < MAX_LOOP_SIZE).then_some(target)
+}
+
+// Instructions that only change registers, or read memory
+fn is_read_only(word: u32) -> bool {
+    match word >> 26 {
+        0x00 => matches!(
+            word & 0x3F,
+            0x00 | 0x02..=0x04 | 0x06 | 0x07 | 0x10 | 0x12 | 0x20..=0x27 | 0x2A | 0x2B
+        ),
+        0x01 => (word >> 16) & 0x1F <= 1,
+        0x02 | 0x04..=0x0F => true,
+        // Loads, their addresses are checked as they run
+        0x20..=0x26 => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_short_backward_branches_start_loops() {
+        // bne v0, zero, -2 instructions
+        assert_eq!(
+            backward_branch_target(0x80010008, 0x1440FFFE),
+            Some(0x80010004)
+        );
+        // Forward, and too far back
+        assert_eq!(backward_branch_target(0x80010008, 0x14400002), None);
+        assert_eq!(backward_branch_target(0x80010100, 0x1440FF00), None);
+        // j to itself
+        assert_eq!(
+            backward_branch_target(0x80010000, 0x08004000),
+            Some(0x80010000)
+        );
+        // bgezal links
+        assert_eq!(backward_branch_target(0x80010008, 0x0411FFFE), None);
+
+        // lw, andi and nop are fine, sw and jal aren't
+        assert!(is_read_only(0x8C420000));
+        assert!(is_read_only(0x30420001));
+        assert!(is_read_only(0));
+        assert!(!is_read_only(0xAC420000));
+        assert!(!is_read_only(0x0C004000));
+    }
+}
@@ -0,0 +1,44 @@
+#![allow(clippy::upper_case_acronyms)]
+
+pub mod archive;
+pub mod bios;
+mod cdrom;
+#[cfg(feature = "chd")]
+mod chd;
+pub mod cpu;
+pub mod disasm;
+pub mod disc;
+mod dma;
+mod emulator;
+mod error;
+pub mod exe;
+mod expansion2;
+mod gpu;
+mod gte;
+pub mod hwregs;
+mod idle;
+mod mdec;
+mod memcard;
+pub mod mmu;
+pub mod psf;
+pub mod resampler;
+pub mod saves;
+mod scheduler;
+mod sio;
+mod sio1;
+mod spu;
+mod timers;
+pub mod trace;
+mod turbo;
+mod watchdog;
+mod xa;
+
+pub use cdrom::CdTiming;
+pub use emulator::{Emulator, Error};
+pub use error::EmuError;
+pub use memcard::MemoryCard;
+pub use sio::{Axis, Button, DigitalPad, DualShock, PadDevice};
+pub use sio1::{ChannelLink, Loopback, SerialLink, TcpLink};
+pub use spu::Interpolation;
+pub use turbo::{Turbo, DEFAULT_TURBO_RATE};
+pub use watchdog::{HangKind, WatchdogConfig};
@@ -1,20 +1,275 @@
-use std::fs::read;
+use std::{
+    env,
+    fs::{read, File},
+    io::{stdout, BufWriter, IsTerminal, Write},
+    process::exit,
+};
 
-use cpu::CPU;
-use mmu::MMU;
+use args::{Args, USAGE};
+use psx_rust::{
+    archive,
+    bios::BiosCallTracer,
+    disc::Disc,
+    mmu::MmuMode,
+    psf,
+    trace::{Compression, TraceWriter},
+    CdTiming, DualShock, EmuError, Emulator, Interpolation, MemoryCard, TcpLink,
+};
 
-mod cpu;
-mod mmu;
-mod timers;
+mod args;
+#[cfg(feature = "frontend")]
+mod frontend;
+mod subcommands;
 
-const BIOS_PATH: &str = "./static/bios/PSXBIOS.bin";
+// Like timeout(1), test ROMs pick their own exit codes
+const HANG_EXIT_CODE: i32 = 124;
 
 fn main() {
-    let bios = read(BIOS_PATH).ok().unwrap();
-    let mmu = MMU::new(bios);
-    let mut cpu = CPU::new(mmu);
+    let arguments: Vec<String> = env::args().skip(1).collect();
+    if let Some(result) = subcommands::run(&arguments) {
+        if let Err(error) = result {
+            eprintln!("{}", error);
+            exit(1);
+        }
+        return;
+    }
+
+    let args = Args::parse().unwrap_or_else(|error| {
+        eprintln!("{}\n{}", error, USAGE);
+        exit(1);
+    });
+
+    // The BIOS and EXEs can be zipped or gzipped, like discs
+    let (_, bios) =
+        archive::read_file(&args.bios, &archive::BIOS_EXTENSIONS).unwrap_or_else(|error| {
+            eprintln!("Failed to read BIOS '{}': {}", args.bios, error);
+            exit(1);
+        });
+
+    let mut emulator = Emulator::new(bios).unwrap_or_else(|error| {
+        eprintln!("Failed to load BIOS '{}': {}", args.bios, error);
+        exit(1);
+    });
+
+    // A PSF piped into a player has the audio on stdout
+    let audio_to_stdout = args.psf.is_some() && args.wav.as_deref().is_none_or(|path| path == "-");
+    emulator.set_tty_enabled(args.tty);
+    emulator.set_tty_callback(Box::new(move |character| {
+        if audio_to_stdout {
+            eprint!("{}", character);
+            return;
+        }
+
+        let mut stdout = stdout();
+        let _ = write!(stdout, "{}", character);
+        let _ = stdout.flush();
+    }));
+
+    if args.permissive {
+        emulator.set_mmu_mode(MmuMode::Permissive);
+    }
+
+    if args.testing {
+        emulator.set_testing(true);
+    }
+
+    // Automated runs stop instead of using up their cycles when the guest hangs. Sound drivers
+    // wait for interrupts in loops that look like hangs.
+    if args.watchdog && args.psf.is_none() && (args.headless || cfg!(not(feature = "frontend"))) {
+        emulator.set_watchdog(Some(args.watchdog_config));
+    }
+
+    if args.trace_bios {
+        emulator.set_bios_tracer(Some(BiosCallTracer::stderr()));
+    }
+
+    if let Some(path) = &args.trace {
+        let compression = if cfg!(feature = "deflate") {
+            Compression::Deflate
+        } else {
+            Compression::None
+        };
+        let trace = File::create(path)
+            .and_then(|file| TraceWriter::new(Box::new(file) as Box<dyn Write>, compression))
+            .unwrap_or_else(|error| {
+                eprintln!("Failed to create trace '{}': {}", path, error);
+                exit(1);
+            });
+
+        emulator.set_instruction_trace(Some(trace));
+    }
+
+    if let Some(path) = &args.expansion_rom {
+        let rom = read(path).unwrap_or_else(|error| {
+            eprintln!("Failed to read expansion ROM '{}': {}", path, error);
+            exit(1);
+        });
+
+        emulator.mmu_mut().load_expansion_rom(rom);
+    }
+
+    if let Some(path) = args.discs.first() {
+        let disc = Disc::open(path).unwrap_or_else(|error| {
+            eprintln!("Failed to open disc '{}': {}", path, error);
+            exit(1);
+        });
+
+        emulator.insert_disc(Some(disc));
+    }
+
+    if args.fast_cd {
+        emulator.set_cd_timing(CdTiming::Fast);
+    }
+
+    if args.fast_spu {
+        emulator.set_spu_interpolation(Interpolation::Linear);
+    }
+
+    if args.skip_idle {
+        emulator.set_idle_loop_skipping(true);
+    }
+
+    if args.analog {
+        emulator.set_controller(0, Some(Box::new(DualShock::new())));
+    }
+
+    if let Some(path) = &args.memory_card {
+        let card = MemoryCard::open(path).unwrap_or_else(|error| {
+            eprintln!("Failed to open memory card '{}': {}", path, error);
+            exit(1);
+        });
+
+        emulator.set_memory_card(0, Some(card));
+    }
+
+    let link = match (&args.link_listen, &args.link_connect) {
+        (Some(address), _) => {
+            println!("Waiting for the link cable connection on {}", address);
+            Some((address, TcpLink::listen(address)))
+        }
+        (None, Some(address)) => Some((address, TcpLink::connect(address))),
+        (None, None) => None,
+    };
+    if let Some((address, link)) = link {
+        let link = link.unwrap_or_else(|error| {
+            eprintln!("Failed to open link cable '{}': {}", address, error);
+            exit(1);
+        });
+
+        emulator.set_serial_link(Some(Box::new(link)));
+    }
+
+    if let Some(path) = &args.exe {
+        let (_, exe) = archive::read_file(path, &archive::EXE_EXTENSIONS).unwrap_or_else(|error| {
+            eprintln!("Failed to read EXE '{}': {}", path, error);
+            exit(1);
+        });
+
+        if let Err(error) = emulator.sideload_exe(&exe) {
+            eprintln!("Failed to load EXE '{}': {}", path, error);
+            exit(1);
+        }
+    }
+
+    for raw in &args.raws {
+        let data = read(&raw.path).unwrap_or_else(|error| {
+            eprintln!("Failed to read '{}': {}", raw.path, error);
+            exit(1);
+        });
+
+        if let Err(error) = emulator.sideload_raw(data, raw.address, raw.entry) {
+            eprintln!("Failed to load '{}': {}", raw.path, error);
+            exit(1);
+        }
+    }
+
+    if let Some(path) = &args.psf {
+        play_psf(&mut emulator, path, args.wav.as_deref(), audio_to_stdout);
+        return;
+    }
+
+    #[cfg(feature = "frontend")]
+    let result = if args.headless {
+        run_headless(&mut emulator, args.max_cycles)
+    } else {
+        frontend::run(&mut emulator, &args)
+    };
+    #[cfg(not(feature = "frontend"))]
+    let result = run_headless(&mut emulator, args.max_cycles);
+
+    // The trace ends with the last instruction, also when emulation stopped with an error
+    if let Some(trace) = emulator.take_instruction_trace() {
+        let count = trace.record_count();
+        match trace.finish() {
+            Ok(_) => println!("Traced {} instructions", count),
+            Err(error) => eprintln!("Failed to write the instruction trace: {}", error),
+        }
+    }
+
+    if let Err(error) = result {
+        eprintln!(
+            "Emulation stopped after {} cycles at pc 0x{:08x}: {}",
+            emulator.cycles(),
+            emulator.pc(),
+            error
+        );
+        match error {
+            EmuError::Hang { .. } => exit(HANG_EXIT_CODE),
+            _ => exit(1),
+        }
+    }
+
+    if let Some(code) = emulator.exit_code() {
+        exit(code as i32);
+    }
+}
+
+// Plays the song until its length and fade are over, there is no video
+fn play_psf(emulator: &mut Emulator, path: &str, wav: Option<&str>, audio_to_stdout: bool) {
+    if audio_to_stdout && stdout().is_terminal() {
+        eprintln!("Pipe the PSF audio into a player, like psx-rust --psf <path> | aplay, or write it with --wav <path>");
+        exit(1);
+    }
+
+    let (exe, psf) = psf::load(path).unwrap_or_else(|error| {
+        eprintln!("Failed to load PSF '{}': {}", path, error);
+        exit(1);
+    });
+
+    if let Some(title) = psf.tag("title") {
+        eprintln!("Playing {}", title);
+    }
+
+    if let Err(error) = emulator.sideload_program(exe) {
+        eprintln!("Failed to load PSF '{}': {}", path, error);
+        exit(1);
+    }
+    let timing = psf.timing();
+    let result = match wav {
+        Some(wav) if !audio_to_stdout => {
+            let file = File::create(wav).unwrap_or_else(|error| {
+                eprintln!("Failed to create '{}': {}", wav, error);
+                exit(1);
+            });
+            psf::render(emulator, timing, BufWriter::new(file)).map(|_| ())
+        }
+        _ => psf::render(emulator, timing, stdout().lock()).map(|_| ()),
+    };
+
+    if let Err(error) = result {
+        eprintln!("Failed to play PSF '{}': {}", path, error);
+        exit(1);
+    }
+}
 
-    loop {
-        cpu.step();
+fn run_headless(emulator: &mut Emulator, max_cycles: Option<u64>) -> Result<(), EmuError> {
+    match max_cycles {
+        Some(cycles) => emulator.run_cycles(cycles),
+        None => {
+            while emulator.exit_code().is_none() {
+                emulator.step()?;
+            }
+            Ok(())
+        }
     }
 }
@@ -0,0 +1,560 @@
+use std::collections::VecDeque;
+
+use crate::dma::DmaDevice;
+
+// Motion Decoder, decompresses the run length coded DCT macroblocks of movies and images into
+// pixels. MDEC in (DMA channel 0) feeds the command and its parameters, MDEC out (channel 1)
+// carries the pixels back.
+
+// Status bits
+const STATUS_OUT_EMPTY: u32 = 1 << 31;
+const STATUS_BUSY: u32 = 1 << 29;
+const STATUS_IN_REQUEST: u32 = 1 << 28;
+const STATUS_OUT_REQUEST: u32 = 1 << 27;
+// Decoding is instant, so the current block always reads as the first one (Y for mono, Cr for
+// color output)
+const STATUS_BLOCK: u32 = 4;
+
+// Control bits
+const CONTROL_RESET: u32 = 1 << 31;
+const CONTROL_IN_REQUEST: u32 = 1 << 30;
+const CONTROL_OUT_REQUEST: u32 = 1 << 29;
+
+// Padding between the blocks, it also ends the coefficients of a block
+const END_OF_BLOCK: u16 = 0xFE00;
+
+// Position in the 8x8 block of every coefficient, in the order they are stored
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Depth {
+    Bit4,
+    Bit8,
+    Bit24,
+    Bit15,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Command {
+    None,
+    Decode,
+    // Only luminance, or luminance and color
+    SetQuantTable { color: bool },
+    SetScaleTable,
+}
+
+pub struct Mdec {
+    command: Command,
+    // Parameter words the command still expects
+    remaining: u32,
+    depth: Depth,
+    signed: bool,
+    // Bit 15 of the 15 bit pixels
+    set_bit15: bool,
+    // Halfwords of the decode command not turned into pixels yet
+    input: VecDeque<u16>,
+    // Bytes of the table being uploaded
+    table: Vec<u8>,
+    output: VecDeque<u32>,
+
+    // Luminance and color quantization, in zigzag order
+    luminance_quant: [u8; 64],
+    color_quant: [u8; 64],
+    // Cosine table of the IDCT, signed 1.15 fixed point
+    scale: [i16; 64],
+
+    in_request: bool,
+    out_request: bool,
+}
+
+impl Mdec {
+    pub fn new() -> Self {
+        Self {
+            command: Command::None,
+            remaining: 0,
+            depth: Depth::Bit4,
+            signed: false,
+            set_bit15: false,
+            input: VecDeque::new(),
+            table: Vec::new(),
+            output: VecDeque::new(),
+            luminance_quant: [0; 64],
+            color_quant: [0; 64],
+            scale: [0; 64],
+            in_request: false,
+            out_request: false,
+        }
+    }
+
+    // The tables are kept
+    fn reset(&mut self) {
+        self.command = Command::None;
+        self.remaining = 0;
+        self.depth = Depth::Bit4;
+        self.signed = false;
+        self.set_bit15 = false;
+        self.input.clear();
+        self.table.clear();
+        self.output.clear();
+    }
+
+    // MDEC1, the low 16 bits count the parameter words still expected minus 1
+    pub fn status(&self) -> u32 {
+        let mut status = self.remaining.wrapping_sub(1) & 0xFFFF;
+        status |= STATUS_BLOCK << 16;
+        status |= (self.set_bit15 as u32) << 23;
+        status |= (self.signed as u32) << 24;
+        status |= (self.depth as u32) << 25;
+
+        if self.output.is_empty() {
+            status |= STATUS_OUT_EMPTY;
+        }
+        if self.remaining > 0 || !self.output.is_empty() {
+            status |= STATUS_BUSY;
+        }
+        if self.can_write() {
+            status |= STATUS_IN_REQUEST;
+        }
+        if self.can_read() {
+            status |= STATUS_OUT_REQUEST;
+        }
+
+        status
+    }
+
+    pub fn write_control(&mut self, value: u32) {
+        if value & CONTROL_RESET != 0 {
+            self.reset();
+        }
+        self.in_request = value & CONTROL_IN_REQUEST != 0;
+        self.out_request = value & CONTROL_OUT_REQUEST != 0;
+    }
+
+    // MDEC0, a command word or one of its parameters
+    pub fn write(&mut self, value: u32) {
+        if self.remaining == 0 {
+            return self.start_command(value);
+        }
+
+        self.remaining -= 1;
+        match self.command {
+            Command::Decode => {
+                self.input.push_back(value as u16);
+                self.input.push_back((value >> 16) as u16);
+                self.decode();
+                if self.remaining == 0 {
+                    // A partial macroblock at the end is dropped
+                    self.input.clear();
+                }
+            }
+            Command::SetQuantTable { color } => {
+                self.table.extend(value.to_le_bytes());
+                if self.remaining == 0 {
+                    self.luminance_quant.copy_from_slice(&self.table[..64]);
+                    if color {
+                        self.color_quant.copy_from_slice(&self.table[64..]);
+                    }
+                    self.table.clear();
+                }
+            }
+            Command::SetScaleTable => {
+                self.table.extend(value.to_le_bytes());
+                if self.remaining == 0 {
+                    for (scale, bytes) in self.scale.iter_mut().zip(self.table.chunks_exact(2)) {
+                        *scale = i16::from_le_bytes([bytes[0], bytes[1]]);
+                    }
+                    self.table.clear();
+                }
+            }
+            Command::None => {}
+        }
+    }
+
+    // Bits 29..31 select the command
+    fn start_command(&mut self, value: u32) {
+        self.depth = match (value >> 27) & 3 {
+            0 => Depth::Bit4,
+            1 => Depth::Bit8,
+            2 => Depth::Bit24,
+            _ => Depth::Bit15,
+        };
+        self.signed = value & (1 << 26) != 0;
+        self.set_bit15 = value & (1 << 25) != 0;
+
+        (self.command, self.remaining) = match value >> 29 {
+            1 => (Command::Decode, value & 0xFFFF),
+            2 => {
+                let color = value & 1 != 0;
+                (
+                    Command::SetQuantTable { color },
+                    if color { 32 } else { 16 },
+                )
+            }
+            3 => (Command::SetScaleTable, 32),
+            _ => {
+                println!("Unknown MDEC command 0x{:08x}", value);
+                (Command::None, 0)
+            }
+        };
+    }
+
+    // Decodes the complete macroblocks at the start of the input, mono output has a block of Y,
+    // color output Cr, Cb and the four Y blocks of a 16x16 macroblock
+    fn decode(&mut self) {
+        let blocks = match self.depth {
+            Depth::Bit4 | Depth::Bit8 => 1,
+            Depth::Bit24 | Depth::Bit15 => 6,
+        };
+
+        loop {
+            let mut position = 0;
+            let mut coefficients = [[0; 64]; 6];
+            for (index, block) in coefficients.iter_mut().enumerate().take(blocks) {
+                let quant = match (blocks, index) {
+                    (6, 0 | 1) => self.color_quant,
+                    _ => self.luminance_quant,
+                };
+                let Some(length) = decode_block(&self.input, position, &quant, block) else {
+                    return;
+                };
+                position += length;
+            }
+            self.input.drain(..position);
+
+            for block in &mut coefficients[..blocks] {
+                self.idct(block);
+            }
+            match blocks {
+                1 => self.output_mono(&coefficients[0]),
+                _ => self.output_color(&coefficients),
+            }
+        }
+    }
+
+    // Two passes over the rows and the columns, the result is rounded and wraps to 9 bits like on
+    // the hardware before it is clamped to a signed byte
+    fn idct(&self, block: &mut [i32; 64]) {
+        let mut rows = [0i64; 64];
+        for y in 0..8 {
+            for x in 0..8 {
+                rows[y * 8 + x] = (0..8)
+                    .map(|u| block[y * 8 + u] as i64 * self.scale[u * 8 + x] as i64)
+                    .sum();
+            }
+        }
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let sum: i64 = (0..8)
+                    .map(|v| rows[v * 8 + x] * self.scale[v * 8 + y] as i64)
+                    .sum();
+                let value = ((sum >> 32) + ((sum >> 31) & 1)) as i32;
+                block[y * 8 + x] = ((value << 23) >> 23).clamp(-128, 127);
+            }
+        }
+    }
+
+    fn output_mono(&mut self, block: &[i32; 64]) {
+        let pixels = block.map(|y| self.component(y));
+        match self.depth {
+            Depth::Bit4 => {
+                for chunk in pixels.chunks_exact(8) {
+                    let word = chunk.iter().enumerate().fold(0, |word, (i, &pixel)| {
+                        word | ((pixel as u32 >> 4) << (i * 4))
+                    });
+                    self.output.push_back(word);
+                }
+            }
+            _ => {
+                for chunk in pixels.chunks_exact(4) {
+                    self.output
+                        .push_back(u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+                }
+            }
+        }
+    }
+
+    // The four Y blocks are the top left, top right, bottom left and bottom right of the
+    // macroblock, Cr and Cb cover all of it at half the resolution
+    fn output_color(&mut self, blocks: &[[i32; 64]; 6]) {
+        let [cr, cb, ..] = blocks;
+        let mut pixels = [[0u8; 3]; 256];
+
+        for (index, luminance) in blocks[2..].iter().enumerate() {
+            let (left, top) = ((index & 1) * 8, (index >> 1) * 8);
+            for y in 0..8 {
+                for x in 0..8 {
+                    let (px, py) = (left + x, top + y);
+                    let chroma = (py / 2) * 8 + px / 2;
+                    let (red, blue) = (cr[chroma], cb[chroma]);
+
+                    let r = (359 * red + 0x80) >> 8;
+                    let g = (((-88 * blue) & !0x1F) + ((-183 * red) & !0x07) + 0x80) >> 8;
+                    let b = (454 * blue + 0x80) >> 8;
+
+                    let luminance = luminance[y * 8 + x];
+                    pixels[py * 16 + px] = [r, g, b].map(|c| self.component(luminance + c));
+                }
+            }
+        }
+
+        match self.depth {
+            Depth::Bit24 => {
+                let bytes: Vec<u8> = pixels.into_iter().flatten().collect();
+                for chunk in bytes.chunks_exact(4) {
+                    self.output
+                        .push_back(u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+                }
+            }
+            _ => {
+                let bit15 = (self.set_bit15 as u32) << 15;
+                let halfword = |[r, g, b]: [u8; 3]| {
+                    (r as u32 >> 3) | ((g as u32 >> 3) << 5) | ((b as u32 >> 3) << 10) | bit15
+                };
+                for pair in pixels.chunks_exact(2) {
+                    self.output
+                        .push_back(halfword(pair[0]) | (halfword(pair[1]) << 16));
+                }
+            }
+        }
+    }
+
+    // A signed color component as a byte of the output format
+    fn component(&self, value: i32) -> u8 {
+        let value = value.clamp(-128, 127) as i8 as u8;
+        if self.signed {
+            value
+        } else {
+            value ^ 0x80
+        }
+    }
+
+    // MDEC0 reads return the pixels
+    pub fn read(&mut self) -> u32 {
+        self.output.pop_front().unwrap_or(0)
+    }
+}
+
+// Reads the coefficients of a block starting at the position, returns the number of halfwords
+// it took or None when the input ends before the block does. The first halfword holds the
+// quantization scale and the DC coefficient, the following ones the number of zeros to skip and
+// the next coefficient.
+fn decode_block(
+    input: &VecDeque<u16>,
+    mut position: usize,
+    quant: &[u8; 64],
+    block: &mut [i32; 64],
+) -> Option<usize> {
+    let start = position;
+    let mut next = || {
+        let value = input.get(position).copied();
+        position += 1;
+        value
+    };
+
+    let mut value = next()?;
+    while value == END_OF_BLOCK {
+        value = next()?;
+    }
+
+    let scale = (value >> 10) as i32;
+    let mut k = 0;
+    let mut coefficient = signed_10bit(value) * quant[0] as i32;
+    loop {
+        // Without a scale the coefficients are stored as is, in row order
+        if scale == 0 {
+            coefficient = signed_10bit(value) * 2;
+        }
+        let coefficient_value = coefficient.clamp(-0x400, 0x3FF);
+        match scale {
+            0 => block[k] = coefficient_value,
+            _ => block[ZIGZAG[k]] = coefficient_value,
+        }
+
+        value = next()?;
+        k += (value >> 10) as usize + 1;
+        if k > 63 {
+            break;
+        }
+        coefficient = (signed_10bit(value) * quant[k] as i32 * scale + 4) / 8;
+    }
+
+    Some(position - start)
+}
+
+fn signed_10bit(value: u16) -> i32 {
+    ((value as i32) << 22) >> 22
+}
+
+impl DmaDevice for Mdec {
+    // Data out request, pixels are waiting
+    fn can_read(&self) -> bool {
+        self.out_request && !self.output.is_empty()
+    }
+
+    // Data in request, commands and parameters are taken at any time since the decoding is instant
+    fn can_write(&self) -> bool {
+        self.in_request
+    }
+
+    fn read_word(&mut self) -> u32 {
+        self.read()
+    }
+
+    fn write_word(&mut self, value: u32) {
+        self.write(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DECODE: u32 = 1 << 29;
+    const SET_QUANT_TABLE: u32 = 2 << 29;
+    const SET_SCALE_TABLE: u32 = 3 << 29;
+    const DEPTH_8BIT: u32 = 1 << 27;
+    const DEPTH_24BIT: u32 = 2 << 27;
+
+    // The IDCT table every game uploads, c(u) * cos((2x + 1) * u * pi / 16) in 1.15 fixed point
+    fn standard_scale_table() -> [i16; 64] {
+        std::array::from_fn(|i| {
+            let (u, x) = ((i / 8) as f64, (i % 8) as f64);
+            let c = if u == 0.0 { 0.5f64.sqrt() } else { 1.0 };
+            (c * ((2.0 * x + 1.0) * u * std::f64::consts::PI / 16.0).cos() * 32768.0).round() as i16
+        })
+    }
+
+    // An MDEC with a quantization of 2 for every coefficient
+    fn mdec() -> Mdec {
+        let mut mdec = Mdec::new();
+        mdec.write(SET_QUANT_TABLE | 1);
+        for _ in 0..32 {
+            mdec.write(0x02020202);
+        }
+        mdec.write(SET_SCALE_TABLE);
+        let scale = standard_scale_table();
+        for pair in scale.chunks_exact(2) {
+            mdec.write(pair[0] as u16 as u32 | ((pair[1] as u16 as u32) << 16));
+        }
+        mdec
+    }
+
+    fn decode(mdec: &mut Mdec, depth: u32, halfwords: &[u16]) -> Vec<u32> {
+        let words: Vec<u32> = halfwords
+            .chunks(2)
+            .map(|pair| pair[0] as u32 | (*pair.get(1).unwrap_or(&END_OF_BLOCK) as u32) << 16)
+            .collect();
+        mdec.write(DECODE | depth | words.len() as u32);
+        for word in words {
+            mdec.write(word);
+        }
+        mdec.output.drain(..).collect()
+    }
+
+    fn bytes(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn tables_are_uploaded() {
+        let mdec = mdec();
+        assert_eq!(mdec.luminance_quant, [2; 64]);
+        assert_eq!(mdec.color_quant, [2; 64]);
+        assert_eq!(mdec.scale[..2], [0x5A82, 0x5A82]);
+        assert_eq!(mdec.scale[8] as u16, 0x7D8A);
+        assert_eq!(mdec.status() & 0xFFFF, 0xFFFF);
+    }
+
+    #[test]
+    fn dc_only_block_is_flat() {
+        let mut mdec = mdec();
+        // Scale 1, DC 200, times the quantization of 2 is 400, the IDCT divides by 8
+        let output = decode(&mut mdec, DEPTH_8BIT, &[(1 << 10) | 200, END_OF_BLOCK]);
+        assert_eq!(output.len(), 16);
+        assert!(bytes(&output).iter().all(|&pixel| pixel == 50 ^ 0x80));
+    }
+
+    #[test]
+    fn horizontal_frequency_varies_along_rows() {
+        let mut mdec = mdec();
+        // The second coefficient, zigzag index 1, is the lowest horizontal frequency
+        let output = decode(&mut mdec, DEPTH_8BIT, &[1 << 10, 100, END_OF_BLOCK]);
+        let pixels = bytes(&output);
+
+        for y in 1..8 {
+            assert_eq!(pixels[y * 8..y * 8 + 8], pixels[..8]);
+        }
+        assert!(pixels[0] > pixels[7]);
+        assert!(pixels[..8].windows(2).all(|pair| pair[0] >= pair[1]));
+    }
+
+    #[test]
+    fn color_macroblock_without_chroma_is_gray() {
+        let mut mdec = mdec();
+        let mut halfwords = Vec::new();
+        // Cr and Cb are 0, the four Y blocks get increasing DC values
+        for dc in [0, 0, 8, 16, 24, 32] {
+            halfwords.extend([(1 << 10) | dc, END_OF_BLOCK]);
+        }
+        let output = decode(&mut mdec, DEPTH_24BIT, &halfwords);
+        let pixels = bytes(&output);
+        assert_eq!(pixels.len(), 16 * 16 * 3);
+
+        let pixel = |x: usize, y: usize| &pixels[(y * 16 + x) * 3..][..3];
+        assert_eq!(pixel(0, 0), [0x82; 3]);
+        assert_eq!(pixel(15, 0), [0x84; 3]);
+        assert_eq!(pixel(0, 15), [0x86; 3]);
+        assert_eq!(pixel(15, 15), [0x88; 3]);
+    }
+
+    #[test]
+    fn macroblocks_are_decoded_as_the_data_arrives() {
+        let mut mdec = mdec();
+        mdec.write(DECODE | DEPTH_8BIT | 2);
+        assert_eq!(mdec.status() & 0xFFFF, 1);
+        assert!(mdec.status() & STATUS_BUSY != 0);
+
+        mdec.write((1 << 10) | 200 | ((END_OF_BLOCK as u32) << 16));
+        assert_eq!(mdec.output.len(), 16);
+        mdec.write((1 << 10) | 200);
+        assert_eq!(mdec.output.len(), 16);
+        assert_eq!(mdec.status() & 0xFFFF, 0xFFFF);
+        assert!(mdec.input.is_empty());
+    }
+
+    #[test]
+    fn data_requests_follow_the_control_register() {
+        let mut mdec = mdec();
+        mdec.write(DECODE | DEPTH_8BIT | 1);
+        assert!(!mdec.can_write());
+
+        mdec.write_control(CONTROL_IN_REQUEST | CONTROL_OUT_REQUEST);
+        assert!(mdec.can_write() && !mdec.can_read());
+        assert!(mdec.status() & STATUS_IN_REQUEST != 0);
+
+        mdec.write_word((1 << 10) | 200 | ((END_OF_BLOCK as u32) << 16));
+        assert!(mdec.can_read());
+        assert!(
+            mdec.status() & (STATUS_OUT_REQUEST | STATUS_BUSY) == STATUS_OUT_REQUEST | STATUS_BUSY
+        );
+        for _ in 0..16 {
+            mdec.read_word();
+        }
+        assert_eq!(
+            mdec.status() & 0xFFFF_0000,
+            STATUS_OUT_EMPTY | STATUS_IN_REQUEST | (STATUS_BLOCK << 16) | (1 << 25)
+        );
+
+        mdec.write_control(CONTROL_RESET);
+        assert_eq!(
+            mdec.status(),
+            STATUS_OUT_EMPTY | (STATUS_BLOCK << 16) | 0xFFFF
+        );
+        assert_eq!(mdec.scale, standard_scale_table());
+    }
+}
@@ -0,0 +1,379 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+// 1024 sectors ("frames") of 128 bytes, the raw .mcr format
+pub const MEMORY_CARD_SIZE: usize = 128 * 1024;
+const SECTOR_SIZE: usize = 128;
+const SECTOR_COUNT: u16 = (MEMORY_CARD_SIZE / SECTOR_SIZE) as u16;
+
+// FLAG bit 3, set until the first successful write so the BIOS can tell the card was replaced
+const FLAG_FRESH: u8 = 0x08;
+
+// Command end bytes
+const END_GOOD: u8 = 0x47;
+const END_BAD_CHECKSUM: u8 = 0x4E;
+const END_BAD_SECTOR: u8 = 0xFF;
+
+// A memory card in one of the controller ports, optionally backed by a file that every written
+// sector is flushed to
+pub struct MemoryCard {
+    data: Box<[u8; MEMORY_CARD_SIZE]>,
+    path: Option<PathBuf>,
+    flag: u8,
+
+    // Bytes exchanged since the card was addressed
+    position: usize,
+    command: u8,
+    // The previously received byte, a lot of responses echo it
+    last: u8,
+    sector: u16,
+    checksum: u8,
+    buffer: [u8; SECTOR_SIZE],
+    // The command was not for the card, it stays quiet until deselected
+    ignoring: bool,
+}
+
+impl MemoryCard {
+    pub fn from_data(data: Vec<u8>) -> Result<Self, io::Error> {
+        let data: Box<[u8; MEMORY_CARD_SIZE]> = data.try_into().map_err(|data: Vec<u8>| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Memory card image is {} bytes, expected {}",
+                    data.len(),
+                    MEMORY_CARD_SIZE
+                ),
+            )
+        })?;
+
+        Ok(Self {
+            data,
+            path: None,
+            flag: FLAG_FRESH,
+            position: 0,
+            command: 0,
+            last: 0,
+            sector: 0,
+            checksum: 0,
+            buffer: [0; SECTOR_SIZE],
+            ignoring: false,
+        })
+    }
+
+    // A card as formatted by the BIOS, with an empty directory
+    pub fn formatted() -> Self {
+        let mut data = vec![0; MEMORY_CARD_SIZE];
+
+        // Header, repeated in the last sector of the first block
+        for sector in [0, 63] {
+            data[sector * SECTOR_SIZE..][..2].copy_from_slice(b"MC");
+        }
+        // Directory entries of the 15 free blocks
+        for sector in 1..16 {
+            let frame = &mut data[sector * SECTOR_SIZE..][..SECTOR_SIZE];
+            frame[0] = 0xA0;
+            frame[8..10].copy_from_slice(&[0xFF, 0xFF]);
+        }
+        // Broken sector list, no replacements
+        for sector in 16..36 {
+            let frame = &mut data[sector * SECTOR_SIZE..][..SECTOR_SIZE];
+            frame[0..4].copy_from_slice(&[0xFF; 4]);
+            frame[8..10].copy_from_slice(&[0xFF, 0xFF]);
+        }
+        for sector in 0..36 {
+            let frame = &mut data[sector * SECTOR_SIZE..][..SECTOR_SIZE];
+            frame[SECTOR_SIZE - 1] = frame[..SECTOR_SIZE - 1].iter().fold(0, |a, b| a ^ b);
+        }
+        let header = data[..SECTOR_SIZE].to_vec();
+        data[63 * SECTOR_SIZE..][..SECTOR_SIZE].copy_from_slice(&header);
+
+        Self::from_data(data).unwrap()
+    }
+
+    // Loads the card image at the path, a missing file is created as a formatted card
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, io::Error> {
+        let path = path.as_ref();
+
+        let mut card = match fs::read(path) {
+            Ok(data) => Self::from_data(data)?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                let card = Self::formatted();
+                fs::write(path, &card.data[..])?;
+                card
+            }
+            Err(error) => return Err(error),
+        };
+
+        card.path = Some(path.to_path_buf());
+        Ok(card)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data[..]
+    }
+
+    // Changes have to be written to the file with flush
+    pub(crate) fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data[..]
+    }
+
+    // Writes the image to the file the card was opened from, if any
+    pub fn flush(&self) -> io::Result<()> {
+        match &self.path {
+            Some(path) => fs::write(path, &self.data[..]),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn deselect(&mut self) {
+        self.position = 0;
+        self.ignoring = false;
+    }
+
+    // Same as PadDevice::transfer, starting with the 0x81 address byte
+    pub(crate) fn transfer(&mut self, value: u8) -> (u8, bool) {
+        if self.ignoring {
+            return (0xFF, false);
+        }
+
+        let position = self.position;
+        self.position += 1;
+
+        let response = match position {
+            0 => (value == 0x81).then_some((0xFF, true)),
+            1 => {
+                self.command = value;
+                match value {
+                    0x52 | 0x57 | 0x53 => Some((self.flag, true)),
+                    _ => None,
+                }
+            }
+            _ => match self.command {
+                0x52 => self.read_sector(position - 2, value),
+                0x57 => Some(self.write_sector(position - 2, value)),
+                _ => self.get_id(position - 2),
+            },
+        };
+
+        self.last = value;
+        response.unwrap_or_else(|| {
+            self.ignoring = true;
+            (0xFF, false)
+        })
+    }
+
+    fn receive_sector(&mut self, step: usize, value: u8) {
+        match step {
+            2 => self.sector = (value as u16) << 8,
+            _ => self.sector |= value as u16,
+        }
+        self.checksum = if step == 2 {
+            value
+        } else {
+            self.checksum ^ value
+        };
+    }
+
+    fn read_sector(&mut self, step: usize, value: u8) -> Option<(u8, bool)> {
+        let valid = self.sector < SECTOR_COUNT;
+
+        Some(match step {
+            0 => (0x5A, true),
+            1 => (0x5D, true),
+            2 => {
+                self.receive_sector(step, value);
+                (0x00, true)
+            }
+            3 => {
+                self.receive_sector(step, value);
+                (self.last, true)
+            }
+            4 => (0x5C, true),
+            5 => (0x5D, true),
+            // An invalid sector is answered with 0xFFFF and ends the command, the bytes after it
+            // are ignored
+            6 if !valid => (0xFF, true),
+            7 if !valid => (0xFF, false),
+            _ if !valid => return None,
+            6 => ((self.sector >> 8) as u8, true),
+            7 => (self.sector as u8, true),
+            8..136 => {
+                let byte = self.data[self.sector as usize * SECTOR_SIZE + step - 8];
+                self.checksum ^= byte;
+                (byte, true)
+            }
+            136 => (self.checksum, true),
+            137 => (END_GOOD, false),
+            _ => return None,
+        })
+    }
+
+    fn write_sector(&mut self, step: usize, value: u8) -> (u8, bool) {
+        match step {
+            0 => (0x5A, true),
+            1 => (0x5D, true),
+            2 | 3 => {
+                let response = if step == 2 { 0x00 } else { self.last };
+                self.receive_sector(step, value);
+                (response, true)
+            }
+            4..132 => {
+                self.buffer[step - 4] = value;
+                self.checksum ^= value;
+                (self.last, true)
+            }
+            // The checksum byte
+            132 => {
+                self.checksum ^= value;
+                (self.last, true)
+            }
+            133 => (0x5C, true),
+            134 => (0x5D, true),
+            _ => {
+                let status = if self.sector >= SECTOR_COUNT {
+                    END_BAD_SECTOR
+                } else if self.checksum != 0 {
+                    END_BAD_CHECKSUM
+                } else {
+                    self.commit_sector();
+                    END_GOOD
+                };
+
+                self.ignoring = true;
+                (status, false)
+            }
+        }
+    }
+
+    fn get_id(&mut self, step: usize) -> Option<(u8, bool)> {
+        const RESPONSE: [u8; 8] = [0x5A, 0x5D, 0x5C, 0x5D, 0x04, 0x00, 0x00, 0x80];
+
+        RESPONSE
+            .get(step)
+            .map(|&byte| (byte, step < RESPONSE.len() - 1))
+    }
+
+    fn commit_sector(&mut self) {
+        let offset = self.sector as usize * SECTOR_SIZE;
+        self.data[offset..offset + SECTOR_SIZE].copy_from_slice(&self.buffer);
+        self.flag &= !FLAG_FRESH;
+
+        if let Err(error) = self.flush() {
+            let path = self.path.as_ref().unwrap();
+            eprintln!("Failed to save memory card '{}': {}", path.display(), error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sends the bytes of a command, the card has to acknowledge all but the last one
+    fn command(card: &mut MemoryCard, bytes: &[u8]) -> Vec<u8> {
+        card.deselect();
+        bytes
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| {
+                let (response, ack) = card.transfer(*byte);
+                assert_eq!(ack, i < bytes.len() - 1, "byte {}", i);
+                response
+            })
+            .collect()
+    }
+
+    fn write_command(sector: u16, data: &[u8], checksum: u8) -> Vec<u8> {
+        let [msb, lsb] = sector.to_be_bytes();
+        let mut bytes = vec![0x81, 0x57, 0x00, 0x00, msb, lsb];
+        bytes.extend_from_slice(data);
+        bytes.extend_from_slice(&[checksum, 0x00, 0x00, 0x00]);
+        bytes
+    }
+
+    // The end byte of a write
+    fn write(card: &mut MemoryCard, sector: u16, data: &[u8]) -> u8 {
+        let checksum = data.iter().fold(0, |a, b| a ^ b) ^ (sector >> 8) as u8 ^ sector as u8;
+        *command(card, &write_command(sector, data, checksum))
+            .last()
+            .unwrap()
+    }
+
+    fn read(card: &mut MemoryCard, sector: u16) -> Vec<u8> {
+        let [msb, lsb] = sector.to_be_bytes();
+        let mut bytes = vec![0x81, 0x52, 0x00, 0x00, msb, lsb, 0x00, 0x00, 0x00, 0x00];
+        bytes.extend_from_slice(&[0; SECTOR_SIZE + 2]);
+        command(card, &bytes)
+    }
+
+    #[test]
+    fn written_sector_reads_back() {
+        let mut card = MemoryCard::formatted();
+        let data: Vec<u8> = (0..128u32).map(|i| (i * 3) as u8).collect();
+
+        // The card is fresh until the first write
+        assert_eq!(read(&mut card, 0x123)[1], FLAG_FRESH);
+        assert_eq!(write(&mut card, 0x123, &data), END_GOOD);
+
+        let response = read(&mut card, 0x123);
+        assert_eq!(response[1], 0);
+        assert_eq!(
+            &response[2..10],
+            &[0x5A, 0x5D, 0x00, 0x01, 0x5C, 0x5D, 0x01, 0x23]
+        );
+        assert_eq!(&response[10..138], &data[..]);
+        let checksum = data.iter().fold(0x01 ^ 0x23, |a, b| a ^ b);
+        assert_eq!(&response[138..], &[checksum, END_GOOD]);
+        assert_eq!(
+            &card.data()[0x123 * SECTOR_SIZE..][..SECTOR_SIZE],
+            &data[..]
+        );
+    }
+
+    #[test]
+    fn bad_checksum_is_rejected() {
+        let mut card = MemoryCard::formatted();
+        let before = card.data().to_vec();
+
+        let data = [0x55; SECTOR_SIZE];
+        let response = command(&mut card, &write_command(2, &data, 0x12));
+        assert_eq!(*response.last().unwrap(), END_BAD_CHECKSUM);
+        assert_eq!(card.data(), &before[..]);
+        assert_eq!(card.flag, FLAG_FRESH);
+
+        // Sectors past the end are rejected too
+        assert_eq!(write(&mut card, SECTOR_COUNT, &data), END_BAD_SECTOR);
+        let bytes = [0x81, 0x52, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00];
+        assert_eq!(&command(&mut card, &bytes)[8..], &[0xFF, 0xFF]);
+
+        // Clocking on past the end of the command doesn't read the sector
+        let responses: Vec<_> = (0..12).map(|_| card.transfer(0)).collect();
+        assert_eq!(responses, [(0xFF, false); 12]);
+        assert_eq!(card.read_sector(8, 0), None);
+        assert_eq!(card.transfer(0x81), (0xFF, false));
+        card.deselect();
+        assert_eq!(card.transfer(0x81), (0xFF, true));
+    }
+
+    #[test]
+    fn writes_are_flushed_to_the_file() {
+        let path = std::env::temp_dir().join(format!("rust-psx-{}.mcr", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        // A missing card is created formatted
+        let mut card = MemoryCard::open(&path).unwrap();
+        assert_eq!(&fs::read(&path).unwrap()[..2], b"MC");
+        assert_eq!(write(&mut card, 40, &[0xAB; SECTOR_SIZE]), END_GOOD);
+
+        let card = MemoryCard::open(&path).unwrap();
+        assert_eq!(
+            &card.data()[40 * SECTOR_SIZE..][..SECTOR_SIZE],
+            &[0xAB; SECTOR_SIZE]
+        );
+        fs::remove_file(&path).unwrap();
+
+        assert!(MemoryCard::from_data(vec![0; 1024]).is_err());
+    }
+}
@@ -1,6 +1,23 @@
-use crate::timers::Timers;
+use std::collections::HashSet;
 
-/**
+use crate::{
+    cdrom::{CdRom, CdTiming},
+    disc::Disc,
+    dma::{Dma, Port},
+    error::EmuError,
+    expansion2::Expansion2,
+    gpu::Gpu,
+    hwregs,
+    mdec::Mdec,
+    memcard::MemoryCard,
+    scheduler::Scheduler,
+    sio::{Axis, Button, PadDevice, Sio0},
+    sio1::{SerialLink, Sio1},
+    spu::{Interpolation, Spu},
+    timers::Timers,
+};
+
+/*
 *   KUSEG     KSEG0     KSEG1
  00000000h 80000000h A0000000h  2048K  Main RAM (first 64K reserved for BIOS)
  1F000000h 9F000000h BF000000h  8192K  Expansion Region 1 (ROM/RAM)
@@ -20,12 +37,19 @@ pub const EXPANSION_1_START: u32 = 0x1F000000;
 pub const EXPANSION_1_SIZE: u32 = 8 * 1024 * 1024;
 pub const EXPANSION_1_END: u32 = EXPANSION_1_START + EXPANSION_1_SIZE;
 
+// The RAM is accessed through an 8MB window, the part beyond the configured size is locked
+pub const RAM_WINDOW_END: u32 = RAM_START + 8 * 1024 * 1024;
+
+pub const SCRATCHPAD_START: u32 = 0x1F800000;
+pub const SCRATCHPAD_SIZE: u32 = 1024;
+pub const SCRATCHPAD_END: u32 = SCRATCHPAD_START + SCRATCHPAD_SIZE;
+
 pub const IO_START: u32 = 0x1F801000;
 pub const IO_SIZE: u32 = 4 * 1024;
 pub const IO_END: u32 = IO_START + IO_SIZE;
 
 pub const EXPANSION_2_START: u32 = 0x1F802000;
-pub const EXPANSION_2_SIZE: u32 = 66;
+pub const EXPANSION_2_SIZE: u32 = 8 * 1024;
 pub const EXPANSION_2_END: u32 = EXPANSION_2_START + EXPANSION_2_SIZE;
 
 pub const BIOS_START: u32 = 0x1FC00000;
@@ -40,9 +64,58 @@ const MEMORY_REGION_MASK: [u32; 8] = [
     0xFFFFFFFF, 0xFFFFFFFF, // KSEG2
 ];
 
+// The scratchpad is part of the data cache, so it is not reachable through the uncached KSEG1 region
+const KSEG1_REGION: u32 = 5;
+
+// Interrupt lines of the interrupt controller, the value is the bit in I_STAT/I_MASK
+#[derive(Clone, Copy)]
+pub enum Irq {
+    VBlank = 0,
+    Gpu = 1,
+    CdRom = 2,
+    Dma = 3,
+    Timer0 = 4,
+    Timer1 = 5,
+    Timer2 = 6,
+    Controller = 7,
+    Sio = 8,
+    Spu = 9,
+    Lightpen = 10,
+}
+
+// How accesses to unmapped addresses are handled
+#[derive(Clone, Copy, PartialEq)]
+pub enum MmuMode {
+    // Abort emulation, useful for test suites
+    Strict,
+    // Reads return open bus (all ones) and writes are dropped, each address is logged once
+    Permissive,
+}
+
+// Whether memory accesses stall the CPU
+#[derive(Clone, Copy, PartialEq)]
+pub enum CycleAccuracy {
+    // Every instruction takes one cycle, apart from waits for the GTE
+    Fast,
+    // Reads add the latency of the accessed memory
+    Accurate,
+}
+
+// Stall cycles of a read on top of the cycle the instruction takes. Writes go through the write
+// queue and don't stall the CPU.
+const RAM_READ_STALL: u32 = 4;
+const IO_READ_STALL: u32 = 2;
+// The BIOS and the expansion regions sit on the slow 8 bit bus
+const BIOS_BYTE_STALL: u32 = 7;
+const BIOS_HALF_STALL: u32 = 11;
+const BIOS_WORD_STALL: u32 = 23;
+
 pub struct MMU {
     bios: Vec<u8>,
+    // ROM plugged into the parallel port (expansion 1), empty when nothing is connected
+    expansion_rom: Vec<u8>,
     ram: Box<[u8; RAM_SIZE as usize]>,
+    scratchpad: [u8; SCRATCHPAD_SIZE as usize],
 
     // Store the 9 values used for memory control 1
     memory_control: [u32; 9],
@@ -55,24 +128,462 @@ pub struct MMU {
     interrupt_mask: u16,
 
     timers: Timers,
+    dma: Dma,
+    gpu: Gpu,
+    mdec: Mdec,
+    cdrom: CdRom,
+    spu: Spu,
+    sio0: Sio0,
+    sio1: Sio1,
+    scheduler: Scheduler,
+    expansion2: Expansion2,
+
+    mode: MmuMode,
+    logged_addresses: HashSet<u32>,
+
+    // Set when an access hits a locked region, the CPU turns this into a bus error exception
+    bus_error: bool,
+
+    cycle_accuracy: CycleAccuracy,
+    // Stall cycles of the accesses since the CPU last collected them
+    access_cycles: u32,
 }
 
 impl MMU {
     pub fn new(bios: Vec<u8>) -> Self {
         Self {
             bios,
+            expansion_rom: Vec::new(),
             ram: vec![0; RAM_SIZE as usize].try_into().unwrap(),
-            memory_control: [0; 9],
-            ram_size: 0,
+            scratchpad: [0; SCRATCHPAD_SIZE as usize],
+            memory_control: [EXPANSION_1_START, EXPANSION_2_START, 0, 0, 0, 0, 0, 0, 0],
+            // The value programmed by the BIOS, 8MB window with the 2MB RAM mirrored
+            ram_size: 0x00000B88,
             cache_control: 0,
             interrupt_status: 0,
             interrupt_mask: 0,
             timers: Timers::new(),
+            dma: Dma::new(),
+            gpu: Gpu::new(),
+            mdec: Mdec::new(),
+            cdrom: CdRom::new(),
+            spu: Spu::new(),
+            sio0: Sio0::new(),
+            sio1: Sio1::new(),
+            scheduler: Scheduler::new(),
+            expansion2: Expansion2::new(),
+            mode: MmuMode::Strict,
+            logged_addresses: HashSet::new(),
+            bus_error: false,
+            cycle_accuracy: CycleAccuracy::Accurate,
+            access_cycles: 0,
         }
     }
 
+    pub fn ram(&self) -> &[u8] {
+        &self.ram[..]
+    }
+
+    // Maps a ROM (e.g. a cheat cart or debugger) at the start of expansion 1, the BIOS runs it
+    // before the shell when it has the "Licensed by Sony" signature at 0x1F000084
+    pub fn load_expansion_rom(&mut self, data: Vec<u8>) {
+        self.expansion_rom = data;
+    }
+
+    // Bytes past the end of the ROM read as open bus
+    fn read_expansion_rom(&self, offset: u32, size: u32) -> u32 {
+        let mut word = 0;
+        for i in 0..size {
+            let value = self
+                .expansion_rom
+                .get((offset + i) as usize)
+                .copied()
+                .unwrap_or(0xFF);
+            word |= (value as u32) << (i * 8);
+        }
+
+        word
+    }
+
     pub fn step(&mut self, cycles: u32) {
-        self.timers.step(cycles);
+        if self.scheduler.advance(cycles) {
+            self.catch_up();
+        }
+    }
+
+    // Runs the devices for the cycles collected by the scheduler, this has to happen before any of
+    // their registers are accessed so the observed values are exact
+    fn catch_up(&mut self) {
+        while self.scheduler.pending() > 0 {
+            let cycles = self
+                .scheduler
+                .pending()
+                .min(self.scheduler.deadline().max(1));
+
+            // Events happen on the last cycle of the chunk, the devices see them the same way as
+            // when they are run cycle by cycle
+            if cycles > 1 {
+                self.run_devices(cycles - 1);
+            }
+            self.run_devices(1);
+
+            let deadline = self
+                .gpu
+                .cycles_until_event()
+                .min(self.timers.ticks_until_event())
+                .min(self.dma.cycles_until_event())
+                .min(self.sio0.cycles_until_event())
+                .min(self.sio1.cycles_until_event())
+                .min(self.cdrom.cycles_until_event())
+                .min(self.spu.cycles_until_event());
+            self.scheduler.consume(cycles, deadline);
+        }
+    }
+
+    fn run_devices(&mut self, cycles: u32) {
+        let video = self.gpu.step(cycles);
+        if let Some(entered) = video.hblank {
+            self.timers.notify_hblank(entered);
+        }
+        if let Some(entered) = video.vblank {
+            self.timers.notify_vblank(entered);
+            if entered {
+                self.request_interrupt(Irq::VBlank);
+            }
+        }
+
+        self.interrupt_status |= self.timers.step(cycles, video.dotclocks, video.hblanks);
+
+        if self.dma.step(cycles) {
+            self.resume_dma();
+        }
+        if self.dma.take_interrupt() {
+            self.request_interrupt(Irq::Dma);
+        }
+
+        self.sio0.step(cycles);
+        if self.sio0.take_interrupt() {
+            self.request_interrupt(Irq::Controller);
+        }
+
+        self.sio1.step(cycles);
+        if self.sio1.take_interrupt() {
+            self.request_interrupt(Irq::Sio);
+        }
+
+        self.cdrom.step(cycles);
+        if self.cdrom.take_interrupt() {
+            self.request_interrupt(Irq::CdRom);
+        }
+
+        self.spu.step(cycles, || self.cdrom.audio_sample());
+        if self.spu.take_interrupt() {
+            self.request_interrupt(Irq::Spu);
+        }
+    }
+
+    // The data moves right away, the channel stays busy for the time the transfer takes. Burst
+    // transfers stall the CPU for it, the others finish through the scheduler.
+    fn run_dma(&mut self, port: Port) {
+        let ram = &mut self.ram[..];
+
+        let words = match port {
+            Port::Otc => Some(self.dma.clear_ordering_table(ram)),
+            Port::Gpu => self.dma.transfer(port, ram, &mut self.gpu),
+            Port::CdRom => self.dma.transfer(port, ram, &mut self.cdrom),
+            Port::Spu => self.dma.transfer(port, ram, &mut self.spu),
+            Port::MdecIn | Port::MdecOut => self.dma.transfer(port, ram, &mut self.mdec),
+            _ => {
+                println!(
+                    "Ignoring DMA transfer on channel {:?} (CHCR 0x{:08x})",
+                    port,
+                    self.dma.channel(port).control
+                );
+                Some(0)
+            }
+        };
+
+        let Some(words) = words else {
+            return;
+        };
+
+        let duration = self.dma.duration(port, words);
+        if self.dma.is_continued(port) {
+            // Even fast transfers wait here, the CPU runs before the rest of a long list is sent
+            self.dma.schedule_completion(port, duration);
+            self.scheduler.consume(0, 0);
+        } else if self.cycle_accuracy == CycleAccuracy::Fast {
+            self.dma.finish(port);
+        } else if self.dma.is_burst(port) {
+            // The CPU can't run until the transfer is done
+            self.access_cycles += duration;
+            self.dma.finish(port);
+        } else {
+            self.dma.schedule_completion(port, duration);
+            self.scheduler.consume(0, 0);
+        }
+    }
+
+    // Gives the channels waiting for their device another go, e.g. MDEC out once MDEC in fed it or
+    // the SPU once SPUCNT selected DMA mode. Called after every write that can make a device ready.
+    fn resume_dma(&mut self) {
+        for index in 0..7 {
+            let port = Port::from_index(index);
+            if self.dma.is_running(port) {
+                self.run_dma(port);
+            }
+        }
+
+        if self.dma.take_interrupt() {
+            self.request_interrupt(Irq::Dma);
+        }
+    }
+
+    pub fn request_interrupt(&mut self, irq: Irq) {
+        self.interrupt_status |= 1 << (irq as u16);
+    }
+
+    pub fn pending_interrupts(&self) -> bool {
+        self.interrupt_status & self.interrupt_mask != 0
+    }
+
+    // Interrupt, timer and CDROM state for the report of a hung guest
+    pub fn device_summary(&self) -> String {
+        format!(
+            "I_STAT 0x{:04x} I_MASK 0x{:04x}\nTimers: {}\nCDROM: {}",
+            self.interrupt_status,
+            self.interrupt_mask,
+            self.timers.summary(),
+            self.cdrom.summary()
+        )
+    }
+
+    // Boot progress code the BIOS last wrote to the POST register
+    pub fn last_post_code(&self) -> u8 {
+        self.expansion2.post_code()
+    }
+
+    // Text sent through the dev unit DUART, or the TTY register of test ROMs, since the last call
+    pub fn take_duart_output(&mut self) -> String {
+        self.expansion2.take_duart_output()
+    }
+
+    // Maps the test ROM registers of PCSX-Redux into expansion 2
+    pub fn set_testing(&mut self, enabled: bool) {
+        self.expansion2.set_testing(enabled);
+    }
+
+    pub fn exit_code(&self) -> Option<u8> {
+        self.expansion2.exit_code()
+    }
+
+    // Stereo samples at 44100 Hz the SPU output since the last call
+    pub fn take_audio_samples(&mut self) -> Vec<(i16, i16)> {
+        self.catch_up();
+        self.spu.take_samples()
+    }
+
+    // The image the GPU currently outputs, see Gpu::render_frame
+    pub fn render_frame(&self) -> Vec<u32> {
+        self.gpu.render_frame()
+    }
+
+    pub fn display_size(&self) -> (usize, usize) {
+        self.gpu.display_size()
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.gpu.frame_count()
+    }
+
+    pub fn is_pal(&self) -> bool {
+        self.gpu.is_pal()
+    }
+
+    pub fn set_simd_rasterizer(&mut self, enabled: bool) {
+        self.gpu.set_simd(enabled);
+    }
+
+    pub fn set_memory_card(&mut self, port: usize, card: Option<MemoryCard>) {
+        self.sio0.set_memory_card(port, card);
+    }
+
+    pub fn memory_card(&self, port: usize) -> Option<&MemoryCard> {
+        self.sio0.memory_card(port)
+    }
+
+    pub fn set_controller(&mut self, port: usize, pad: Option<Box<dyn PadDevice>>) {
+        self.sio0.set_pad(port, pad);
+    }
+
+    pub fn insert_disc(&mut self, disc: Option<Disc>) {
+        self.cdrom.insert_disc(disc);
+    }
+
+    pub fn open_lid(&mut self) {
+        self.catch_up();
+        self.cdrom.open_lid();
+        // An aborted read answers with INT5
+        self.scheduler.consume(0, 0);
+    }
+
+    pub fn swap_disc(&mut self, disc: Disc) {
+        self.cdrom.swap_disc(disc);
+    }
+
+    pub fn close_lid(&mut self) {
+        self.catch_up();
+        self.cdrom.close_lid();
+    }
+
+    pub fn set_cd_timing(&mut self, timing: CdTiming) {
+        self.cdrom.set_timing(timing);
+    }
+
+    pub fn set_spu_interpolation(&mut self, interpolation: Interpolation) {
+        self.spu.set_interpolation(interpolation);
+    }
+
+    pub fn set_serial_link(&mut self, link: Option<Box<dyn SerialLink>>) {
+        self.sio1.set_link(link);
+    }
+
+    pub fn set_multitap(&mut self, port: usize, enabled: bool) {
+        self.sio0.set_multitap(port, enabled);
+    }
+
+    pub fn set_multitap_controller(
+        &mut self,
+        port: usize,
+        slot: usize,
+        pad: Option<Box<dyn PadDevice>>,
+    ) {
+        self.sio0.set_multitap_pad(port, slot, pad);
+    }
+
+    // Updates the controller plugged into the first port
+    pub fn set_button_state(&mut self, button: Button, pressed: bool) {
+        if let Some(pad) = self.sio0.pad_mut(0) {
+            pad.set_button(button, pressed);
+        }
+    }
+
+    pub fn set_axis(&mut self, axis: Axis, value: u8) {
+        if let Some(pad) = self.sio0.pad_mut(0) {
+            pad.set_axis(axis, value);
+        }
+    }
+
+    pub fn toggle_analog_mode(&mut self) {
+        if let Some(pad) = self.sio0.pad_mut(0) {
+            pad.toggle_analog();
+        }
+    }
+
+    // The motor intensities of the first controller when the game changed them
+    pub fn take_rumble(&mut self) -> Option<(u8, u8)> {
+        self.sio0.take_rumble()
+    }
+
+    pub fn mode(&self) -> MmuMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: MmuMode) {
+        self.mode = mode;
+    }
+
+    fn unmapped_read(&mut self, address: u32, size: u32) -> Result<u32, EmuError> {
+        if self.mode == MmuMode::Strict {
+            return Err(EmuError::UnmappedRead { address, size });
+        }
+
+        if self.logged_addresses.insert(address) {
+            println!(
+                "Ignoring read from unmapped address 0x{:08x} ({})",
+                address,
+                hwregs::describe(address, size)
+            );
+        }
+
+        Ok(size_mask(size))
+    }
+
+    fn unmapped_write(&mut self, address: u32, size: u32, value: u32) -> Result<(), EmuError> {
+        if self.mode == MmuMode::Strict {
+            return Err(EmuError::UnmappedWrite {
+                address,
+                size,
+                value,
+            });
+        }
+
+        if self.logged_addresses.insert(address) {
+            println!(
+                "Ignoring write 0x{:08x} to unmapped address 0x{:08x} ({})",
+                value,
+                address,
+                hwregs::describe(address, size)
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn take_bus_error(&mut self) -> bool {
+        std::mem::take(&mut self.bus_error)
+    }
+
+    pub fn cycle_accuracy(&self) -> CycleAccuracy {
+        self.cycle_accuracy
+    }
+
+    pub fn set_cycle_accuracy(&mut self, accuracy: CycleAccuracy) {
+        self.cycle_accuracy = accuracy;
+        self.access_cycles = 0;
+    }
+
+    pub fn take_access_cycles(&mut self) -> u32 {
+        std::mem::take(&mut self.access_cycles)
+    }
+
+    fn charge_read(&mut self, address: u32, region: u32, size: u32) {
+        if self.cycle_accuracy == CycleAccuracy::Fast {
+            return;
+        }
+
+        let slow_bus_stall = match size {
+            1 => BIOS_BYTE_STALL,
+            2 => BIOS_HALF_STALL,
+            _ => BIOS_WORD_STALL,
+        };
+
+        self.access_cycles += match address {
+            RAM_START..RAM_WINDOW_END => RAM_READ_STALL,
+            SCRATCHPAD_START..SCRATCHPAD_END if region != KSEG1_REGION => 0,
+            IO_START..IO_END => IO_READ_STALL,
+            EXPANSION_1_START..EXPANSION_1_END => slow_bus_stall,
+            EXPANSION_2_START..EXPANSION_2_END => slow_bus_stall,
+            BIOS_START..BIOS_END => slow_bus_stall,
+            _ => 0,
+        };
+    }
+
+    // Size of the accessible part of the 8MB RAM window configured by RAM_SIZE bits 9..11,
+    // accesses past it hit locked (or high-Z) memory
+    fn ram_window_size(&self) -> u32 {
+        const MB: u32 = 1024 * 1024;
+
+        match (self.ram_size >> 9) & 7 {
+            0 | 2 => MB,
+            1 | 3 => 4 * MB,
+            4 | 6 => 2 * MB,
+            _ => 8 * MB,
+        }
+    }
+
+    fn is_ram_locked(&self, address: u32) -> bool {
+        address < RAM_WINDOW_END && address >= self.ram_window_size()
     }
 
     pub fn is_instruction_cache_enabled(&self) -> bool {
@@ -83,39 +594,56 @@ impl MMU {
         (self.cache_control & 4) != 0
     }
 
-    pub fn read(&self, address: u32, size: u32) -> u32 {
-        let address = address & MEMORY_REGION_MASK[(address >> 29) as usize];
+    pub fn read(&mut self, address: u32, size: u32) -> Result<u32, EmuError> {
+        let region = address >> 29;
+        let address = address & MEMORY_REGION_MASK[region as usize];
+
+        self.charge_read(address, region, size);
+
+        if (IO_START..IO_END).contains(&address) {
+            return self.read_io(address, size);
+        }
 
-        if size > 1 {
-            // TODO: Simplify
-            match address {
-                0x1F801070 => return self.interrupt_status as u32,
-                0x1F801074 => return self.interrupt_mask as u32,
-                0x1F801080..0x1F801100 => return 0, // TODO: DMA
-                0x1F801C00..0x1F801E80 => return 0, // TODO: SPU
-                // Timers
-                0x1F801100..0x1F80112F => return self.timers.read(address - 0x1F801100),
-                _ => {}
+        if self.is_ram_locked(address) {
+            self.bus_error = true;
+            return Ok(0);
+        }
+
+        if address == 0xFFFE0130 {
+            return Ok(self.cache_control);
+        }
+
+        if (EXPANSION_2_START..EXPANSION_2_END).contains(&address) {
+            let mut word = 0;
+            for i in 0..size {
+                let value = self.expansion2.read(address - EXPANSION_2_START + i);
+                word |= (value as u32) << (i * 8);
             }
+
+            return Ok(word);
         }
 
         let mut word = 0;
 
         let offset = match address {
-            RAM_START..RAM_END => address,
+            // The 2MB RAM is mirrored across the whole 8MB window
+            RAM_START..RAM_WINDOW_END => address & (RAM_SIZE - 1),
+            SCRATCHPAD_START..SCRATCHPAD_END if region != KSEG1_REGION => {
+                address - SCRATCHPAD_START
+            }
             BIOS_START..BIOS_END => address - BIOS_START,
-            EXPANSION_1_START..EXPANSION_1_END => 0,
-            _ => panic!("Cannot read from address 0x{:2x}", address),
+            EXPANSION_1_START..EXPANSION_1_END => address - EXPANSION_1_START,
+            _ => return self.unmapped_read(address, size),
         } as usize;
 
         let source = match address {
-            RAM_START..RAM_END => &self.ram[offset..offset + 4],
-            BIOS_START..BIOS_END => &self.bios[offset..offset + 4],
+            RAM_START..RAM_WINDOW_END => &self.ram[offset..offset + size as usize],
+            SCRATCHPAD_START..SCRATCHPAD_END => &self.scratchpad[offset..offset + size as usize],
+            BIOS_START..BIOS_END => &self.bios[offset..offset + size as usize],
             EXPANSION_1_START..EXPANSION_1_END => {
-                // Emulate nothing being connected to the expansion port
-                return !0;
+                return Ok(self.read_expansion_rom(offset as u32, size));
             }
-            _ => panic!("Cannot read from address"),
+            _ => unreachable!(),
         };
 
         for i in 0..size {
@@ -123,52 +651,864 @@ impl MMU {
             word |= (value as u32) << (i * 8)
         }
 
-        word
+        Ok(word)
+    }
+
+    // The I/O registers honor byte lanes, so sub-word reads return the addressed part of the register
+    fn read_io(&mut self, address: u32, size: u32) -> Result<u32, EmuError> {
+        let aligned_address = address & !3;
+        let shift = (address & 3) * 8;
+
+        let word = match aligned_address {
+            0x1F801000..=0x1F801020 => {
+                self.memory_control[((aligned_address - IO_START) >> 2) as usize]
+            }
+            0x1F801060 => self.ram_size,
+            0x1F801070 => self.interrupt_status as u32,
+            0x1F801074 => self.interrupt_mask as u32,
+            0x1F801080..0x1F801100 => {
+                self.catch_up();
+                self.dma.read(aligned_address - 0x1F801080)
+            }
+            0x1F801040..0x1F801050 => {
+                self.catch_up();
+                self.sio0.read(aligned_address - 0x1F801040)
+            }
+            0x1F801050..0x1F801060 => {
+                self.catch_up();
+                let value = self.sio1.read(aligned_address - 0x1F801050);
+                if self.sio1.take_interrupt() {
+                    self.request_interrupt(Irq::Sio);
+                }
+                value
+            }
+            // Reads pop the FIFOs, so only the addressed registers are read
+            0x1F801800 => {
+                self.catch_up();
+                let mut word = 0;
+                for i in 0..size {
+                    let offset = (address & 3) + i;
+                    word |= (self.cdrom.read(offset) as u32) << (offset * 8);
+                }
+                word
+            }
+            0x1F801810 => {
+                let value = self.gpu.read();
+                // The end of a VRAM to CPU copy makes the GPU ready for commands again
+                self.resume_dma();
+                value
+            }
+            0x1F801814 => {
+                self.catch_up();
+                self.gpu.status()
+            }
+            0x1F801820 => {
+                let value = self.mdec.read();
+                // The output may have room for the next macroblock again
+                self.resume_dma();
+                value
+            }
+            0x1F801824 => self.mdec.status(),
+            0x1F801C00..0x1F801E80 => {
+                // The voices update ENVX and the end flags
+                self.catch_up();
+                let offset = aligned_address - 0x1F801C00;
+                self.spu.read(offset) as u32 | ((self.spu.read(offset + 2) as u32) << 16)
+            }
+            // Timers
+            0x1F801100..0x1F80112F => {
+                self.catch_up();
+                self.timers.read(aligned_address - 0x1F801100)
+            }
+            _ => return self.unmapped_read(address, size),
+        };
+
+        Ok((word >> shift) & size_mask(size))
+    }
+
+    // Cycles until the next device event, nothing the CPU can see changes before it
+    pub fn cycles_until_event(&self) -> u32 {
+        self.scheduler
+            .deadline()
+            .saturating_sub(self.scheduler.pending())
     }
 
-    pub fn write(&mut self, address: u32, size: u32, value: u32) {
-        let address = address & MEMORY_REGION_MASK[(address >> 29) as usize];
+    // Changes with every device event
+    pub fn event_count(&self) -> u64 {
+        self.scheduler.events()
+    }
+
+    // Whether reading the address has no effect besides the stall, as an idle loop's reads must
+    pub fn is_idle_read(&self, address: u32) -> bool {
+        let region = address >> 29;
+        let address = address & MEMORY_REGION_MASK[region as usize];
 
         match address {
-            RAM_START..RAM_END => {
+            // The status registers of the CDROM, SIO0, the GPU and MDEC, the DMA, timer counters
+            // and targets and the SPU. Reading the timer modes clears their flags.
+            0x1F801800 | 0x1F801044 | 0x1F801814 | 0x1F801824 => true,
+            0x1F801080..0x1F801100 | 0x1F801C00..0x1F801E80 => true,
+            0x1F801100..0x1F80112F => (address & 0xF) >> 2 != 1,
+            // Memory and the registers that only store what was written
+            _ => self.peek(address, 1).is_some(),
+        }
+    }
+
+    // Side-effect free read for debugging tools, returns None for live I/O registers and RAM
+    // outside of the RAM_SIZE window
+    pub fn peek(&self, address: u32, size: u32) -> Option<u32> {
+        let region = address >> 29;
+        let address = address & MEMORY_REGION_MASK[region as usize];
+
+        if self.is_ram_locked(address) {
+            return None;
+        }
+
+        let source = match address {
+            RAM_START..RAM_WINDOW_END => &self.ram[(address & (RAM_SIZE - 1)) as usize..],
+            SCRATCHPAD_START..SCRATCHPAD_END if region != KSEG1_REGION => {
+                &self.scratchpad[(address - SCRATCHPAD_START) as usize..]
+            }
+            BIOS_START..BIOS_END => &self.bios[(address - BIOS_START) as usize..],
+            EXPANSION_1_START..EXPANSION_1_END => {
+                return Some(self.read_expansion_rom(address - EXPANSION_1_START, size))
+            }
+            // Plain storage registers can be served from their last written value
+            0x1F801000..=0x1F801020 => {
+                return Some(self.memory_control[((address - IO_START) >> 2) as usize])
+            }
+            0x1F801060 => return Some(self.ram_size),
+            0x1F801070 => return Some(self.interrupt_status as u32),
+            0x1F801074 => return Some(self.interrupt_mask as u32),
+            0xFFFE0130 => return Some(self.cache_control),
+            _ => return None,
+        };
+
+        let mut word = 0;
+        for i in 0..size.min(source.len() as u32) {
+            word |= (source[i as usize] as u32) << (i * 8);
+        }
+
+        Some(word)
+    }
+
+    // Side-effect free write for debugging tools, only memory can be poked
+    pub fn poke(&mut self, address: u32, size: u32, value: u32) -> bool {
+        let region = address >> 29;
+        let address = address & MEMORY_REGION_MASK[region as usize];
+
+        if self.is_ram_locked(address) {
+            return false;
+        }
+
+        let destination = match address {
+            RAM_START..RAM_WINDOW_END => &mut self.ram[(address & (RAM_SIZE - 1)) as usize..],
+            SCRATCHPAD_START..SCRATCHPAD_END if region != KSEG1_REGION => {
+                &mut self.scratchpad[(address - SCRATCHPAD_START) as usize..]
+            }
+            BIOS_START..BIOS_END => &mut self.bios[(address - BIOS_START) as usize..],
+            _ => return false,
+        };
+
+        if destination.len() < size as usize {
+            return false;
+        }
+
+        for i in 0..size {
+            destination[i as usize] = (value >> (i * 8)) as u8;
+        }
+
+        true
+    }
+
+    // The memory backing an address up to the end of its region (or RAM mirror), None for I/O
+    fn memory_slice(&self, address: u32) -> Option<&[u8]> {
+        let region = address >> 29;
+        let address = address & MEMORY_REGION_MASK[region as usize];
+
+        if self.is_ram_locked(address) {
+            return None;
+        }
+
+        match address {
+            RAM_START..RAM_WINDOW_END => Some(&self.ram[(address & (RAM_SIZE - 1)) as usize..]),
+            SCRATCHPAD_START..SCRATCHPAD_END if region != KSEG1_REGION => {
+                Some(&self.scratchpad[(address - SCRATCHPAD_START) as usize..])
+            }
+            BIOS_START..BIOS_END => Some(&self.bios[(address - BIOS_START) as usize..]),
+            EXPANSION_1_START..EXPANSION_1_END
+                if ((address - EXPANSION_1_START) as usize) < self.expansion_rom.len() =>
+            {
+                Some(&self.expansion_rom[(address - EXPANSION_1_START) as usize..])
+            }
+            _ => None,
+        }
+    }
+
+    // Same as memory_slice but only for the writable memories
+    fn memory_slice_mut(&mut self, address: u32) -> Option<&mut [u8]> {
+        let region = address >> 29;
+        let address = address & MEMORY_REGION_MASK[region as usize];
+
+        if self.is_ram_locked(address) {
+            return None;
+        }
+
+        match address {
+            RAM_START..RAM_WINDOW_END => Some(&mut self.ram[(address & (RAM_SIZE - 1)) as usize..]),
+            SCRATCHPAD_START..SCRATCHPAD_END if region != KSEG1_REGION => {
+                Some(&mut self.scratchpad[(address - SCRATCHPAD_START) as usize..])
+            }
+            _ => None,
+        }
+    }
+
+    // Block read for DMA, memory is copied a region at a time and I/O is read a word at a time
+    // through read, with its side effects
+    pub fn read_bytes(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), EmuError> {
+        let mut done = 0;
+
+        while done < buffer.len() {
+            let current = address.wrapping_add(done as u32);
+
+            match self.memory_slice(current) {
+                Some(source) => {
+                    let length = source.len().min(buffer.len() - done);
+                    buffer[done..done + length].copy_from_slice(&source[..length]);
+                    done += length;
+                }
+                None => {
+                    let word = self.read(current & !3, 4)?;
+                    done += copy_word(word, current, &mut buffer[done..]);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // The same for tooling, side-effect free like peek. I/O registers that can't be peeked read as
+    // open bus.
+    pub fn peek_bytes(&self, address: u32, buffer: &mut [u8]) {
+        let mut done = 0;
+
+        while done < buffer.len() {
+            let current = address.wrapping_add(done as u32);
+
+            match self.memory_slice(current) {
+                Some(source) => {
+                    let length = source.len().min(buffer.len() - done);
+                    buffer[done..done + length].copy_from_slice(&source[..length]);
+                    done += length;
+                }
+                None => {
+                    let word = self.peek(current & !3, 4).unwrap_or(!0);
+                    done += copy_word(word, current, &mut buffer[done..]);
+                }
+            }
+        }
+    }
+
+    pub fn read_u32_slice(&mut self, address: u32, buffer: &mut [u32]) -> Result<(), EmuError> {
+        let mut bytes = vec![0; buffer.len() * 4];
+        self.read_bytes(address, &mut bytes)?;
+
+        for (word, bytes) in buffer.iter_mut().zip(bytes.chunks_exact(4)) {
+            *word = u32::from_le_bytes(bytes.try_into().unwrap());
+        }
+
+        Ok(())
+    }
+
+    // Block write, memory is copied a region at a time and anything else goes through write
+    pub fn write_bytes(&mut self, address: u32, data: &[u8]) -> Result<(), EmuError> {
+        let mut done = 0;
+
+        while done < data.len() {
+            let current = address.wrapping_add(done as u32);
+
+            match self.memory_slice_mut(current) {
+                Some(destination) => {
+                    let length = destination.len().min(data.len() - done);
+                    destination[..length].copy_from_slice(&data[done..done + length]);
+                    done += length;
+                }
+                None => {
+                    self.write(current, 1, data[done] as u32)?;
+                    done += 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn write(&mut self, address: u32, size: u32, value: u32) -> Result<(), EmuError> {
+        let region = address >> 29;
+        let address = address & MEMORY_REGION_MASK[region as usize];
+
+        if self.is_ram_locked(address) {
+            self.bus_error = true;
+            return Ok(());
+        }
+
+        match address {
+            RAM_START..RAM_WINDOW_END => {
+                let offset = address & (RAM_SIZE - 1);
                 for i in 0..size {
-                    self.ram[(address + i) as usize] = (value >> (i * 8)) as u8;
+                    self.ram[(offset + i) as usize] = (value >> (i * 8)) as u8;
+                }
+            }
+            SCRATCHPAD_START..SCRATCHPAD_END if region != KSEG1_REGION => {
+                let offset = address - SCRATCHPAD_START;
+                for i in 0..size {
+                    self.scratchpad[(offset + i) as usize] = (value >> (i * 8)) as u8;
                 }
             }
             EXPANSION_1_START..EXPANSION_1_END => {
-                panic!("Cannot write to expansion");
+                // The expansion ROM is read only
             }
             // IO
-            0x1F80100..=0x1F801020 => {
+            0x1F801000..=0x1F801020 => {
                 let index = (address - IO_START) >> 2;
-                self.memory_control[index as usize] = value;
+
+                // The expansion regions can't be relocated, keep their fixed base addresses
+                let fixed_base = match index {
+                    0 => Some(EXPANSION_1_START),
+                    1 => Some(EXPANSION_2_START),
+                    _ => None,
+                };
+
+                match fixed_base {
+                    Some(base) if base != value => println!(
+                        "Ignoring relocation of {} to 0x{:08x}",
+                        hwregs::describe(address, size),
+                        value
+                    ),
+                    _ => self.memory_control[index as usize] = value,
+                }
             }
             0x1F801060 => {
                 self.ram_size = value;
             }
             0x1F801070 => {
-                self.interrupt_status = value as u16;
+                // Writing to I_STAT acknowledges interrupts, bits written as 0 are cleared
+                self.interrupt_status &= value as u16;
             }
             0x1F801074 => {
                 self.interrupt_mask = value as u16;
             }
             0x1F801080..0x1F801100 => {
-                println!("Ignoring DMA write");
+                self.catch_up();
+                if let Some(port) = self.dma.write(address - 0x1F801080, value) {
+                    self.run_dma(port);
+                    self.resume_dma();
+                }
+
+                if self.dma.take_interrupt() {
+                    self.request_interrupt(Irq::Dma);
+                }
+                if self.gpu.take_interrupt() {
+                    self.request_interrupt(Irq::Gpu);
+                }
+                if self.spu.take_interrupt() {
+                    self.request_interrupt(Irq::Spu);
+                }
             }
             // Timers
             0x1F801100..0x1F80112F => {
+                self.catch_up();
                 self.timers.write(address - 0x1F801100, value);
+                // The next timer event moved
+                self.scheduler.consume(0, 0);
+            }
+            0x1F801040..0x1F801050 => {
+                self.catch_up();
+                let offset = address - 0x1F801040;
+                if size == 4 {
+                    self.sio0.write(offset, value as u16);
+                    self.sio0.write(offset + 2, (value >> 16) as u16);
+                } else {
+                    self.sio0.write(offset & !1, value as u16);
+                }
+                // A byte transfer may have started
+                self.scheduler.consume(0, 0);
+            }
+            0x1F801050..0x1F801060 => {
+                self.catch_up();
+                let offset = address - 0x1F801050;
+                if size == 4 {
+                    self.sio1.write(offset, value as u16);
+                    self.sio1.write(offset + 2, (value >> 16) as u16);
+                } else {
+                    self.sio1.write(offset & !1, value as u16);
+                }
+                if self.sio1.take_interrupt() {
+                    self.request_interrupt(Irq::Sio);
+                }
+                self.scheduler.consume(0, 0);
+            }
+            0x1F801800..0x1F801804 => {
+                self.catch_up();
+                for i in 0..size {
+                    let offset = address - 0x1F801800 + i;
+                    self.cdrom.write(offset, (value >> (i * 8)) as u8);
+                }
+                if self.cdrom.take_interrupt() {
+                    self.request_interrupt(Irq::CdRom);
+                }
+                // A sector may have been loaded into the data FIFO
+                self.resume_dma();
+                // A command may have been started or an interrupt acknowledged
+                self.scheduler.consume(0, 0);
+            }
+            0x1F801810 => {
+                self.gpu.gp0(value);
+                if self.gpu.take_interrupt() {
+                    self.request_interrupt(Irq::Gpu);
+                }
+                // The GPU may be ready for the next command words
+                self.resume_dma();
+            }
+            0x1F801814 => {
+                // The display mode changes the video timing
+                self.catch_up();
+                self.gpu.gp1(value);
+                // The DMA direction may have been set
+                self.resume_dma();
+                self.scheduler.consume(0, 0);
+            }
+            0x1F801820 => {
+                self.mdec.write(value);
+                // A command may be waiting for its parameters, or pixels for MDEC out
+                self.resume_dma();
+            }
+            0x1F801824 => {
+                self.mdec.write_control(value);
+                // The data requests may have been enabled
+                self.resume_dma();
             }
             0x1F801C00..0x1F801E80 => {
-                // TODO: Sound Processing Unit registers
+                // Key on and off take effect from the current sample
+                self.catch_up();
+                let offset = address - 0x1F801C00;
+                if size == 4 {
+                    self.spu.write(offset, value as u16);
+                    self.spu.write(offset + 2, (value >> 16) as u16);
+                } else {
+                    self.spu.write(offset & !1, value as u16);
+                }
+                // SPUCNT may have selected DMA mode
+                self.resume_dma();
+                // Key on and transfers can hit the IRQ address, SPUCNT may have enabled the IRQ
+                if self.spu.take_interrupt() {
+                    self.request_interrupt(Irq::Spu);
+                }
+                self.scheduler.consume(0, 0);
             }
             EXPANSION_2_START..EXPANSION_2_END => {
-                // TODO: DUART
+                for i in 0..size {
+                    let offset = address - EXPANSION_2_START + i;
+                    self.expansion2.write(offset, (value >> (i * 8)) as u8);
+                }
             }
             0xFFFE0130 => {
                 self.cache_control = value;
             }
-            _ => panic!("Cannot write to address 0x{:2x}", address),
+            _ => return self.unmapped_write(address, size, value),
+        }
+
+        Ok(())
+    }
+}
+
+fn size_mask(size: u32) -> u32 {
+    if size >= 4 {
+        !0
+    } else {
+        (1 << (size * 8)) - 1
+    }
+}
+
+// Copies the bytes of the word from the address on, returns how many fit in the buffer
+fn copy_word(word: u32, address: u32, buffer: &mut [u8]) -> usize {
+    let offset = (address & 3) as usize;
+    let length = (4 - offset).min(buffer.len());
+    buffer[..length].copy_from_slice(&word.to_le_bytes()[offset..offset + length]);
+    length
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DPCR: u32 = 0x1F8010F0;
+    const SPUCNT: u32 = 0x1F801DAA;
+    // GP0 when written
+    const GPUREAD: u32 = 0x1F801810;
+    const GP1: u32 = 0x1F801814;
+
+    fn mmu() -> MMU {
+        MMU::new(vec![0; 512 * 1024])
+    }
+
+    // MADR, BCR and CHCR of a channel
+    fn start_dma(mmu: &mut MMU, port: Port, base: u32, block_control: u32, control: u32) {
+        let channel = 0x1F801080 + port as u32 * 0x10;
+        mmu.write(DPCR, 4, 0x0FFFFFFF).unwrap();
+        mmu.write(channel, 4, base).unwrap();
+        mmu.write(channel + 4, 4, block_control).unwrap();
+        mmu.write(channel + 8, 4, control).unwrap();
+    }
+
+    fn is_busy(mmu: &mut MMU, port: Port) -> bool {
+        let control = mmu.read(0x1F801088 + port as u32 * 0x10, 4).unwrap();
+        control & (1 << 24) != 0
+    }
+
+    fn run(mmu: &mut MMU, cycles: u32) {
+        for _ in 0..cycles {
+            mmu.step(1);
+        }
+    }
+
+    #[test]
+    fn spu_dma_waits_for_spucnt_dma_mode() {
+        let mut mmu = mmu();
+        for i in 0..16 {
+            mmu.write(0x1000 + i * 4, 4, 0x01010101 * i).unwrap();
+        }
+
+        // 16 words from RAM in one block while the SPU transfer mode is still stopped
+        start_dma(&mut mmu, Port::Spu, 0x1000, 0x0001_0010, 0x01000201);
+        run(&mut mmu, 100);
+        assert!(is_busy(&mut mmu, Port::Spu));
+
+        mmu.write(SPUCNT, 2, 0x0020).unwrap();
+        run(&mut mmu, 100);
+        assert!(!is_busy(&mut mmu, Port::Spu));
+
+        // Read the words back into RAM somewhere else
+        mmu.write(SPUCNT, 2, 0x0000).unwrap();
+        mmu.write(0x1F801DA6, 2, 0).unwrap();
+        mmu.write(SPUCNT, 2, 0x0030).unwrap();
+        start_dma(&mut mmu, Port::Spu, 0x2000, 0x0001_0010, 0x01000200);
+        run(&mut mmu, 100);
+        assert!(!is_busy(&mut mmu, Port::Spu));
+        for i in 0..16 {
+            assert_eq!(mmu.read(0x2000 + i * 4, 4).unwrap(), 0x01010101 * i);
+        }
+    }
+
+    #[test]
+    fn gpu_dma_waits_for_the_dma_direction() {
+        let mut mmu = mmu();
+
+        // NOP command words, before GP1(04h) the GPU doesn't request data
+        start_dma(&mut mmu, Port::Gpu, 0x1000, 0x0001_0010, 0x01000201);
+        run(&mut mmu, 100);
+        assert!(is_busy(&mut mmu, Port::Gpu));
+
+        mmu.write(GP1, 4, 0x04000002).unwrap();
+        run(&mut mmu, 100);
+        assert!(!is_busy(&mut mmu, Port::Gpu));
+    }
+
+    #[test]
+    fn mdec_decodes_a_macroblock_through_dma() {
+        let mut mmu = mmu();
+        // All quantization values 2, the scale table with only the DC row set
+        mmu.write(0x1F801820, 4, 0x4000_0000).unwrap();
+        for _ in 0..16 {
+            mmu.write(0x1F801820, 4, 0x02020202).unwrap();
+        }
+        mmu.write(0x1F801820, 4, 0x6000_0000).unwrap();
+        for i in 0..32 {
+            let value = if i < 4 { 0x5A825A82 } else { 0 };
+            mmu.write(0x1F801820, 4, value).unwrap();
+        }
+
+        // A mono 8 bit decode of one block with a DC of 200, then padding
+        let words = [
+            0x2000_0000 | 0x0800_0000 | 2,
+            0xFE00_0000 | (1 << 10) | 200,
+            0xFE00FE00,
+        ];
+        for (i, word) in words.iter().enumerate() {
+            mmu.write(0x1000 + i as u32 * 4, 4, *word).unwrap();
+        }
+        mmu.write(0x1F801824, 4, 0x6000_0000).unwrap();
+
+        start_dma(&mut mmu, Port::MdecOut, 0x2000, 0x0001_0010, 0x01000200);
+        start_dma(&mut mmu, Port::MdecIn, 0x1000, 0x0003_0001, 0x01000201);
+        run(&mut mmu, 100);
+
+        assert!(!is_busy(&mut mmu, Port::MdecIn));
+        assert!(!is_busy(&mut mmu, Port::MdecOut));
+        for i in 0..16 {
+            assert_eq!(mmu.read(0x2000 + i * 4, 4).unwrap(), 0xB2B2B2B2);
+        }
+        assert_eq!(mmu.read(0x1F801824, 4).unwrap() & (1 << 31), 1 << 31);
+    }
+
+    #[test]
+    fn circular_gpu_linked_list_raises_the_dma_interrupt() {
+        let mut mmu = mmu();
+        // Two packets pointing to each other
+        mmu.write(0x1000, 4, 0x0000_2000).unwrap();
+        mmu.write(0x2000, 4, 0x0000_1000).unwrap();
+
+        start_dma(&mut mmu, Port::Gpu, 0x1000, 0, 0x01000401);
+        run(&mut mmu, 100);
+        assert!(!is_busy(&mut mmu, Port::Gpu));
+        assert_ne!(mmu.read(0x1F8010F4, 4).unwrap() & (1 << 15), 0);
+        assert_ne!(mmu.read(0x1F801070, 4).unwrap() & (1 << Irq::Dma as u32), 0);
+    }
+
+    #[test]
+    fn long_gpu_linked_lists_let_the_cpu_run() {
+        let mut mmu = mmu();
+        // 0x2000 packets of 3 NOPs
+        for i in 0..0x2000 {
+            let address = 0x10000 + i * 16;
+            let next = if i == 0x1FFF { 0xFFFFFF } else { address + 16 };
+            mmu.write(address, 4, 0x0300_0000 | next).unwrap();
+        }
+
+        start_dma(&mut mmu, Port::Gpu, 0x10000, 0, 0x01000401);
+        assert!(is_busy(&mut mmu, Port::Gpu));
+        run(&mut mmu, 0x8000 + 100);
+        assert!(!is_busy(&mut mmu, Port::Gpu));
+        assert_eq!(mmu.read(0x1F8010A0, 4).unwrap(), 0xFFFFFF);
+    }
+
+    #[test]
+    fn peek_leaves_the_devices_alone() {
+        let mut mmu = mmu();
+
+        // Timer 0 reaches its target, the mode register read clears the reached bit
+        mmu.write(0x1F801108, 4, 10).unwrap();
+        mmu.write(0x1F801104, 4, 0x0008).unwrap();
+        run(&mut mmu, 20);
+        assert_eq!(mmu.peek(0x1F801104, 4), None);
+        assert_ne!(mmu.read(0x1F801104, 4).unwrap() & (1 << 11), 0);
+        assert_eq!(mmu.read(0x1F801104, 4).unwrap() & (1 << 11), 0);
+
+        // GetStat, reading the response pops it from the FIFO
+        mmu.write(0x1F801800, 1, 0).unwrap();
+        mmu.write(0x1F801801, 1, 0x01).unwrap();
+        run(&mut mmu, 0x10000);
+        assert_eq!(mmu.peek(0x1F801801, 1), None);
+        assert_eq!(mmu.peek(0x1F801802, 1), None);
+        assert_ne!(mmu.read(0x1F801800, 1).unwrap() & (1 << 5), 0);
+        mmu.read(0x1F801801, 1).unwrap();
+        assert_eq!(mmu.read(0x1F801800, 1).unwrap() & (1 << 5), 0);
+
+        // Memory is served as is
+        mmu.write(0x100, 4, 0x12345678).unwrap();
+        assert_eq!(mmu.peek(0x80000100, 4), Some(0x12345678));
+        assert_eq!(mmu.peek(0x1F801070, 4), Some(0));
+    }
+
+    #[test]
+    fn i_stat_writes_acknowledge_the_zero_bits() {
+        let mut mmu = mmu();
+        mmu.request_interrupt(Irq::VBlank);
+        mmu.request_interrupt(Irq::CdRom);
+        mmu.request_interrupt(Irq::Timer1);
+        assert_eq!(mmu.read(0x1F801070, 4).unwrap(), 0x25);
+
+        // Acknowledging the CDROM writes its complement, the other bits stay pending
+        mmu.write(0x1F801070, 4, !(1 << Irq::CdRom as u32)).unwrap();
+        assert_eq!(mmu.read(0x1F801070, 4).unwrap(), 0x21);
+
+        // Bits written as 1 don't raise interrupts
+        mmu.write(0x1F801070, 4, 0xFFFFFFFF).unwrap();
+        assert_eq!(mmu.read(0x1F801070, 4).unwrap(), 0x21);
+        mmu.write(0x1F801070, 4, 0).unwrap();
+        assert_eq!(mmu.read(0x1F801070, 4).unwrap(), 0);
+    }
+
+    #[test]
+    fn scratchpad_is_only_reachable_through_kuseg_and_kseg0() {
+        let mut mmu = mmu();
+        mmu.write(0x1F800000, 4, 0x44332211).unwrap();
+        mmu.write(0x9F800004, 2, 0x6655).unwrap();
+        mmu.write(0x1F8003FF, 1, 0x77).unwrap();
+
+        assert_eq!(mmu.read(0x9F800000, 4).unwrap(), 0x44332211);
+        assert_eq!(mmu.read(0x1F800002, 2).unwrap(), 0x4433);
+        assert_eq!(mmu.read(0x1F800001, 1).unwrap(), 0x22);
+        assert_eq!(mmu.read(0x1F800004, 4).unwrap(), 0x6655);
+        assert_eq!(mmu.read(0x9F8003FC, 4).unwrap(), 0x77000000);
+
+        // KSEG1 bypasses the data cache the scratchpad lives in
+        assert!(matches!(
+            mmu.read(0xBF800000, 4),
+            Err(EmuError::UnmappedRead { size: 4, .. })
+        ));
+        assert!(mmu.write(0xBF800000, 4, 0).is_err());
+        assert_eq!(mmu.read(0x1F800000, 4).unwrap(), 0x44332211);
+    }
+
+    #[test]
+    fn memory_control_registers_read_back() {
+        let mut mmu = mmu();
+        // The BIOS values, the expansion bases are the fixed addresses
+        let values = [
+            0x1F000000, 0x1F802000, 0x0013243F, 0x00003022, 0x0013243F, 0x200931E1, 0x00020843,
+            0x00070777, 0x00031125,
+        ];
+        for (i, value) in values.iter().enumerate() {
+            mmu.write(0x1F801000 + i as u32 * 4, 4, *value).unwrap();
+        }
+        for (i, value) in values.iter().enumerate() {
+            assert_eq!(mmu.read(0x1F801000 + i as u32 * 4, 4).unwrap(), *value);
+        }
+
+        // Relocating expansion 1 and 2 is ignored
+        mmu.write(0x1F801000, 4, 0x1F100000).unwrap();
+        mmu.write(0x1F801004, 4, 0x1F900000).unwrap();
+        assert_eq!(mmu.read(0x1F801000, 4).unwrap(), 0x1F000000);
+        assert_eq!(mmu.read(0x1F801004, 4).unwrap(), 0x1F802000);
+    }
+
+    #[test]
+    fn ram_is_mirrored_across_the_8mb_window() {
+        let mut mmu = mmu();
+        mmu.write(0x00000100, 4, 0x12345678).unwrap();
+
+        for address in [0x00200100, 0x00400100, 0x00600100, 0x80600100, 0xA0600100] {
+            assert_eq!(mmu.read(address, 4).unwrap(), 0x12345678);
+        }
+
+        // Writes through a mirror land in the same RAM
+        mmu.write(0xA0600104, 2, 0xBEEF).unwrap();
+        assert_eq!(mmu.read(0x00000104, 4).unwrap(), 0xBEEF);
+    }
+
+    #[test]
+    fn bulk_accesses_match_single_accesses() {
+        let mut mmu = mmu();
+        let data: Vec<u8> = (0..0x10000).map(|i| (i * 7) as u8).collect();
+        mmu.write_bytes(0x80010000, &data).unwrap();
+        for i in (0..0x10000).step_by(0x1001) {
+            assert_eq!(mmu.read(0x10000 + i, 1).unwrap(), data[i as usize] as u32);
+        }
+
+        let mut buffer = vec![0; 0x10000];
+        mmu.read_bytes(0xA0010000, &mut buffer).unwrap();
+        assert_eq!(buffer, data);
+        mmu.peek_bytes(0x80010000, &mut buffer);
+        assert_eq!(buffer, data);
+
+        // The copy continues into the next mirror at the end of RAM
+        mmu.write_bytes(0x001FFFFE, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(mmu.read(0x001FFFFC, 4).unwrap(), 0x02010000);
+        assert_eq!(mmu.read(0x00000000, 2).unwrap(), 0x0403);
+
+        let mut words = [0; 2];
+        mmu.read_u32_slice(0x80010000, &mut words).unwrap();
+        assert_eq!(
+            words,
+            [mmu.read(0x10000, 4).unwrap(), mmu.read(0x10004, 4).unwrap()]
+        );
+
+        // I/O goes through the registers a word at a time
+        mmu.write_bytes(0x1F801074, &[0x85]).unwrap();
+        assert_eq!(mmu.read(0x1F801074, 4).unwrap(), 0x85);
+        let mut buffer = [0; 4];
+        mmu.read_bytes(0x1F801072, &mut buffer).unwrap();
+        assert_eq!(buffer, [0, 0, 0x85, 0]);
+        // Live registers are read, peeking them is open bus
+        mmu.step(300);
+        let mut peeked = [0; 4];
+        mmu.peek_bytes(0x1F801100, &mut peeked);
+        assert_eq!(peeked, [0xFF; 4]);
+        mmu.read_bytes(0x1F801100, &mut buffer).unwrap();
+        assert_eq!(buffer, mmu.read(0x1F801100, 4).unwrap().to_le_bytes());
+        assert_ne!(buffer, [0; 4]);
+    }
+
+    #[test]
+    fn scheduled_timer_interrupts_match_per_cycle_stepping() {
+        // Timer 2 with a target of 100 in repeat mode, stepped cycle by cycle on its own
+        let mut timers = Timers::new();
+        timers.write(0x28, 100);
+        timers.write(0x24, 0x58);
+        let expected: Vec<u32> = (1..=1000).filter(|_| timers.step(1, 0, 0) != 0).collect();
+        assert_eq!(expected.len(), 9);
+
+        let mut mmu = mmu();
+        mmu.write(0x1F801128, 4, 100).unwrap();
+        mmu.write(0x1F801124, 4, 0x58).unwrap();
+        let mut interrupts = Vec::new();
+        for cycle in 1..=1000 {
+            mmu.step(1);
+            if mmu.read(0x1F801070, 4).unwrap() & (1 << Irq::Timer2 as u32) != 0 {
+                interrupts.push(cycle);
+                mmu.write(0x1F801070, 4, 0).unwrap();
+            }
+
+            // The counter is exact in between events
+            if cycle == 550 {
+                assert_eq!(mmu.read(0x1F801120, 4).unwrap(), 550 % 101);
+            }
+        }
+        assert_eq!(interrupts, expected);
+    }
+
+    #[test]
+    fn otc_builds_an_empty_ordering_table() {
+        let mut mmu = mmu();
+        mmu.write(0x1F8010F4, 4, 0x00C00000).unwrap();
+        start_dma(&mut mmu, Port::Otc, 0x103C, 16, 0x11000000);
+        run(&mut mmu, 100);
+
+        // Each entry links to the one before it, the first ends the list
+        assert_eq!(mmu.read(0x1000, 4).unwrap(), 0x00FFFFFF);
+        for i in 1..16 {
+            let address = 0x1000 + i * 4;
+            assert_eq!(mmu.read(address, 4).unwrap(), address - 4);
+        }
+        assert_eq!(mmu.read(0x1040, 4).unwrap(), 0);
+        assert_eq!(mmu.read(0xFFC, 4).unwrap(), 0);
+
+        assert!(!is_busy(&mut mmu, Port::Otc));
+        assert_eq!(mmu.read(0x1F8010E8, 4).unwrap() & (1 << 28), 0);
+        assert_eq!(mmu.read(0x1F8010F4, 4).unwrap() >> 24, 0xC0);
+        assert_ne!(mmu.read(0x1F801070, 4).unwrap() & (1 << Irq::Dma as u32), 0);
+    }
+
+    #[test]
+    fn gpu_info_is_read_through_gpuread() {
+        let mut mmu = mmu();
+        // Drawing offset (-3,100), drawing area (8,16)-(300,200) and texture window
+        let offset = ((100 << 11) | (-3i32 as u32 & 0x7FF)) & 0x3FFFFF;
+        mmu.write(GPUREAD, 4, 0xE5000000 | offset).unwrap();
+        mmu.write(GPUREAD, 4, 0xE3000000 | (16 << 10) | 8).unwrap();
+        mmu.write(GPUREAD, 4, 0xE4000000 | (200 << 10) | 300)
+            .unwrap();
+        mmu.write(GPUREAD, 4, 0xE2012345).unwrap();
+
+        mmu.write(GP1, 4, 0x10000005).unwrap();
+        assert_eq!(mmu.read(GPUREAD, 4).unwrap(), offset);
+        // Nothing pending, the previous value is returned again
+        assert_eq!(mmu.read(GPUREAD, 4).unwrap(), offset);
+
+        for (index, expected) in [
+            (0x02, 0x12345),
+            (0x03, (16 << 10) | 8),
+            (0x04, (200 << 10) | 300),
+            (0x07, 2),
+            (0x05, offset),
+        ] {
+            mmu.write(GP1, 4, 0x10000000 | index).unwrap();
+            assert_eq!(mmu.read(GPUREAD, 4).unwrap(), expected, "0x{:02x}", index);
         }
+
+        // Other sub-commands leave the latch alone
+        mmu.write(GP1, 4, 0x10000000).unwrap();
+        assert_eq!(mmu.read(GPUREAD, 4).unwrap(), offset);
     }
 }
@@ -0,0 +1,472 @@
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    archive::crc32,
+    exe::{Exe, ExeError},
+    EmuError, Emulator,
+};
+
+// PSF1 files hold a zlib compressed PS-X EXE and tags. A minipsf only has the song specific
+// code, the driver and samples are in the _lib file it names.
+const MAGIC: &[u8] = b"PSF";
+const VERSION: u8 = 0x01;
+const HEADER_SIZE: usize = 16;
+const TAG_MAGIC: &[u8] = b"[TAG]";
+
+// Libraries can have libraries themselves, the limit stops files that include each other
+const MAX_LIB_DEPTH: usize = 10;
+
+const SAMPLE_RATE: u32 = 44100;
+// One frame of CPU time between writes of the output
+const CYCLES_PER_CHUNK: u64 = 33_868_800 / 60;
+
+// Players use this for songs without a length tag
+const DEFAULT_LENGTH: f64 = 180.0;
+const DEFAULT_FADE: f64 = 10.0;
+
+#[derive(Debug)]
+pub enum PsfError {
+    Io { path: PathBuf, error: io::Error },
+    Invalid { path: PathBuf, message: String },
+    Exe { path: PathBuf, error: ExeError },
+    TooDeep { path: PathBuf },
+    Emulation(EmuError),
+    Output(io::Error),
+}
+
+impl fmt::Display for PsfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PsfError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            PsfError::Invalid { path, message } => write!(f, "{}: {}", path.display(), message),
+            PsfError::Exe { path, error } => write!(f, "{}: {}", path.display(), error),
+            PsfError::TooDeep { path } => write!(
+                f,
+                "{}: More than {} nested libraries",
+                path.display(),
+                MAX_LIB_DEPTH
+            ),
+            PsfError::Emulation(error) => write!(f, "Emulation stopped: {}", error),
+            PsfError::Output(error) => write!(f, "Failed to write the audio: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for PsfError {}
+
+pub struct Psf {
+    // Unused by PSF1, kept for completeness
+    pub reserved: Vec<u8>,
+    // The decompressed PS-X EXE
+    pub program: Vec<u8>,
+    pub tags: Vec<(String, String)>,
+}
+
+impl Psf {
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        if data.len() < HEADER_SIZE || &data[..MAGIC.len()] != MAGIC {
+            return Err("Missing PSF header".to_string());
+        }
+
+        if data[3] != VERSION {
+            return Err(format!(
+                "Version 0x{:02x} is not a PlayStation PSF1 file",
+                data[3]
+            ));
+        }
+
+        let word = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        let reserved_size = word(4) as usize;
+        let program_size = word(8) as usize;
+        let program_end = HEADER_SIZE + reserved_size + program_size;
+        if data.len() < program_end {
+            return Err(format!(
+                "Truncated, expected {} bytes but got {}",
+                program_end,
+                data.len()
+            ));
+        }
+
+        let compressed = &data[HEADER_SIZE + reserved_size..program_end];
+        if crc32(compressed) != word(12) {
+            return Err("The program is corrupt".to_string());
+        }
+
+        let program = if compressed.is_empty() {
+            Vec::new()
+        } else {
+            decompress(compressed)?
+        };
+
+        // The tags are lines of name=value, repeated names continue the value on a new line
+        let mut tags: Vec<(String, String)> = Vec::new();
+        if let Some(text) = data[program_end..].strip_prefix(TAG_MAGIC) {
+            for line in String::from_utf8_lossy(text).split('\n') {
+                let Some((name, value)) = line.split_once('=') else {
+                    continue;
+                };
+
+                let (name, value) = (name.trim().to_lowercase(), value.trim());
+                match tags.iter_mut().find(|(existing, _)| *existing == name) {
+                    Some((_, existing)) => {
+                        existing.push('\n');
+                        existing.push_str(value);
+                    }
+                    None => tags.push((name, value.to_string())),
+                }
+            }
+        }
+
+        Ok(Self {
+            reserved: data[HEADER_SIZE..HEADER_SIZE + reserved_size].to_vec(),
+            program,
+            tags,
+        })
+    }
+
+    // Tag names are case insensitive
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // The length and fade tags, with the default length for untagged songs
+    pub fn timing(&self) -> Timing {
+        match self.tag("length").and_then(parse_time) {
+            Some(length) => Timing {
+                length,
+                fade: self.tag("fade").and_then(parse_time).unwrap_or(0.0),
+            },
+            None => Timing {
+                length: DEFAULT_LENGTH,
+                fade: DEFAULT_FADE,
+            },
+        }
+    }
+}
+
+#[cfg(feature = "deflate")]
+fn decompress(compressed: &[u8]) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+
+    let mut program = Vec::new();
+    flate2::read::ZlibDecoder::new(compressed)
+        .read_to_end(&mut program)
+        .map_err(|error| format!("Failed to decompress the program: {}", error))?;
+    Ok(program)
+}
+
+#[cfg(not(feature = "deflate"))]
+fn decompress(_compressed: &[u8]) -> Result<Vec<u8>, String> {
+    Err("The program is compressed, which needs the deflate feature".to_string())
+}
+
+// Seconds of "[[h:]m:]s[.fff]", some taggers write a decimal comma
+fn parse_time(text: &str) -> Option<f64> {
+    let mut seconds = 0.0;
+    for part in text.trim().split(':') {
+        let part: f64 = part.trim().replace(',', ".").parse().ok()?;
+        if !part.is_finite() || part < 0.0 {
+            return None;
+        }
+        seconds = seconds * 60.0 + part;
+    }
+    Some(seconds)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timing {
+    // Seconds at full volume, followed by a linear fade to silence
+    pub length: f64,
+    pub fade: f64,
+}
+
+impl Timing {
+    fn samples(seconds: f64) -> u64 {
+        (seconds * SAMPLE_RATE as f64).round() as u64
+    }
+
+    pub fn total_samples(&self) -> u64 {
+        Self::samples(self.length) + Self::samples(self.fade)
+    }
+}
+
+// Loads a PSF with its libraries into one EXE and returns it with the tags of the file. The _lib
+// is loaded first and the file on top, then _lib2 and up. The registers are those of the first
+// EXE loaded, like other players do.
+pub fn load(path: impl AsRef<Path>) -> Result<(Exe, Psf), PsfError> {
+    let path = path.as_ref();
+    let psf = read(path)?;
+
+    let mut image = Image::default();
+    image.load(path, &psf, 0)?;
+    let exe = image.into_exe(path)?;
+
+    Ok((exe, psf))
+}
+
+fn read(path: &Path) -> Result<Psf, PsfError> {
+    let data = fs::read(path).map_err(|error| PsfError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+
+    Psf::parse(&data).map_err(|message| PsfError::Invalid {
+        path: path.to_path_buf(),
+        message,
+    })
+}
+
+#[derive(Default)]
+struct Image {
+    // Registers of the first EXE
+    registers: Option<(u32, u32, Option<u32>)>,
+    segments: Vec<(u32, Vec<u8>)>,
+}
+
+impl Image {
+    fn load(&mut self, path: &Path, psf: &Psf, depth: usize) -> Result<(), PsfError> {
+        if depth > MAX_LIB_DEPTH {
+            return Err(PsfError::TooDeep {
+                path: path.to_path_buf(),
+            });
+        }
+
+        // Library names are relative to the file that uses them
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let lib = |name: &str| -> Result<(PathBuf, Psf), PsfError> {
+            let path = directory.join(name);
+            let psf = read(&path)?;
+            Ok((path, psf))
+        };
+
+        if let Some(name) = psf.tag("_lib") {
+            let (path, psf) = lib(name)?;
+            self.load(&path, &psf, depth + 1)?;
+        }
+
+        let exe = Exe::parse(&psf.program).map_err(|error| PsfError::Exe {
+            path: path.to_path_buf(),
+            error,
+        })?;
+
+        // Exe::parse made sure this is RAM
+        let offset = exe.destination & 0x1FFFFFFF;
+
+        self.registers.get_or_insert((exe.pc, exe.gp, exe.sp));
+        self.segments.push((offset, exe.data));
+
+        for n in 2..=9 {
+            if let Some(name) = psf.tag(&format!("_lib{}", n)) {
+                let (path, psf) = lib(name)?;
+                self.load(&path, &psf, depth + 1)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // One block from the lowest to the highest address, later segments overwrite earlier ones
+    fn into_exe(self, path: &Path) -> Result<Exe, PsfError> {
+        let start = self.segments.iter().map(|(offset, _)| *offset).min();
+        let end = self
+            .segments
+            .iter()
+            .map(|(offset, data)| *offset as usize + data.len())
+            .max();
+        let (Some(start), Some(end), Some((pc, gp, sp))) = (start, end, self.registers) else {
+            return Err(PsfError::Invalid {
+                path: path.to_path_buf(),
+                message: "No program to load".to_string(),
+            });
+        };
+
+        let mut data = vec![0; end - start as usize];
+        for (offset, segment) in &self.segments {
+            let offset = (offset - start) as usize;
+            data[offset..offset + segment.len()].copy_from_slice(segment);
+        }
+
+        Ok(Exe {
+            pc,
+            gp,
+            destination: 0x80000000 | start,
+            bss_start: 0,
+            bss_size: 0,
+            sp,
+            data,
+        })
+    }
+}
+
+// The header of a 16 bit stereo WAV file with the number of samples
+pub fn write_wav_header(out: &mut impl Write, samples: u64) -> io::Result<()> {
+    let data_size = (samples * 4).min(u32::MAX as u64 - 36) as u32;
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_size).to_le_bytes())?;
+    out.write_all(b"WAVEfmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    // PCM, 2 channels
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&2u16.to_le_bytes())?;
+    out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    out.write_all(&(SAMPLE_RATE * 4).to_le_bytes())?;
+    out.write_all(&4u16.to_le_bytes())?;
+    out.write_all(&16u16.to_le_bytes())?;
+    out.write_all(b"data")?;
+    out.write_all(&data_size.to_le_bytes())
+}
+
+// Plays the sideloaded PSF and writes it as a WAV file as it goes, so the output can be piped into
+// a player. The samples before the program starts are the BIOS and are dropped. A program that
+// exits early is padded with silence to the length in the header.
+pub fn render<W: Write>(
+    emulator: &mut Emulator,
+    timing: Timing,
+    mut out: W,
+) -> Result<W, PsfError> {
+    let total = timing.total_samples();
+    let fade_start = Timing::samples(timing.length);
+    write_wav_header(&mut out, total).map_err(PsfError::Output)?;
+
+    while emulator.exe_pending() && emulator.exit_code().is_none() {
+        emulator.step().map_err(PsfError::Emulation)?;
+    }
+    emulator.take_audio_samples();
+
+    let mut written = 0;
+    let mut chunk = Vec::new();
+    while written < total {
+        let samples = if emulator.exit_code().is_none() {
+            emulator
+                .run_cycles(CYCLES_PER_CHUNK)
+                .map_err(PsfError::Emulation)?;
+            emulator.take_audio_samples()
+        } else {
+            vec![(0, 0); (total - written).min(SAMPLE_RATE as u64) as usize]
+        };
+
+        chunk.clear();
+        for (left, right) in samples {
+            if written == total {
+                break;
+            }
+
+            let (left, right) = if written >= fade_start {
+                let remaining = (total - written) as i64;
+                let fade = (total - fade_start) as i64;
+                (
+                    (left as i64 * remaining / fade) as i16,
+                    (right as i64 * remaining / fade) as i16,
+                )
+            } else {
+                (left, right)
+            };
+
+            chunk.extend_from_slice(&left.to_le_bytes());
+            chunk.extend_from_slice(&right.to_le_bytes());
+            written += 1;
+        }
+
+        out.write_all(&chunk).map_err(PsfError::Output)?;
+    }
+
+    out.flush().map_err(PsfError::Output)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "deflate")]
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/psf")
+            .join(name)
+    }
+
+    #[test]
+    fn times_parse_like_the_tags_write_them() {
+        assert_eq!(parse_time("5"), Some(5.0));
+        assert_eq!(parse_time("1:30"), Some(90.0));
+        assert_eq!(parse_time("1:02:03.5"), Some(3723.5));
+        assert_eq!(parse_time("0:01,25"), Some(1.25));
+        assert_eq!(parse_time("soon"), None);
+        assert_eq!(parse_time("-1"), None);
+    }
+
+    #[test]
+    #[cfg(feature = "deflate")]
+    fn minipsfs_are_merged_with_their_libraries() {
+        let (exe, psf) = load(fixture("song.minipsf")).unwrap();
+
+        // The library holds 0x80010000..0x80010800 and starts with a spin loop, the song
+        // overwrites 0x400..0x500 of it and the second library 0x480..0x4C0
+        assert_eq!(exe.destination, 0x80010000);
+        assert_eq!(exe.data.len(), 0x800);
+        assert_eq!(exe.data[..4], 0x1000FFFFu32.to_le_bytes());
+        assert!(exe.data[8..0x400].iter().all(|&byte| byte == 0x11));
+        assert!(exe.data[0x400..0x480].iter().all(|&byte| byte == 0x22));
+        assert!(exe.data[0x480..0x4C0].iter().all(|&byte| byte == 0x33));
+        assert!(exe.data[0x4C0..0x500].iter().all(|&byte| byte == 0x22));
+        assert!(exe.data[0x500..].iter().all(|&byte| byte == 0x11));
+
+        // The registers of the library, not of the song
+        assert_eq!(exe.pc, 0x80010000);
+        assert_eq!(exe.sp, Some(0x801FFF00));
+
+        assert_eq!(psf.tag("title"), Some("Test Song"));
+        assert_eq!(psf.tag("comment"), Some("Two\nlines"));
+        assert_eq!(
+            psf.timing(),
+            Timing {
+                length: 0.1,
+                fade: 0.05
+            }
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "deflate")]
+    fn corrupt_programs_are_rejected() {
+        let mut data = fs::read(fixture("lib.psflib")).unwrap();
+        data[HEADER_SIZE] ^= 0xFF;
+        assert_eq!(
+            Psf::parse(&data).err(),
+            Some("The program is corrupt".to_string())
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "deflate")]
+    fn renders_have_the_tagged_length() {
+        // The BIOS jumps straight to the shell, where the song is sideloaded
+        let mut bios = vec![0; 512 * 1024];
+        // lui t0, 0x8003; jr t0
+        bios[..4].copy_from_slice(&0x3C088003u32.to_le_bytes());
+        bios[4..8].copy_from_slice(&0x01000008u32.to_le_bytes());
+        let mut emulator = Emulator::new(bios).unwrap();
+
+        let (exe, psf) = load(fixture("song.minipsf")).unwrap();
+        emulator.sideload_program(exe).unwrap();
+
+        let wav = render(&mut emulator, psf.timing(), Vec::new()).unwrap();
+
+        // 0.1 seconds and 0.05 of fade at 44100 Hz, stereo after the header
+        let samples = 4410 + 2205;
+        assert_eq!(wav.len(), 44 + samples * 4);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(
+            u32::from_le_bytes(wav[40..44].try_into().unwrap()),
+            samples as u32 * 4
+        );
+        assert!(!emulator.exe_pending());
+    }
+}
@@ -0,0 +1,168 @@
+// Converts the 44.1kHz stereo SPU output to whatever rate the audio device runs at.
+// Uses cubic (Catmull-Rom) interpolation, and the ratio can be nudged based on how full the
+// output buffer is so the resampler doubles as drift compensation.
+
+// The ratio is never adjusted by more than this fraction (0.5%), this is inaudible
+const MAX_RATIO_ADJUSTMENT: f64 = 0.005;
+
+pub struct Resampler {
+    // Input samples consumed per output sample
+    step: f64,
+    adjustment: f64,
+    // Fractional position between history[1] and history[2]
+    position: f64,
+    // The last four input frames, interpolation happens between the middle two
+    history: [(f32, f32); 4],
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, output_rate: u32) -> Self {
+        Self {
+            step: input_rate as f64 / output_rate as f64,
+            adjustment: 1.0,
+            position: 0.0,
+            history: [(0.0, 0.0); 4],
+        }
+    }
+
+    pub fn set_rates(&mut self, input_rate: u32, output_rate: u32) {
+        self.step = input_rate as f64 / output_rate as f64;
+    }
+
+    // Fill level of the buffer being fed by the resampler, 0.0 is empty and 1.0 is full.
+    // A fill level above half makes the resampler produce fewer samples and vice versa.
+    pub fn set_fill_level(&mut self, fill_level: f64) {
+        let deviation = (fill_level.clamp(0.0, 1.0) - 0.5) * 2.0;
+        self.adjustment = 1.0 + deviation * MAX_RATIO_ADJUSTMENT;
+    }
+
+    pub fn ratio(&self) -> f64 {
+        1.0 / (self.step * self.adjustment)
+    }
+
+    pub fn reset(&mut self) {
+        self.position = 0.0;
+        self.history = [(0.0, 0.0); 4];
+    }
+
+    pub fn process(&mut self, input: &[(i16, i16)], output: &mut Vec<(i16, i16)>) {
+        let step = self.step * self.adjustment;
+
+        for &(left, right) in input {
+            self.history.rotate_left(1);
+            self.history[3] = (left as f32, right as f32);
+
+            while self.position < 1.0 {
+                let t = self.position as f32;
+
+                let left = cubic(
+                    self.history[0].0,
+                    self.history[1].0,
+                    self.history[2].0,
+                    self.history[3].0,
+                    t,
+                );
+                let right = cubic(
+                    self.history[0].1,
+                    self.history[1].1,
+                    self.history[2].1,
+                    self.history[3].1,
+                    t,
+                );
+
+                output.push((to_sample(left), to_sample(right)));
+
+                self.position += step;
+            }
+
+            self.position -= 1.0;
+        }
+    }
+}
+
+// Catmull-Rom spline between p1 and p2
+fn cubic(p0: f32, p1: f32, p2: f32, p3: f32, t: f32) -> f32 {
+    let a = -0.5 * p0 + 1.5 * p1 - 1.5 * p2 + 0.5 * p3;
+    let b = p0 - 2.5 * p1 + 2.0 * p2 - 0.5 * p3;
+    let c = -0.5 * p0 + 0.5 * p2;
+    let d = p1;
+
+    ((a * t + b) * t + c) * t + d
+}
+
+fn to_sample(value: f32) -> i16 {
+    value.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+
+    // One second of a 1kHz sine in both channels
+    fn sine(rate: u32) -> Vec<(i16, i16)> {
+        (0..rate)
+            .map(|i| {
+                let value = ((2.0 * PI * 1000.0 * i as f64 / rate as f64).sin() * 16000.0) as i16;
+                (value, value)
+            })
+            .collect()
+    }
+
+    // Amplitude of the frequency in the samples, by correlating with a sine and a cosine
+    fn amplitude(samples: &[f64], frequency: f64, rate: f64) -> f64 {
+        let (mut sin, mut cos) = (0.0, 0.0);
+        for (i, sample) in samples.iter().enumerate() {
+            let phase = 2.0 * PI * frequency * i as f64 / rate;
+            sin += sample * phase.sin();
+            cos += sample * phase.cos();
+        }
+
+        2.0 * (sin * sin + cos * cos).sqrt() / samples.len() as f64
+    }
+
+    #[test]
+    fn sine_keeps_its_frequency_at_48khz() {
+        let mut resampler = Resampler::new(44100, 48000);
+        let mut output = Vec::new();
+        resampler.process(&sine(44100), &mut output);
+        assert!(output.len().abs_diff(48000) <= 1);
+        assert!(output.iter().all(|(left, right)| left == right));
+
+        // Rising zero crossings after the start, one per period
+        let samples: Vec<f64> = output[100..].iter().map(|(left, _)| *left as f64).collect();
+        let crossings = samples
+            .windows(2)
+            .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+            .count();
+        assert!(crossings.abs_diff(998) <= 1);
+
+        // The harmonics stay below 0.1% of the fundamental
+        let window = &samples[..48000 - 200];
+        let fundamental = amplitude(window, 1000.0, 48000.0);
+        let harmonics: f64 = (2..=5)
+            .map(|harmonic| amplitude(window, 1000.0 * harmonic as f64, 48000.0).powi(2))
+            .sum();
+        assert!((fundamental - 16000.0).abs() < 100.0);
+        assert!(harmonics.sqrt() / fundamental < 0.001);
+    }
+
+    #[test]
+    fn full_buffer_slows_the_output_down() {
+        let input = sine(44100);
+        let mut counts = Vec::new();
+        for fill_level in [0.0, 0.5, 1.0] {
+            let mut resampler = Resampler::new(44100, 48000);
+            resampler.set_fill_level(fill_level);
+            let mut output = Vec::new();
+            resampler.process(&input, &mut output);
+            counts.push(output.len());
+        }
+
+        assert!(counts[0] > counts[1] && counts[1] > counts[2]);
+        // Never more than half a percent
+        assert!(counts[0] - counts[1] <= 241);
+        assert!(counts[1] - counts[2] <= 241);
+    }
+}
@@ -0,0 +1,474 @@
+use std::fmt;
+
+use crate::memcard::MemoryCard;
+
+// The file system of memory cards. Block 0 holds the header and a directory frame for each of the
+// other 15 blocks of 8KB. A save takes one or more blocks, its directory frames are a linked list
+// through their next block field. The first block of a save starts with the title frame and the
+// icon frames.
+
+const FRAME_SIZE: usize = 128;
+const BLOCK_SIZE: usize = 64 * FRAME_SIZE;
+pub const BLOCK_COUNT: usize = 15;
+// The frame in the directory, followed by the data of the blocks
+const MCS_HEADER_SIZE: usize = FRAME_SIZE;
+
+// Directory frame states, in use or free and where in the list of a save the block is
+const FIRST_BLOCK: u8 = 0x51;
+const MIDDLE_BLOCK: u8 = 0x52;
+const LAST_BLOCK: u8 = 0x53;
+const FREE_BLOCK: u8 = 0xA0;
+// Deleting a save only marks its blocks free, adding this to the state
+const DELETED: u8 = 0x50;
+const NO_NEXT_BLOCK: u16 = 0xFFFF;
+
+// Region, product code and an identifier of the game's choosing
+const FILENAME_OFFSET: usize = 0x0A;
+const FILENAME_SIZE: usize = 20;
+const TITLE_OFFSET: usize = 0x04;
+const TITLE_SIZE: usize = 64;
+const PALETTE_OFFSET: usize = 0x60;
+
+#[derive(Debug, PartialEq)]
+pub enum SaveError {
+    // A directory list is broken, it loops or leads to a block that is not in use
+    BrokenChain { block: usize },
+    NotFound(String),
+    // The BIOS refuses files with the name of one that is already on the card
+    Exists(String),
+    NotEnoughSpace { needed: usize, free: usize },
+    InvalidMcs(String),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::BrokenChain { block } => {
+                write!(f, "The directory entries of block {} are broken", block)
+            }
+            SaveError::NotFound(name) => write!(f, "No save '{}' on the card", name),
+            SaveError::Exists(name) => write!(f, "The card already has a save '{}'", name),
+            SaveError::NotEnoughSpace { needed, free } => write!(
+                f,
+                "The save needs {} blocks but only {} are free",
+                needed, free
+            ),
+            SaveError::InvalidMcs(message) => write!(f, "Invalid .mcs file, {}", message),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+pub struct Save {
+    // The blocks (1..15) in the order of the data
+    pub blocks: Vec<usize>,
+    // As stored in the directory, for example BASLUS-00067DRACULA
+    pub filename: String,
+    pub size: u32,
+    // Decoded from Shift-JIS, empty without a title frame
+    pub title: String,
+    // 16x16 pixels of 0xRRGGBBAA each, the BIOS animates through them
+    pub icons: Vec<[u32; 256]>,
+}
+
+impl Save {
+    // The letter after the leading B, I for Japan, A for America and E for Europe
+    pub fn region(&self) -> &str {
+        self.filename.get(1..2).unwrap_or("")
+    }
+
+    pub fn product_code(&self) -> &str {
+        self.filename.get(2..12).unwrap_or("")
+    }
+}
+
+impl MemoryCard {
+    fn frame(&self, frame: usize) -> &[u8] {
+        &self.data()[frame * FRAME_SIZE..][..FRAME_SIZE]
+    }
+
+    // The directory frames follow the header, the frame number is the block number
+    fn directory_frame(&self, block: usize) -> &[u8] {
+        self.frame(block)
+    }
+
+    fn block(&self, block: usize) -> &[u8] {
+        &self.data()[block * BLOCK_SIZE..][..BLOCK_SIZE]
+    }
+
+    fn state(&self, block: usize) -> u8 {
+        self.directory_frame(block)[0]
+    }
+
+    // The blocks of the save starting at the block, following the next block fields
+    fn chain(&self, first: usize) -> Result<Vec<usize>, SaveError> {
+        let mut blocks = vec![first];
+        loop {
+            let block = *blocks.last().unwrap();
+            let frame = self.directory_frame(block);
+            let next = u16::from_le_bytes([frame[8], frame[9]]);
+            let state = frame[0];
+
+            match (state, next) {
+                (FIRST_BLOCK, NO_NEXT_BLOCK) if blocks.len() == 1 => return Ok(blocks),
+                (LAST_BLOCK, NO_NEXT_BLOCK) if blocks.len() > 1 => return Ok(blocks),
+                (FIRST_BLOCK, _) | (MIDDLE_BLOCK, _) if (next as usize) < BLOCK_COUNT => {
+                    let next = next as usize + 1;
+                    let expected = self.state(next);
+                    if blocks.contains(&next) || !matches!(expected, MIDDLE_BLOCK | LAST_BLOCK) {
+                        return Err(SaveError::BrokenChain { block });
+                    }
+                    blocks.push(next);
+                }
+                _ => return Err(SaveError::BrokenChain { block }),
+            }
+        }
+    }
+
+    // The saves in the order of their first block, deleted ones are not listed
+    pub fn saves(&self) -> Result<Vec<Save>, SaveError> {
+        (1..=BLOCK_COUNT)
+            .filter(|&block| self.state(block) == FIRST_BLOCK)
+            .map(|first| {
+                let frame = self.directory_frame(first);
+                let blocks = self.chain(first)?;
+                let title_frame = self.frame(first * 64);
+                let has_title = &title_frame[..2] == b"SC";
+
+                Ok(Save {
+                    filename: decode_ascii(&frame[FILENAME_OFFSET..][..FILENAME_SIZE]),
+                    size: u32::from_le_bytes(frame[4..8].try_into().unwrap()),
+                    title: if has_title {
+                        decode_shift_jis(&title_frame[TITLE_OFFSET..][..TITLE_SIZE])
+                    } else {
+                        String::new()
+                    },
+                    icons: if has_title {
+                        self.icons(first)
+                    } else {
+                        Vec::new()
+                    },
+                    blocks,
+                })
+            })
+            .collect()
+    }
+
+    // Looked up by the filename or the number of the first block
+    pub fn find_save(&self, name: &str) -> Result<Save, SaveError> {
+        self.saves()?
+            .into_iter()
+            .find(|save| save.filename == name || name.parse() == Ok(save.blocks[0]))
+            .ok_or_else(|| SaveError::NotFound(name.to_string()))
+    }
+
+    // 1 to 3 frames after the title frame, 4 bit indices into the palette of the title frame
+    fn icons(&self, first: usize) -> Vec<[u32; 256]> {
+        let title_frame = self.frame(first * 64);
+        let count = match title_frame[2] {
+            0x12 => 2,
+            0x13 => 3,
+            _ => 1,
+        };
+        let palette: Vec<u16> = title_frame[PALETTE_OFFSET..]
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+
+        (1..=count)
+            .map(|frame| {
+                let bitmap = self.frame(first * 64 + frame);
+                let mut icon = [0; 256];
+                for (i, pixel) in icon.iter_mut().enumerate() {
+                    let index = (bitmap[i / 2] >> ((i & 1) * 4)) & 0xF;
+                    *pixel = bgr15_to_rgba(palette[index as usize]);
+                }
+                icon
+            })
+            .collect()
+    }
+
+    // The directory frame of the first block and the data of all blocks, as in .mcs files
+    pub fn export_save(&self, name: &str) -> Result<Vec<u8>, SaveError> {
+        let save = self.find_save(name)?;
+        let mut mcs = self.directory_frame(save.blocks[0]).to_vec();
+        for &block in &save.blocks {
+            mcs.extend_from_slice(self.block(block));
+        }
+
+        Ok(mcs)
+    }
+
+    // Writes the save of a .mcs file to the lowest free blocks, returns the blocks it went to
+    pub fn import_save(&mut self, mcs: &[u8]) -> Result<Vec<usize>, SaveError> {
+        if mcs.len() < MCS_HEADER_SIZE + BLOCK_SIZE
+            || !(mcs.len() - MCS_HEADER_SIZE).is_multiple_of(BLOCK_SIZE)
+        {
+            return Err(SaveError::InvalidMcs(format!(
+                "{} bytes is not a header and whole blocks",
+                mcs.len()
+            )));
+        }
+        let header = &mcs[..MCS_HEADER_SIZE];
+        if header[0] != FIRST_BLOCK {
+            return Err(SaveError::InvalidMcs(
+                "the directory frame is not of a first block".to_string(),
+            ));
+        }
+
+        let filename = decode_ascii(&header[FILENAME_OFFSET..][..FILENAME_SIZE]);
+        if self.saves()?.iter().any(|save| save.filename == filename) {
+            return Err(SaveError::Exists(filename));
+        }
+
+        let blocks: Vec<&[u8]> = mcs[MCS_HEADER_SIZE..].chunks(BLOCK_SIZE).collect();
+        let free: Vec<usize> = (1..=BLOCK_COUNT)
+            .filter(|&block| self.state(block) & 0xF0 == FREE_BLOCK)
+            .collect();
+        if free.len() < blocks.len() {
+            return Err(SaveError::NotEnoughSpace {
+                needed: blocks.len(),
+                free: free.len(),
+            });
+        }
+
+        let targets = free[..blocks.len()].to_vec();
+        let size = (blocks.len() * BLOCK_SIZE) as u32;
+        for (i, (&target, data)) in targets.iter().zip(&blocks).enumerate() {
+            let mut frame = [0; FRAME_SIZE];
+            frame[0] = match i {
+                0 => FIRST_BLOCK,
+                _ if i == blocks.len() - 1 => LAST_BLOCK,
+                _ => MIDDLE_BLOCK,
+            };
+            let next = targets
+                .get(i + 1)
+                .map_or(NO_NEXT_BLOCK, |&next| next as u16 - 1);
+            frame[8..10].copy_from_slice(&next.to_le_bytes());
+            // Only the first frame has the size and the name
+            if i == 0 {
+                frame[4..8].copy_from_slice(&size.to_le_bytes());
+                frame[FILENAME_OFFSET..][..FILENAME_SIZE]
+                    .copy_from_slice(&header[FILENAME_OFFSET..][..FILENAME_SIZE]);
+            }
+            self.write_directory_frame(target, frame);
+            self.data_mut()[target * BLOCK_SIZE..][..BLOCK_SIZE].copy_from_slice(data);
+        }
+
+        Ok(targets)
+    }
+
+    // Marks the blocks of the save as deleted like the BIOS does, the data stays on the card
+    pub fn delete_save(&mut self, name: &str) -> Result<(), SaveError> {
+        let save = self.find_save(name)?;
+        for block in save.blocks {
+            let mut frame: [u8; FRAME_SIZE] = self.directory_frame(block).try_into().unwrap();
+            frame[0] += DELETED;
+            self.write_directory_frame(block, frame);
+        }
+
+        Ok(())
+    }
+
+    fn write_directory_frame(&mut self, block: usize, mut frame: [u8; FRAME_SIZE]) {
+        frame[FRAME_SIZE - 1] = frame[..FRAME_SIZE - 1].iter().fold(0, |a, b| a ^ b);
+        self.data_mut()[block * FRAME_SIZE..][..FRAME_SIZE].copy_from_slice(&frame);
+    }
+
+    pub fn free_blocks(&self) -> usize {
+        (1..=BLOCK_COUNT)
+            .filter(|&block| self.state(block) & 0xF0 == FREE_BLOCK)
+            .count()
+    }
+}
+
+// Directory names are ASCII, padded with zeros
+fn decode_ascii(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| byte as char)
+        .collect()
+}
+
+// Shift-JIS row 1, symbols (0x8140..0x81AC without 0x817F)
+const SYMBOLS: &str = "\u{3000}、。，．・：；？！゛゜´｀¨＾￣＿ヽヾゝゞ〃仝々〆〇ー―‐／＼～∥｜…‥‘’“”（）〔〕［］｛｝〈〉《》「」『』【】＋－±×÷＝≠＜＞≦≧∞∴♂♀°′″℃￥＄￠￡％＃＆＊＠§☆★○●◎◇◆□■△▲▽▼※〒→←↑↓〓";
+const GREEK: &str = "ΑΒΓΔΕΖΗΘΙΚΛΜΝΞΟΠΡΣΤΥΦΧΨΩ";
+
+// Titles are Shift-JIS, mostly the full width letters and digits, kana and symbols. Kanji are not
+// decoded and show as the replacement character.
+pub fn decode_shift_jis(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    let mut bytes = bytes.iter().copied().take_while(|&byte| byte != 0);
+
+    while let Some(lead) = bytes.next() {
+        let character = match lead {
+            0x20..=0x7E => Some(lead as char),
+            // Half width katakana
+            0xA1..=0xDF => char::from_u32(0xFF61 + (lead - 0xA1) as u32),
+            0x81..=0x9F | 0xE0..=0xEF => {
+                let Some(trail) = bytes.next() else {
+                    break;
+                };
+                decode_double_byte(lead, trail)
+            }
+            _ => None,
+        };
+
+        text.push(character.unwrap_or('\u{FFFD}'));
+    }
+
+    text
+}
+
+fn decode_double_byte(lead: u8, trail: u8) -> Option<char> {
+    let offset = |first: u8, start: u32| char::from_u32(start + (trail - first) as u32);
+
+    match (lead, trail) {
+        (0x81, 0x40..=0x7E) => SYMBOLS.chars().nth((trail - 0x40) as usize),
+        (0x81, 0x80..=0xAC) => SYMBOLS.chars().nth((trail - 0x41) as usize),
+        // Full width digits and letters
+        (0x82, 0x4F..=0x58) => offset(0x4F, 0xFF10),
+        (0x82, 0x60..=0x79) => offset(0x60, 0xFF21),
+        (0x82, 0x81..=0x9A) => offset(0x81, 0xFF41),
+        (0x82, 0x9F..=0xF1) => offset(0x9F, 0x3041),
+        // Katakana, the trail byte skips 0x7F
+        (0x83, 0x40..=0x7E) => offset(0x40, 0x30A1),
+        (0x83, 0x80..=0x96) => offset(0x80, 0x30E0),
+        (0x83, 0x9F..=0xB6) => GREEK.chars().nth((trail - 0x9F) as usize),
+        (0x83, 0xBF..=0xD6) => GREEK
+            .chars()
+            .nth((trail - 0xBF) as usize)
+            .and_then(|letter| letter.to_lowercase().next()),
+        _ => None,
+    }
+}
+
+// The BIOS draws the icons as textures, so palette entries of 0 are transparent
+fn bgr15_to_rgba(pixel: u16) -> u32 {
+    let channel = |shift: u16| {
+        let value = ((pixel >> shift) & 0x1F) as u32;
+        (value << 3) | (value >> 2)
+    };
+    let alpha = if pixel == 0 { 0 } else { 0xFF };
+
+    (channel(0) << 24) | (channel(5) << 16) | (channel(10) << 8) | alpha
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> MemoryCard {
+        let path =
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/memcards/fragmented.mcr");
+        MemoryCard::from_data(std::fs::read(path).unwrap()).unwrap()
+    }
+
+    fn checksums_are_valid(card: &MemoryCard) -> bool {
+        (0..16).all(|frame| card.frame(frame).iter().fold(0, |a, b| a ^ b) == 0)
+    }
+
+    #[test]
+    fn saves_are_listed_with_their_blocks_titles_and_icons() {
+        let card = fixture();
+        let saves = card.saves().unwrap();
+
+        assert_eq!(saves.len(), 2);
+        assert_eq!(saves[0].filename, "BASLUS-00001ALPHA");
+        assert_eq!(saves[0].blocks, [1]);
+        assert_eq!(saves[0].title, "ＡＢＣ　１２３k");
+        assert_eq!(saves[0].icons.len(), 1);
+        assert_eq!(saves[0].icons[0][..3], [0xFFFFFFFF, 0xFFFFFFFF, 0]);
+
+        // Fragmented over three blocks, the deleted save in between is not listed
+        assert_eq!(saves[1].product_code(), "SLES-01234");
+        assert_eq!(saves[1].region(), "E");
+        assert_eq!(saves[1].blocks, [2, 5, 3]);
+        assert_eq!(saves[1].size, 3 * BLOCK_SIZE as u32);
+        assert_eq!(saves[1].title, "セーブ　データｱ\u{FFFD}");
+        assert_eq!(saves[1].icons.len(), 3);
+        assert_eq!(saves[1].icons[1][0], 0xFF0000FF);
+        assert_eq!(card.free_blocks(), 11);
+    }
+
+    #[test]
+    fn exported_saves_import_into_the_free_blocks() {
+        let mut card = fixture();
+        let mcs = card.export_save("BESLES-01234BRAVO").unwrap();
+        assert_eq!(mcs.len(), MCS_HEADER_SIZE + 3 * BLOCK_SIZE);
+        assert_eq!(mcs[MCS_HEADER_SIZE + BLOCK_SIZE], 0x21);
+
+        assert_eq!(
+            card.import_save(&mcs),
+            Err(SaveError::Exists("BESLES-01234BRAVO".to_string()))
+        );
+
+        // Under another name it goes to the deleted block and the first free ones after it
+        let mut renamed = mcs.clone();
+        renamed[FILENAME_OFFSET + 12..FILENAME_OFFSET + 17].copy_from_slice(b"COPY\0");
+        assert_eq!(card.import_save(&renamed).unwrap(), [4, 6, 7]);
+        assert!(checksums_are_valid(&card));
+
+        let copy = card.find_save("BESLES-01234COPY").unwrap();
+        assert_eq!(copy.blocks, [4, 6, 7]);
+        assert_eq!(copy.title, "セーブ　データｱ\u{FFFD}");
+        assert_eq!(
+            card.export_save("4").unwrap()[MCS_HEADER_SIZE..],
+            mcs[MCS_HEADER_SIZE..]
+        );
+
+        assert_eq!(card.free_blocks(), 8);
+
+        let mut formatted = MemoryCard::formatted();
+        assert_eq!(formatted.import_save(&mcs).unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn deleted_saves_free_their_blocks() {
+        let mut card = fixture();
+        card.delete_save("2").unwrap();
+
+        assert!(checksums_are_valid(&card));
+        assert_eq!(card.saves().unwrap().len(), 1);
+        assert_eq!(card.free_blocks(), 14);
+        assert_eq!(
+            card.delete_save("BESLES-01234BRAVO"),
+            Err(SaveError::NotFound("BESLES-01234BRAVO".to_string()))
+        );
+
+        let mut full = MemoryCard::formatted();
+        let mut mcs = card.export_save("1").unwrap();
+        mcs.extend(vec![0; 15 * BLOCK_SIZE]);
+        assert_eq!(
+            full.import_save(&mcs),
+            Err(SaveError::NotEnoughSpace {
+                needed: 16,
+                free: 15
+            })
+        );
+    }
+
+    #[test]
+    fn broken_chains_are_reported() {
+        let mut card = fixture();
+        // The middle block of the fragmented save points back at itself
+        card.data_mut()[5 * FRAME_SIZE + 8] = 4;
+
+        assert_eq!(
+            card.saves().err(),
+            Some(SaveError::BrokenChain { block: 5 })
+        );
+    }
+
+    #[test]
+    fn shift_jis_titles_decode_kana_and_symbols() {
+        assert_eq!(decode_shift_jis(b"\x81\x7b\x81\x80\x81\xac"), "＋÷〓");
+        assert_eq!(
+            decode_shift_jis(b"\x82\xa0\x82\xf1\x83\x94\x83\x9f\x83\xbf"),
+            "あんヴΑα"
+        );
+        assert_eq!(decode_shift_jis(b"\xdf\x00ignored"), "ﾟ");
+    }
+}
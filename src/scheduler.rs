@@ -0,0 +1,46 @@
+// Collects elapsed cycles until the next event (a timer interrupt, a blank starting or ending),
+// so the devices are only run when something observable can happen
+pub struct Scheduler {
+    pending: u32,
+    // Cycles from the last catch up until the next event
+    deadline: u32,
+    // Deadlines reached so far, the devices may have changed what the CPU reads at each
+    events: u64,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self {
+            pending: 0,
+            deadline: 0,
+            events: 0,
+        }
+    }
+
+    // Returns whether the deadline has been reached
+    pub fn advance(&mut self, cycles: u32) -> bool {
+        self.pending = self.pending.saturating_add(cycles);
+        self.pending >= self.deadline
+    }
+
+    pub fn pending(&self) -> u32 {
+        self.pending
+    }
+
+    pub fn deadline(&self) -> u32 {
+        self.deadline
+    }
+
+    pub fn events(&self) -> u64 {
+        self.events
+    }
+
+    // Called after the devices were run for `cycles`, `deadline` is the time until the next event
+    pub fn consume(&mut self, cycles: u32, deadline: u32) {
+        if cycles >= self.deadline {
+            self.events += 1;
+        }
+        self.pending -= cycles;
+        self.deadline = deadline;
+    }
+}
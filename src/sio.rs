@@ -0,0 +1,963 @@
+use std::collections::VecDeque;
+
+use crate::memcard::MemoryCard;
+
+// Serial port 0, the controllers and memory cards. Bytes are exchanged one at a time, the device
+// answers every byte and pulls /ACK low when it wants the next one.
+
+const RX_FIFO_SIZE: usize = 8;
+// CPU cycles between the end of a byte and the /ACK pulse, and the length of the pulse
+const ACK_DELAY: u32 = 100;
+const ACK_LENGTH: u32 = 100;
+
+// The bit of each button in the (active low) button bytes
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Button {
+    Select = 0,
+    L3 = 1,
+    R3 = 2,
+    Start = 3,
+    Up = 4,
+    Right = 5,
+    Down = 6,
+    Left = 7,
+    L2 = 8,
+    R2 = 9,
+    L1 = 10,
+    R1 = 11,
+    Triangle = 12,
+    Circle = 13,
+    Cross = 14,
+    Square = 15,
+}
+
+// The analog sticks, 0x00 is left/up and 0xFF right/down
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Axis {
+    RightX = 0,
+    RightY = 1,
+    LeftX = 2,
+    LeftY = 3,
+}
+
+// A controller plugged into one of the ports
+pub trait PadDevice {
+    // Answers a byte of the host, starting with the address byte after the port was selected.
+    // Returns the response and whether the device acknowledges the byte and expects another one.
+    fn transfer(&mut self, value: u8) -> (u8, bool);
+    // /JOY went high, the next byte starts a new command
+    fn deselect(&mut self);
+    fn set_button(&mut self, button: Button, pressed: bool);
+
+    fn set_axis(&mut self, _axis: Axis, _value: u8) {}
+    // The analog button, switches between digital and analog mode unless the game locked it
+    fn toggle_analog(&mut self) {}
+    // Intensity of the small and the large motor
+    fn rumble(&self) -> (u8, u8) {
+        (0, 0)
+    }
+}
+
+fn set_button_bit(buttons: &mut u16, button: Button, pressed: bool) {
+    let bit = 1 << button as u16;
+    if pressed {
+        *buttons &= !bit;
+    } else {
+        *buttons |= bit;
+    }
+}
+
+// SCPH-1080, ID 0x5A41 followed by the two button bytes
+pub struct DigitalPad {
+    // Bit set when released
+    buttons: u16,
+    position: usize,
+    // The command was not addressed to the pad, it stays quiet until deselected
+    ignoring: bool,
+}
+
+impl DigitalPad {
+    pub fn new() -> Self {
+        Self {
+            buttons: 0xFFFF,
+            position: 0,
+            ignoring: false,
+        }
+    }
+}
+
+impl PadDevice for DigitalPad {
+    fn transfer(&mut self, value: u8) -> (u8, bool) {
+        if self.ignoring {
+            return (0xFF, false);
+        }
+
+        let position = self.position;
+        self.position += 1;
+
+        match (position, value) {
+            // Controllers are addressed with 0x01, 0x81 is the memory card
+            (0, 0x01) => (0xFF, true),
+            // Read buttons
+            (1, 0x42) => (0x41, true),
+            (2, _) => (0x5A, true),
+            (3, _) => (self.buttons as u8, true),
+            (4, _) => ((self.buttons >> 8) as u8, false),
+            _ => {
+                self.ignoring = true;
+                (0xFF, false)
+            }
+        }
+    }
+
+    fn deselect(&mut self) {
+        self.position = 0;
+        self.ignoring = false;
+    }
+
+    fn set_button(&mut self, button: Button, pressed: bool) {
+        set_button_bit(&mut self.buttons, button, pressed);
+    }
+}
+
+impl Default for DigitalPad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// SCPH-1200, starts in digital mode. In analog mode (ID 0x5A73) the poll also returns the stick
+// axes, in config mode (ID 0x5AF3) the mode, rumble mapping and a few constant tables are available.
+pub struct DualShock {
+    buttons: u16,
+    axes: [u8; 4],
+    analog: bool,
+    // The analog button does nothing
+    locked: bool,
+    config: bool,
+    // Which motor each of the bytes after 0x5A in a poll drives, 0x00 small, 0x01 large
+    rumble_map: [u8; 6],
+    rumble: (u8, u8),
+
+    position: usize,
+    command: u8,
+    // The bytes after the ID, starting with 0x5A
+    response: Vec<u8>,
+    ignoring: bool,
+}
+
+impl DualShock {
+    pub fn new() -> Self {
+        Self {
+            buttons: 0xFFFF,
+            axes: [0x80; 4],
+            analog: false,
+            locked: false,
+            config: false,
+            rumble_map: [0xFF; 6],
+            rumble: (0, 0),
+            position: 0,
+            command: 0,
+            response: Vec::new(),
+            ignoring: false,
+        }
+    }
+
+    // The low nibble is the type, the high nibble the number of halfwords after 0x5A
+    fn id(&self) -> u8 {
+        if self.config {
+            0xF3
+        } else if self.analog {
+            0x73
+        } else {
+            0x41
+        }
+    }
+
+    fn poll_response(&self, analog: bool) -> Vec<u8> {
+        let mut response = vec![0x5A, self.buttons as u8, (self.buttons >> 8) as u8];
+        if analog {
+            response.extend(self.axes);
+        }
+
+        response
+    }
+
+    fn start_command(&mut self, command: u8) -> Option<Vec<u8>> {
+        let response = match (self.config, command) {
+            (false, 0x42 | 0x43) => self.poll_response(self.analog),
+            (false, _) => return None,
+            (true, 0x42) => self.poll_response(true),
+            // Status, the third byte is the analog LED
+            (true, 0x45) => vec![0x5A, 0x01, 0x02, self.analog as u8, 0x02, 0x01, 0x00],
+            (true, 0x46) => vec![0x5A, 0x00, 0x00, 0x01, 0x02, 0x00, 0x0A],
+            (true, 0x47) => vec![0x5A, 0x00, 0x00, 0x02, 0x00, 0x01, 0x00],
+            (true, 0x4C) => vec![0x5A, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00],
+            // The previous mapping
+            (true, 0x4D) => [&[0x5A], &self.rumble_map[..]].concat(),
+            (true, _) => vec![0x5A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        };
+
+        Some(response)
+    }
+
+    // Applies the byte the host sent at the index into the response
+    fn receive(&mut self, index: usize, value: u8) {
+        match (self.command, index) {
+            (0x42, 1..=6) => match self.rumble_map[index - 1] {
+                0x00 => self.rumble.0 = if value & 1 != 0 { 0xFF } else { 0x00 },
+                0x01 => self.rumble.1 = value,
+                _ => {}
+            },
+            // Enter or leave config mode, takes effect with the next command
+            (0x43, 1) => self.config = value == 0x01,
+            (0x44, 1) if self.config => self.analog = value == 0x01,
+            (0x44, 2) if self.config => self.locked = value == 0x03,
+            // Second table
+            (0x46, 1) if self.config && value == 0x01 => {
+                self.response[3..].copy_from_slice(&[0x01, 0x01, 0x01, 0x14]);
+            }
+            (0x4C, 1) if self.config && value == 0x01 => self.response[4] = 0x07,
+            (0x4D, 1..=6) if self.config => self.rumble_map[index - 1] = value,
+            _ => {}
+        }
+    }
+}
+
+impl PadDevice for DualShock {
+    fn transfer(&mut self, value: u8) -> (u8, bool) {
+        if self.ignoring {
+            return (0xFF, false);
+        }
+
+        let position = self.position;
+        self.position += 1;
+
+        match position {
+            0 if value == 0x01 => return (0xFF, true),
+            0 => {}
+            1 => {
+                let id = self.id();
+                self.command = value;
+                if let Some(response) = self.start_command(value) {
+                    self.response = response;
+                    return (id, true);
+                }
+            }
+            _ => {
+                let index = position - 2;
+                if let Some(&response) = self.response.get(index) {
+                    self.receive(index, value);
+                    return (response, index + 1 < self.response.len());
+                }
+            }
+        }
+
+        self.ignoring = true;
+        (0xFF, false)
+    }
+
+    fn deselect(&mut self) {
+        self.position = 0;
+        self.ignoring = false;
+    }
+
+    fn set_button(&mut self, button: Button, pressed: bool) {
+        set_button_bit(&mut self.buttons, button, pressed);
+    }
+
+    fn set_axis(&mut self, axis: Axis, value: u8) {
+        self.axes[axis as usize] = value;
+    }
+
+    fn toggle_analog(&mut self) {
+        if !self.locked {
+            self.analog = !self.analog;
+        }
+    }
+
+    fn rumble(&self) -> (u8, u8) {
+        self.rumble
+    }
+}
+
+impl Default for DualShock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The device in a slot that answers the current command, decided by the address byte
+#[derive(Clone, Copy, PartialEq)]
+enum Target {
+    None,
+    Pad,
+    MemoryCard,
+    // Nothing answered the address byte
+    Ignored,
+}
+
+// A controller and a memory card, plugged into a port directly or into a multitap
+struct Slot {
+    pad: Option<Box<dyn PadDevice>>,
+    memory_card: Option<MemoryCard>,
+    target: Target,
+}
+
+impl Slot {
+    fn new(pad: Option<Box<dyn PadDevice>>) -> Self {
+        Self {
+            pad,
+            memory_card: None,
+            target: Target::None,
+        }
+    }
+
+    fn transfer(&mut self, value: u8) -> (u8, bool) {
+        if self.target == Target::None {
+            // 0x01 addresses the controller, 0x81 the memory card
+            self.target = match value {
+                0x01 if self.pad.is_some() => Target::Pad,
+                0x81 if self.memory_card.is_some() => Target::MemoryCard,
+                _ => Target::Ignored,
+            };
+        }
+
+        match self.target {
+            Target::Pad => self.pad.as_mut().unwrap().transfer(value),
+            Target::MemoryCard => self.memory_card.as_mut().unwrap().transfer(value),
+            _ => (0xFF, false),
+        }
+    }
+
+    fn deselect(&mut self) {
+        self.target = Target::None;
+        if let Some(pad) = &mut self.pad {
+            pad.deselect();
+        }
+        if let Some(card) = &mut self.memory_card {
+            card.deselect();
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum MultitapMode {
+    None,
+    Slot(usize),
+    PollAll,
+    Ignored,
+}
+
+// SCPH-1070. The low nibble of the address byte (0x01..0x04, 0x81..0x84) selects slot A to D, the
+// bytes are passed through to it. Sending 0x01 as the third byte of a poll makes the next poll
+// return all four controllers at once: 0x80, 0x5A and an 8 byte block (ID, 0x5A and six data
+// bytes) for each slot, filled with 0xFF when the slot is empty or the pad answers fewer bytes.
+struct Multitap {
+    slots: [Slot; 4],
+    poll_all: bool,
+    mode: MultitapMode,
+    position: usize,
+    command: u8,
+    // The pad of the current block stopped answering
+    block_done: bool,
+}
+
+impl Multitap {
+    fn new(first: Slot) -> Self {
+        Self {
+            slots: [first, Slot::new(None), Slot::new(None), Slot::new(None)],
+            poll_all: false,
+            mode: MultitapMode::None,
+            position: 0,
+            command: 0,
+            block_done: false,
+        }
+    }
+
+    fn transfer(&mut self, value: u8) -> (u8, bool) {
+        let position = self.position;
+        self.position += 1;
+
+        if position == 0 {
+            self.mode = match value & 0x0F {
+                1 if self.poll_all && value == 0x01 => MultitapMode::PollAll,
+                slot @ 1..=4 => MultitapMode::Slot(slot as usize - 1),
+                _ => MultitapMode::Ignored,
+            };
+        }
+        if position == 1 {
+            self.command = value;
+        }
+        // The third byte of a poll picks the format of the next one
+        let slot_a = matches!(self.mode, MultitapMode::Slot(0) | MultitapMode::PollAll);
+        if position == 2 && self.command == 0x42 && slot_a {
+            self.poll_all = value == 0x01;
+        }
+
+        match self.mode {
+            MultitapMode::Slot(slot) if position == 0 => {
+                self.slots[slot].transfer((value & 0xF0) | 0x01)
+            }
+            MultitapMode::Slot(slot) => self.slots[slot].transfer(value),
+            MultitapMode::PollAll => self.poll_all(position, value),
+            _ => (0xFF, false),
+        }
+    }
+
+    fn poll_all(&mut self, position: usize, value: u8) -> (u8, bool) {
+        match position {
+            0 => (0xFF, true),
+            1 if value == 0x42 => (0x80, true),
+            1 => {
+                self.mode = MultitapMode::Ignored;
+                (0xFF, false)
+            }
+            2 => (0x5A, true),
+            3..35 => {
+                let slot = &mut self.slots[(position - 3) / 8];
+                if (position - 3).is_multiple_of(8) {
+                    slot.deselect();
+                    self.block_done = !slot.transfer(0x01).1;
+                }
+
+                let mut response = 0xFF;
+                if !self.block_done {
+                    let (byte, ack) = slot.transfer(value);
+                    response = byte;
+                    self.block_done = !ack;
+                }
+
+                (response, position < 34)
+            }
+            _ => (0xFF, false),
+        }
+    }
+
+    fn deselect(&mut self) {
+        self.mode = MultitapMode::None;
+        self.position = 0;
+        for slot in &mut self.slots {
+            slot.deselect();
+        }
+    }
+}
+
+enum Port {
+    Direct(Slot),
+    Multitap(Box<Multitap>),
+}
+
+impl Port {
+    fn transfer(&mut self, value: u8) -> (u8, bool) {
+        match self {
+            Port::Direct(slot) => slot.transfer(value),
+            Port::Multitap(multitap) => multitap.transfer(value),
+        }
+    }
+
+    fn deselect(&mut self) {
+        match self {
+            Port::Direct(slot) => slot.deselect(),
+            Port::Multitap(multitap) => multitap.deselect(),
+        }
+    }
+
+    // The slot of a port without multitap is slot A
+    fn slot_mut(&mut self, index: usize) -> Option<&mut Slot> {
+        match self {
+            Port::Direct(slot) if index == 0 => Some(slot),
+            Port::Direct(_) => None,
+            Port::Multitap(multitap) => Some(&mut multitap.slots[index]),
+        }
+    }
+
+    fn first_slot(&self) -> &Slot {
+        match self {
+            Port::Direct(slot) => slot,
+            Port::Multitap(multitap) => &multitap.slots[0],
+        }
+    }
+}
+
+pub struct Sio0 {
+    ports: [Port; 2],
+    // Motor intensities of the first pad in the first port, and whether they changed since the
+    // last check
+    rumble: (u8, u8),
+    rumble_changed: bool,
+    rx_fifo: VecDeque<u8>,
+    // Written to JOY_DATA while the previous byte was still being sent
+    tx_pending: Option<u8>,
+    // Byte being sent and the cycles until it is done
+    transfer: Option<(u8, u32)>,
+    // Cycles until the device pulls /ACK low
+    ack_delay: Option<u32>,
+    // Cycles until /ACK goes high again
+    ack_remaining: Option<u32>,
+    interrupt: bool,
+    interrupt_pending: bool,
+    mode: u16,
+    control: u16,
+    baud: u16,
+}
+
+impl Sio0 {
+    pub fn new() -> Self {
+        Self {
+            ports: [
+                Port::Direct(Slot::new(Some(Box::new(DigitalPad::new())))),
+                Port::Direct(Slot::new(None)),
+            ],
+            rumble: (0, 0),
+            rumble_changed: false,
+            rx_fifo: VecDeque::with_capacity(RX_FIFO_SIZE),
+            tx_pending: None,
+            transfer: None,
+            ack_delay: None,
+            ack_remaining: None,
+            interrupt: false,
+            interrupt_pending: false,
+            mode: 0,
+            control: 0,
+            baud: 0,
+        }
+    }
+
+    // The pad in slot A of the port
+    pub fn pad_mut(&mut self, port: usize) -> Option<&mut (dyn PadDevice + 'static)> {
+        self.ports[port].slot_mut(0)?.pad.as_deref_mut()
+    }
+
+    pub fn set_pad(&mut self, port: usize, pad: Option<Box<dyn PadDevice>>) {
+        self.ports[port].slot_mut(0).unwrap().pad = pad;
+    }
+
+    // Plugging in a multitap moves the devices of the port to slot A and back when unplugging it
+    pub fn set_multitap(&mut self, port: usize, enabled: bool) {
+        let current = std::mem::replace(&mut self.ports[port], Port::Direct(Slot::new(None)));
+        self.ports[port] = match (current, enabled) {
+            (Port::Direct(slot), true) => Port::Multitap(Box::new(Multitap::new(slot))),
+            (Port::Multitap(multitap), false) => {
+                let [first, ..] = multitap.slots;
+                Port::Direct(first)
+            }
+            (current, _) => current,
+        };
+        self.ports[port].deselect();
+    }
+
+    // Slots B to D need a multitap, it is plugged in when missing
+    pub fn set_multitap_pad(&mut self, port: usize, slot: usize, pad: Option<Box<dyn PadDevice>>) {
+        if slot > 0 {
+            self.set_multitap(port, true);
+        }
+        self.ports[port].slot_mut(slot).unwrap().pad = pad;
+    }
+
+    pub fn set_memory_card(&mut self, port: usize, card: Option<MemoryCard>) {
+        self.ports[port].slot_mut(0).unwrap().memory_card = card;
+    }
+
+    pub fn memory_card(&self, port: usize) -> Option<&MemoryCard> {
+        self.ports[port].first_slot().memory_card.as_ref()
+    }
+
+    fn is_selected(&self) -> bool {
+        self.control & 2 != 0
+    }
+
+    // JOY_CTRL bit 13
+    fn selected_port(&self) -> usize {
+        ((self.control >> 13) & 1) as usize
+    }
+
+    // 8 bits at the baud rate, JOY_MODE bits 0..1 select the multiplier of JOY_BAUD
+    fn transfer_cycles(&self) -> u32 {
+        let factor = match self.mode & 3 {
+            2 => 16,
+            3 => 64,
+            _ => 1,
+        };
+
+        (self.baud as u32 * factor).max(1) * 8
+    }
+
+    fn status(&self) -> u32 {
+        let mut status = 0;
+        // TX ready, the next byte can be written
+        status |= self.tx_pending.is_none() as u32;
+        status |= (!self.rx_fifo.is_empty() as u32) << 1;
+        // TX finished
+        status |= ((self.transfer.is_none() && self.tx_pending.is_none()) as u32) << 2;
+        // The /ACK input, set while it is low
+        status |= (self.ack_remaining.is_some() as u32) << 7;
+        status |= (self.interrupt as u32) << 9;
+
+        status
+    }
+
+    // Offsets are relative to 0x1F801040
+    pub fn read(&mut self, offset: u32) -> u32 {
+        match offset {
+            0x0 => self.rx_fifo.pop_front().unwrap_or(0xFF) as u32,
+            0x4 => self.status(),
+            0x8 => self.mode as u32 | ((self.control as u32) << 16),
+            0xC => (self.baud as u32) << 16,
+            _ => 0,
+        }
+    }
+
+    // The registers are at most 16 bit wide, word writes are split by the caller
+    pub fn write(&mut self, offset: u32, value: u16) {
+        match offset {
+            0x0 => {
+                if self.transfer.is_none() {
+                    self.start_transfer(value as u8);
+                } else {
+                    self.tx_pending = Some(value as u8);
+                }
+            }
+            0x8 => self.mode = value,
+            0xA => self.write_control(value),
+            0xE => self.baud = value,
+            _ => {}
+        }
+    }
+
+    fn write_control(&mut self, value: u16) {
+        // Reset
+        if value & (1 << 6) != 0 {
+            *self = Self {
+                ports: std::mem::replace(
+                    &mut self.ports,
+                    [Port::Direct(Slot::new(None)), Port::Direct(Slot::new(None))],
+                ),
+                ..Self::new()
+            };
+            self.deselect_all();
+            return;
+        }
+
+        let was_selected = self.is_selected();
+        // Bit 4 acknowledges the interrupt and is not stored
+        self.control = value & !(1 << 4);
+        if value & (1 << 4) != 0 {
+            self.interrupt = false;
+        }
+
+        if was_selected && !self.is_selected() {
+            self.deselect_all();
+        }
+    }
+
+    fn deselect_all(&mut self) {
+        for port in &mut self.ports {
+            port.deselect();
+        }
+    }
+
+    fn start_transfer(&mut self, value: u8) {
+        self.transfer = Some((value, self.transfer_cycles()));
+    }
+
+    // The byte is exchanged with the selected device once all bits were shifted out
+    fn finish_transfer(&mut self, value: u8) {
+        let port = self.selected_port();
+        let (response, ack) = if self.is_selected() {
+            self.ports[port].transfer(value)
+        } else {
+            (0xFF, false)
+        };
+
+        let rumble = match &self.ports[0].first_slot().pad {
+            Some(pad) => pad.rumble(),
+            None => (0, 0),
+        };
+        if rumble != self.rumble {
+            self.rumble = rumble;
+            self.rumble_changed = true;
+        }
+
+        if self.rx_fifo.len() == RX_FIFO_SIZE {
+            self.rx_fifo.pop_front();
+        }
+        self.rx_fifo.push_back(response);
+
+        if ack {
+            self.ack_delay = Some(ACK_DELAY);
+        }
+
+        if let Some(value) = self.tx_pending.take() {
+            self.start_transfer(value);
+        }
+    }
+
+    // Runs the transfer and /ACK timers
+    pub fn step(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        if let Some((value, remaining)) = self.transfer {
+            if remaining <= cycles {
+                self.transfer = None;
+                self.finish_transfer(value);
+                // The /ACK delay starts when the byte is done
+                cycles -= remaining;
+            } else {
+                self.transfer = Some((value, remaining - cycles));
+            }
+        }
+
+        if let Some(remaining) = self.ack_delay {
+            if remaining <= cycles {
+                self.ack_delay = None;
+                self.ack_remaining = Some(ACK_LENGTH);
+
+                // ACK interrupt enable
+                if self.control & (1 << 12) != 0 && !self.interrupt {
+                    self.interrupt = true;
+                    self.interrupt_pending = true;
+                }
+            } else {
+                self.ack_delay = Some(remaining - cycles);
+            }
+        } else if let Some(remaining) = self.ack_remaining {
+            self.ack_remaining = remaining.checked_sub(cycles).filter(|&left| left > 0);
+        }
+    }
+
+    pub fn cycles_until_event(&self) -> u32 {
+        [
+            self.transfer.map(|(_, remaining)| remaining),
+            self.ack_delay,
+            self.ack_remaining,
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+        .unwrap_or(u32::MAX)
+    }
+
+    pub fn take_rumble(&mut self) -> Option<(u8, u8)> {
+        std::mem::take(&mut self.rumble_changed).then_some(self.rumble)
+    }
+
+    // Whether IRQ7 has to be requested since the last call
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.interrupt_pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // TX enable, /JOY of port 1 and the ACK interrupt
+    const SELECT: u16 = 0x1003;
+
+    fn sio0() -> Sio0 {
+        let mut sio = Sio0::new();
+        sio.write(0xE, 0x88);
+        sio.write(0x8, 0x0D);
+        sio.write(0xA, SELECT);
+        sio
+    }
+
+    // Sends a byte, returns the response and whether the device raised IRQ7 for it
+    fn exchange(sio: &mut Sio0, value: u8) -> (u8, bool) {
+        sio.write(0x0, value as u16);
+        sio.step(0x88 * 8);
+        assert_ne!(sio.read(0x4) & 2, 0);
+        let response = sio.read(0x0) as u8;
+
+        sio.step(ACK_DELAY + ACK_LENGTH);
+        let acknowledged = sio.take_interrupt();
+        // Acknowledge the interrupt
+        sio.write(0xA, SELECT | (1 << 4));
+        (response, acknowledged)
+    }
+
+    #[test]
+    fn digital_pad_answers_a_poll() {
+        let mut sio = sio0();
+        sio.pad_mut(0).unwrap().set_button(Button::Cross, true);
+        sio.pad_mut(0).unwrap().set_button(Button::Start, true);
+
+        let responses: Vec<(u8, bool)> = [0x01, 0x42, 0x00, 0x00, 0x00]
+            .into_iter()
+            .map(|value| exchange(&mut sio, value))
+            .collect();
+        assert_eq!(
+            responses,
+            [
+                (0xFF, true),
+                (0x41, true),
+                (0x5A, true),
+                (0xF7, true),
+                (0xBF, false)
+            ]
+        );
+
+        // The next poll starts after /JOY went high
+        sio.write(0xA, 0);
+        sio.write(0xA, SELECT);
+        assert_eq!(exchange(&mut sio, 0x01), (0xFF, true));
+    }
+
+    #[test]
+    fn ack_comes_after_a_delay() {
+        let mut sio = sio0();
+        sio.write(0x0, 0x01);
+        // TX ready is set again once the byte has moved to the shift register
+        assert_eq!(sio.read(0x4) & 5, 1);
+        sio.step(0x88 * 8);
+        assert_eq!(sio.read(0x4) & 5, 5);
+
+        sio.step(ACK_DELAY - 1);
+        assert!(!sio.take_interrupt());
+        assert_eq!(sio.read(0x4) & (1 << 7), 0);
+        sio.step(1);
+        assert!(sio.take_interrupt());
+        assert_eq!(sio.read(0x4) & 0x280, 0x280);
+
+        // /ACK goes high again, the interrupt stays until it is acknowledged
+        sio.step(ACK_LENGTH);
+        assert_eq!(sio.read(0x4) & 0x280, 0x200);
+        sio.write(0xA, SELECT | (1 << 4));
+        assert_eq!(sio.read(0x4) & (1 << 9), 0);
+    }
+
+    #[test]
+    fn rx_fifo_buffers_the_responses() {
+        let mut sio = sio0();
+        // The second byte waits for the first one to be sent
+        sio.write(0x0, 0x01);
+        sio.write(0x0, 0x42);
+        assert_eq!(sio.read(0x4) & 1, 0);
+        sio.step(0x88 * 8);
+        sio.step(0x88 * 8);
+
+        assert_eq!(sio.read(0x0), 0xFF);
+        assert_eq!(sio.read(0x0), 0x41);
+        assert_eq!(sio.read(0x4) & 2, 0);
+        // An empty FIFO reads as 0xFF
+        assert_eq!(sio.read(0x0), 0xFF);
+    }
+
+    // Sends a command to the pad, it has to acknowledge all but the last byte
+    fn command(pad: &mut dyn PadDevice, bytes: &[u8]) -> Vec<u8> {
+        pad.deselect();
+        bytes
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| {
+                let (response, ack) = pad.transfer(*byte);
+                assert_eq!(ack, i < bytes.len() - 1, "byte {}", i);
+                response
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dualshock_config_mode_switches_to_analog() {
+        let mut pad = DualShock::new();
+        pad.set_axis(Axis::LeftX, 0x12);
+        pad.set_axis(Axis::RightY, 0xEE);
+        pad.set_button(Button::Square, true);
+
+        // Digital mode answers without the axes
+        let poll = [0x01, 0x42, 0x00, 0x00, 0x00];
+        assert_eq!(command(&mut pad, &poll), [0xFF, 0x41, 0x5A, 0xFF, 0x7F]);
+
+        // Enter config mode, switch to analog and lock the mode
+        assert_eq!(command(&mut pad, &[0x01, 0x43, 0x00, 0x01, 0x00])[1], 0x41);
+        let response = command(
+            &mut pad,
+            &[0x01, 0x44, 0x00, 0x01, 0x03, 0x00, 0x00, 0x00, 0x00],
+        );
+        assert_eq!(response[1], 0xF3);
+        let status = command(
+            &mut pad,
+            &[0x01, 0x45, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        );
+        assert_eq!(&status[2..], &[0x5A, 0x01, 0x02, 0x01, 0x02, 0x01, 0x00]);
+        // The first byte drives the small motor, the second one the large motor
+        let mapping = [0x01, 0x4D, 0x00, 0x00, 0x01, 0xFF, 0xFF, 0xFF, 0xFF];
+        assert_eq!(&command(&mut pad, &mapping)[3..], &[0xFF; 6]);
+        command(
+            &mut pad,
+            &[0x01, 0x43, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        );
+
+        let poll = [0x01, 0x42, 0x00, 0x01, 0x80, 0x00, 0x00, 0x00, 0x00];
+        assert_eq!(
+            command(&mut pad, &poll),
+            [0xFF, 0x73, 0x5A, 0xFF, 0x7F, 0x80, 0xEE, 0x12, 0x80]
+        );
+        assert_eq!(pad.rumble(), (0xFF, 0x80));
+
+        // The analog button is locked
+        pad.toggle_analog();
+        assert_eq!(command(&mut pad, &poll)[1], 0x73);
+    }
+
+    #[test]
+    fn dualshock_analog_button_toggles_the_axes() {
+        let mut pad = DualShock::new();
+        let poll = [0x01, 0x42, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+        pad.toggle_analog();
+        assert_eq!(command(&mut pad, &poll)[1..3], [0x73, 0x5A]);
+        assert_eq!(&command(&mut pad, &poll)[5..], &[0x80; 4]);
+
+        pad.toggle_analog();
+        assert_eq!(
+            command(&mut pad, &poll[..5]),
+            [0xFF, 0x41, 0x5A, 0xFF, 0xFF]
+        );
+        // Without a mapping the motors stay off
+        assert_eq!(pad.rumble(), (0, 0));
+    }
+
+    #[test]
+    fn multitap_returns_all_four_slots() {
+        let mut sio = sio0();
+        let mut pad = DigitalPad::new();
+        pad.set_button(Button::Cross, true);
+        sio.set_multitap_pad(0, 2, Some(Box::new(pad)));
+
+        let poll = |sio: &mut Sio0, bytes: &[u8]| -> Vec<(u8, bool)> {
+            sio.write(0xA, 0);
+            sio.write(0xA, SELECT);
+            bytes.iter().map(|byte| exchange(sio, *byte)).collect()
+        };
+
+        // Addressed through slot C, the first byte is passed on as 0x01
+        let responses = poll(&mut sio, &[0x03, 0x42, 0x00, 0x00, 0x00]);
+        assert_eq!(responses[4], (0xBF, false));
+        // Slot B is empty
+        assert_eq!(poll(&mut sio, &[0x02, 0x42])[0], (0xFF, false));
+
+        // 0x01 as the third byte of a poll of slot A switches to the long format
+        poll(&mut sio, &[0x01, 0x42, 0x01, 0x00, 0x00]);
+        let mut bytes = vec![0x01, 0x42, 0x00];
+        for _ in 0..4 {
+            bytes.extend_from_slice(&[0x42, 0, 0, 0, 0, 0, 0, 0]);
+        }
+        let responses = poll(&mut sio, &bytes);
+        assert_eq!(responses.len(), 35);
+        assert!(responses[..34].iter().all(|(_, ack)| *ack));
+        assert!(!responses[34].1);
+
+        let bytes: Vec<u8> = responses.iter().map(|(byte, _)| *byte).collect();
+        assert_eq!(&bytes[..3], &[0xFF, 0x80, 0x5A]);
+        let pad = [0x41, 0x5A, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        let empty = [0xFF; 8];
+        assert_eq!(&bytes[3..11], &pad);
+        assert_eq!(&bytes[11..19], &empty);
+        assert_eq!(
+            &bytes[19..27],
+            &[0x41, 0x5A, 0xFF, 0xBF, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(&bytes[27..35], &empty);
+    }
+}
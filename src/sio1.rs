@@ -0,0 +1,375 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+// Serial port 1, the link cable port. The bytes are passed to a SerialLink, which carries them to
+// another emulator (or back to this one).
+
+const RX_FIFO_SIZE: usize = 8;
+
+// Carries the bytes sent through the serial port to the other end of the cable
+pub trait SerialLink {
+    fn send(&mut self, byte: u8);
+    // The next byte from the other end, None when nothing arrived yet
+    fn recv(&mut self) -> Option<u8>;
+}
+
+// Both ends in the same process, e.g. two emulators stepped on one thread
+pub struct ChannelLink {
+    sender: Sender<u8>,
+    receiver: Receiver<u8>,
+}
+
+impl ChannelLink {
+    pub fn pair() -> (Self, Self) {
+        let (sender_a, receiver_b) = channel();
+        let (sender_b, receiver_a) = channel();
+
+        (
+            Self {
+                sender: sender_a,
+                receiver: receiver_a,
+            },
+            Self {
+                sender: sender_b,
+                receiver: receiver_b,
+            },
+        )
+    }
+}
+
+impl SerialLink for ChannelLink {
+    // The other end may be gone already, the bytes are dropped like on an unplugged cable
+    fn send(&mut self, byte: u8) {
+        let _ = self.sender.send(byte);
+    }
+
+    fn recv(&mut self) -> Option<u8> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+// A cable between two emulator processes, one of them listens and the other connects
+pub struct TcpLink {
+    stream: TcpStream,
+    // Bytes the socket did not take yet
+    pending: Vec<u8>,
+}
+
+impl TcpLink {
+    pub fn connect(address: impl ToSocketAddrs) -> Result<Self, io::Error> {
+        Self::from_stream(TcpStream::connect(address)?)
+    }
+
+    // Blocks until the other end connected
+    pub fn listen(address: impl ToSocketAddrs) -> Result<Self, io::Error> {
+        let (stream, _) = TcpListener::bind(address)?.accept()?;
+        Self::from_stream(stream)
+    }
+
+    fn from_stream(stream: TcpStream) -> Result<Self, io::Error> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+
+        Ok(Self {
+            stream,
+            pending: Vec::new(),
+        })
+    }
+
+    fn flush(&mut self) {
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => break,
+                Ok(written) => {
+                    self.pending.drain(..written);
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) => {
+                    eprintln!("Serial link failed: {}", error);
+                    self.pending.clear();
+                }
+            }
+        }
+    }
+}
+
+impl SerialLink for TcpLink {
+    fn send(&mut self, byte: u8) {
+        self.pending.push(byte);
+        self.flush();
+    }
+
+    fn recv(&mut self) -> Option<u8> {
+        self.flush();
+
+        let mut byte = [0];
+        match self.stream.read(&mut byte) {
+            Ok(1) => Some(byte[0]),
+            _ => None,
+        }
+    }
+}
+
+// TX wired to RX, every sent byte is received again
+pub struct Loopback {
+    bytes: VecDeque<u8>,
+}
+
+impl Loopback {
+    pub fn new() -> Self {
+        Self {
+            bytes: VecDeque::new(),
+        }
+    }
+}
+
+impl Default for Loopback {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SerialLink for Loopback {
+    fn send(&mut self, byte: u8) {
+        self.bytes.push_back(byte);
+    }
+
+    fn recv(&mut self) -> Option<u8> {
+        self.bytes.pop_front()
+    }
+}
+
+pub struct Sio1 {
+    link: Option<Box<dyn SerialLink>>,
+    rx_fifo: VecDeque<u8>,
+    // Written to SIO_DATA while the previous byte was still being sent
+    tx_pending: Option<u8>,
+    // Byte being sent and the cycles until it is done
+    transfer: Option<(u8, u32)>,
+    // Cycles until the next byte can be received, a character takes as long in both directions
+    rx_delay: u32,
+    overrun: bool,
+    interrupt: bool,
+    interrupt_pending: bool,
+    mode: u16,
+    control: u16,
+    misc: u16,
+    baud: u16,
+}
+
+impl Sio1 {
+    pub fn new() -> Self {
+        Self {
+            link: None,
+            rx_fifo: VecDeque::with_capacity(RX_FIFO_SIZE),
+            tx_pending: None,
+            transfer: None,
+            rx_delay: 0,
+            overrun: false,
+            interrupt: false,
+            interrupt_pending: false,
+            mode: 0,
+            control: 0,
+            misc: 0,
+            baud: 0,
+        }
+    }
+
+    pub fn set_link(&mut self, link: Option<Box<dyn SerialLink>>) {
+        self.link = link;
+    }
+
+    // Start bit, 5 to 8 data bits (SIO_MODE bits 2..3), parity (bit 4) and the stop bits (bits 6..7)
+    fn character_cycles(&self) -> u32 {
+        let factor = match self.mode & 3 {
+            2 => 16,
+            3 => 64,
+            _ => 1,
+        };
+        let data_bits = 5 + ((self.mode >> 2) & 3) as u32;
+        let parity_bits = ((self.mode >> 4) & 1) as u32;
+        let stop_bits = match (self.mode >> 6) & 3 {
+            2 | 3 => 2,
+            _ => 1,
+        };
+
+        (self.baud as u32 * factor).max(1) * (1 + data_bits + parity_bits + stop_bits)
+    }
+
+    fn tx_enabled(&self) -> bool {
+        self.control & 1 != 0
+    }
+
+    fn rx_enabled(&self) -> bool {
+        self.control & (1 << 2) != 0
+    }
+
+    fn status(&self) -> u32 {
+        // The other end of the cable is assumed to always be ready, so DSR and CTS follow whether
+        // there is a cable at all
+        let connected = self.link.is_some() as u32;
+
+        let mut status = 0;
+        status |= self.tx_pending.is_none() as u32;
+        status |= (!self.rx_fifo.is_empty() as u32) << 1;
+        status |= ((self.transfer.is_none() && self.tx_pending.is_none()) as u32) << 2;
+        status |= (self.overrun as u32) << 4;
+        status |= connected << 7;
+        status |= connected << 8;
+        status |= (self.interrupt as u32) << 9;
+
+        status
+    }
+
+    // Offsets are relative to 0x1F801050
+    pub fn read(&mut self, offset: u32) -> u32 {
+        match offset {
+            0x0 => {
+                let value = self.rx_fifo.pop_front().unwrap_or(0) as u32;
+                self.update_interrupt();
+                value
+            }
+            0x4 => self.status(),
+            0x8 => self.mode as u32 | ((self.control as u32) << 16),
+            0xC => self.misc as u32 | ((self.baud as u32) << 16),
+            _ => 0,
+        }
+    }
+
+    // The registers are at most 16 bit wide, word writes are split by the caller
+    pub fn write(&mut self, offset: u32, value: u16) {
+        match offset {
+            0x0 => {
+                if self.transfer.is_none() && self.tx_enabled() {
+                    self.transfer = Some((value as u8, self.character_cycles()));
+                } else {
+                    self.tx_pending = Some(value as u8);
+                }
+            }
+            0x8 => self.mode = value,
+            0xA => self.write_control(value),
+            0xC => self.misc = value,
+            0xE => self.baud = value,
+            _ => {}
+        }
+
+        self.update_interrupt();
+    }
+
+    fn write_control(&mut self, value: u16) {
+        // Reset
+        if value & (1 << 6) != 0 {
+            *self = Self {
+                link: self.link.take(),
+                ..Self::new()
+            };
+            return;
+        }
+
+        // Bit 4 acknowledges the interrupt and the errors and is not stored
+        self.control = value & !(1 << 4);
+        if value & (1 << 4) != 0 {
+            self.interrupt = false;
+            self.overrun = false;
+        }
+
+        if self.transfer.is_none() && self.tx_enabled() {
+            self.start_pending();
+        }
+    }
+
+    fn start_pending(&mut self) {
+        if let Some(value) = self.tx_pending.take() {
+            self.transfer = Some((value, self.character_cycles()));
+        }
+    }
+
+    // SIO_CTRL bit 10 requests IRQ8 while the next byte can be written, bit 11 once the RX FIFO
+    // holds 1, 2, 4 or 8 bytes (bits 8..9)
+    fn update_interrupt(&mut self) {
+        let rx_threshold = 1 << ((self.control >> 8) & 3);
+        let rx = self.control & (1 << 11) != 0 && self.rx_fifo.len() >= rx_threshold;
+        let tx = self.control & (1 << 10) != 0 && self.tx_pending.is_none();
+
+        if (rx || tx) && !self.interrupt {
+            self.interrupt = true;
+            self.interrupt_pending = true;
+        }
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        if let Some((value, remaining)) = self.transfer {
+            if remaining <= cycles {
+                self.transfer = None;
+                if let Some(link) = &mut self.link {
+                    link.send(value);
+                }
+                if self.tx_enabled() {
+                    self.start_pending();
+                }
+            } else {
+                self.transfer = Some((value, remaining - cycles));
+            }
+        }
+
+        self.rx_delay = self.rx_delay.saturating_sub(cycles);
+        if self.rx_delay == 0 && self.rx_enabled() {
+            if let Some(value) = self.link.as_mut().and_then(|link| link.recv()) {
+                if self.rx_fifo.len() == RX_FIFO_SIZE {
+                    self.overrun = true;
+                } else {
+                    self.rx_fifo.push_back(value);
+                }
+                self.rx_delay = self.character_cycles();
+            }
+        }
+
+        self.update_interrupt();
+    }
+
+    // Received bytes are picked up whenever the devices run, only the transmission is timed
+    pub fn cycles_until_event(&self) -> u32 {
+        self.transfer.map_or(u32::MAX, |(_, remaining)| remaining)
+    }
+
+    // Whether IRQ8 has to be requested since the last call
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.interrupt_pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loopback_receives_the_sent_byte_and_raises_irq8() {
+        let mut sio = Sio1::new();
+        sio.set_link(Some(Box::new(Loopback::new())));
+        sio.write(0x8, 0x0D);
+        sio.write(0xE, 0x20);
+        // TX and RX enabled, RX interrupt at one byte
+        sio.write(0xA, 0x0805);
+        assert_eq!(sio.read(0x4) & 0x187, 0x185);
+
+        sio.write(0x0, 0xA5);
+        assert_eq!(sio.read(0x4) & 4, 0);
+        // A start bit, 8 data bits and a stop bit
+        sio.step(0x20 * 10 - 1);
+        assert_eq!(sio.read(0x4) & 6, 0);
+        sio.step(1);
+        assert_eq!(sio.read(0x4) & 6, 6);
+        assert!(sio.take_interrupt());
+        assert_ne!(sio.read(0x4) & (1 << 9), 0);
+
+        assert_eq!(sio.read(0x0), 0xA5);
+        assert_eq!(sio.read(0x4) & 2, 0);
+        sio.write(0xA, 0x0815);
+        assert_eq!(sio.read(0x4) & (1 << 9), 0);
+        assert!(!sio.take_interrupt());
+    }
+}
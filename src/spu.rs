@@ -0,0 +1,1105 @@
+use std::collections::VecDeque;
+
+use crate::dma::DmaDevice;
+
+pub const SOUND_RAM_SIZE: usize = 512 * 1024;
+
+// One output sample every 768 CPU cycles, 44100 Hz
+const CYCLES_PER_SAMPLE: u32 = 768;
+// Samples nobody took are dropped after a second
+const MAX_BUFFERED_SAMPLES: usize = 44100;
+
+const VOICE_COUNT: usize = 24;
+// Halfwords written to the transfer FIFO before they go to the sound RAM
+const TRANSFER_FIFO_SIZE: usize = 32;
+
+// SPUCNT bits
+const CONTROL_CD_AUDIO: u16 = 1 << 0;
+const CONTROL_IRQ_ENABLE: u16 = 1 << 6;
+const CONTROL_UNMUTE: u16 = 1 << 14;
+const CONTROL_ENABLE: u16 = 1 << 15;
+// SPUSTAT mirrors SPUCNT bits 0..5
+const STATUS_CONTROL_MASK: u16 = 0x3F;
+const STATUS_IRQ: u16 = 1 << 6;
+const STATUS_DMA_REQUEST: u16 = 1 << 7;
+const STATUS_DMA_WRITE_REQUEST: u16 = 1 << 8;
+const STATUS_DMA_READ_REQUEST: u16 = 1 << 9;
+
+// ADPCM blocks are 16 bytes, a header and flags followed by 28 4 bit samples
+const BLOCK_SIZE: u32 = 16;
+const BLOCK_SAMPLES: usize = 28;
+// Block flags, the second byte
+const FLAG_LOOP_END: u8 = 1 << 0;
+const FLAG_LOOP_REPEAT: u8 = 1 << 1;
+const FLAG_LOOP_START: u8 = 1 << 2;
+
+// Prediction filter weights in 1/64
+const POSITIVE_WEIGHTS: [i32; 5] = [0, 60, 115, 98, 122];
+const NEGATIVE_WEIGHTS: [i32; 5] = [0, 0, -52, -55, -60];
+
+// Phases of the envelope. Attack goes up to the maximum, decay down to the sustain level, sustain
+// holds until key off and release goes down to 0.
+#[derive(Clone, Copy, Default, PartialEq)]
+enum AdsrPhase {
+    Attack,
+    Decay,
+    Sustain,
+    #[default]
+    Release,
+}
+
+// A voice, the registers at 0x1F801C00 + voice * 0x10 and the playback state
+#[derive(Clone, Copy, Default)]
+struct Voice {
+    volume: (u16, u16),
+    // 0x1000 plays the sample at 44100 Hz
+    pitch: u16,
+    // Sound RAM addresses in 8 byte units
+    start_address: u16,
+    repeat_address: u16,
+    // Attack, decay, sustain and release, the low and high halfword
+    adsr: u32,
+    // ENVX, the current level of the envelope
+    envelope: u16,
+    phase: AdsrPhase,
+    // Samples since the last envelope step
+    envelope_cycles: u32,
+    // Volumes after the sweep, at 0x1F801E00 + voice * 4
+    current_volume: (u16, u16),
+
+    // Byte address of the block being played and the position in it, a 4.12 fixed point sample
+    // counter
+    address: u32,
+    counter: u32,
+    flags: u8,
+    samples: [i16; BLOCK_SAMPLES],
+    // The last samples of the previous block, for the interpolation
+    previous: [i16; 3],
+    // The two last decoded samples, for the prediction filter
+    history: [i32; 2],
+    // The last output after the envelope, the next voice can use it for pitch modulation
+    output: i16,
+    // Byte of the last sample played, or of the header right after a block was decoded. The IRQ
+    // address is checked against the bytes the voice moves over.
+    last_byte: u32,
+}
+
+impl Voice {
+    fn key_on(&mut self, ram: &[u8]) {
+        self.address = self.start_address as u32 * 8;
+        self.counter = 0;
+        self.previous = [0; 3];
+        self.history = [0; 2];
+        self.envelope = 0;
+        self.phase = AdsrPhase::Attack;
+        self.envelope_cycles = 0;
+        self.decode_block(ram);
+    }
+
+    fn key_off(&mut self) {
+        self.phase = AdsrPhase::Release;
+        self.envelope_cycles = 0;
+    }
+
+    // Shift, step and whether the current phase is exponential, from the ADSR register
+    fn envelope_parameters(&self) -> (u32, i32, bool) {
+        let adsr = self.adsr;
+        match self.phase {
+            AdsrPhase::Attack => (
+                (adsr >> 10) & 0x1F,
+                7 - ((adsr >> 8) & 3) as i32,
+                adsr & (1 << 15) != 0,
+            ),
+            AdsrPhase::Decay => ((adsr >> 4) & 0xF, -8, true),
+            AdsrPhase::Sustain => {
+                let step = ((adsr >> 22) & 3) as i32;
+                let step = match adsr & (1 << 30) {
+                    0 => 7 - step,
+                    _ => -8 + step,
+                };
+                ((adsr >> 24) & 0x1F, step, adsr & (1 << 31) != 0)
+            }
+            AdsrPhase::Release => ((adsr >> 16) & 0x1F, -8, adsr & (1 << 21) != 0),
+        }
+    }
+
+    // Every 2^(shift - 11) samples the level moves by step << (11 - shift). Exponential increases
+    // are 4 times slower above 0x6000, exponential decreases are scaled by the level.
+    fn tick_envelope(&mut self) {
+        let (shift, step, exponential) = self.envelope_parameters();
+        let level = self.envelope as i32;
+
+        let mut cycles = 1 << shift.saturating_sub(11);
+        let mut step = step << 11u32.saturating_sub(shift);
+        if exponential && step > 0 && level > 0x6000 {
+            cycles *= 4;
+        }
+        if exponential && step < 0 {
+            step = step * level / 0x8000;
+        }
+
+        self.envelope_cycles += 1;
+        if self.envelope_cycles < cycles {
+            return;
+        }
+        self.envelope_cycles = 0;
+
+        let level = (level + step).clamp(0, 0x7FFF);
+        self.envelope = level as u16;
+
+        let sustain_level = ((self.adsr & 0xF) as i32 + 1) * 0x800;
+        match self.phase {
+            AdsrPhase::Attack if level == 0x7FFF => self.phase = AdsrPhase::Decay,
+            AdsrPhase::Decay if level <= sustain_level => self.phase = AdsrPhase::Sustain,
+            _ => {}
+        }
+    }
+
+    // The sample at the counter, interpolated from it and the 3 before. Then the counter moves on
+    // by the step. Returns None while the voice stays in its block, otherwise whether the block it
+    // left had the loop end flag.
+    fn tick(&mut self, ram: &[u8], step: u32, interpolation: Interpolation) -> Option<bool> {
+        let interpolated = self.interpolate(interpolation);
+        self.output = ((interpolated * self.envelope as i16 as i32) >> 15) as i16;
+        self.tick_envelope();
+
+        // Steps are at most 4 samples, so only one block is fetched per sample
+        self.counter += step;
+        if self.counter < (BLOCK_SAMPLES as u32) << 12 {
+            return None;
+        }
+        self.counter -= (BLOCK_SAMPLES as u32) << 12;
+
+        Some(self.next_block(ram))
+    }
+
+    // The gaussian table weights the 4 samples by the 8 bit fraction of the counter. It mostly
+    // mixes the middle two, which linear interpolation does alone.
+    fn interpolate(&self, interpolation: Interpolation) -> i32 {
+        let index = (self.counter >> 12) as usize;
+        let sample = |offset: usize| match (index + offset).checked_sub(3) {
+            Some(index) => self.samples[index] as i32,
+            None => self.previous[index + offset] as i32,
+        };
+        let position = ((self.counter >> 4) & 0xFF) as usize;
+
+        let interpolated = match interpolation {
+            Interpolation::Gaussian => {
+                ((GAUSSIAN_TABLE[0xFF - position] * sample(0)) >> 15)
+                    + ((GAUSSIAN_TABLE[0x1FF - position] * sample(1)) >> 15)
+                    + ((GAUSSIAN_TABLE[0x100 + position] * sample(2)) >> 15)
+                    + ((GAUSSIAN_TABLE[position] * sample(3)) >> 15)
+            }
+            Interpolation::Linear => sample(1) + (((sample(2) - sample(1)) * position as i32) >> 8),
+        };
+        interpolated.clamp(i16::MIN as i32, i16::MAX as i32)
+    }
+
+    // Blocks with the loop end flag jump to the repeat address, without the repeat flag as well
+    // the voice stops
+    fn next_block(&mut self, ram: &[u8]) -> bool {
+        let ended = self.flags & FLAG_LOOP_END != 0;
+        if ended {
+            self.address = self.repeat_address as u32 * 8;
+            if self.flags & FLAG_LOOP_REPEAT == 0 {
+                self.phase = AdsrPhase::Release;
+                self.envelope = 0;
+            }
+        } else {
+            self.address = (self.address + BLOCK_SIZE) % SOUND_RAM_SIZE as u32;
+        }
+
+        self.previous
+            .copy_from_slice(&self.samples[BLOCK_SAMPLES - 3..]);
+        self.decode_block(ram);
+        ended
+    }
+
+    // Byte of the sample the voice plays next, two samples to a byte after the 2 header bytes
+    fn sample_byte(&self) -> u32 {
+        self.address + 2 + (self.counter >> 13)
+    }
+
+    // The header holds the shift in the low and the filter in the high nibble
+    fn decode_block(&mut self, ram: &[u8]) {
+        self.last_byte = self.address + 1;
+        // A block starting in the last 8 bytes wraps around to the start of the sound RAM
+        let block: [u8; BLOCK_SIZE as usize] =
+            std::array::from_fn(|i| ram[(self.address as usize + i) % SOUND_RAM_SIZE]);
+        // Shifts above 12 behave like 9
+        let shift = match block[0] & 0xF {
+            shift @ 0..=12 => shift,
+            _ => 9,
+        };
+        let filter = (((block[0] >> 4) & 7) as usize).min(4);
+        self.flags = block[1];
+        if self.flags & FLAG_LOOP_START != 0 {
+            self.repeat_address = (self.address / 8) as u16;
+        }
+
+        let [old, older] = &mut self.history;
+        for (i, sample) in self.samples.iter_mut().enumerate() {
+            let nibble = (block[2 + i / 2] >> ((i & 1) * 4)) & 0xF;
+            let raw = ((nibble as i16) << 12) >> shift;
+
+            let prediction =
+                (*old * POSITIVE_WEIGHTS[filter] + *older * NEGATIVE_WEIGHTS[filter] + 32) / 64;
+            let value = (raw as i32 + prediction).clamp(i16::MIN as i32, i16::MAX as i32);
+
+            *older = *old;
+            *old = value;
+            *sample = value as i16;
+        }
+    }
+}
+
+// Sound Processing Unit, the 24 ADPCM voices, the sound RAM transfers, the CD audio input and the
+// sound RAM IRQ. Reverb and noise are not emulated yet.
+pub struct Spu {
+    sound_ram: Box<[u8; SOUND_RAM_SIZE]>,
+    voices: [Voice; VOICE_COUNT],
+
+    // SPUCNT
+    control: u16,
+    main_volume: (u16, u16),
+    current_main_volume: (u16, u16),
+    reverb_volume: (u16, u16),
+    cd_volume: (u16, u16),
+    extern_volume: (u16, u16),
+
+    // One bit per voice. Key on and off read back the last written value, the end flags are set
+    // by the hardware.
+    key_on: u32,
+    key_off: u32,
+    pitch_modulation: u32,
+    noise: u32,
+    reverb_enable: u32,
+    end_flags: u32,
+
+    // In 8 byte units
+    reverb_start: u16,
+    irq_address: u16,
+    // SPUSTAT bit 6, set when the IRQ address is accessed and cleared by disabling the IRQ
+    interrupt: bool,
+    interrupt_pending: bool,
+    // The reverb configuration at 0x1F801DC0
+    reverb: [u16; 32],
+
+    // The register holds the address in 8 byte units
+    transfer_address_register: u16,
+    // Byte address the next transferred halfword goes to
+    transfer_address: u32,
+    transfer_fifo: Vec<u16>,
+    // Usually 0x0004, the other values change how the halfwords are stored
+    transfer_control: u16,
+
+    // Cycles since the last output sample
+    cycles: u32,
+    output: VecDeque<(i16, i16)>,
+    interpolation: Interpolation,
+}
+
+// How voices resample their ADPCM samples
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Interpolation {
+    // The 4 tap gaussian filter of the console, SPU test programs check its exact output
+    Gaussian,
+    // Between the two samples closest to the counter, cheaper but duller at high pitches
+    Linear,
+}
+
+// SPUCNT bits 4..5
+#[derive(Clone, Copy, PartialEq)]
+enum TransferMode {
+    Stop,
+    ManualWrite,
+    DmaWrite,
+    DmaRead,
+}
+
+impl Spu {
+    pub fn new() -> Self {
+        Self {
+            sound_ram: vec![0; SOUND_RAM_SIZE].try_into().unwrap(),
+            voices: [Voice::default(); VOICE_COUNT],
+            control: 0,
+            main_volume: (0, 0),
+            current_main_volume: (0, 0),
+            reverb_volume: (0, 0),
+            cd_volume: (0, 0),
+            extern_volume: (0, 0),
+            key_on: 0,
+            key_off: 0,
+            pitch_modulation: 0,
+            noise: 0,
+            reverb_enable: 0,
+            end_flags: 0,
+            reverb_start: 0,
+            irq_address: 0,
+            interrupt: false,
+            interrupt_pending: false,
+            reverb: [0; 32],
+            transfer_address_register: 0,
+            transfer_address: 0,
+            transfer_fifo: Vec::with_capacity(TRANSFER_FIFO_SIZE),
+            transfer_control: 0,
+            cycles: 0,
+            output: VecDeque::new(),
+            interpolation: Interpolation::Gaussian,
+        }
+    }
+
+    fn transfer_mode(&self) -> TransferMode {
+        match (self.control >> 4) & 3 {
+            0 => TransferMode::Stop,
+            1 => TransferMode::ManualWrite,
+            2 => TransferMode::DmaWrite,
+            _ => TransferMode::DmaRead,
+        }
+    }
+
+    // SPUCNT bits 0..5 and the DMA requests of the transfer mode. Transfers complete instantly, so
+    // the busy bit is never set.
+    fn status(&self) -> u16 {
+        let mut status = self.control & STATUS_CONTROL_MASK;
+        if self.interrupt {
+            status |= STATUS_IRQ;
+        }
+        match self.transfer_mode() {
+            TransferMode::DmaWrite => status |= STATUS_DMA_REQUEST | STATUS_DMA_WRITE_REQUEST,
+            TransferMode::DmaRead => status |= STATUS_DMA_REQUEST | STATUS_DMA_READ_REQUEST,
+            _ => {}
+        }
+
+        status
+    }
+
+    // Offsets are relative to 0x1F801C00, the registers are 16 bit wide
+    pub fn read(&self, offset: u32) -> u16 {
+        match offset {
+            0x000..0x180 => {
+                let voice = &self.voices[offset as usize / 0x10];
+                match offset & 0xF {
+                    0x0 => voice.volume.0,
+                    0x2 => voice.volume.1,
+                    0x4 => voice.pitch,
+                    0x6 => voice.start_address,
+                    0x8 => voice.adsr as u16,
+                    0xA => (voice.adsr >> 16) as u16,
+                    0xC => voice.envelope,
+                    _ => voice.repeat_address,
+                }
+            }
+            0x180 => self.main_volume.0,
+            0x182 => self.main_volume.1,
+            0x184 => self.reverb_volume.0,
+            0x186 => self.reverb_volume.1,
+            0x188..0x1A0 => {
+                let bits = match offset & !3 {
+                    0x188 => self.key_on,
+                    0x18C => self.key_off,
+                    0x190 => self.pitch_modulation,
+                    0x194 => self.noise,
+                    0x198 => self.reverb_enable,
+                    _ => self.end_flags,
+                };
+                (bits >> ((offset & 2) * 8)) as u16
+            }
+            0x1A2 => self.reverb_start,
+            0x1A4 => self.irq_address,
+            0x1A6 => self.transfer_address_register,
+            0x1AA => self.control,
+            0x1AC => self.transfer_control,
+            0x1AE => self.status(),
+            0x1B0 => self.cd_volume.0,
+            0x1B2 => self.cd_volume.1,
+            0x1B4 => self.extern_volume.0,
+            0x1B6 => self.extern_volume.1,
+            0x1B8 => self.current_main_volume.0,
+            0x1BA => self.current_main_volume.1,
+            0x1C0..0x200 => self.reverb[(offset as usize - 0x1C0) / 2],
+            0x200..0x260 => {
+                let voice = &self.voices[(offset as usize - 0x200) / 4];
+                match offset & 2 {
+                    0 => voice.current_volume.0,
+                    _ => voice.current_volume.1,
+                }
+            }
+            // The transfer FIFO and the unknown registers
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, offset: u32, value: u16) {
+        match offset {
+            0x000..0x180 => {
+                let voice = &mut self.voices[offset as usize / 0x10];
+                match offset & 0xF {
+                    0x0 => {
+                        voice.volume.0 = value;
+                        voice.current_volume.0 = fixed_volume(value);
+                    }
+                    0x2 => {
+                        voice.volume.1 = value;
+                        voice.current_volume.1 = fixed_volume(value);
+                    }
+                    0x4 => voice.pitch = value,
+                    0x6 => voice.start_address = value,
+                    0x8 => voice.adsr = (voice.adsr & 0xFFFF_0000) | value as u32,
+                    0xA => voice.adsr = (voice.adsr & 0xFFFF) | ((value as u32) << 16),
+                    0xC => voice.envelope = value,
+                    _ => voice.repeat_address = value,
+                }
+            }
+            0x180 => {
+                self.main_volume.0 = value;
+                self.current_main_volume.0 = fixed_volume(value);
+            }
+            0x182 => {
+                self.main_volume.1 = value;
+                self.current_main_volume.1 = fixed_volume(value);
+            }
+            0x184 => self.reverb_volume.0 = value,
+            0x186 => self.reverb_volume.1 = value,
+            // The end flags are read only
+            0x188..0x19C => {
+                let bits = match offset & !3 {
+                    0x188 => &mut self.key_on,
+                    0x18C => &mut self.key_off,
+                    0x190 => &mut self.pitch_modulation,
+                    0x194 => &mut self.noise,
+                    _ => &mut self.reverb_enable,
+                };
+                let shift = (offset & 2) * 8;
+                *bits = (*bits & !(0xFFFF << shift)) | ((value as u32) << shift);
+                *bits &= (1 << VOICE_COUNT) - 1;
+
+                let voices = ((value as u32) << shift) & ((1 << VOICE_COUNT) - 1);
+                match offset & !3 {
+                    0x188 => self.key_on(voices),
+                    0x18C => self.key_off(voices),
+                    _ => {}
+                }
+            }
+            0x1A2 => self.reverb_start = value,
+            0x1A4 => self.irq_address = value,
+            0x1A6 => {
+                self.transfer_address_register = value;
+                self.transfer_address = value as u32 * 8;
+            }
+            // Writes to a full FIFO are lost
+            0x1A8 if self.transfer_fifo.len() < TRANSFER_FIFO_SIZE => {
+                self.transfer_fifo.push(value)
+            }
+            0x1AA => {
+                self.control = value;
+                if self.control & CONTROL_IRQ_ENABLE == 0 {
+                    self.interrupt = false;
+                }
+                // Manual write mode empties the FIFO into the sound RAM
+                if self.transfer_mode() == TransferMode::ManualWrite {
+                    for value in std::mem::take(&mut self.transfer_fifo) {
+                        self.write_halfword(value);
+                    }
+                }
+            }
+            0x1AC => self.transfer_control = value,
+            0x1B0 => self.cd_volume.0 = value,
+            0x1B2 => self.cd_volume.1 = value,
+            0x1B4 => self.extern_volume.0 = value,
+            0x1B6 => self.extern_volume.1 = value,
+            0x1B8 => self.current_main_volume.0 = value,
+            0x1BA => self.current_main_volume.1 = value,
+            0x1C0..0x200 => self.reverb[(offset as usize - 0x1C0) / 2] = value,
+            0x200..0x260 => {
+                let voice = &mut self.voices[(offset as usize - 0x200) / 4];
+                match offset & 2 {
+                    0 => voice.current_volume.0 = value,
+                    _ => voice.current_volume.1 = value,
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn key_on(&mut self, voices: u32) {
+        for index in 0..VOICE_COUNT {
+            if voices & (1 << index) != 0 {
+                self.voices[index].key_on(&self.sound_ram[..]);
+                self.end_flags &= !(1 << index);
+                // The header is read right away, the samples as they are played
+                self.check_irq(self.voices[index].address, 2);
+            }
+        }
+    }
+
+    fn key_off(&mut self, voices: u32) {
+        for (index, voice) in self.voices.iter_mut().enumerate() {
+            if voices & (1 << index) != 0 {
+                voice.key_off();
+            }
+        }
+    }
+
+    // Sets the IRQ flag when the bytes at the address include the IRQ address. IRQ9 is only
+    // requested again after the flag was cleared. Voices at the end of the sound RAM can run past
+    // it, the bytes there wrap around to the start.
+    fn check_irq(&mut self, address: u32, length: u32) {
+        let irq_address = self.irq_address as u32 * 8;
+        let bytes = address..address + length;
+        if self.control & CONTROL_IRQ_ENABLE != 0
+            && !self.interrupt
+            && (bytes.contains(&irq_address)
+                || bytes.contains(&(irq_address + SOUND_RAM_SIZE as u32)))
+        {
+            self.interrupt = true;
+            self.interrupt_pending = true;
+        }
+    }
+
+    // Whether IRQ9 has to be requested since the last call
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.interrupt_pending)
+    }
+
+    // Voices can hit the IRQ address on any output sample while the IRQ is enabled
+    pub fn cycles_until_event(&self) -> u32 {
+        if self.control & CONTROL_IRQ_ENABLE != 0 && !self.interrupt {
+            CYCLES_PER_SAMPLE - self.cycles
+        } else {
+            u32::MAX
+        }
+    }
+
+    // The voices are mixed with their volumes and the main volume. The CDROM sends a sample
+    // along with every output sample, it is dropped while CD audio is disabled in SPUCNT.
+    pub fn step(&mut self, cycles: u32, mut cd_audio: impl FnMut() -> (i16, i16)) {
+        self.cycles += cycles;
+        while self.cycles >= CYCLES_PER_SAMPLE {
+            self.cycles -= CYCLES_PER_SAMPLE;
+
+            let (mut left, mut right) = self.mix_voices();
+            let cd = cd_audio();
+            if self.control & CONTROL_CD_AUDIO != 0 {
+                left += apply_volume(cd.0, self.cd_volume.0) as i32;
+                right += apply_volume(cd.1, self.cd_volume.1) as i32;
+            }
+            let sample = (clamp_sample(left), clamp_sample(right));
+
+            if self.output.len() == MAX_BUFFERED_SAMPLES {
+                self.output.pop_front();
+            }
+            self.output.push_back(sample);
+        }
+    }
+
+    // The voices only play while the SPU is enabled, the mute bit silences them without stopping
+    // them
+    fn mix_voices(&mut self) -> (i32, i32) {
+        if self.control & CONTROL_ENABLE == 0 {
+            return (0, 0);
+        }
+
+        let (mut left, mut right) = (0, 0);
+        let mut previous_output = 0;
+        for index in 0..VOICE_COUNT {
+            let voice = &mut self.voices[index];
+            // Pitch modulation scales the step by the output of the previous voice
+            let mut step = voice.pitch as u32;
+            if index > 0 && self.pitch_modulation & (1 << index) != 0 {
+                let factor = previous_output as i32 + 0x8000;
+                step = (((voice.pitch as i16 as i32 * factor) >> 15) & 0xFFFF) as u32;
+            }
+
+            // The bytes up to the sample played now, high pitches skip samples
+            let (first_byte, played_byte) = (voice.last_byte + 1, voice.sample_byte());
+            voice.last_byte = played_byte;
+
+            let fetched = voice.tick(&self.sound_ram[..], step.min(0x4000), self.interpolation);
+            let (output, volume, address) = (voice.output, voice.current_volume, voice.address);
+            if fetched == Some(true) {
+                self.end_flags |= 1 << index;
+            }
+            self.check_irq(first_byte, played_byte + 1 - first_byte);
+            if fetched.is_some() {
+                self.check_irq(address, 2);
+            }
+            previous_output = output;
+
+            left += apply_volume(output, volume.0) as i32;
+            right += apply_volume(output, volume.1) as i32;
+        }
+
+        if self.control & CONTROL_UNMUTE == 0 {
+            return (0, 0);
+        }
+
+        (
+            apply_volume(clamp_sample(left), self.current_main_volume.0) as i32,
+            apply_volume(clamp_sample(right), self.current_main_volume.1) as i32,
+        )
+    }
+
+    pub fn set_interpolation(&mut self, interpolation: Interpolation) {
+        self.interpolation = interpolation;
+    }
+
+    // Stereo samples at 44100 Hz produced since the last call
+    pub fn take_samples(&mut self) -> Vec<(i16, i16)> {
+        self.output.drain(..).collect()
+    }
+
+    pub fn dma_write(&mut self, value: u32) {
+        self.write_halfword(value as u16);
+        self.write_halfword((value >> 16) as u16);
+    }
+
+    pub fn dma_read(&mut self) -> u32 {
+        let low = self.read_halfword() as u32;
+        let high = self.read_halfword() as u32;
+
+        low | (high << 16)
+    }
+
+    fn write_halfword(&mut self, value: u16) {
+        self.check_irq(self.transfer_address, 2);
+        let address = self.transfer_address as usize;
+        self.sound_ram[address..address + 2].copy_from_slice(&value.to_le_bytes());
+        self.transfer_address = (self.transfer_address + 2) % SOUND_RAM_SIZE as u32;
+    }
+
+    fn read_halfword(&mut self) -> u16 {
+        self.check_irq(self.transfer_address, 2);
+        let address = self.transfer_address as usize;
+        let value = u16::from_le_bytes([self.sound_ram[address], self.sound_ram[address + 1]]);
+        self.transfer_address = (self.transfer_address + 2) % SOUND_RAM_SIZE as u32;
+
+        value
+    }
+}
+
+impl DmaDevice for Spu {
+    fn can_read(&self) -> bool {
+        self.transfer_mode() == TransferMode::DmaRead
+    }
+
+    fn can_write(&self) -> bool {
+        self.transfer_mode() == TransferMode::DmaWrite
+    }
+
+    fn read_word(&mut self) -> u32 {
+        self.dma_read()
+    }
+
+    fn write_word(&mut self, value: u32) {
+        self.dma_write(value);
+    }
+}
+
+// Without bit 15 the volume registers hold a fixed volume divided by 2, sweeps are not emulated yet
+fn fixed_volume(value: u16) -> u16 {
+    match value & 0x8000 {
+        0 => value << 1,
+        _ => 0,
+    }
+}
+
+// Volumes are signed, 0x7FFF is 100%
+fn apply_volume(sample: i16, volume: u16) -> i16 {
+    ((sample as i32 * volume as i16 as i32) >> 15) as i16
+}
+
+fn clamp_sample(sample: i32) -> i16 {
+    sample.clamp(i16::MIN as i32, i16::MAX as i32) as i16
+}
+
+// Weights of the 4 samples around the position, by the 8 bit fraction of the counter
+const GAUSSIAN_TABLE: [i32; 512] = [
+    -0x0001, -0x0001, -0x0001, -0x0001, -0x0001, -0x0001, -0x0001, -0x0001, -0x0001, -0x0001,
+    -0x0001, -0x0001, -0x0001, -0x0001, -0x0001, -0x0001, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
+    0x0000, 0x0000, 0x0001, 0x0001, 0x0001, 0x0001, 0x0002, 0x0002, 0x0002, 0x0003, 0x0003, 0x0003,
+    0x0004, 0x0004, 0x0005, 0x0005, 0x0006, 0x0007, 0x0007, 0x0008, 0x0009, 0x0009, 0x000A, 0x000B,
+    0x000C, 0x000D, 0x000E, 0x000F, 0x0010, 0x0011, 0x0012, 0x0013, 0x0015, 0x0016, 0x0018, 0x0019,
+    0x001B, 0x001C, 0x001E, 0x0020, 0x0021, 0x0023, 0x0025, 0x0027, 0x0029, 0x002C, 0x002E, 0x0030,
+    0x0033, 0x0035, 0x0038, 0x003A, 0x003D, 0x0040, 0x0043, 0x0046, 0x0049, 0x004D, 0x0050, 0x0054,
+    0x0057, 0x005B, 0x005F, 0x0063, 0x0067, 0x006B, 0x006F, 0x0074, 0x0078, 0x007D, 0x0082, 0x0087,
+    0x008C, 0x0091, 0x0096, 0x009C, 0x00A1, 0x00A7, 0x00AD, 0x00B3, 0x00BA, 0x00C0, 0x00C7, 0x00CD,
+    0x00D4, 0x00DB, 0x00E3, 0x00EA, 0x00F2, 0x00FA, 0x0101, 0x010A, 0x0112, 0x011B, 0x0123, 0x012C,
+    0x0135, 0x013F, 0x0148, 0x0152, 0x015C, 0x0166, 0x0171, 0x017B, 0x0186, 0x0191, 0x019C, 0x01A8,
+    0x01B4, 0x01C0, 0x01CC, 0x01D9, 0x01E5, 0x01F2, 0x0200, 0x020D, 0x021B, 0x0229, 0x0237, 0x0246,
+    0x0255, 0x0264, 0x0273, 0x0283, 0x0293, 0x02A3, 0x02B4, 0x02C4, 0x02D6, 0x02E7, 0x02F9, 0x030B,
+    0x031D, 0x0330, 0x0343, 0x0356, 0x036A, 0x037E, 0x0392, 0x03A7, 0x03BC, 0x03D1, 0x03E7, 0x03FC,
+    0x0413, 0x042A, 0x0441, 0x0458, 0x0470, 0x0488, 0x04A0, 0x04B9, 0x04D2, 0x04EC, 0x0506, 0x0520,
+    0x053B, 0x0556, 0x0572, 0x058E, 0x05AA, 0x05C7, 0x05E4, 0x0601, 0x061F, 0x063E, 0x065C, 0x067C,
+    0x069B, 0x06BB, 0x06DC, 0x06FD, 0x071E, 0x0740, 0x0762, 0x0784, 0x07A7, 0x07CB, 0x07EF, 0x0813,
+    0x0838, 0x085D, 0x0883, 0x08A9, 0x08D0, 0x08F7, 0x091E, 0x0946, 0x096F, 0x0998, 0x09C1, 0x09EB,
+    0x0A16, 0x0A40, 0x0A6C, 0x0A98, 0x0AC4, 0x0AF1, 0x0B1E, 0x0B4C, 0x0B7A, 0x0BA9, 0x0BD8, 0x0C07,
+    0x0C38, 0x0C68, 0x0C99, 0x0CCB, 0x0CFD, 0x0D30, 0x0D63, 0x0D97, 0x0DCB, 0x0E00, 0x0E35, 0x0E6B,
+    0x0EA1, 0x0ED7, 0x0F0F, 0x0F46, 0x0F7F, 0x0FB7, 0x0FF1, 0x102A, 0x1065, 0x109F, 0x10DB, 0x1116,
+    0x1153, 0x118F, 0x11CD, 0x120B, 0x1249, 0x1288, 0x12C7, 0x1307, 0x1347, 0x1388, 0x13C9, 0x140B,
+    0x144D, 0x1490, 0x14D4, 0x1517, 0x155C, 0x15A0, 0x15E6, 0x162C, 0x1672, 0x16B9, 0x1700, 0x1747,
+    0x1790, 0x17D8, 0x1821, 0x186B, 0x18B5, 0x1900, 0x194B, 0x1996, 0x19E2, 0x1A2E, 0x1A7B, 0x1AC8,
+    0x1B16, 0x1B64, 0x1BB3, 0x1C02, 0x1C51, 0x1CA1, 0x1CF1, 0x1D42, 0x1D93, 0x1DE5, 0x1E37, 0x1E89,
+    0x1EDC, 0x1F2F, 0x1F82, 0x1FD6, 0x202A, 0x207F, 0x20D4, 0x2129, 0x217F, 0x21D5, 0x222C, 0x2282,
+    0x22DA, 0x2331, 0x2389, 0x23E1, 0x2439, 0x2492, 0x24EB, 0x2545, 0x259E, 0x25F8, 0x2653, 0x26AD,
+    0x2708, 0x2763, 0x27BE, 0x281A, 0x2876, 0x28D2, 0x292E, 0x298B, 0x29E7, 0x2A44, 0x2AA1, 0x2AFF,
+    0x2B5C, 0x2BBA, 0x2C18, 0x2C76, 0x2CD4, 0x2D33, 0x2D91, 0x2DF0, 0x2E4F, 0x2EAE, 0x2F0D, 0x2F6C,
+    0x2FCC, 0x302B, 0x308B, 0x30EA, 0x314A, 0x31AA, 0x3209, 0x3269, 0x32C9, 0x3329, 0x3389, 0x33E9,
+    0x3449, 0x34A9, 0x3509, 0x3569, 0x35C9, 0x3629, 0x3689, 0x36E8, 0x3748, 0x37A8, 0x3807, 0x3867,
+    0x38C6, 0x3926, 0x3985, 0x39E4, 0x3A43, 0x3AA2, 0x3B00, 0x3B5F, 0x3BBD, 0x3C1B, 0x3C79, 0x3CD7,
+    0x3D35, 0x3D92, 0x3DEF, 0x3E4C, 0x3EA9, 0x3F05, 0x3F62, 0x3FBD, 0x4019, 0x4074, 0x40D0, 0x412A,
+    0x4185, 0x41DF, 0x4239, 0x4292, 0x42EB, 0x4344, 0x439C, 0x43F4, 0x444C, 0x44A3, 0x44FA, 0x4550,
+    0x45A6, 0x45FC, 0x4651, 0x46A6, 0x46FA, 0x474E, 0x47A1, 0x47F4, 0x4846, 0x4898, 0x48E9, 0x493A,
+    0x498A, 0x49D9, 0x4A29, 0x4A77, 0x4AC5, 0x4B13, 0x4B5F, 0x4BAC, 0x4BF7, 0x4C42, 0x4C8D, 0x4CD7,
+    0x4D20, 0x4D68, 0x4DB0, 0x4DF7, 0x4E3E, 0x4E84, 0x4EC9, 0x4F0E, 0x4F52, 0x4F95, 0x4FD7, 0x5019,
+    0x505A, 0x509A, 0x50DA, 0x5118, 0x5156, 0x5194, 0x51D0, 0x520C, 0x5247, 0x5281, 0x52BA, 0x52F3,
+    0x532A, 0x5361, 0x5397, 0x53CC, 0x5401, 0x5434, 0x5467, 0x5499, 0x54CA, 0x54FA, 0x5529, 0x5558,
+    0x5585, 0x55B2, 0x55DE, 0x5609, 0x5632, 0x565B, 0x5684, 0x56AB, 0x56D1, 0x56F6, 0x571B, 0x573E,
+    0x5761, 0x5782, 0x57A3, 0x57C3, 0x57E2, 0x57FF, 0x581C, 0x5838, 0x5853, 0x586D, 0x5886, 0x589E,
+    0x58B5, 0x58CB, 0x58E0, 0x58F4, 0x5907, 0x5919, 0x592A, 0x593A, 0x5949, 0x5958, 0x5965, 0x5971,
+    0x597C, 0x5986, 0x598F, 0x5997, 0x599E, 0x59A4, 0x59A9, 0x59AD, 0x59B0, 0x59B2, 0x59B3,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs the SPU for a number of output samples without CD audio
+    fn run(spu: &mut Spu, samples: u32) {
+        spu.step(samples * CYCLES_PER_SAMPLE, || (0, 0));
+    }
+
+    #[test]
+    fn voice_wraps_around_the_end_of_the_sound_ram() {
+        let mut spu = Spu::new();
+        spu.sound_ram[SOUND_RAM_SIZE - 8..]
+            .copy_from_slice(&[0, 0, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11]);
+        spu.write(0x1AA, CONTROL_ENABLE);
+        spu.write(0x004, 0x4000);
+        spu.write(0x006, 0xFFFF);
+        spu.write(0x188, 1);
+
+        assert_eq!(spu.voices[0].samples[..12], [0x1000; 12]);
+        run(&mut spu, 100);
+        assert!(spu.voices[0].address < SOUND_RAM_SIZE as u32);
+    }
+
+    // A voice at 0x1000 playing one sample per output sample with the IRQ enabled
+    fn spu_with_irq_at(irq_address: u16) -> Spu {
+        let mut spu = Spu::new();
+        spu.write(0x1AA, CONTROL_ENABLE | CONTROL_IRQ_ENABLE);
+        spu.write(0x1A4, irq_address / 8);
+        spu.write(0x004, 0x1000);
+        spu.write(0x006, 0x1000 / 8);
+        spu.write(0x188, 1);
+        spu
+    }
+
+    #[test]
+    fn irq_fires_when_the_voice_reaches_the_sample_at_the_address() {
+        // Byte 8 of the block holds samples 12 and 13
+        let mut spu = spu_with_irq_at(0x1008);
+        run(&mut spu, 12);
+        assert!(!spu.take_interrupt());
+        run(&mut spu, 1);
+        assert!(spu.take_interrupt());
+        assert_ne!(spu.read(0x1AE) & STATUS_IRQ, 0);
+    }
+
+    #[test]
+    fn irq_at_a_block_header_fires_when_the_block_is_fetched() {
+        let mut spu = spu_with_irq_at(0x1010);
+        run(&mut spu, BLOCK_SAMPLES as u32 - 1);
+        assert!(!spu.take_interrupt());
+        run(&mut spu, 1);
+        assert!(spu.take_interrupt());
+    }
+
+    #[test]
+    fn irq_is_not_skipped_at_high_pitches() {
+        // 3.5 samples per output sample play samples 0, 3, 7, 10 and 14, the byte of samples 12
+        // and 13 is skipped
+        let mut spu = spu_with_irq_at(0x1008);
+        spu.write(0x004, 0x3800);
+        run(&mut spu, 4);
+        assert!(!spu.take_interrupt());
+        run(&mut spu, 1);
+        assert!(spu.take_interrupt());
+    }
+
+    #[test]
+    fn dma_and_fifo_writes_share_the_transfer_address() {
+        let mut spu = Spu::new();
+        spu.write(0x1A6, 0x100 / 8);
+        spu.write(0x1AA, 0x0020);
+        assert!(spu.can_write() && !spu.can_read());
+        spu.dma_write(0x22221111);
+        spu.dma_write(0x44443333);
+        assert_eq!(spu.transfer_address, 0x108);
+
+        // The CPU continues through the FIFO, manual write mode flushes it
+        spu.write(0x1AA, 0);
+        spu.write(0x1A8, 0x5555);
+        spu.write(0x1A8, 0x6666);
+        spu.write(0x1AA, 0x0010);
+        assert_eq!(spu.transfer_address, 0x10C);
+
+        spu.write(0x1AA, 0);
+        spu.write(0x1A6, 0x100 / 8);
+        spu.write(0x1AA, 0x0030);
+        assert!(spu.can_read());
+        let words: Vec<u32> = (0..3).map(|_| spu.dma_read()).collect();
+        assert_eq!(words, [0x22221111, 0x44443333, 0x66665555]);
+        assert_eq!(spu.sound_ram[0x10C..0x110], [0; 4]);
+    }
+
+    #[test]
+    fn voice_registers_read_back() {
+        let mut spu = Spu::new();
+        for voice in 0..VOICE_COUNT as u32 {
+            for register in (0..0x10).step_by(2) {
+                spu.write(
+                    voice * 0x10 + register,
+                    (voice << 8 | register) as u16 | 0x8000,
+                );
+            }
+        }
+        for voice in 0..VOICE_COUNT as u32 {
+            for register in (0..0x10).step_by(2) {
+                assert_eq!(
+                    spu.read(voice * 0x10 + register),
+                    (voice << 8 | register) as u16 | 0x8000
+                );
+            }
+        }
+
+        for offset in [
+            0x180, 0x182, 0x184, 0x186, 0x1A2, 0x1A4, 0x1A6, 0x1AC, 0x1B0, 0x1B6,
+        ] {
+            spu.write(offset, 0x1234);
+            assert_eq!(spu.read(offset), 0x1234, "0x{:03x}", offset);
+        }
+
+        // SPUSTAT mirrors the low bits of SPUCNT
+        spu.write(0x1AA, CONTROL_ENABLE | CONTROL_UNMUTE | 0x15);
+        assert_eq!(spu.read(0x1AA), CONTROL_ENABLE | CONTROL_UNMUTE | 0x15);
+        assert_eq!(spu.read(0x1AE) & STATUS_CONTROL_MASK, 0x15);
+    }
+
+    #[test]
+    fn transfer_fifo_is_written_to_the_sound_ram() {
+        let mut spu = Spu::new();
+        spu.write(0x1A6, 0x2000 / 8);
+        for value in 0..TRANSFER_FIFO_SIZE as u16 + 4 {
+            spu.write(0x1A8, value * 0x101);
+        }
+        assert_eq!(spu.sound_ram[0x2000..0x2002], [0; 2]);
+
+        // The writes to the full FIFO were lost
+        spu.write(0x1AA, 0x0010);
+        let written: Vec<u16> = spu.sound_ram[0x2000..0x2000 + TRANSFER_FIFO_SIZE * 2 + 8]
+            .chunks_exact(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect();
+        let expected: Vec<u16> = (0..TRANSFER_FIFO_SIZE as u16)
+            .map(|value| value * 0x101)
+            .chain([0; 4])
+            .collect();
+        assert_eq!(written, expected);
+        assert_eq!(spu.transfer_address, 0x2000 + TRANSFER_FIFO_SIZE as u32 * 2);
+        // The register keeps the start address
+        assert_eq!(spu.read(0x1A6), 0x2000 / 8);
+    }
+
+    // SPU-ADPCM as documented: the nibble is shifted into the top of a 16 bit sample, shifted
+    // right, and the filter adds a prediction from the two previous samples
+    fn reference_decode(blocks: &[u8]) -> Vec<i16> {
+        let filters = [(0, 0), (60, 0), (115, -52), (98, -55), (122, -60)];
+        let (mut old, mut older) = (0i32, 0i32);
+        let mut samples = Vec::new();
+        for block in blocks.chunks_exact(BLOCK_SIZE as usize) {
+            let shift = (block[0] & 0xF) as i32;
+            let (positive, negative) = filters[(block[0] >> 4) as usize];
+            for byte in &block[2..] {
+                for nibble in [byte & 0xF, byte >> 4] {
+                    let sample = (((nibble as i32) << 28) >> 28) << (12 - shift);
+                    let sample = sample + (old * positive + older * negative + 32) / 64;
+                    let sample = sample.clamp(-0x8000, 0x7FFF);
+                    older = old;
+                    old = sample;
+                    samples.push(sample as i16);
+                }
+            }
+        }
+        samples
+    }
+
+    #[test]
+    fn adpcm_blocks_decode_like_the_reference() {
+        // Every filter and a range of shifts, the last block ends the sample
+        let mut seed = 0x1234_5678u32;
+        let mut blocks = Vec::new();
+        for i in 0..10u8 {
+            let flags = if i == 9 { FLAG_LOOP_END } else { 0 };
+            blocks.extend([(i % 5) << 4 | (i * 5 % 13), flags]);
+            blocks.extend((0..14).map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16) as u8
+            }));
+        }
+        let expected = reference_decode(&blocks);
+
+        let mut spu = Spu::new();
+        spu.write(0x1A6, 0x1000 / 8);
+        spu.write(0x1AA, 0x0020);
+        for word in blocks.chunks_exact(4) {
+            spu.dma_write(u32::from_le_bytes(word.try_into().unwrap()));
+        }
+        spu.write(0x1AA, CONTROL_ENABLE);
+        spu.write(0x004, 0x1000);
+        spu.write(0x006, 0x1000 / 8);
+        spu.write(0x188, 1);
+        assert_eq!(spu.read(0x19C) & 1, 0);
+
+        let mut decoded = spu.voices[0].samples.to_vec();
+        for _ in 1..10 {
+            run(&mut spu, BLOCK_SAMPLES as u32);
+            decoded.extend(spu.voices[0].samples);
+        }
+        assert_eq!(decoded, expected);
+
+        // Leaving the last block sets its end flag, key on clears it again
+        assert_eq!(spu.read(0x19C) & 1, 0);
+        run(&mut spu, BLOCK_SAMPLES as u32);
+        assert_eq!(spu.read(0x19C) & 1, 1);
+        spu.write(0x188, 1);
+        assert_eq!(spu.read(0x19C) & 1, 0);
+
+        // One stereo sample every 768 cycles
+        spu.take_samples();
+        spu.step(CYCLES_PER_SAMPLE * 100 + 767, || (0, 0));
+        assert_eq!(spu.take_samples().len(), 100);
+    }
+
+    // Voice 0 playing the silent start of the sound RAM with the ADSR register
+    fn spu_with_envelope(adsr: u32) -> Spu {
+        let mut spu = Spu::new();
+        spu.write(0x1AA, CONTROL_ENABLE);
+        spu.write(0x004, 0x1000);
+        spu.write(0x008, adsr as u16);
+        spu.write(0x00A, (adsr >> 16) as u16);
+        spu.write(0x188, 1);
+        spu
+    }
+
+    #[test]
+    fn attack_rises_at_its_rate() {
+        // Shift 10, step 7 adds 14 every sample
+        let mut spu = spu_with_envelope(10 << 10);
+        run(&mut spu, 100);
+        assert_eq!(spu.read(0x00C), 1400);
+
+        // Shift 13 adds 7 every 4 samples
+        let mut spu = spu_with_envelope(13 << 10);
+        run(&mut spu, 100);
+        assert_eq!(spu.read(0x00C), 175);
+
+        // Exponential attack at shift 0 adds 0x3800 and slows down 4 times above 0x6000, then
+        // clamps at the top
+        let mut spu = spu_with_envelope(1 << 15);
+        run(&mut spu, 2);
+        assert_eq!(spu.read(0x00C), 0x7000);
+        run(&mut spu, 3);
+        assert_eq!(spu.read(0x00C), 0x7000);
+        run(&mut spu, 1);
+        assert_eq!(spu.read(0x00C), 0x7FFF);
+        assert!(spu.voices[0].phase == AdsrPhase::Decay);
+    }
+
+    #[test]
+    fn key_off_releases_from_any_phase() {
+        // A slow attack, a slow decay after an instant attack, and a slow sustain after an instant
+        // attack and decay. Release is linear at shift 0.
+        let phases = [
+            (AdsrPhase::Attack, 12 << 10),
+            (AdsrPhase::Decay, 15 << 4),
+            (AdsrPhase::Sustain, 0xF | 20 << 24),
+        ];
+        for (phase, adsr) in phases {
+            let mut spu = spu_with_envelope(adsr);
+            run(&mut spu, 10);
+            assert!(spu.voices[0].phase == phase);
+            let level = spu.read(0x00C);
+            assert_ne!(level, 0);
+
+            spu.write(0x18C, 1);
+            assert!(spu.voices[0].phase == AdsrPhase::Release);
+            run(&mut spu, 1);
+            assert!(spu.read(0x00C) < level);
+            run(&mut spu, 4);
+            assert_eq!(spu.read(0x00C), 0);
+        }
+    }
+
+    // The interpolated samples of voice 0 over two blocks of large steps. The vectors follow the
+    // documented gaussian formula and the linear one over the samples decoded by hand.
+    fn interpolated(interpolation: Interpolation, pitch: u16) -> Vec<i32> {
+        const BLOCKS: [u8; 32] = [
+            0x00, 0x00, 0x87, 0xD3, 0x06, 0x2B, 0x91, 0xF4, 0xA5, 0x87, 0x3E, 0x60, 0x2C, 0x78,
+            0xD1, 0xB5, 0x02, 0x00, 0xBB, 0xFD, 0x87, 0x42, 0x06, 0x23, 0x98, 0xBA, 0xCF, 0xF9,
+            0x52, 0xA0, 0xDD, 0x98,
+        ];
+
+        let mut spu = Spu::new();
+        spu.sound_ram[0x1000..0x1020].copy_from_slice(&BLOCKS);
+        spu.set_interpolation(interpolation);
+        spu.write(0x1AA, CONTROL_ENABLE);
+        spu.write(0x004, pitch);
+        spu.write(0x006, 0x1000 / 8);
+        spu.write(0x188, 1);
+
+        (0..20)
+            .map(|_| {
+                let sample = spu.voices[0].interpolate(spu.interpolation);
+                run(&mut spu, 1);
+                sample
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gaussian_interpolation_matches_the_known_vectors() {
+        assert_eq!(
+            interpolated(Interpolation::Gaussian, 0x0C00),
+            [
+                -1, 1565, 13530, 8439, -16931, -2574, -130, 229, 15419, 6225, -9553, -10611, 3343,
+                3487, -11659, -13162, 6664, 3364, 7873, 5478
+            ]
+        );
+        assert_eq!(
+            interpolated(Interpolation::Gaussian, 0x2A00),
+            [
+                -1, -7189, 229, -13271, -11659, 3189, 9253, -742, 14785, -14801, 465, -10095,
+                -4053, 3019, 3563, 1389, -6507, -5395, -4841, 1662
+            ]
+        );
+    }
+
+    #[test]
+    fn linear_interpolation_matches_the_known_vectors() {
+        assert_eq!(
+            interpolated(Interpolation::Linear, 0x0C00),
+            [
+                0, 0, 14336, 13312, -32768, 1024, 0, -3072, 24576, 6144, -10240, -13312, 8192,
+                5120, -12288, -17408, 16384, 1024, 8192, 9216
+            ]
+        );
+        assert_eq!(
+            interpolated(Interpolation::Linear, 0x2A00),
+            [
+                0, -9728, -3072, -17920, -12288, -1024, 15360, -512, 24576, -17408, 0, -15360,
+                -4096, 5248, 3584, 1152, -8192, -5504, -4864, 1664
+            ]
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use std::{
+    fs::{self, File},
+    io::{stdout, BufWriter, Write},
+};
+
+use psx_rust::{
+    trace::{compare, TraceReader},
+    MemoryCard,
+};
+
+// Tools that work on files instead of running the emulator, None when the arguments don't start
+// with a subcommand
+pub fn run(args: &[String]) -> Option<Result<(), String>> {
+    let (command, args) = args.split_first()?;
+    match command.as_str() {
+        "trace" => Some(trace(args)),
+        "memcard" => Some(memcard(args)),
+        _ => None,
+    }
+}
+
+fn trace(args: &[String]) -> Result<(), String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["dump", path] => dump(path, false),
+        ["dump", path, "--disasm"] | ["dump", "--disasm", path] => dump(path, true),
+        ["compare", expected, actual] => {
+            let difference = compare(open_trace(expected)?, open_trace(actual)?)
+                .map_err(|error| format!("Failed to read trace: {}", error))?;
+            match difference {
+                Some(difference) => Err(difference.to_string()),
+                None => {
+                    println!("The traces match");
+                    Ok(())
+                }
+            }
+        }
+        _ => Err(
+            "Expected trace dump <trace> [--disasm] or trace compare <expected> <actual>".into(),
+        ),
+    }
+}
+
+fn open_trace(path: &str) -> Result<TraceReader<File>, String> {
+    File::open(path)
+        .and_then(TraceReader::new)
+        .map_err(|error| format!("Failed to open trace '{}': {}", path, error))
+}
+
+fn dump(path: &str, disassembly: bool) -> Result<(), String> {
+    let mut out = BufWriter::new(stdout().lock());
+    for record in open_trace(path)? {
+        let record =
+            record.map_err(|error| format!("Failed to read trace '{}': {}", path, error))?;
+        // Stop quietly when the output is piped into head
+        if writeln!(out, "{}", record.to_text(disassembly)).is_err() {
+            return Ok(());
+        }
+    }
+
+    let _ = out.flush();
+    Ok(())
+}
+
+const MEMCARD_USAGE: &str = "Expected memcard list <card>, memcard export <card> <save> <file.mcs>, memcard import <card> <file.mcs> or memcard delete <card> <save>";
+
+// Saves are named by their filename or the number of their first block
+fn memcard(args: &[String]) -> Result<(), String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["list", path] => {
+            let card = read_card(path)?;
+            let saves = card.saves().map_err(|error| error.to_string())?;
+            println!("First Blocks Icons Name                 Title");
+            for save in &saves {
+                println!(
+                    "{:>5} {:>6} {:>5} {:<20} {}",
+                    save.blocks[0],
+                    save.blocks.len(),
+                    save.icons.len(),
+                    save.filename,
+                    save.title
+                );
+            }
+            println!("{} saves, {} blocks free", saves.len(), card.free_blocks());
+            Ok(())
+        }
+        ["export", path, save, output] => {
+            let mcs = read_card(path)?
+                .export_save(save)
+                .map_err(|error| error.to_string())?;
+            fs::write(output, mcs)
+                .map_err(|error| format!("Failed to write '{}': {}", output, error))
+        }
+        ["import", path, input] => {
+            let mcs = fs::read(input)
+                .map_err(|error| format!("Failed to read '{}': {}", input, error))?;
+            // A missing card is created formatted
+            let mut card = open_card(path)?;
+            let blocks = card.import_save(&mcs).map_err(|error| error.to_string())?;
+            println!("Imported to blocks {:?}", blocks);
+            write_card(&card, path)
+        }
+        ["delete", path, save] => {
+            let mut card = open_card(path)?;
+            card.delete_save(save).map_err(|error| error.to_string())?;
+            write_card(&card, path)
+        }
+        _ => Err(MEMCARD_USAGE.into()),
+    }
+}
+
+fn read_card(path: &str) -> Result<MemoryCard, String> {
+    fs::read(path)
+        .and_then(MemoryCard::from_data)
+        .map_err(|error| format!("Failed to read memory card '{}': {}", path, error))
+}
+
+fn open_card(path: &str) -> Result<MemoryCard, String> {
+    MemoryCard::open(path)
+        .map_err(|error| format!("Failed to open memory card '{}': {}", path, error))
+}
+
+fn write_card(card: &MemoryCard, path: &str) -> Result<(), String> {
+    card.flush()
+        .map_err(|error| format!("Failed to write memory card '{}': {}", path, error))
+}
@@ -1,12 +1,90 @@
+use crate::mmu::Irq;
+
 pub struct Timers {
     timers: [Timer; 3],
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum ClockSource {
+    System,
+    // Timer 0 only
+    Dotclock,
+    // Timer 1 only
+    Hblank,
+    // Timer 2 only
+    SystemDiv8,
+}
+
 struct Timer {
-    pub sync_enabled: bool,
     pub counter: u16,
     target: u16,
-    use_system_clock: bool,
+    pub mode: Mode,
+    reached_target: bool,
+    reached_overflow: bool,
+    // Mode bit 10, the interrupt request is active low
+    interrupt_line: bool,
+    // One-shot timers only interrupt once until the mode is written again
+    interrupt_fired: bool,
+    interrupt_pending: bool,
+    // System cycles not yet counted by the /8 clock source
+    divider_remainder: u32,
+    in_blank: bool,
+    // Sync mode 3 waits for the first blank
+    blank_seen: bool,
+}
+
+// The writable part of the mode register (bits 0..9)
+#[derive(Clone, Copy, Default)]
+pub struct Mode {
+    pub sync_enabled: bool,
+    // Timers 0 and 1 sync to the hblank and vblank respectively:
+    // 0 pauses during the blank, 1 resets at the blank, 2 resets at the blank and pauses outside of it,
+    // 3 pauses until the first blank. Timer 2 is stopped in modes 0 and 3.
+    pub sync_mode: u8,
+    // Otherwise the counter wraps after 0xFFFF
+    pub reset_on_target: bool,
+    pub irq_on_target: bool,
+    pub irq_on_overflow: bool,
+    pub irq_repeat: bool,
+    // Otherwise bit 10 is pulsed
+    pub irq_toggle: bool,
+    pub clock_source: u8,
+}
+
+impl Mode {
+    fn from_bits(value: u16) -> Self {
+        Self {
+            sync_enabled: value & 1 != 0,
+            sync_mode: ((value >> 1) & 3) as u8,
+            reset_on_target: value & (1 << 3) != 0,
+            irq_on_target: value & (1 << 4) != 0,
+            irq_on_overflow: value & (1 << 5) != 0,
+            irq_repeat: value & (1 << 6) != 0,
+            irq_toggle: value & (1 << 7) != 0,
+            clock_source: ((value >> 8) & 3) as u8,
+        }
+    }
+
+    // Bits 8..9 select a different clock for each timer
+    fn clock_source(&self, index: usize) -> ClockSource {
+        match (index, self.clock_source) {
+            (0, 1 | 3) => ClockSource::Dotclock,
+            (1, 1 | 3) => ClockSource::Hblank,
+            (2, 2 | 3) => ClockSource::SystemDiv8,
+            _ => ClockSource::System,
+        }
+    }
+
+    fn bits(&self) -> u16 {
+        (self.sync_enabled as u16)
+            | ((self.sync_mode as u16) << 1)
+            | ((self.reset_on_target as u16) << 3)
+            | ((self.irq_on_target as u16) << 4)
+            | ((self.irq_on_overflow as u16) << 5)
+            | ((self.irq_repeat as u16) << 6)
+            | ((self.irq_toggle as u16) << 7)
+            | ((self.clock_source as u16) << 8)
+    }
 }
 
 impl Timers {
@@ -16,25 +94,103 @@ impl Timers {
         }
     }
 
-    pub fn step(&mut self, cycles: u32) {
-        for timer in &mut self.timers {
-            timer.step(cycles);
+    // Returns the I_STAT bits of the timers that interrupted
+    pub fn step(&mut self, cycles: u32, dotclocks: u32, hblanks: u32) -> u16 {
+        let mut interrupts = 0;
+
+        for (index, timer) in self.timers.iter_mut().enumerate() {
+            if timer.is_paused(index) {
+                continue;
+            }
+
+            let ticks = match timer.mode.clock_source(index) {
+                ClockSource::System => cycles,
+                ClockSource::Dotclock => dotclocks,
+                ClockSource::Hblank => hblanks,
+                ClockSource::SystemDiv8 => {
+                    let total = timer.divider_remainder + cycles;
+                    timer.divider_remainder = total % 8;
+                    total / 8
+                }
+            };
+            timer.count(ticks);
+
+            if std::mem::take(&mut timer.interrupt_pending) {
+                interrupts |= 1 << (Irq::Timer0 as u16 + index as u16);
+            }
         }
+
+        interrupts
     }
 
-    pub fn read(&self, address: u32) -> u32 {
-        panic!("TODO")
+    // Lower bound of the cycles until a timer reaches its target or overflows. Every clock source
+    // ticks at most once per cycle, so the ticks can be used as cycles.
+    pub fn ticks_until_event(&self) -> u32 {
+        self.timers
+            .iter()
+            .enumerate()
+            .filter(|(index, timer)| !timer.is_paused(*index))
+            .map(|(_, timer)| timer.ticks_until_event())
+            .min()
+            .unwrap_or(u32::MAX)
     }
 
-    pub fn write(&mut self, address: u32, value: u32) {
-        let timer_index = address >> 4;
+    pub fn notify_hblank(&mut self, entered: bool) {
+        self.timers[0].notify_blank(entered);
+    }
+
+    pub fn notify_vblank(&mut self, entered: bool) {
+        self.timers[1].notify_blank(entered);
+    }
 
-        let timer = &mut self.timers[timer_index as usize];
+    // Each timer is a 16 byte block with the counter, mode and target registers
+    pub fn read(&mut self, address: u32) -> u32 {
+        let timer = &mut self.timers[(address >> 4) as usize];
 
-        match address % 4 {
+        match address & 0xF {
+            0 => timer.counter as u32,
+            4 => timer.read_mode() as u32,
+            8 => timer.target as u32,
+            // Unused, reads as open bus
+            _ => !0,
+        }
+    }
+
+    // Counter, target and mode of every timer for debugging output
+    pub fn summary(&self) -> String {
+        let timers: Vec<String> = self
+            .timers
+            .iter()
+            .enumerate()
+            .map(|(index, timer)| {
+                format!(
+                    "T{} 0x{:04x}/0x{:04x} mode 0x{:03x}",
+                    index,
+                    timer.counter,
+                    timer.target,
+                    timer.mode.bits()
+                )
+            })
+            .collect();
+
+        timers.join(", ")
+    }
+
+    pub fn write(&mut self, address: u32, value: u32) {
+        let timer = &mut self.timers[(address >> 4) as usize];
+
+        match address & 0xF {
             0 => timer.counter = value as u16,
+            4 => {
+                timer.mode = Mode::from_bits(value as u16);
+                // Writing the mode register restarts the counter and rearms the interrupt
+                timer.counter = 0;
+                timer.interrupt_line = true;
+                timer.interrupt_fired = false;
+                timer.blank_seen = false;
+            }
             8 => timer.target = value as u16,
-            _ => panic!("Failed to write to timer {}", timer_index),
+            _ => {}
         }
     }
 }
@@ -42,16 +198,277 @@ impl Timers {
 impl Timer {
     pub fn new() -> Self {
         Self {
-            sync_enabled: false,
             counter: 0,
             target: 0,
-            use_system_clock: true,
+            mode: Mode::default(),
+            reached_target: false,
+            reached_overflow: false,
+            interrupt_line: true,
+            interrupt_fired: false,
+            interrupt_pending: false,
+            divider_remainder: 0,
+            in_blank: false,
+            blank_seen: false,
+        }
+    }
+
+    fn is_paused(&self, index: usize) -> bool {
+        if !self.mode.sync_enabled {
+            return false;
+        }
+
+        match (index, self.mode.sync_mode) {
+            (2, mode) => mode == 0 || mode == 3,
+            (_, 0) => self.in_blank,
+            (_, 1) => false,
+            (_, 2) => !self.in_blank,
+            _ => !self.blank_seen,
+        }
+    }
+
+    fn notify_blank(&mut self, entered: bool) {
+        self.in_blank = entered;
+        if !entered || !self.mode.sync_enabled {
+            return;
+        }
+
+        self.blank_seen = true;
+        if self.mode.sync_mode == 1 || self.mode.sync_mode == 2 {
+            self.counter = 0;
+        }
+    }
+
+    // The reached target/overflow flags are cleared once the mode register is read
+    fn read_mode(&mut self) -> u16 {
+        let mut mode = self.mode.bits() | ((self.interrupt_line as u16) << 10);
+        if std::mem::take(&mut self.reached_target) {
+            mode |= 1 << 11;
+        }
+        if std::mem::take(&mut self.reached_overflow) {
+            mode |= 1 << 12;
+        }
+
+        mode
+    }
+
+    // Advances the counter by whole chunks, stopping at the target and at 0xFFFF on the way so
+    // none of them are skipped when many ticks elapse at once
+    fn count(&mut self, ticks: u32) {
+        let target = self.target as u32;
+        let mut remaining = ticks;
+
+        while remaining > 0 {
+            let counter = self.counter as u32;
+
+            // The counter goes back to 0 on the tick after reaching the target or overflowing
+            if (self.mode.reset_on_target && counter == target) || counter == 0xFFFF {
+                self.counter = 0;
+                remaining -= 1;
+                self.check_flags();
+                continue;
+            }
+
+            let next = if target > counter { target } else { 0xFFFF };
+            let advance = remaining.min(next - counter);
+
+            self.counter = (counter + advance) as u16;
+            remaining -= advance;
+            self.check_flags();
         }
     }
 
-    pub fn step(&mut self, cycles: u32) {
-        if self.use_system_clock {
-            // self.counter += cycles as u16;
+    fn ticks_until_event(&self) -> u32 {
+        let counter = self.counter as u32;
+        let target = self.target as u32;
+
+        if (self.mode.reset_on_target && counter == target) || counter == 0xFFFF {
+            1
+        } else if target > counter {
+            target - counter
+        } else {
+            0xFFFF - counter
         }
     }
+
+    fn check_flags(&mut self) {
+        if self.counter == self.target {
+            self.reached_target = true;
+            if self.mode.irq_on_target {
+                self.interrupt();
+            }
+        }
+        if self.counter == 0xFFFF {
+            self.reached_overflow = true;
+            if self.mode.irq_on_overflow {
+                self.interrupt();
+            }
+        }
+    }
+
+    fn interrupt(&mut self) {
+        if !self.mode.irq_repeat && self.interrupt_fired {
+            return;
+        }
+        self.interrupt_fired = true;
+
+        if self.mode.irq_toggle {
+            // Only the falling edge of the toggled line interrupts
+            self.interrupt_line = !self.interrupt_line;
+            if !self.interrupt_line {
+                self.interrupt_pending = true;
+            }
+        } else {
+            // The line is pulsed low for a few cycles, it is back high by the time it can be read
+            self.interrupt_pending = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reached_flags_clear_on_read() {
+        let mut timers = Timers::new();
+        timers.write(0x08, 10);
+        timers.step(10, 0, 0);
+        timers.step(0xFFFF - 10, 0, 0);
+
+        let mode = timers.read(0x04);
+        assert_eq!(mode & (3 << 11), 3 << 11);
+        assert_eq!(timers.read(0x04) & (3 << 11), 0);
+
+        // Offset 0xC isn't a register
+        assert_eq!(timers.read(0x0C), 0xFFFFFFFF);
+        assert_eq!(timers.read(0x2C), 0xFFFFFFFF);
+    }
+
+    #[test]
+    fn mode_and_target_round_trip_for_every_timer() {
+        let mut timers = Timers::new();
+
+        for index in 0..3 {
+            let base = index * 0x10;
+            timers.write(base, 0x1234);
+            timers.write(base + 8, 0x4321 + index);
+            assert_eq!(timers.read(base), 0x1234);
+
+            // Writing the mode resets the counter, bit 10 reads back as the idle interrupt line
+            timers.write(base + 4, 0x03FF);
+            assert_eq!(timers.read(base), 0);
+            assert_eq!(timers.read(base + 4), 0x07FF);
+            assert_eq!(timers.read(base + 8), 0x4321 + index);
+
+            timers.write(base + 4, 0x0158);
+            assert_eq!(timers.read(base + 4), 0x0558);
+        }
+        assert!(timers.timers[2].mode.reset_on_target);
+        assert_eq!(timers.timers[2].mode.clock_source, 1);
+    }
+
+    #[test]
+    fn long_steps_wrap_at_the_target_and_overflow() {
+        let mut timers = Timers::new();
+
+        // Free running, wraps once after 0xFFFF
+        timers.step(70000, 0, 0);
+        assert_eq!(timers.read(0x00), 70000 - 0x10000);
+        assert_ne!(timers.read(0x04) & (1 << 12), 0);
+
+        // Reset after reaching 1000, a period is 1001 ticks. The toggled interrupt line goes low
+        // on every odd wrap.
+        let mut timers = Timers::new();
+        timers.write(0x18, 1000);
+        timers.write(0x14, 0x00D8);
+        assert_ne!(timers.step(70000, 0, 0), 0);
+        assert_eq!(timers.read(0x10), 70000 % 1001);
+        assert_eq!(70000 / 1001, 69);
+        let mode = timers.read(0x14);
+        assert_eq!(mode & (1 << 10), 0);
+        assert_ne!(mode & (1 << 11), 0);
+        assert_eq!(mode & (1 << 12), 0);
+
+        // One more wrap brings it back high
+        timers.step(1001, 0, 0);
+        assert_ne!(timers.read(0x14) & (1 << 10), 0);
+    }
+
+    // Number of timer 2 interrupts in the cycles, stepping one cycle at a time
+    fn timer2_interrupts(mode: u32, cycles: u32) -> usize {
+        let mut timers = Timers::new();
+        timers.write(0x28, 100);
+        timers.write(0x24, mode);
+
+        (0..cycles)
+            .filter(|_| timers.step(1, 0, 0) == 1 << Irq::Timer2 as u16)
+            .count()
+    }
+
+    #[test]
+    fn target_interrupts_repeat_or_fire_once() {
+        // Reset on target, interrupt on target, repeat
+        assert_eq!(timer2_interrupts(0x58, 250), 2);
+        // One-shot
+        assert_eq!(timer2_interrupts(0x18, 250), 1);
+        // The overflow interrupt never comes with the counter reset at the target
+        assert_eq!(timer2_interrupts(0x68, 250), 0);
+    }
+
+    #[test]
+    fn clock_sources_select_the_ticks() {
+        let mut timers = Timers::new();
+        timers.write(0x04, 0x100);
+        timers.write(0x14, 0x100);
+        timers.write(0x24, 0x200);
+
+        // 13 cycles don't divide by 8, the remainder is kept for the next step
+        timers.step(13, 5, 2);
+        assert_eq!(timers.read(0x00), 5);
+        assert_eq!(timers.read(0x10), 2);
+        assert_eq!(timers.read(0x20), 1);
+
+        timers.step(13, 5, 2);
+        assert_eq!(timers.read(0x20), 3);
+        for _ in 0..6 {
+            timers.step(1, 0, 0);
+        }
+        assert_eq!(timers.read(0x20), 4);
+
+        // Clock source 1 of timer 2 is still the system clock
+        timers.write(0x24, 0x100);
+        timers.step(13, 5, 2);
+        assert_eq!(timers.read(0x20), 13);
+    }
+
+    #[test]
+    fn sync_modes_follow_the_blanks() {
+        let mut timers = Timers::new();
+
+        // Timer 1 resets when the vblank starts and keeps counting through it
+        timers.write(0x14, 0x03);
+        timers.step(500, 0, 0);
+        timers.notify_vblank(true);
+        assert_eq!(timers.read(0x10), 0);
+        timers.step(20, 0, 0);
+        timers.notify_vblank(false);
+        assert_eq!(timers.read(0x10), 20);
+
+        // Timer 0 pauses during the hblank
+        timers.write(0x04, 0x01);
+        timers.step(10, 0, 0);
+        timers.notify_hblank(true);
+        timers.step(10, 0, 0);
+        timers.notify_hblank(false);
+        timers.step(10, 0, 0);
+        assert_eq!(timers.read(0x00), 20);
+
+        // Timer 2 is stopped in sync mode 0 and runs freely in mode 1
+        timers.write(0x24, 0x01);
+        timers.step(100, 0, 0);
+        assert_eq!(timers.read(0x20), 0);
+        timers.write(0x24, 0x03);
+        timers.step(100, 0, 0);
+        assert_eq!(timers.read(0x20), 100);
+    }
 }
@@ -0,0 +1,432 @@
+use std::{
+    fmt,
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Write},
+};
+
+use crate::disasm::disassemble;
+
+// Binary instruction traces. A header of the magic, the version and the compression is followed
+// by fixed size records. The PC is stored relative to the instruction after the previous one, so
+// straight-line code stores zeros and the stream compresses well.
+
+const MAGIC: [u8; 4] = *b"PSXT";
+const VERSION: u8 = 1;
+// PC delta, opcode, register, value and flags
+const RECORD_SIZE: usize = 14;
+
+// The instruction ran in the delay slot of a branch
+pub const DELAY_SLOT: u8 = 1 << 0;
+// An exception (including interrupts) was taken instead of or after the instruction
+pub const EXCEPTION: u8 = 1 << 1;
+// More than one register changed, only the lowest one is recorded
+pub const MULTIPLE_WRITES: u8 = 1 << 2;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Compression {
+    None,
+    // Needs the deflate feature
+    Deflate,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct TraceRecord {
+    pub pc: u32,
+    pub opcode: u32,
+    // The register the instruction changed and its new value, r0 is never written
+    pub write: Option<(u8, u32)>,
+    pub flags: u8,
+}
+
+impl TraceRecord {
+    // Without the disassembly the opcode is printed as a word
+    pub fn to_text(&self, disassembly: bool) -> String {
+        let mut text = if disassembly {
+            format!(
+                "{:08x}: {:08x} {:<32}",
+                self.pc,
+                self.opcode,
+                disassemble(self.pc, self.opcode)
+            )
+        } else {
+            format!("{:08x}: {:08x}", self.pc, self.opcode)
+        };
+
+        if let Some((register, value)) = self.write {
+            text += &format!(" r{}=0x{:08x}", register, value);
+        }
+        if self.flags & DELAY_SLOT != 0 {
+            text += " (delay slot)";
+        }
+        if self.flags & EXCEPTION != 0 {
+            text += " (exception)";
+        }
+        if self.flags & MULTIPLE_WRITES != 0 {
+            text += " (more registers written)";
+        }
+
+        text.trim_end().to_string()
+    }
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_text(true))
+    }
+}
+
+enum Sink<W: Write> {
+    Plain(BufWriter<W>),
+    #[cfg(feature = "deflate")]
+    Deflate(flate2::write::DeflateEncoder<BufWriter<W>>),
+}
+
+pub struct TraceWriter<W: Write> {
+    sink: Sink<W>,
+    // Where the previous record expected the next instruction
+    next_pc: u32,
+    record_count: u64,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(writer: W, compression: Compression) -> io::Result<Self> {
+        let mut writer = BufWriter::new(writer);
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[VERSION, compression as u8])?;
+
+        let sink = match compression {
+            Compression::None => Sink::Plain(writer),
+            #[cfg(feature = "deflate")]
+            Compression::Deflate => Sink::Deflate(flate2::write::DeflateEncoder::new(
+                writer,
+                flate2::Compression::default(),
+            )),
+            #[cfg(not(feature = "deflate"))]
+            Compression::Deflate => return Err(unsupported_compression()),
+        };
+
+        Ok(Self {
+            sink,
+            next_pc: 0,
+            record_count: 0,
+        })
+    }
+
+    pub fn write(&mut self, record: &TraceRecord) -> io::Result<()> {
+        let mut bytes = [0; RECORD_SIZE];
+        let delta = record.pc.wrapping_sub(self.next_pc);
+        bytes[0..4].copy_from_slice(&delta.to_le_bytes());
+        bytes[4..8].copy_from_slice(&record.opcode.to_le_bytes());
+        let (register, value) = record.write.unwrap_or((0, 0));
+        bytes[8] = register;
+        bytes[9..13].copy_from_slice(&value.to_le_bytes());
+        bytes[13] = record.flags;
+
+        self.next_pc = record.pc.wrapping_add(4);
+        self.record_count += 1;
+
+        match &mut self.sink {
+            Sink::Plain(writer) => writer.write_all(&bytes),
+            #[cfg(feature = "deflate")]
+            Sink::Deflate(encoder) => encoder.write_all(&bytes),
+        }
+    }
+
+    pub fn record_count(&self) -> u64 {
+        self.record_count
+    }
+
+    // Ends the compressed stream and flushes everything to the writer
+    #[cfg_attr(
+        not(feature = "deflate"),
+        allow(clippy::infallible_destructuring_match)
+    )]
+    pub fn finish(self) -> io::Result<W> {
+        let writer = match self.sink {
+            Sink::Plain(writer) => writer,
+            #[cfg(feature = "deflate")]
+            Sink::Deflate(encoder) => encoder.finish()?,
+        };
+
+        writer.into_inner().map_err(|error| error.into_error())
+    }
+}
+
+enum Source<R: Read> {
+    Plain(BufReader<R>),
+    #[cfg(feature = "deflate")]
+    Deflate(flate2::read::DeflateDecoder<BufReader<R>>),
+}
+
+// Iterates over the records of a trace
+pub struct TraceReader<R: Read> {
+    source: Source<R>,
+    next_pc: u32,
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let mut header = [0; 6];
+        reader.read_exact(&mut header)?;
+        if header[0..4] != MAGIC {
+            return Err(io::Error::new(ErrorKind::InvalidData, "Not a trace file"));
+        }
+        if header[4] != VERSION {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Unsupported trace version {}", header[4]),
+            ));
+        }
+
+        let source = match header[5] {
+            0 => Source::Plain(reader),
+            #[cfg(feature = "deflate")]
+            1 => Source::Deflate(flate2::read::DeflateDecoder::new(reader)),
+            #[cfg(not(feature = "deflate"))]
+            1 => return Err(unsupported_compression()),
+            compression => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Unknown trace compression {}", compression),
+                ))
+            }
+        };
+
+        Ok(Self { source, next_pc: 0 })
+    }
+
+    #[cfg_attr(
+        not(feature = "deflate"),
+        allow(clippy::infallible_destructuring_match)
+    )]
+    fn read_record(&mut self) -> io::Result<Option<TraceRecord>> {
+        let mut bytes = [0; RECORD_SIZE];
+        let reader: &mut dyn Read = match &mut self.source {
+            Source::Plain(reader) => reader,
+            #[cfg(feature = "deflate")]
+            Source::Deflate(decoder) => decoder,
+        };
+
+        // The trace ends at a record boundary, anything else is truncated
+        let mut filled = 0;
+        while filled < RECORD_SIZE {
+            match reader.read(&mut bytes[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "Trace ends in the middle of a record",
+                    ))
+                }
+                Ok(read) => filled += read,
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+
+        let word =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let pc = self.next_pc.wrapping_add(word(0));
+        self.next_pc = pc.wrapping_add(4);
+
+        Ok(Some(TraceRecord {
+            pc,
+            opcode: word(4),
+            write: (bytes[8] != 0).then(|| (bytes[8], word(9))),
+            flags: bytes[13],
+        }))
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<TraceRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+#[cfg(not(feature = "deflate"))]
+fn unsupported_compression() -> io::Error {
+    io::Error::new(
+        ErrorKind::Unsupported,
+        "Compressed traces need the deflate feature",
+    )
+}
+
+// Where two traces stop agreeing
+#[derive(PartialEq, Eq, Debug)]
+pub enum TraceDifference {
+    // The records at this index differ
+    Record {
+        index: u64,
+        expected: TraceRecord,
+        actual: TraceRecord,
+    },
+    // One trace ended after this many records, the other one has more
+    Length {
+        index: u64,
+        expected_longer: bool,
+    },
+}
+
+impl fmt::Display for TraceDifference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceDifference::Record {
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Instruction {} differs\n  expected {}\n  actual   {}",
+                index, expected, actual
+            ),
+            TraceDifference::Length {
+                index,
+                expected_longer: true,
+            } => write!(f, "The actual trace ends after {} instructions", index),
+            TraceDifference::Length { index, .. } => {
+                write!(f, "The expected trace ends after {} instructions", index)
+            }
+        }
+    }
+}
+
+// The first difference between a reference trace and a new one, None when they match
+pub fn compare(
+    expected: impl Iterator<Item = io::Result<TraceRecord>>,
+    actual: impl Iterator<Item = io::Result<TraceRecord>>,
+) -> io::Result<Option<TraceDifference>> {
+    let mut expected = expected;
+    let mut actual = actual;
+    let mut index = 0;
+
+    loop {
+        match (expected.next().transpose()?, actual.next().transpose()?) {
+            (None, None) => return Ok(None),
+            (Some(expected), Some(actual)) if expected == actual => {}
+            (Some(expected), Some(actual)) => {
+                return Ok(Some(TraceDifference::Record {
+                    index,
+                    expected,
+                    actual,
+                }))
+            }
+            (expected, _) => {
+                return Ok(Some(TraceDifference::Length {
+                    index,
+                    expected_longer: expected.is_some(),
+                }))
+            }
+        }
+        index += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Straight-line code with taken branches, loads into registers and an exception now and then
+    fn generated_trace(count: u32) -> Vec<TraceRecord> {
+        let mut pc = 0x80010000;
+        (0..count)
+            .map(|i| {
+                let record = TraceRecord {
+                    pc,
+                    opcode: 0x24080000 | (i & 0xFFFF),
+                    write: (i % 3 != 0).then_some(((i % 31 + 1) as u8, i.wrapping_mul(0x9E3779B9))),
+                    flags: if i % 97 == 0 { EXCEPTION } else { 0 },
+                };
+                pc = if i % 16 == 15 {
+                    pc.wrapping_sub(60)
+                } else {
+                    pc.wrapping_add(4)
+                };
+                record
+            })
+            .collect()
+    }
+
+    fn round_trip(compression: Compression) {
+        let records = generated_trace(1000);
+        let mut writer = TraceWriter::new(Vec::new(), compression).unwrap();
+        for record in &records {
+            writer.write(record).unwrap();
+        }
+        assert_eq!(writer.record_count(), 1000);
+        let bytes = writer.finish().unwrap();
+
+        let read: Vec<_> = TraceReader::new(bytes.as_slice())
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(read, records);
+    }
+
+    #[test]
+    fn records_round_trip() {
+        round_trip(Compression::None);
+        #[cfg(feature = "deflate")]
+        round_trip(Compression::Deflate);
+    }
+
+    #[test]
+    fn truncated_and_foreign_files_are_rejected() {
+        let mut writer = TraceWriter::new(Vec::new(), Compression::None).unwrap();
+        writer.write(&TraceRecord::default()).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let mut reader = TraceReader::new(&bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(
+            reader.next().unwrap().unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+        assert!(TraceReader::new(&b"PSX-EXE\0"[..]).is_err());
+    }
+
+    fn ok(records: &[TraceRecord]) -> impl Iterator<Item = io::Result<TraceRecord>> + '_ {
+        records.iter().copied().map(Ok)
+    }
+
+    #[test]
+    fn comparison_finds_the_first_difference() {
+        let records = generated_trace(100);
+        let mut changed = records.clone();
+        changed[42].write = Some((2, 0));
+
+        assert_eq!(compare(ok(&records), ok(&records)).unwrap(), None);
+        assert_eq!(
+            compare(ok(&records), ok(&changed)).unwrap(),
+            Some(TraceDifference::Record {
+                index: 42,
+                expected: records[42],
+                actual: changed[42],
+            })
+        );
+        assert_eq!(
+            compare(ok(&records), ok(&records[..60])).unwrap(),
+            Some(TraceDifference::Length {
+                index: 60,
+                expected_longer: true,
+            })
+        );
+    }
+
+    #[test]
+    fn records_print_with_disassembly() {
+        let record = TraceRecord {
+            pc: 0xBFC00000,
+            opcode: 0x3C080013,
+            write: Some((8, 0x00130000)),
+            flags: DELAY_SLOT,
+        };
+
+        assert_eq!(
+            record.to_text(false),
+            "bfc00000: 3c080013 r8=0x00130000 (delay slot)"
+        );
+        assert!(record.to_text(true).contains("lui"));
+    }
+}
@@ -0,0 +1,73 @@
+use crate::sio::Button;
+
+// At 60 frames per second two frames pressed and two released is 15 presses a second
+pub const DEFAULT_TURBO_RATE: u32 = 2;
+
+// Auto-fire of held buttons. Turbo buttons are pressed for rate frames and released for as many
+// while held. The pulse follows the frame count of the GPU, so a replay presses the same frames.
+pub struct Turbo {
+    rate: u32,
+    buttons: u16, // Bitmask of the turbo buttons
+}
+
+impl Turbo {
+    pub fn new(rate: u32) -> Self {
+        Self {
+            rate: rate.max(1),
+            buttons: 0,
+        }
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    pub fn is_enabled(&self, button: Button) -> bool {
+        self.buttons & (1 << button as u16) != 0
+    }
+
+    // Returns whether the button now fires by itself
+    pub fn toggle(&mut self, button: Button) -> bool {
+        self.buttons ^= 1 << button as u16;
+        self.is_enabled(button)
+    }
+
+    // Whether the pad sees the button pressed in the frame
+    pub fn apply(&self, button: Button, held: bool, frame: u64) -> bool {
+        held && (!self.is_enabled(button) || (frame / self.rate as u64).is_multiple_of(2))
+    }
+}
+
+impl Default for Turbo {
+    fn default() -> Self {
+        Self::new(DEFAULT_TURBO_RATE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn held_turbo_button_alternates_by_the_rate() {
+        let mut turbo = Turbo::new(3);
+        assert!(turbo.toggle(Button::Cross));
+
+        let pressed: Vec<bool> = (0..60)
+            .map(|frame| turbo.apply(Button::Cross, true, frame))
+            .collect();
+        for (frame, pressed) in pressed.iter().enumerate() {
+            assert_eq!(*pressed, frame % 6 < 3, "frame {}", frame);
+        }
+        assert_eq!(pressed.iter().filter(|pressed| **pressed).count(), 30);
+
+        // Other buttons and released ones are passed through
+        assert!((0..60).all(|frame| turbo.apply(Button::Circle, true, frame)));
+        assert!((0..60).all(|frame| !turbo.apply(Button::Cross, false, frame)));
+
+        // The phase only depends on the frame, not on when the button was pressed
+        assert!(!turbo.apply(Button::Cross, true, 3));
+        assert!(!turbo.toggle(Button::Cross));
+        assert!(turbo.apply(Button::Cross, true, 3));
+    }
+}
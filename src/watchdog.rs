@@ -0,0 +1,148 @@
+use std::fmt;
+
+// Hang detection for automated runs. A guest is considered hung when its PC stays in a small
+// window for too long, or the same PC and registers keep coming back. Interrupts jump to the
+// exception vector outside the window, so every delivered interrupt starts the count over.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct WatchdogConfig {
+    // Size of the address window in bytes
+    pub window: u32,
+    // Instructions the PC may stay in the window
+    pub instructions: u64,
+    // Times the same state may come back at the start of the window
+    pub repeats: u64,
+}
+
+impl Default for WatchdogConfig {
+    // About 3 seconds of emulated time in a 16 instruction window
+    fn default() -> Self {
+        Self {
+            window: 64,
+            instructions: 100_000_000,
+            repeats: 10_000_000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HangKind {
+    // The loop doesn't read memory, only an interrupt could get it out
+    Spin,
+    // The loop waits for memory or a device register that never changes
+    Poll,
+}
+
+impl fmt::Display for HangKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HangKind::Spin => write!(f, "spinning without reading memory"),
+            HangKind::Poll => write!(f, "polling memory that doesn't change"),
+        }
+    }
+}
+
+pub struct Watchdog {
+    config: WatchdogConfig,
+    // Lowest and highest PC since the window started
+    start: u32,
+    end: u32,
+    instructions: u64,
+    // The registers the last time the PC was at the start of the window
+    anchor: [u32; 32],
+    repeats: u64,
+}
+
+impl Watchdog {
+    pub fn new(config: WatchdogConfig) -> Self {
+        Self {
+            config,
+            start: 0,
+            end: 0,
+            instructions: 0,
+            anchor: [0; 32],
+            repeats: 0,
+        }
+    }
+
+    // Called before every instruction, returns whether the guest is hung
+    pub fn observe(&mut self, pc: u32, registers: &[u32; 32]) -> bool {
+        let start = self.start.min(pc);
+        let end = self.end.max(pc);
+        if end - start >= self.config.window {
+            self.start = pc;
+            self.end = pc;
+            self.instructions = 0;
+            self.anchor = *registers;
+            self.repeats = 0;
+            return false;
+        }
+
+        self.start = start;
+        self.end = end;
+        self.instructions += 1;
+
+        if pc == self.start {
+            if *registers == self.anchor {
+                self.repeats += 1;
+            } else {
+                self.anchor = *registers;
+                self.repeats = 0;
+            }
+        }
+
+        self.instructions >= self.config.instructions || self.repeats >= self.config.repeats
+    }
+
+    // First and last address of the instructions the guest is stuck in
+    pub fn window(&self) -> (u32, u32) {
+        (self.start, self.end)
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+}
+
+// Loops with loads wait for something, the others for nothing
+pub fn classify(words: &[u32]) -> HangKind {
+    let loads = words
+        .iter()
+        .any(|word| matches!(word >> 26, 0x20..=0x26 | 0x30..=0x33));
+
+    if loads {
+        HangKind::Poll
+    } else {
+        HangKind::Spin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaving_the_window_starts_over() {
+        let mut watchdog = Watchdog::new(WatchdogConfig {
+            window: 16,
+            instructions: 100,
+            repeats: 100,
+        });
+        let mut registers = [0; 32];
+
+        // A loop that counts in a register, the registers never repeat. The first instruction
+        // starts the window.
+        for i in 0..100 {
+            registers[8] = i;
+            assert!(!watchdog.observe(0x1000 + (i % 4) * 4, &registers));
+        }
+
+        // An interrupt handler runs somewhere else
+        assert!(!watchdog.observe(0x80, &registers));
+        for i in 0..=100 {
+            registers[8] = i;
+            assert_eq!(watchdog.observe(0x1000 + (i % 4) * 4, &registers), i == 100);
+        }
+        assert_eq!(watchdog.window(), (0x1000, 0x100C));
+    }
+}
@@ -0,0 +1,182 @@
+use crate::disc::SECTOR_SIZE;
+
+// XA-ADPCM, the compressed audio in Mode 2 Form 2 sectors. The 0x900 bytes after the subheader
+// are 18 sound groups of 128 bytes, each with 16 header bytes and 28 words of samples.
+
+const SOUND_GROUPS: usize = 18;
+const SOUND_GROUP_SIZE: usize = 128;
+
+// Coding info bits, the last subheader byte
+const CODING_STEREO: u8 = 1 << 0;
+const CODING_HALF_RATE: u8 = 1 << 2;
+const CODING_8BIT: u8 = 1 << 4;
+
+// Prediction filter weights in 1/64
+const POSITIVE_WEIGHTS: [i32; 4] = [0, 60, 115, 98];
+const NEGATIVE_WEIGHTS: [i32; 4] = [0, 0, -52, -55];
+
+pub struct XaDecoder {
+    // The two previous samples of the left and right channel, mono uses the left one
+    history: [[i32; 2]; 2],
+}
+
+impl XaDecoder {
+    pub fn new() -> Self {
+        Self {
+            history: [[0; 2]; 2],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.history = [[0; 2]; 2];
+    }
+
+    // 37800 or 18900 Hz
+    pub fn sample_rate(coding: u8) -> u32 {
+        match coding & CODING_HALF_RATE {
+            0 => 37800,
+            _ => 18900,
+        }
+    }
+
+    // Decodes the samples of a raw sector, mono samples go to both channels
+    pub fn decode_sector(&mut self, sector: &[u8; SECTOR_SIZE], output: &mut Vec<(i16, i16)>) {
+        let coding = sector[19];
+        let stereo = coding & CODING_STEREO != 0;
+        let eight_bit = coding & CODING_8BIT != 0;
+        let units = if eight_bit { 4 } else { 8 };
+
+        for group in sector[24..]
+            .chunks_exact(SOUND_GROUP_SIZE)
+            .take(SOUND_GROUPS)
+        {
+            let mut samples = [[0; 28]; 8];
+            for (unit, samples) in samples.iter_mut().enumerate().take(units) {
+                let channel = if stereo { unit & 1 } else { 0 };
+                self.decode_unit(group, unit, eight_bit, channel, samples);
+            }
+
+            if stereo {
+                for pair in samples[..units].chunks_exact(2) {
+                    output.extend(pair[0].iter().copied().zip(pair[1].iter().copied()));
+                }
+            } else {
+                for unit in &samples[..units] {
+                    output.extend(unit.iter().map(|&sample| (sample, sample)));
+                }
+            }
+        }
+    }
+
+    // 28 samples of one sound unit. The header byte holds the shift in the low and the filter in
+    // the high nibble, the samples of the units are interleaved by byte (8 bit) or nibble (4 bit).
+    fn decode_unit(
+        &mut self,
+        group: &[u8],
+        unit: usize,
+        eight_bit: bool,
+        channel: usize,
+        samples: &mut [i16; 28],
+    ) {
+        let header = group[4 + unit];
+        // Shifts above 12 behave like 9
+        let shift = match header & 0xF {
+            shift @ 0..=12 => shift,
+            _ => 9,
+        };
+        let filter = ((header >> 4) & 3) as usize;
+        let [old, older] = &mut self.history[channel];
+
+        for (i, sample) in samples.iter_mut().enumerate() {
+            let raw = if eight_bit {
+                (group[16 + i * 4 + unit] as i16) << 8
+            } else {
+                let byte = group[16 + i * 4 + unit / 2];
+                (((byte >> ((unit & 1) * 4)) & 0xF) as i16) << 12
+            };
+
+            let prediction =
+                (*old * POSITIVE_WEIGHTS[filter] + *older * NEGATIVE_WEIGHTS[filter] + 32) / 64;
+            let value =
+                ((raw >> shift) as i32 + prediction).clamp(i16::MIN as i32, i16::MAX as i32);
+
+            *older = *old;
+            *old = value;
+            *sample = value as i16;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One sound group with the headers of the units, the unit 0..3 header bytes are repeated at
+    // 8..11. Unit 0 has shift 0 and filter 0, unit 1 shift 12 and filter 1, unit 2 shift 4 and
+    // filter 2, unit 3 shift 8 and filter 3.
+    fn reference_sector(coding: u8) -> [u8; SECTOR_SIZE] {
+        let mut sector = [0; SECTOR_SIZE];
+        sector[15] = 2;
+        sector[19] = coding;
+
+        let group = &mut sector[24..24 + SOUND_GROUP_SIZE];
+        for (unit, header) in [0x00, 0x1C, 0x24, 0x38].into_iter().enumerate() {
+            group[4 + unit] = header;
+            group[8 + unit] = header;
+        }
+        for i in 0..28 {
+            group[16 + i * 4] = (i as u8 & 7) | 0xF0;
+            group[16 + i * 4 + 1] = 0x39;
+        }
+
+        sector
+    }
+
+    // Worked out with a separate implementation of the decoder described by nocash
+    const UNIT_1: [i16; 28] = [
+        11519, 10798, 10122, 9488, 8894, 8337, 7815, 7326, 6867, 6437, 6034, 5656, 5302, 4970,
+        4658, 4366, 4092, 3835, 3594, 3368, 3157, 2959, 2773, 2599, 2436, 2283, 2139, 2004,
+    ];
+    const UNIT_2_START: [i16; 8] = [71, -3292, -7764, -13067, -18963, -25248, -31751, -32768];
+    const UNIT_3: [i16; 28] = [
+        -21967, -5428, 10614, 20965, 23029, 17294, 6739, -4494, -12624, -15419, -12713, -6167,
+        1530, 7691, 10510, 9532, 5612, 450, -4085, -6593, -6536, -4293, -908, 2347, 4422, 4802,
+        3601, 1435,
+    ];
+
+    #[test]
+    fn decodes_mono_4bit_sector() {
+        let mut output = Vec::new();
+        XaDecoder::new().decode_sector(&reference_sector(0), &mut output);
+
+        assert_eq!(output.len(), SOUND_GROUPS * 8 * 28);
+        assert!(output.iter().all(|(left, right)| left == right));
+        let left: Vec<i16> = output.iter().map(|(left, _)| *left).collect();
+
+        let unit_0: Vec<i16> = (0..28).map(|i| (i & 7) * 0x1000).collect();
+        assert_eq!(left[..28], unit_0[..]);
+        assert_eq!(left[28..56], UNIT_1);
+        assert_eq!(left[56..64], UNIT_2_START);
+        assert!(left[64..84].iter().all(|&sample| sample == i16::MIN));
+        assert_eq!(left[84..112], UNIT_3);
+        assert!(left[112..].iter().all(|&sample| sample == 0));
+    }
+
+    #[test]
+    fn stereo_sectors_pair_units_with_separate_history() {
+        let mut output = Vec::new();
+        XaDecoder::new().decode_sector(&reference_sector(CODING_STEREO), &mut output);
+
+        assert_eq!(output.len(), SOUND_GROUPS * 4 * 28);
+        for (i, (left, right)) in output[..28].iter().enumerate() {
+            assert_eq!(*left, (i as i16 & 7) * 0x1000);
+            assert_eq!(*right, -1);
+        }
+    }
+
+    #[test]
+    fn half_rate_coding() {
+        assert_eq!(XaDecoder::sample_rate(0), 37800);
+        assert_eq!(XaDecoder::sample_rate(CODING_HALF_RATE), 18900);
+    }
+}
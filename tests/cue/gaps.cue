@@ -0,0 +1,11 @@
+FILE "gaps.bin" BINARY
+  TRACK 01 MODE1/2352
+    INDEX 01 00:00:00
+    POSTGAP 00:00:02
+  TRACK 02 AUDIO
+    PREGAP 00:00:03
+    INDEX 01 00:00:10
+  TRACK 03 AUDIO
+    INDEX 00 00:00:15
+    INDEX 01 00:00:17
+    POSTGAP 00:00:01